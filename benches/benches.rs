@@ -18,6 +18,10 @@ extern crate npnc;
 
 use std::env;
 
+use npnc::bounded::mpmc::{Scq};
+use npnc::reclaim::{Hazard};
+use npnc::unbounded::mpmc::{FaaArray};
+
 const WARMUP: usize = 1_000_000;
 const MEASUREMENT: usize = 100_000_000;
 const RANKS: &[f64] = &[50.0, 70.0, 90.0, 95.0, 99.0, 99.9, 99.99, 99.999, 99.9999, 99.99999];
@@ -33,13 +37,13 @@ fn thousands(ops: f64) -> String {
 }
 
 macro_rules! bench_throughput {
-    ([$($path:tt)*], $producers:expr, $consumers:expr) => ({
+    ([$($path:tt)*]$(<$($param:ty),*>)*, $producers:expr, $consumers:expr) => ({
         queuecheck_bench_throughput!(
             (WARMUP, MEASUREMENT),
             $producers,
             $consumers,
-            |p: &npnc::$($path)*::Producer<i32>, i: i32| p.produce(i).unwrap(),
-            |c: &npnc::$($path)*::Consumer<i32>| c.consume().ok()
+            |p: &npnc::$($path)*::Producer<i32$($(, $param)*)*>, i: i32| p.produce(i).unwrap(),
+            |c: &npnc::$($path)*::Consumer<i32$($(, $param)*)*>| c.consume().ok()
         )
     });
 }
@@ -49,6 +53,10 @@ macro_rules! bench_throughput_spsc {
         let (producer, consumer) = npnc::$($path)*::channel($($size)*);
         bench_throughput!([$($path)*], vec![producer], vec![consumer])
     });
+    ([$($path:tt)*]<$($param:ty),*>$(, $size:expr)*) => ({
+        let (producer, consumer) = npnc::$($path)*::custom_channel($($size)*);
+        bench_throughput!([$($path)*]<$($param),*>, vec![producer], vec![consumer])
+    });
 }
 
 macro_rules! run_throughput {
@@ -64,13 +72,13 @@ macro_rules! run_throughput {
 }
 
 macro_rules! bench_latency {
-    ([$($path:tt)*], $producers:expr, $consumers:expr) => ({
+    ([$($path:tt)*]$(<$($param:ty),*>)*, $producers:expr, $consumers:expr) => ({
         queuecheck_bench_latency!(
             (WARMUP, MEASUREMENT),
             $producers,
             $consumers,
-            |p: &npnc::$($path)*::Producer<i32>, i: i32| p.produce(i).unwrap(),
-            |c: &npnc::$($path)*::Consumer<i32>| c.consume().ok()
+            |p: &npnc::$($path)*::Producer<i32$($(, $param)*)*>, i: i32| p.produce(i).unwrap(),
+            |c: &npnc::$($path)*::Consumer<i32$($(, $param)*)*>| c.consume().ok()
         )
     });
 }
@@ -80,6 +88,10 @@ macro_rules! bench_latency_spsc {
         let (producer, consumer) = npnc::$($path)*::channel($($size)*);
         bench_latency!([$($path)*], vec![producer], vec![consumer])
     });
+    ([$($path:tt)*]<$($param:ty),*>$(, $size:expr)*) => ({
+        let (producer, consumer) = npnc::$($path)*::custom_channel($($size)*);
+        bench_latency!([$($path)*]<$($param),*>, vec![producer], vec![consumer])
+    });
 }
 
 macro_rules! run_latency {
@@ -97,9 +109,38 @@ fn main() {
     run_throughput!(filter, "bounded_spsc", 25, bench_throughput_spsc!([bounded::spsc], 2 << 24));
     run_throughput!(filter, "unbounded_spsc", 5, bench_throughput_spsc!([unbounded::spsc]));
     run_throughput!(filter, "bounded_mpmc", 5, bench_throughput_spsc!([bounded::mpmc], 2 << 24));
+    run_throughput!(
+        filter, "bounded_mpmc_scq", 5, bench_throughput_spsc!([bounded::mpmc]<Scq<i32>>, 2 << 24)
+    );
+    run_throughput!(filter, "bounded_mpsc", 5, bench_throughput_spsc!([bounded::mpsc], 2 << 24));
+    run_throughput!(filter, "bounded_spmc", 5, bench_throughput_spsc!([bounded::spmc], 2 << 24));
     run_throughput!(filter, "unbounded_mpmc", 3, bench_throughput_spsc!([unbounded::mpmc], 0));
+    run_throughput!(
+        filter,
+        "unbounded_mpmc_faa_array",
+        3,
+        bench_throughput_spsc!([unbounded::mpmc]<Hazard, FaaArray<i32>>, 0)
+    );
+    run_throughput!(filter, "unbounded_mpsc", 5, bench_throughput_spsc!([unbounded::mpsc]));
+    run_throughput!(filter, "unbounded_spmc", 5, bench_throughput_spsc!([unbounded::spmc], 0));
+    run_throughput!(filter, "unbounded_relaxed", 5, bench_throughput_spsc!([unbounded::relaxed]));
+    run_throughput!(filter, "stack", 5, bench_throughput_spsc!([stack], 0));
     run_latency!(filter, "bounded_spsc", bench_latency_spsc!([bounded::spsc], 2 << 24));
     run_latency!(filter, "unbounded_spsc", bench_latency_spsc!([unbounded::spsc]));
     run_latency!(filter, "bounded_mpmc", bench_latency_spsc!([bounded::mpmc], 2 << 24));
+    run_latency!(
+        filter, "bounded_mpmc_scq", bench_latency_spsc!([bounded::mpmc]<Scq<i32>>, 2 << 24)
+    );
+    run_latency!(filter, "bounded_mpsc", bench_latency_spsc!([bounded::mpsc], 2 << 24));
+    run_latency!(filter, "bounded_spmc", bench_latency_spsc!([bounded::spmc], 2 << 24));
     run_latency!(filter, "unbounded_mpmc", bench_latency_spsc!([unbounded::mpmc], 0));
+    run_latency!(
+        filter,
+        "unbounded_mpmc_faa_array",
+        bench_latency_spsc!([unbounded::mpmc]<Hazard, FaaArray<i32>>, 0)
+    );
+    run_latency!(filter, "unbounded_mpsc", bench_latency_spsc!([unbounded::mpsc]));
+    run_latency!(filter, "unbounded_spmc", bench_latency_spsc!([unbounded::spmc], 0));
+    run_latency!(filter, "unbounded_relaxed", bench_latency_spsc!([unbounded::relaxed]));
+    run_latency!(filter, "stack", bench_latency_spsc!([stack], 0));
 }
@@ -0,0 +1,318 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Producers and consumers that fall back to a lock under pathological contention.
+//!
+//! Under heavy contention, the compare-and-swap loops of the MPMC queues can fail over and over,
+//! which wastes cycles and hurts tail latency. The producers and consumers returned by `wrap` add
+//! and remove items with `produce_weak` and `consume_weak`, which give up with a `Contended` error
+//! instead of retrying internally. Once an operation has been contended a fixed number of times in
+//! a row (a retry storm), the producers (or consumers) switch to a slow path that serializes them
+//! through a mutex (which is futex-based on Linux), so they take turns instead of invalidating each
+//! other's cachelines. Once the mutex has been acquired without waiting a fixed number of times in
+//! a row (the cooldown), contention has subsided and they switch back to the lock-free path. The
+//! producers and consumers switch independently since they do not contend with each other.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::adaptive;
+//! use npnc::bounded::mpmc;
+//!
+//! fn main() {
+//!     let (producer, consumer) = mpmc::channel(64);
+//!     let (producer, consumer) = adaptive::wrap(producer, consumer, 16, 64);
+//!
+//!     producer.produce(1).unwrap();
+//!     assert_eq!(consumer.consume(), Ok(1));
+//!     assert!(!producer.is_serialized());
+//!     assert_eq!(producer.storms(), 0);
+//! }
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that falls back to a lock under pathological contention.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+    side: Arc<Side>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        if !self.side.serialized.load(Relaxed) {
+            for _ in 0..self.side.retries {
+                match self.consumer.consume_weak() {
+                    Err(ConsumeError::Contended) => { },
+                    result => return result,
+                }
+            }
+            self.side.enter();
+        }
+
+        let _guard = self.side.acquire();
+        self.consumer.consume()
+    }
+
+    /// Returns whether the consumers are currently serialized through the slow path.
+    pub fn is_serialized(&self) -> bool {
+        self.side.serialized.load(Relaxed)
+    }
+
+    /// Returns the number of times the consumers have switched to the slow path.
+    pub fn storms(&self) -> usize {
+        self.side.storms.load(Relaxed)
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        Consumer { consumer: self.consumer.clone(), side: self.side.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that falls back to a lock under pathological contention.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: P,
+    side: Arc<Side>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `Err` if the queue is full or has no remaining consumers.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        let mut item = item;
+        if !self.side.serialized.load(Relaxed) {
+            for _ in 0..self.side.retries {
+                match self.producer.produce_weak(item) {
+                    Err(ProduceError::Contended(returned)) => item = returned,
+                    result => return result,
+                }
+            }
+            self.side.enter();
+        }
+
+        let _guard = self.side.acquire();
+        self.producer.produce(item)
+    }
+
+    /// Returns whether the producers are currently serialized through the slow path.
+    pub fn is_serialized(&self) -> bool {
+        self.side.serialized.load(Relaxed)
+    }
+
+    /// Returns the number of times the producers have switched to the slow path.
+    pub fn storms(&self) -> usize {
+        self.side.storms.load(Relaxed)
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        Producer { producer: self.producer.clone(), side: self.side.clone() }
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// Side __________________________________________
+
+/// The contention state shared by the producers or the consumers of a queue.
+#[derive(Debug)]
+struct Side {
+    /// The number of times the slow path has been acquired without waiting in a row.
+    lock: Mutex<usize>,
+    serialized: AtomicBool,
+    storms: AtomicUsize,
+    retries: usize,
+    cooldown: usize,
+}
+
+impl Side {
+    //- Constructors -----------------------------
+
+    fn new(retries: usize, cooldown: usize) -> Arc<Self> {
+        Arc::new(Side {
+            lock: Mutex::new(0),
+            serialized: AtomicBool::new(false),
+            storms: AtomicUsize::new(0),
+            retries,
+            cooldown,
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    /// Switches to the slow path after a retry storm.
+    fn enter(&self) {
+        if !self.serialized.swap(true, Relaxed) {
+            self.storms.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Acquires the slow path and switches back to the lock-free path if contention has subsided.
+    fn acquire(&self) -> MutexGuard<'_, usize> {
+        let mut guard = match self.lock.try_lock() {
+            Ok(mut guard) => { *guard += 1; guard },
+            Err(TryLockError::Poisoned(error)) => error.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                let mut guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+                *guard = 0;
+                guard
+            },
+        };
+
+        if *guard >= self.cooldown {
+            self.serialized.store(false, Relaxed);
+            *guard = 0;
+        }
+        guard
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Wraps the supplied producer and consumer so they fall back to a lock under pathological
+/// contention.
+///
+/// The value of `retries` indicates the number of times in a row an operation can be contended
+/// before the producers or consumers switch to the slow path and the value of `cooldown` indicates
+/// the number of times in a row the slow path must be acquired without waiting before they switch
+/// back to the lock-free path.
+///
+/// # Panics
+///
+/// * `retries` is zero
+/// * `cooldown` is zero
+pub fn wrap<P, C>(
+    producer: P, consumer: C, retries: usize, cooldown: usize
+) -> (Producer<P>, Consumer<C>) {
+    assert!(retries != 0, "`retries` is zero");
+    assert!(cooldown != 0, "`cooldown` is zero");
+    let producer = Producer { producer, side: Side::new(retries, cooldown) };
+    (producer, Consumer { consumer, side: Side::new(retries, cooldown) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::{Cell};
+
+    /// A queue whose weak operations are contended the supplied number of times.
+    struct Contended(Cell<usize>);
+
+    impl Contended {
+        fn contend(&self) -> bool {
+            let remaining = self.0.get();
+            self.0.set(remaining.saturating_sub(1));
+            remaining != 0
+        }
+    }
+
+    impl Consume<i32> for Contended {
+        fn consume(&self) -> Result<i32, ConsumeError> {
+            Ok(1)
+        }
+
+        fn consume_weak(&self) -> Result<i32, ConsumeError> {
+            if self.contend() { Err(ConsumeError::Contended) } else { Ok(1) }
+        }
+    }
+
+    impl Produce<i32> for Contended {
+        fn produce(&self, _: i32) -> Result<(), ProduceError<i32>> {
+            Ok(())
+        }
+
+        fn produce_weak(&self, item: i32) -> Result<(), ProduceError<i32>> {
+            if self.contend() { Err(ProduceError::Contended(item)) } else { Ok(()) }
+        }
+    }
+
+    #[test]
+    fn test_storm() {
+        let (producer, consumer) = (Contended(Cell::new(3)), Contended(Cell::new(1)));
+        let (producer, consumer) = wrap(producer, consumer, 2, 2);
+
+        // The first two attempts are contended, so the producers switch to the slow path.
+        producer.produce(1).unwrap();
+        assert!(producer.is_serialized());
+        assert_eq!(producer.storms(), 1);
+
+        // The slow path is acquired without waiting twice in a row, so the producers switch back.
+        producer.produce(2).unwrap();
+        assert!(!producer.is_serialized());
+
+        // A single contended attempt does not start another storm.
+        producer.produce(3).unwrap();
+        assert_eq!((producer.is_serialized(), producer.storms()), (false, 1));
+
+        // The consumers switch independently of the producers.
+        assert_eq!(consumer.consume(), Ok(1));
+        assert_eq!((consumer.is_serialized(), consumer.storms()), (false, 0));
+    }
+
+    #[test]
+    #[should_panic(expected="`cooldown` is zero")]
+    fn test_zero_cooldown() {
+        let _ = wrap(Contended(Cell::new(0)), Contended(Cell::new(0)), 1, 0);
+    }
+}
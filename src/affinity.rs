@@ -0,0 +1,138 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Placement of producer and consumer threads on specific cores.
+//!
+//! The latency of a queue depends heavily on which cores its producers and consumers run on
+//! (e.g., whether they share a cache). The functions in this module pin threads to cores and
+//! construct queues on specific cores so that the memory used by a queue is allocated near the
+//! cores that will use it on systems that allocate memory on first use.
+//!
+//! This module requires the `affinity` feature and is only supported on Linux. The functions in
+//! this module return `Err` on other platforms.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! extern crate npnc;
+//!
+//! use npnc::affinity;
+//! use npnc::bounded::spsc;
+//!
+//! fn main() {
+//!     let (producer, consumer) = affinity::construct_on(1, || spsc::channel(64)).unwrap();
+//!
+//!     let a = affinity::spawn(1, move || {
+//!         for index in 0..32 {
+//!             producer.produce(index).unwrap();
+//!         }
+//!     });
+//!
+//!     let b = affinity::spawn(2, move || {
+//!         let mut count = 0;
+//!         while count < 32 {
+//!             if consumer.consume().is_ok() {
+//!                 count += 1;
+//!             }
+//!         }
+//!     });
+//!
+//!     a.join().unwrap().unwrap();
+//!     b.join().unwrap().unwrap();
+//! }
+//! ```
+
+use std::io;
+use std::thread::{self, JoinHandle};
+
+//================================================
+// Functions
+//================================================
+
+/// Pins the current thread to the supplied core.
+#[cfg(target_os="linux")]
+pub fn pin(core: usize) -> io::Result<()> {
+    use std::mem;
+
+    use libc;
+
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if core >= mem::size_of::<libc::cpu_set_t>() * 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid core"));
+        }
+        libc::CPU_SET(core, &mut set);
+        let size = mem::size_of::<libc::cpu_set_t>();
+        if libc::sched_setaffinity(0, size, &set) == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// Pins the current thread to the supplied core.
+#[cfg(not(target_os="linux"))]
+pub fn pin(_: usize) -> io::Result<()> {
+    let message = "thread affinity is not supported on this platform";
+    Err(io::Error::other(message))
+}
+
+/// Spawns a thread pinned to the supplied core that calls the supplied function.
+///
+/// The thread returns `Err` without calling the function if it could not be pinned.
+pub fn spawn<F, T>(core: usize, f: F) -> JoinHandle<io::Result<T>>
+    where F: FnOnce() -> T + Send + 'static, T: Send + 'static
+{
+    thread::spawn(move || pin(core).map(|_| f()))
+}
+
+/// Calls the supplied function on a thread pinned to the supplied core and returns the result.
+///
+/// This can be used to construct a queue so that the memory used by the queue is allocated near
+/// the supplied core.
+pub fn construct_on<F, T>(core: usize, f: F) -> io::Result<T>
+    where F: FnOnce() -> T + Send + 'static, T: Send + 'static
+{
+    match spawn(core, f).join() {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::other("the function panicked")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_core() {
+        let error = construct_on(usize::MAX, || 1).unwrap_err();
+        if cfg!(target_os="linux") {
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[cfg(target_os="linux")]
+    #[test]
+    fn test_construct_on() {
+        use libc;
+
+        // The core the current thread is running on is one the threads are allowed to run on.
+        let core = unsafe { libc::sched_getcpu() } as usize;
+        let pinned = construct_on(core, || unsafe { libc::sched_getcpu() }).unwrap();
+        assert_eq!(pinned as usize, core);
+        let error = construct_on(core, || panic!("panicked")).unwrap_err();
+        assert_eq!(error.to_string(), "the function panicked");
+    }
+}
@@ -0,0 +1,437 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded single-producer, single-consumer wait-free ring for audio frames.
+//!
+//! A frame contains one sample for each channel of an audio stream. The ring only ever adds or
+//! removes whole frames, so the samples for the channels of a stream never get out of step. Frames
+//! are copied into and out of caller-provided slices of interleaved samples, and neither side of
+//! the ring allocates or retries once the ring has been constructed, so the producer and consumer
+//! can be used from real-time audio callbacks. The `interleave` and `deinterleave` functions
+//! convert between interleaved samples and one slice per channel.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::audio;
+//!
+//! fn main() {
+//!     let (producer, consumer) = audio::channel::<f32>(4, 2);
+//!
+//!     assert_eq!(producer.write_frames(&[0.1, 0.2, 0.3, 0.4, 0.5]), 2);
+//!     assert_eq!(consumer.len(), 2);
+//!
+//!     let mut left = [0.0; 2];
+//!     let mut right = [0.0; 2];
+//!     let mut samples = [0.0; 4];
+//!     assert_eq!(consumer.read_frames(&mut samples), 2);
+//!     assert_eq!(audio::deinterleave(&samples, &mut [&mut left, &mut right]), 2);
+//!     assert_eq!(left, [0.1, 0.3]);
+//!     assert_eq!(right, [0.2, 0.4]);
+//! }
+//! ```
+
+use std::cmp;
+use std::mem;
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {MemoryUsage, padding};
+use buffer::{Buffer};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for a bounded SPSC wait-free ring for audio frames.
+#[derive(Debug)]
+pub struct Consumer<S>(Arc<Ring<S>>) where S: Sample;
+
+impl<S> Consumer<S> where S: Sample {
+    //- Accessors --------------------------------
+
+    /// Moves frames from the front of the ring into the supplied slice of interleaved samples until
+    /// the slice can't hold another frame or the ring is empty and returns the number of frames
+    /// moved.
+    pub fn read_frames(&self, samples: &mut [S]) -> usize {
+        self.0.read(samples)
+    }
+
+    /// Moves frames from the front of the ring into the supplied slices, one for each channel,
+    /// until one of the slices is full or the ring is empty and returns the number of frames
+    /// moved.
+    ///
+    /// # Panics
+    ///
+    /// * `planes` does not contain one slice for each channel
+    pub fn read_planar(&self, planes: &mut [&mut [S]]) -> usize {
+        assert!(planes.len() == self.0.channels, "`planes` has the wrong length");
+        self.0.read_planar(planes)
+    }
+
+    /// Returns the number of frames currently in the ring.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the ring is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of frames the ring can contain.
+    pub fn capacity(&self) -> usize {
+        self.0.frames
+    }
+
+    /// Returns the number of channels in a frame.
+    pub fn channels(&self) -> usize {
+        self.0.channels
+    }
+
+    /// Returns the memory currently used by the ring.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+}
+
+unsafe impl<S> Send for Consumer<S> where S: Sample { }
+
+// Producer ______________________________________
+
+/// A producer for a bounded SPSC wait-free ring for audio frames.
+#[derive(Debug)]
+pub struct Producer<S>(Arc<Ring<S>>) where S: Sample;
+
+impl<S> Producer<S> where S: Sample {
+    //- Accessors --------------------------------
+
+    /// Copies frames from the front of the supplied slice of interleaved samples to the back of
+    /// the ring until the slice has no whole frames left or the ring is full and returns the number
+    /// of frames copied.
+    ///
+    /// Trailing samples that do not make up a whole frame are never copied.
+    pub fn write_frames(&self, samples: &[S]) -> usize {
+        self.0.write(samples)
+    }
+
+    /// Copies frames from the front of the supplied slices, one for each channel, to the back of
+    /// the ring until one of the slices has no samples left or the ring is full and returns the
+    /// number of frames copied.
+    ///
+    /// # Panics
+    ///
+    /// * `planes` does not contain one slice for each channel
+    pub fn write_planar(&self, planes: &[&[S]]) -> usize {
+        assert!(planes.len() == self.0.channels, "`planes` has the wrong length");
+        self.0.write_planar(planes)
+    }
+
+    /// Returns the number of frames currently in the ring.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the ring is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of frames the ring can contain.
+    pub fn capacity(&self) -> usize {
+        self.0.frames
+    }
+
+    /// Returns the number of channels in a frame.
+    pub fn channels(&self) -> usize {
+        self.0.channels
+    }
+
+    /// Returns the memory currently used by the ring.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+}
+
+unsafe impl<S> Send for Producer<S> where S: Sample { }
+
+// Ring __________________________________________
+
+/// A ring of samples whose positions are counted in samples and only move by whole frames.
+#[derive(Debug)]
+#[repr(C)]
+struct Ring<S> {
+    write: AtomicUsize,
+    _wpadding: [usize; padding(1)],
+    read: AtomicUsize,
+    _rpadding: [usize; padding(1)],
+    buffer: Buffer<S>,
+    frames: usize,
+    channels: usize,
+}
+
+impl<S> Ring<S> where S: Sample {
+    //- Constructors -----------------------------
+
+    fn new(frames: usize, channels: usize) -> Arc<Self> {
+        let samples = frames.checked_mul(channels).expect("the ring is too large");
+        Arc::new(Ring {
+            write: AtomicUsize::new(0),
+            _wpadding: [0; padding(1)],
+            read: AtomicUsize::new(0),
+            _rpadding: [0; padding(1)],
+            buffer: Buffer::try_new(samples.next_power_of_two()).expect("failed to allocate ring"),
+            frames,
+            channels,
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn len(&self) -> usize {
+        // The front is loaded first so that it can't be past the loaded back of the ring.
+        let read = self.read.load(Acquire);
+        self.write.load(Acquire).wrapping_sub(read) / self.channels
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let bytes = self.buffer.size() * mem::size_of::<S>();
+        MemoryUsage { bytes, nodes: 0, retired: 0 }
+    }
+
+    /// Returns the position of the back of the ring and the number of frames that can be added.
+    fn free(&self) -> (usize, usize) {
+        let write = self.write.load(Relaxed);
+        let used = write.wrapping_sub(self.read.load(Acquire)) / self.channels;
+        (write, self.frames - used)
+    }
+
+    /// Returns the position of the front of the ring and the number of frames that can be removed.
+    fn available(&self) -> (usize, usize) {
+        let read = self.read.load(Relaxed);
+        (read, self.write.load(Acquire).wrapping_sub(read) / self.channels)
+    }
+
+    fn write(&self, samples: &[S]) -> usize {
+        let (write, free) = self.free();
+        let count = cmp::min(free, samples.len() / self.channels);
+        for (index, sample) in samples[..count * self.channels].iter().enumerate() {
+            unsafe { self.buffer.wrapping_set(write.wrapping_add(index), *sample); }
+        }
+        self.write.store(write.wrapping_add(count * self.channels), Release);
+        count
+    }
+
+    fn write_planar(&self, planes: &[&[S]]) -> usize {
+        let (write, free) = self.free();
+        let count = planes.iter().fold(free, |c, p| cmp::min(c, p.len()));
+        for (channel, plane) in planes.iter().enumerate() {
+            for (frame, sample) in plane[..count].iter().enumerate() {
+                let index = write.wrapping_add(frame * self.channels + channel);
+                unsafe { self.buffer.wrapping_set(index, *sample); }
+            }
+        }
+        self.write.store(write.wrapping_add(count * self.channels), Release);
+        count
+    }
+
+    fn read(&self, samples: &mut [S]) -> usize {
+        let (read, available) = self.available();
+        let count = cmp::min(available, samples.len() / self.channels);
+        for (index, sample) in samples[..count * self.channels].iter_mut().enumerate() {
+            *sample = unsafe { self.buffer.wrapping_get(read.wrapping_add(index)) };
+        }
+        self.read.store(read.wrapping_add(count * self.channels), Release);
+        count
+    }
+
+    fn read_planar(&self, planes: &mut [&mut [S]]) -> usize {
+        let (read, available) = self.available();
+        let count = planes.iter().fold(available, |c, p| cmp::min(c, p.len()));
+        for (channel, plane) in planes.iter_mut().enumerate() {
+            for (frame, sample) in plane[..count].iter_mut().enumerate() {
+                let index = read.wrapping_add(frame * self.channels + channel);
+                *sample = unsafe { self.buffer.wrapping_get(index) };
+            }
+        }
+        self.read.store(read.wrapping_add(count * self.channels), Release);
+        count
+    }
+}
+
+unsafe impl<S> Sync for Ring<S> where S: Sample { }
+
+//================================================
+// Traits
+//================================================
+
+mod sealed {
+    pub trait Sealed { }
+}
+
+// Sample ________________________________________
+
+/// A type of audio sample that can be stored in a ring.
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+pub trait Sample: sealed::Sealed + Copy + Send + 'static { }
+
+impl sealed::Sealed for f32 { }
+impl Sample for f32 { }
+
+impl sealed::Sealed for i16 { }
+impl Sample for i16 { }
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for a bounded SPSC wait-free ring that can contain up to
+/// `frames` frames of `channels` samples each.
+///
+/// # Panics
+///
+/// * `frames` is zero
+/// * `channels` is zero
+pub fn channel<S>(frames: usize, channels: usize) -> (Producer<S>, Consumer<S>) where S: Sample {
+    assert!(frames != 0, "`frames` is zero");
+    assert!(channels != 0, "`channels` is zero");
+    let ring = Ring::new(frames, channels);
+    (Producer(ring.clone()), Consumer(ring))
+}
+
+/// Interleaves the samples in the supplied slices, one for each channel, into the supplied slice
+/// and returns the number of frames interleaved.
+///
+/// Frames are interleaved until one of the channel slices has no samples left or the output slice
+/// can't hold another frame.
+pub fn interleave<S>(planes: &[&[S]], samples: &mut [S]) -> usize where S: Sample {
+    if planes.is_empty() {
+        return 0;
+    }
+
+    let channels = planes.len();
+    let count = planes.iter().fold(samples.len() / channels, |c, p| cmp::min(c, p.len()));
+    for (channel, plane) in planes.iter().enumerate() {
+        for (frame, sample) in plane[..count].iter().enumerate() {
+            samples[frame * channels + channel] = *sample;
+        }
+    }
+    count
+}
+
+/// Deinterleaves the samples in the supplied slice into the supplied slices, one for each channel,
+/// and returns the number of frames deinterleaved.
+///
+/// Frames are deinterleaved until the input slice has no whole frames left or one of the channel
+/// slices is full.
+pub fn deinterleave<S>(samples: &[S], planes: &mut [&mut [S]]) -> usize where S: Sample {
+    if planes.is_empty() {
+        return 0;
+    }
+
+    let channels = planes.len();
+    let count = planes.iter().fold(samples.len() / channels, |c, p| cmp::min(c, p.len()));
+    for (channel, plane) in planes.iter_mut().enumerate() {
+        for (frame, sample) in plane[..count].iter_mut().enumerate() {
+            *sample = samples[frame * channels + channel];
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_frames() {
+        let (producer, consumer) = channel::<i16>(3, 2);
+        assert_eq!(producer.capacity(), 3);
+
+        // Trailing samples that do not make up a whole frame are never copied.
+        assert_eq!(producer.write_frames(&[1, 2, 3]), 1);
+        assert_eq!(producer.write_frames(&[3, 4, 5, 6, 7, 8]), 2);
+        assert_eq!(producer.write_frames(&[9, 10]), 0);
+        assert_eq!(consumer.len(), 3);
+
+        let mut samples = [0; 5];
+        assert_eq!(consumer.read_frames(&mut samples), 2);
+        assert_eq!(samples, [1, 2, 3, 4, 0]);
+
+        // The ring wraps around the end of the buffer.
+        assert_eq!(producer.write_frames(&[9, 10, 11, 12, 13, 14]), 2);
+        let mut samples = [0; 6];
+        assert_eq!(consumer.read_frames(&mut samples), 3);
+        assert_eq!(samples, [5, 6, 9, 10, 11, 12]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_planar() {
+        let (producer, consumer) = channel::<f32>(4, 2);
+        assert_eq!(producer.write_planar(&[&[0.1, 0.3, 0.5], &[0.2, 0.4]]), 2);
+        assert_eq!(producer.len(), 2);
+
+        let (mut left, mut right) = ([0.0; 4], [0.0; 4]);
+        assert_eq!(consumer.read_planar(&mut [&mut left, &mut right]), 2);
+        assert_eq!((left, right), ([0.1, 0.3, 0.0, 0.0], [0.2, 0.4, 0.0, 0.0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "`planes` has the wrong length")]
+    fn test_planar_channels() {
+        let (producer, _) = channel::<f32>(4, 2);
+        producer.write_planar(&[&[0.1]]);
+    }
+
+    #[test]
+    fn test_interleave() {
+        let mut samples = [0; 5];
+        assert_eq!(interleave(&[&[1, 3, 5], &[2, 4, 6]], &mut samples), 2);
+        assert_eq!(samples, [1, 2, 3, 4, 0]);
+
+        let (mut left, mut right) = ([0; 3], [0; 1]);
+        assert_eq!(deinterleave(&samples, &mut [&mut left, &mut right]), 1);
+        assert_eq!((left, right), ([1, 0, 0], [2]));
+        assert_eq!(interleave::<i16>(&[], &mut samples), 0);
+    }
+
+    #[test]
+    fn test_threads() {
+        let (producer, consumer) = channel::<i16>(16, 2);
+        let thread = thread::spawn(move || {
+            let mut next = 0;
+            while next < 1000 {
+                next += producer.write_frames(&[next as i16, -(next as i16)]);
+            }
+        });
+
+        let mut samples = vec![];
+        let mut buffer = [0; 8];
+        while samples.len() < 2000 {
+            let count = consumer.read_frames(&mut buffer);
+            samples.extend_from_slice(&buffer[..count * 2]);
+        }
+        thread.join().unwrap();
+        assert!(samples.chunks(2).enumerate().all(|(i, f)| f == [i as i16, -(i as i16)]));
+    }
+}
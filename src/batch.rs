@@ -0,0 +1,198 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Removing items from a queue in batches.
+//!
+//! A `BatchedConsumer` moves up to a fixed number of items from a queue into a local buffer at once
+//! with `consume_uninit` and then returns the items from the local buffer, so most calls to
+//! `consume` don't access the shared state of the queue.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::batch::{BatchedConsumer};
+//! use npnc::bounded::spsc;
+//!
+//! fn main() {
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let consumer = BatchedConsumer::new(consumer, 16);
+//!
+//!     for item in 0..4 {
+//!         producer.produce(item).unwrap();
+//!     }
+//!
+//!     assert_eq!(consumer.consume(), Ok(0));
+//!     assert_eq!(consumer.buffered(), 3);
+//!     assert_eq!(consumer.get_ref().len(), 0);
+//! }
+//! ```
+
+use std::fmt;
+use std::ptr;
+use std::cell::{Cell, UnsafeCell};
+use std::marker::{PhantomData};
+use std::mem::{MaybeUninit};
+
+use {Consume, ConsumeError};
+
+//================================================
+// Structs
+//================================================
+
+// BatchedConsumer _______________________________
+
+/// A consumer that removes items from a queue in batches.
+///
+/// The items in the local buffer are dropped when this consumer is dropped.
+pub struct BatchedConsumer<T, C> {
+    consumer: C,
+    buffer: UnsafeCell<Vec<MaybeUninit<T>>>,
+    read: Cell<usize>,
+    write: Cell<usize>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, C> BatchedConsumer<T, C> where C: Consume<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `BatchedConsumer` that removes up to the supplied number of items from the
+    /// queue at once.
+    ///
+    /// # Panics
+    ///
+    /// * `batch` is `0`
+    pub fn new(consumer: C, batch: usize) -> Self {
+        assert!(batch != 0, "`batch` is zero");
+        let buffer = (0..batch).map(|_| MaybeUninit::uninit()).collect();
+        BatchedConsumer {
+            consumer,
+            buffer: UnsafeCell::new(buffer),
+            read: Cell::new(0),
+            write: Cell::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the front of the local buffer, refilling the
+    /// local buffer from the queue if it is empty.
+    ///
+    /// This method returns `Err` if the local buffer and the queue are empty.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        let buffer = unsafe { &mut *self.buffer.get() };
+        if self.read.get() == self.write.get() {
+            let count = self.consumer.consume_uninit(buffer);
+            self.read.set(0);
+            self.write.set(count);
+            if count == 0 {
+                return self.consumer.consume();
+            }
+        }
+
+        let read = self.read.get();
+        self.read.set(read + 1);
+        Ok(unsafe { ptr::read(buffer[read].as_ptr()) })
+    }
+
+    /// Returns the number of items in the local buffer.
+    pub fn buffered(&self) -> usize {
+        self.write.get() - self.read.get()
+    }
+
+    /// Returns the maximum number of items removed from the queue at once.
+    pub fn batch(&self) -> usize {
+        unsafe { (*self.buffer.get()).len() }
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<T, C> Consume<T> for BatchedConsumer<T, C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        BatchedConsumer::consume(self)
+    }
+}
+
+impl<T, C> Drop for BatchedConsumer<T, C> {
+    fn drop(&mut self) {
+        let buffer = unsafe { &mut *self.buffer.get() };
+        for slot in &mut buffer[self.read.get()..self.write.get()] {
+            unsafe { ptr::drop_in_place(slot.as_mut_ptr()); }
+        }
+    }
+}
+
+impl<T, C> fmt::Debug for BatchedConsumer<T, C> where C: fmt::Debug {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("BatchedConsumer")
+            .field("consumer", &self.consumer)
+            .field("buffered", &(self.write.get() - self.read.get()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc};
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_consume() {
+        let (producer, consumer) = spsc::channel(8);
+        let consumer = BatchedConsumer::new(consumer, 3);
+        assert_eq!(consumer.batch(), 3);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        for item in 0..5 {
+            producer.produce(item).unwrap();
+        }
+        assert_eq!(consumer.consume(), Ok(0));
+        assert_eq!((consumer.buffered(), consumer.get_ref().len()), (2, 2));
+        let items = (1..5).map(|_| consumer.consume()).collect::<Vec<_>>();
+        assert_eq!(items, (1..5).map(Ok).collect::<Vec<_>>());
+        assert_eq!(consumer.buffered(), 0);
+
+        drop(producer);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_drop() {
+        let item = Arc::new(());
+        let (producer, consumer) = spsc::channel(8);
+        let consumer = BatchedConsumer::new(consumer, 4);
+        for _ in 0..6 {
+            producer.produce(item.clone()).unwrap();
+        }
+        drop(consumer.consume());
+        drop((producer, consumer));
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    #[should_panic(expected="`batch` is zero")]
+    fn test_zero() {
+        let (_producer, consumer) = spsc::channel::<i32>(8);
+        let _ = BatchedConsumer::new(consumer, 0);
+    }
+}
@@ -0,0 +1,610 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Blocking consumers that spin briefly and then sleep.
+//!
+//! The consumers returned by `wrap` can wait for items with `consume_blocking`. A waiting consumer
+//! first spins, trying to remove an item a fixed number of times (the spin budget), which keeps
+//! the latency low while items are arriving quickly. Once the spin budget is used up, the consumer
+//! sleeps until a wrapped producer adds an item or the queue is disconnected, so an idle queue does
+//! not keep a core busy. The wrapped producers only make a system call to wake the consumers while
+//! a consumer is sleeping. `consume_timeout` waits the same way but gives up with a `Timeout` error
+//! once a duration has elapsed (e.g., so a worker loop can also perform periodic housekeeping).
+//!
+//! The producers returned by `wrap` can likewise wait for room in a bounded queue with
+//! `produce_blocking`, which spins and then sleeps until a wrapped consumer removes an item or the
+//! queue is disconnected. The wrapped consumers only make a system call to wake the producers while
+//! a producer is sleeping.
+//!
+//! With the `futex` feature on Linux, a sleeping thread costs a single futex wait and waking it
+//! costs a single futex wake. Otherwise, a sleeping thread is parked and the threads that wake it
+//! briefly lock a list of the sleeping threads. Neither adds a lock to the path taken while no
+//! thread is sleeping.
+//!
+//! The producers and consumers returned by `wrap_with` sleep and wake each other with a supplied
+//! implementation of `Park` instead. On ARM targets, `Event` sleeps the core with the WFE
+//! instruction until an event is signaled with the SEV instruction or an interrupt occurs, so a
+//! consumer can sleep until an interrupt handler adds an item.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::thread;
+//!
+//! use npnc::ConsumeError;
+//! use npnc::blocking;
+//! use npnc::bounded::spsc;
+//!
+//! fn main() {
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let (producer, consumer) = blocking::wrap(producer, consumer, 100);
+//!
+//!     let thread = thread::spawn(move || {
+//!         for item in 0..32 {
+//!             producer.produce(item).unwrap();
+//!         }
+//!     });
+//!
+//!     let mut sum = 0;
+//!     while let Ok(item) = consumer.consume_blocking() {
+//!         sum += item;
+//!     }
+//!
+//!     thread.join().unwrap();
+//!     assert_eq!(sum, 496);
+//!     assert_eq!(consumer.consume_blocking(), Err(ConsumeError::Disconnected));
+//! }
+//! ```
+
+use std::fmt;
+use std::hint;
+use std::sync::{Arc};
+use std::sync::atomic::{self, AtomicU32, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration, Instant};
+
+#[cfg(all(target_os="linux", feature="futex"))]
+use std::cmp;
+#[cfg(all(target_os="linux", feature="futex"))]
+use std::ptr;
+#[cfg(not(all(target_os="linux", feature="futex")))]
+use std::thread::{self, Thread};
+#[cfg(not(all(target_os="linux", feature="futex")))]
+use std::sync::{Mutex};
+
+#[cfg(all(target_os="linux", feature="futex"))]
+use libc;
+
+#[cfg(any(target_arch="arm", target_arch="aarch64"))]
+use std::arch;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that can wait for items to be added to a queue and wakes the producers waiting for
+/// room in the queue.
+///
+/// The waiting producers are also woken when this consumer is dropped so that they can observe
+/// that the queue has been disconnected.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: Option<C>,
+    signal: Arc<Signal>,
+    space: Arc<Signal>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue without waiting.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        let item = self.get_ref().consume()?;
+        self.space.notify();
+        Ok(item)
+    }
+
+    /// Removes and returns an item from the queue, waiting while the queue is empty.
+    ///
+    /// This method only returns `Err` if the queue is empty and has no remaining producers or has
+    /// been closed.
+    pub fn consume_blocking<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        self.signal.wait(None, || match self.consume() {
+            Err(ConsumeError::Empty) | Err(ConsumeError::Contended) => None,
+            result => Some(result),
+        }).unwrap()
+    }
+
+    /// Removes and returns an item from the queue, waiting up to the supplied duration while the
+    /// queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed. A
+    /// duration too long to be represented as a deadline waits indefinitely.
+    pub fn consume_timeout<T>(&self, timeout: Duration) -> Result<T, ConsumeError>
+        where C: Consume<T>
+    {
+        let deadline = Instant::now().checked_add(timeout);
+        let result = self.signal.wait(deadline, || match self.consume() {
+            Err(ConsumeError::Empty) | Err(ConsumeError::Contended) => None,
+            result => Some(result),
+        });
+        result.unwrap_or(Err(ConsumeError::Timeout))
+    }
+
+    /// Returns the number of times this consumer tries to remove an item before sleeping.
+    pub fn spin(&self) -> usize {
+        self.signal.spin
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        self.consumer.as_ref().unwrap()
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        let space = self.space.clone();
+        Consumer { consumer: self.consumer.clone(), signal: self.signal.clone(), space }
+    }
+}
+
+impl<C> Drop for Consumer<C> {
+    fn drop(&mut self) {
+        // The consumer is dropped before the producers are woken so that the producers can observe
+        // that the queue has been disconnected.
+        self.consumer = None;
+        self.space.notify();
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+
+    fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consumer::consume_timeout(self, timeout)
+    }
+}
+
+// Event _________________________________________
+
+/// An implementation of `Park` that sleeps with the WFE instruction and wakes with the SEV
+/// instruction.
+///
+/// A core executing WFE sleeps until any core executes SEV or an interrupt occurs, so a core may be
+/// woken by events unrelated to the queue. WFE can't time out, so a sleeping core only notices that
+/// a deadline has passed the next time it is woken (e.g., by a timer interrupt or the event stream
+/// of the generic timer).
+#[cfg(any(target_arch="arm", target_arch="aarch64"))]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Event;
+
+#[cfg(any(target_arch="arm", target_arch="aarch64"))]
+impl Park for Event {
+    fn park(&self, _: Option<Instant>) {
+        unsafe { arch::asm!("wfe", options(nomem, nostack, preserves_flags)); }
+    }
+
+    fn unpark(&self) {
+        unsafe { arch::asm!("sev", options(nomem, nostack, preserves_flags)); }
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that can wait for room in a queue and wakes the consumers waiting for items to be
+/// added to the queue.
+///
+/// The waiting consumers are also woken when this producer is dropped so that they can observe
+/// that the queue has been disconnected.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: Option<P>,
+    signal: Arc<Signal>,
+    space: Arc<Signal>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        self.get_ref().produce(item)?;
+        self.signal.notify();
+        Ok(())
+    }
+
+    /// Adds the supplied item to the queue, waiting while the queue is full.
+    ///
+    /// This method only returns `Err` if the queue has no remaining consumers or has been closed
+    /// or if the queue rejects the item for another reason (e.g., the item could not be
+    /// allocated).
+    pub fn produce_blocking<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        let mut item = Some(item);
+        self.space.wait(None, || match self.get_ref().produce(item.take().unwrap()) {
+            Err(ProduceError::Full(returned)) | Err(ProduceError::Contended(returned)) => {
+                item = Some(returned);
+                None
+            },
+            result => Some(result),
+        }).unwrap()?;
+        self.signal.notify();
+        Ok(())
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        self.producer.as_ref().unwrap()
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        let space = self.space.clone();
+        Producer { producer: self.producer.clone(), signal: self.signal.clone(), space }
+    }
+}
+
+impl<P> Drop for Producer<P> {
+    fn drop(&mut self) {
+        // The producer is dropped before the consumers are woken so that the consumers can observe
+        // that the queue has been disconnected.
+        self.producer = None;
+        self.signal.notify();
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// Signal ________________________________________
+
+/// A wait queue that is only notified while threads are sleeping on it.
+///
+/// Sleeping threads wait for the epoch to change, so a thread that is notified between deciding to
+/// sleep and sleeping does not sleep at all.
+struct Signal {
+    spin: usize,
+    /// The supplied way to sleep and wake threads, if any.
+    park: Option<Arc<dyn Park>>,
+    /// The number of threads that are sleeping or about to sleep.
+    sleepers: AtomicUsize,
+    /// The number of times this signal has been notified while threads were sleeping.
+    epoch: AtomicU32,
+    /// The threads that are sleeping or about to sleep.
+    #[cfg(not(all(target_os="linux", feature="futex")))]
+    threads: Mutex<Vec<Thread>>,
+}
+
+impl Signal {
+    //- Constructors -----------------------------
+
+    #[cfg(all(target_os="linux", feature="futex"))]
+    fn new(spin: usize, park: Option<Arc<dyn Park>>) -> Arc<Self> {
+        Arc::new(Signal {
+            spin,
+            park,
+            sleepers: AtomicUsize::new(0),
+            epoch: AtomicU32::new(0),
+        })
+    }
+
+    #[cfg(not(all(target_os="linux", feature="futex")))]
+    fn new(spin: usize, park: Option<Arc<dyn Park>>) -> Arc<Self> {
+        Arc::new(Signal {
+            spin,
+            park,
+            sleepers: AtomicUsize::new(0),
+            epoch: AtomicU32::new(0),
+            threads: Mutex::new(vec![]),
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    /// Wakes the threads sleeping on this signal.
+    ///
+    /// With the `sanitize` feature, this method always wakes the sleeping threads instead of
+    /// checking for sleepers after a fence, since ThreadSanitizer does not model fences.
+    fn notify(&self) {
+        atomic::fence(SeqCst);
+        if cfg!(feature="sanitize") || self.sleepers.load(SeqCst) != 0 {
+            self.epoch.fetch_add(1, SeqCst);
+            self.wake();
+        }
+    }
+
+    /// Calls the supplied function until it returns `Some` or the supplied deadline has passed,
+    /// spinning and then sleeping between calls.
+    fn wait<R, F>(&self, deadline: Option<Instant>, mut f: F) -> Option<R>
+        where F: FnMut() -> Option<R>
+    {
+        for _ in 0..self.spin {
+            if let Some(result) = f() {
+                return Some(result);
+            }
+            hint::spin_loop();
+        }
+
+        loop {
+            if let Some(result) = f() {
+                return Some(result);
+            }
+
+            let now = Instant::now();
+            if matches!(deadline, Some(deadline) if now >= deadline) {
+                return None;
+            }
+
+            // The epoch is read and the thread is counted as a sleeper before trying again so that
+            // a thread that calls `notify` in the meantime will change the epoch and wake it.
+            let epoch = self.epoch.load(SeqCst);
+            self.sleepers.fetch_add(1, SeqCst);
+            atomic::fence(SeqCst);
+            if let Some(result) = f() {
+                self.sleepers.fetch_sub(1, SeqCst);
+                return Some(result);
+            }
+
+            self.sleep(epoch, deadline);
+            self.sleepers.fetch_sub(1, SeqCst);
+        }
+    }
+
+    /// Sleeps until the epoch is no longer the supplied epoch or the supplied deadline has passed.
+    ///
+    /// This method may return early, so the caller should check its condition again.
+    fn sleep(&self, epoch: u32, deadline: Option<Instant>) {
+        let park = match self.park {
+            Some(ref park) => park,
+            None => return self.sleep_thread(epoch, deadline),
+        };
+
+        while self.epoch.load(SeqCst) == epoch {
+            if matches!(deadline, Some(deadline) if Instant::now() >= deadline) {
+                break;
+            }
+            park.park(deadline);
+        }
+    }
+
+    /// Sleeps the current thread until the epoch is no longer the supplied epoch or the supplied
+    /// deadline has passed.
+    ///
+    /// This method may return early, so the caller should check its condition again.
+    #[cfg(all(target_os="linux", feature="futex"))]
+    fn sleep_thread(&self, epoch: u32, deadline: Option<Instant>) {
+        let timeout = deadline.map(|d| {
+            let timeout = d.saturating_duration_since(Instant::now());
+            let seconds = cmp::min(timeout.as_secs(), libc::time_t::MAX as u64);
+            libc::timespec {
+                tv_sec: seconds as libc::time_t,
+                tv_nsec: timeout.subsec_nanos() as libc::c_long,
+            }
+        });
+        let timeout = timeout.as_ref().map_or(ptr::null(), |t| t as *const libc::timespec);
+        futex(&self.epoch, libc::FUTEX_WAIT, epoch, timeout);
+    }
+
+    /// Sleeps the current thread until the epoch is no longer the supplied epoch or the supplied
+    /// deadline has passed.
+    ///
+    /// This method may return early, so the caller should check its condition again.
+    #[cfg(not(all(target_os="linux", feature="futex")))]
+    fn sleep_thread(&self, epoch: u32, deadline: Option<Instant>) {
+        let thread = thread::current();
+        self.threads.lock().unwrap().push(thread.clone());
+        while self.epoch.load(SeqCst) == epoch {
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    thread::park_timeout(deadline - now);
+                },
+                None => thread::park(),
+            }
+        }
+        self.threads.lock().unwrap().retain(|t| t.id() != thread.id());
+    }
+
+    /// Wakes the threads sleeping on this signal after the epoch has been changed.
+    fn wake(&self) {
+        match self.park {
+            Some(ref park) => park.unpark(),
+            None => self.wake_threads(),
+        }
+    }
+
+    /// Wakes the threads sleeping in `sleep_thread` after the epoch has been changed.
+    #[cfg(all(target_os="linux", feature="futex"))]
+    fn wake_threads(&self) {
+        futex(&self.epoch, libc::FUTEX_WAKE, i32::MAX as u32, ptr::null());
+    }
+
+    /// Wakes the threads sleeping in `sleep_thread` after the epoch has been changed.
+    #[cfg(not(all(target_os="linux", feature="futex")))]
+    fn wake_threads(&self) {
+        for thread in self.threads.lock().unwrap().iter() {
+            thread.unpark();
+        }
+    }
+}
+
+impl fmt::Debug for Signal {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "Signal {{ spin: {:?}, sleepers: {:?}, epoch: {:?} }}",
+            self.spin,
+            self.sleepers,
+            self.epoch,
+        )
+    }
+}
+
+//================================================
+// Traits
+//================================================
+
+// Park __________________________________________
+
+/// A way for the producers and consumers returned by `wrap_with` to sleep and wake each other.
+pub trait Park: Send + Sync {
+    /// Sleeps until `unpark` is called, returning no later than the supplied deadline, if any.
+    ///
+    /// This method may return early, since the caller checks its condition again. However, if
+    /// `unpark` has been called since this method last returned, this method must return
+    /// immediately (as `thread::park` does) so that a wakeup is not lost between the caller
+    /// checking its condition and calling this method.
+    fn park(&self, deadline: Option<Instant>);
+
+    /// Wakes the threads sleeping in `park`.
+    fn unpark(&self);
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Performs the supplied futex operation on the supplied futex word.
+#[cfg(all(target_os="linux", feature="futex"))]
+fn futex(word: &AtomicU32, op: libc::c_int, value: u32, timeout: *const libc::timespec) {
+    let (word, op) = (word as *const AtomicU32, op | libc::FUTEX_PRIVATE_FLAG);
+    unsafe { libc::syscall(libc::SYS_futex, word, op, value, timeout); }
+}
+
+/// Wraps the supplied producer and consumer so the consumer can wait for items and the producer
+/// can wait for room, trying to remove or add an item up to `spin` times before sleeping.
+///
+/// The supplied producer and consumer should belong to the same queue and should not be used to
+/// add or remove items once wrapped.
+pub fn wrap<P, C>(producer: P, consumer: C, spin: usize) -> (Producer<P>, Consumer<C>) {
+    wrap_signals(producer, consumer, Signal::new(spin, None), Signal::new(spin, None))
+}
+
+/// Wraps the supplied producer and consumer like `wrap`, but the producer and consumer sleep and
+/// wake each other with the supplied implementation of `Park`.
+pub fn wrap_with<P, C, W>(
+    producer: P, consumer: C, spin: usize, park: W
+) -> (Producer<P>, Consumer<C>) where W: Park + 'static {
+    let park: Arc<dyn Park> = Arc::new(park);
+    let signal = Signal::new(spin, Some(park.clone()));
+    wrap_signals(producer, consumer, signal, Signal::new(spin, Some(park)))
+}
+
+/// Wraps the supplied producer and consumer with the supplied signals.
+fn wrap_signals<P, C>(
+    producer: P, consumer: C, signal: Arc<Signal>, space: Arc<Signal>
+) -> (Producer<P>, Consumer<C>) {
+    let producer = Some(producer);
+    let producer = Producer { producer, signal: signal.clone(), space: space.clone() };
+    (producer, Consumer { consumer: Some(consumer), signal, space })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::sync::{Condvar, Mutex};
+
+    use bounded::{spsc};
+
+    #[derive(Default)]
+    struct Condition {
+        unparked: Mutex<bool>,
+        condvar: Condvar,
+        unparks: AtomicUsize,
+    }
+
+    impl Park for Arc<Condition> {
+        fn park(&self, deadline: Option<Instant>) {
+            let mut unparked = self.unparked.lock().unwrap();
+            if !*unparked {
+                let timeout = deadline.map_or(Duration::from_secs(1), |d| {
+                    d.saturating_duration_since(Instant::now())
+                });
+                unparked = self.condvar.wait_timeout(unparked, timeout).unwrap().0;
+            }
+            *unparked = false;
+        }
+
+        fn unpark(&self) {
+            *self.unparked.lock().unwrap() = true;
+            self.condvar.notify_all();
+            self.unparks.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        let (producer, consumer) = spsc::channel(2);
+        let (producer, consumer) = wrap(producer, consumer, 0);
+        let thread = thread::spawn(move || {
+            for item in 0..8 {
+                producer.produce_blocking(item).unwrap();
+            }
+        });
+        let items = (0..8).map(|_| consumer.consume_blocking()).collect::<Vec<_>>();
+        assert_eq!(items, (0..8).map(Ok).collect::<Vec<_>>());
+
+        thread.join().unwrap();
+        assert_eq!(consumer.consume_blocking(), Err(ConsumeError::Disconnected));
+        let timeout = Duration::from_millis(10);
+        assert_eq!(consumer.consume_timeout(timeout), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_wrap_timeout() {
+        let (producer, consumer) = spsc::channel(1);
+        let (producer, consumer) = wrap(producer, consumer, 4);
+        let timeout = Duration::from_millis(10);
+        assert_eq!(consumer.consume_timeout(timeout), Err(ConsumeError::Timeout));
+        producer.produce(1).unwrap();
+        assert_eq!(consumer.consume_timeout(timeout), Ok(1));
+        drop(consumer);
+        assert_eq!(producer.produce_blocking(2), Err(ProduceError::Disconnected(2)));
+    }
+
+    #[test]
+    fn test_wrap_with() {
+        let condition = Arc::new(Condition::default());
+        let (producer, consumer) = spsc::channel(2);
+        let (producer, consumer) = wrap_with(producer, consumer, 0, condition.clone());
+        let thread = thread::spawn(move || {
+            for item in 0..64 {
+                producer.produce_blocking(item).unwrap();
+            }
+        });
+        let items = (0..64).map(|_| consumer.consume_blocking()).collect::<Vec<_>>();
+        assert_eq!(items, (0..64).map(Ok).collect::<Vec<_>>());
+
+        thread.join().unwrap();
+        assert_eq!(consumer.consume_blocking(), Err(ConsumeError::Disconnected));
+        assert!(condition.unparks.load(SeqCst) > 0);
+    }
+}
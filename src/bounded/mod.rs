@@ -15,4 +15,6 @@
 //! Bounded lock-free queues.
 
 pub mod mpmc;
+pub mod mpsc;
+pub mod spmc;
 pub mod spsc;
@@ -0,0 +1,890 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded multi-producer, multi-consumer lock-free queue.
+//!
+//! The algorithm used by a queue is selected with the `B` type parameter of its producers and
+//! consumers:
+//!
+//! * `Vyukov` &ndash; the default, which claims slots stamped with sequence numbers with a
+//!   compare-and-swap
+//! * `Scq` &ndash; the scalable circular queue, which claims slots with fetch-and-add and has
+//!   higher throughput when many threads are adding or removing items
+//!
+//! Queues that use a non-default algorithm are constructed with `custom_channel` or a `Builder`.
+
+use std::cmp;
+use std::vec;
+use std::marker::{PhantomData};
+use std::mem::{MaybeUninit};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration, Instant};
+
+use {ChannelError, Consume, ConsumeError, MemoryUsage, Produce, ProduceError, padding};
+use notify::{Notifier};
+
+mod scq;
+mod vyukov;
+
+pub use self::scq::{Scq};
+pub use self::vyukov::{Vyukov};
+
+/// The producer and consumer of a queue.
+type Channel<T, B> = (Producer<T, B>, Consumer<T, B>);
+
+//================================================
+// Structs
+//================================================
+
+// Builder _______________________________________
+
+/// A builder for a bounded MPMC lock-free queue.
+///
+/// Only options that change how the queue itself behaves are set with a `Builder`. The padding
+/// between the fields of the queue is chosen at compile time with the `cacheline-64` and `compact`
+/// features, and the buffer is always allocated with the global allocator. Waiting for room or
+/// for items is provided by the wrappers in the `blocking` module, and counting or recording
+/// operations by the wrappers in the `counted` and `trace` modules.
+///
+/// # Example
+///
+/// ```
+/// extern crate npnc;
+///
+/// use npnc::bounded::mpmc::{Builder, Scq};
+///
+/// fn main() {
+///     let (producer, consumer) = Builder::new(1000).exact().backend::<Scq<i32>>().build();
+///     assert_eq!(producer.capacity(), 1000);
+///     producer.produce(1).unwrap();
+///     assert_eq!(consumer.consume(), Ok(1));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Builder<T, B=Vyukov<T>> where B: Backend<T> {
+    capacity: usize,
+    exact: bool,
+    attempts: usize,
+    evict: bool,
+    _marker: PhantomData<fn() -> (T, B)>,
+}
+
+impl<T> Builder<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Builder` for a queue that can contain the supplied number of items.
+    ///
+    /// The capacity must be a power of two unless `exact` is called.
+    pub fn new(capacity: usize) -> Self {
+        Builder {
+            capacity,
+            exact: false,
+            attempts: usize::MAX,
+            evict: false,
+            _marker: PhantomData,
+        }
+    }
+
+    //- Consumers --------------------------------
+
+    /// Sets the maximum number of times a producer or consumer will attempt to claim a slot before
+    /// giving up and returning a `Contended` error.
+    ///
+    /// # Panics
+    ///
+    /// * `attempts` is zero
+    pub fn attempts(self, attempts: usize) -> Self {
+        assert!(attempts != 0, "`attempts` is zero");
+        Builder { attempts, ..self }
+    }
+}
+
+impl<T, B> Builder<T, B> where B: Backend<T> {
+    //- Consumers --------------------------------
+
+    /// Allows a capacity that is not a power of two.
+    ///
+    /// The buffer used by the queue is rounded up to the next power of two, but producers will
+    /// receive `Full` errors once the queue contains the requested number of items.
+    pub fn exact(self) -> Self {
+        Builder { exact: true, ..self }
+    }
+
+    /// Makes producers evict the item at the front of the queue to make room for the item being
+    /// added instead of returning `Full` errors when the queue is full.
+    ///
+    /// The evicted items are dropped. Use `produce_or_evict_oldest` instead to get the evicted
+    /// items back or to decide whether to evict them.
+    pub fn evict_oldest(self) -> Self {
+        Builder { evict: true, ..self }
+    }
+
+    /// Sets the algorithm used by the queue.
+    pub fn backend<C>(self) -> Builder<T, C> where C: Backend<T> {
+        Builder {
+            capacity: self.capacity,
+            exact: self.exact,
+            attempts: self.attempts,
+            evict: self.evict,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a producer and consumer for the queue.
+    ///
+    /// # Panics
+    ///
+    /// * the capacity is zero
+    /// * the capacity is not a power of two and `exact` was not called
+    pub fn build(self) -> (Producer<T, B>, Consumer<T, B>) {
+        assert!(self.capacity != 0, "`capacity` is zero");
+        if !self.exact {
+            assert!(self.capacity.is_power_of_two(), "`capacity` is not a power of two");
+        }
+        self.try_build().expect("failed to allocate queue")
+    }
+
+    /// Attempts to return a producer and consumer for the queue.
+    ///
+    /// This method returns `Err` instead of panicking or aborting if the capacity is not valid or
+    /// the buffer for the queue can't be allocated.
+    pub fn try_build(self) -> Result<Channel<T, B>, ChannelError> {
+        let size = if self.exact && self.capacity != 0 {
+            self.capacity.checked_next_power_of_two().ok_or(ChannelError::Overflow)?
+        } else if self.capacity.is_power_of_two() {
+            self.capacity
+        } else {
+            return Err(ChannelError::InvalidCapacity);
+        };
+
+        let queue = Queue::new(B::try_new(size, self.attempts)?, self.capacity, self.evict);
+        Ok((Producer(queue.clone()), Consumer(queue)))
+    }
+}
+
+// Consumer ______________________________________
+
+/// A consumer for a bounded MPMC lock-free queue.
+#[derive(Debug)]
+pub struct Consumer<T, B=Vyukov<T>>(Arc<Queue<T, B>>) where B: Backend<T>;
+
+impl<T, B> Consumer<T, B> where B: Backend<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the front of the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+
+    /// Attempts to remove and return the item at the front of the queue without retrying if
+    /// another thread claims the item first.
+    ///
+    /// This method returns `Err` if the queue is empty, and may return `Contended` even if the
+    /// queue is not empty. With the `Scq` algorithm, this method is the same as `consume`.
+    pub fn consume_weak(&self) -> Result<T, ConsumeError> {
+        self.0.consume_weak()
+    }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        Consume::consume_uninit(self, items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        Consume::consume_latest(self)
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        self.0.discard(n)
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the queue is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of items the queue can contain.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.0.producer.load(Acquire)
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.0.consumer.load(Acquire)
+    }
+
+    /// Removes the items currently in the queue and returns an iterator over them.
+    ///
+    /// With the `Vyukov` algorithm, the items at the front of the queue that have been completely
+    /// added are claimed at once with a single update of the read position, so this consumer
+    /// doesn't have to compete with the producers for each item. With the `Scq` algorithm, the
+    /// items are removed one at a time until as many items as the queue contained when this method
+    /// was called have been removed.
+    pub fn drain_snapshot(&self) -> vec::IntoIter<T> {
+        let mut items = vec![];
+        self.0.drain(usize::MAX, |i| items.push(i));
+        items.into_iter()
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producers will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty, no matter how many
+    /// producers and consumers are still connected. The items already in the queue can still be
+    /// removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Disconnects the producers and returns the items remaining in the queue.
+    ///
+    /// Once this method has been called, the producers will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty. Items the producers
+    /// were in the middle of adding when this method was called may be left in the queue.
+    pub fn close_and_drain(&self) -> Vec<T> {
+        self.0.close();
+        let mut items = vec![];
+        loop {
+            match self.0.consume() {
+                Ok(item) => items.push(item),
+                Err(ConsumeError::Contended) => { },
+                Err(_) => return items,
+            }
+        }
+    }
+}
+
+impl<T, B> Clone for Consumer<T, B> where B: Backend<T> {
+    fn clone(&self) -> Self {
+        self.0.consumer.fetch_add(1, Release);
+        Consumer(self.0.clone())
+    }
+}
+
+impl<T, B> Consume<T> for Consumer<T, B> where B: Backend<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+
+    fn consume_weak(&self) -> Result<T, ConsumeError> {
+        self.0.consume_weak()
+    }
+
+    fn discard(&self, n: usize) -> usize {
+        self.0.discard(n)
+    }
+}
+
+impl<T, B> Drop for Consumer<T, B> where B: Backend<T> {
+    fn drop(&mut self) {
+        if self.0.consumer.fetch_sub(1, Release) == 1 {
+            self.0.disconnect.notify();
+        }
+    }
+}
+
+unsafe impl<T, B> Send for Consumer<T, B> where T: Send, B: Backend<T> { }
+
+// Producer __________________________________
+
+/// A producer for a bounded MPMC lock-free queue.
+#[derive(Debug)]
+pub struct Producer<T, B=Vyukov<T>>(Arc<Queue<T, B>>) where B: Backend<T>;
+
+impl<T, B> Producer<T, B> where B: Backend<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of the queue.
+    ///
+    /// This method returns `Err` if the queue is full or has no remaining consumers.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    /// Attempts to add the supplied item to the back of the queue, retrying with backoff while the
+    /// queue is full until the supplied deadline.
+    ///
+    /// This method returns `Timeout` with the item if the queue is still full once the deadline has
+    /// passed (e.g., so a pipeline can shed load) and `Disconnected` if the queue has no remaining
+    /// consumers.
+    pub fn produce_deadline(&self, item: T, deadline: Instant) -> Result<(), ProduceError<T>> {
+        ::produce_deadline(item, Some(deadline), |item| self.0.produce(item))
+    }
+
+    /// Adds the supplied item to the back of the queue, retrying with backoff while the queue is
+    /// full.
+    ///
+    /// This method only returns `Err` if the queue has no remaining consumers or has been closed.
+    /// The waiting thread spins, yields, and then sleeps briefly between attempts; the producers in
+    /// the `blocking` module sleep until they are woken by a consumer instead.
+    pub fn produce_blocking(&self, item: T) -> Result<(), ProduceError<T>> {
+        ::produce_deadline(item, None, |item| self.0.produce(item))
+    }
+
+    /// Attempts to add the supplied item to the back of the queue without retrying if another
+    /// thread claims the slot first.
+    ///
+    /// This method returns `Err` if the queue is full or has no remaining consumers, and may return
+    /// `Contended` even if the queue is not full. With the `Scq` algorithm, this method is the same
+    /// as `produce`.
+    pub fn produce_weak(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce_weak(item)
+    }
+
+    /// Moves items from the front of the supplied vector to the back of the queue until the vector
+    /// is empty or the queue is full and returns the number of items moved.
+    ///
+    /// The items that were not moved are left in the vector in their original order.
+    pub fn produce_many(&self, items: &mut Vec<T>) -> usize {
+        Produce::produce_many(self, items)
+    }
+
+    /// Attempts to add the supplied item to the back of the queue, evicting the item at the front
+    /// of the queue to make room for it if the queue is full and the supplied policy allows it.
+    ///
+    /// The policy is only called if the queue is full and is passed the item being added. If it
+    /// returns `true`, the oldest item in the queue is removed and then the supplied item is added.
+    /// Only the oldest item can be evicted since items can't be removed from the middle of the
+    /// queue.
+    /// The outcome of adding the supplied item is returned along with the evicted item, if any.
+    /// Another producer may fill the room made by the eviction before the supplied item is added,
+    /// in which case both the error containing the supplied item and the evicted item are returned.
+    pub fn produce_or_evict_oldest<F>(
+        &self, item: T, policy: F
+    ) -> (Result<(), ProduceError<T>>, Option<T>) where F: FnOnce(&T) -> bool {
+        self.0.produce_or_evict_oldest(item, policy)
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the queue is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of items the queue can contain.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.0.producer.load(Acquire)
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.0.consumer.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producers will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty, no matter how many
+    /// producers and consumers are still connected. The items already in the queue can still be
+    /// removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers or has been
+    /// closed.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
+}
+
+impl<T, B> Clone for Producer<T, B> where B: Backend<T> {
+    fn clone(&self) -> Self {
+        self.0.producer.fetch_add(1, Release);
+        Producer(self.0.clone())
+    }
+}
+
+impl<T, B> Produce<T> for Producer<T, B> where B: Backend<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    fn produce_weak(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce_weak(item)
+    }
+}
+
+impl<T, B> Drop for Producer<T, B> where B: Backend<T> {
+    fn drop(&mut self) {
+        self.0.producer.fetch_sub(1, Release);
+    }
+}
+
+unsafe impl<T, B> Send for Producer<T, B> where T: Send, B: Backend<T> { }
+
+// Queue _________________________________________
+
+#[derive(Debug)]
+#[repr(C)]
+struct Queue<T, B> where B: Backend<T> {
+    consumer: AtomicUsize,
+    closed: AtomicBool,
+    _cpadding: [usize; padding(2)],
+    producer: AtomicUsize,
+    _ppadding: [usize; padding(1)],
+    count: AtomicUsize,
+    _npadding: [usize; padding(1)],
+    capacity: usize,
+    evict: bool,
+    backend: B,
+    disconnect: Notifier,
+    _marker: PhantomData<T>,
+}
+
+impl<T, B> Queue<T, B> where B: Backend<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Queue` that uses the supplied backend and can contain up to the supplied
+    /// number of items, evicting the item at its front when it is full if `evict` is `true`.
+    fn new(backend: B, capacity: usize, evict: bool) -> Arc<Self> {
+        Arc::new(Queue {
+            consumer: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
+            _cpadding: [0; padding(2)],
+            producer: AtomicUsize::new(1),
+            _ppadding: [0; padding(1)],
+            count: AtomicUsize::new(0),
+            _npadding: [0; padding(1)],
+            capacity,
+            evict,
+            backend,
+            disconnect: Notifier::new(),
+            _marker: PhantomData,
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn len(&self) -> usize {
+        cmp::min(self.backend.len(), self.capacity)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns whether the capacity of this queue is less than the capacity of its backend.
+    fn is_exact(&self) -> bool {
+        self.capacity < self.backend.capacity()
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.backend.memory_usage()
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Release);
+        self.disconnect.notify();
+    }
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        let mut item = item;
+        loop {
+            match self.produce_with(item, B::produce) {
+                Err(ProduceError::Full(rejected)) if self.evict => {
+                    // The queue may have been emptied by a consumer in the meantime, in which case
+                    // the next attempt to add the item will succeed.
+                    item = rejected;
+                    let _ = self.consume();
+                },
+                result => return result,
+            }
+        }
+    }
+
+    fn produce_weak(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.produce_with(item, B::produce_weak)
+    }
+
+    fn produce_or_evict_oldest<F>(
+        &self, item: T, policy: F
+    ) -> (Result<(), ProduceError<T>>, Option<T>) where F: FnOnce(&T) -> bool {
+        let item = match self.produce(item) {
+            Err(ProduceError::Full(item)) => item,
+            result => return (result, None),
+        };
+
+        if !policy(&item) {
+            return (Err(ProduceError::Full(item)), None);
+        }
+
+        // The queue may have been emptied by a consumer in the meantime, in which case there is
+        // nothing to evict.
+        let evicted = self.consume().ok();
+        (self.produce(item), evicted)
+    }
+
+    /// Adds the supplied item to the backend of this queue with the supplied function.
+    fn produce_with<F>(&self, item: T, produce: F) -> Result<(), ProduceError<T>>
+        where F: FnOnce(&B, T) -> Result<(), ProduceError<T>>
+    {
+        // Return an error if all of the consumers have been disconnected or the queue has been
+        // closed.
+        if self.consumer.load(Acquire) == 0 || self.closed.load(Acquire) {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        if !self.is_exact() {
+            return produce(&self.backend, item);
+        }
+
+        // Return an error if the queue contains as many items as it can contain. The item is
+        // counted before it is added so that the count never underflows.
+        if self.count.fetch_add(1, AcqRel) >= self.capacity {
+            self.count.fetch_sub(1, Release);
+            return Err(ProduceError::Full(item));
+        }
+
+        produce(&self.backend, item).inspect_err(|_| { self.count.fetch_sub(1, Release); })
+    }
+
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.consume_with(B::consume)
+    }
+
+    fn consume_weak(&self) -> Result<T, ConsumeError> {
+        self.consume_with(B::consume_weak)
+    }
+
+    /// Removes an item from the backend of this queue with the supplied function.
+    fn consume_with<F>(&self, consume: F) -> Result<T, ConsumeError>
+        where F: FnOnce(&B) -> Result<T, ConsumeError>
+    {
+        // The producers are checked first so that an item added by a producer before it was
+        // disconnected is never missed.
+        let disconnected = self.producer.load(Acquire) == 0 || self.closed.load(Acquire);
+        match consume(&self.backend) {
+            Ok(item) => {
+                if self.is_exact() {
+                    self.count.fetch_sub(1, Release);
+                }
+                Ok(item)
+            },
+            Err(ConsumeError::Empty) if disconnected => Err(ConsumeError::Disconnected),
+            result => result,
+        }
+    }
+
+    fn drain<F>(&self, limit: usize, f: F) -> usize where F: FnMut(T) {
+        let count = self.backend.drain(limit, f);
+        if self.is_exact() {
+            self.count.fetch_sub(count, Release);
+        }
+        count
+    }
+
+    fn discard(&self, n: usize) -> usize {
+        self.drain(n, drop)
+    }
+}
+
+unsafe impl<T, B> Sync for Queue<T, B> where T: Send, B: Backend<T> { }
+
+//================================================
+// Traits
+//================================================
+
+mod sealed {
+    pub trait Sealed { }
+}
+
+// Backend _______________________________________
+
+/// A bounded MPMC lock-free queue algorithm.
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+pub trait Backend<T>: sealed::Sealed + Sized {
+    #[doc(hidden)]
+    fn try_new(size: usize, attempts: usize) -> Result<Self, ChannelError>;
+
+    #[doc(hidden)]
+    fn len(&self) -> usize;
+
+    #[doc(hidden)]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[doc(hidden)]
+    fn capacity(&self) -> usize;
+
+    #[doc(hidden)]
+    fn memory_usage(&self) -> MemoryUsage;
+
+    #[doc(hidden)]
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>>;
+
+    #[doc(hidden)]
+    fn produce_weak(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.produce(item)
+    }
+
+    #[doc(hidden)]
+    fn consume(&self) -> Result<T, ConsumeError>;
+
+    #[doc(hidden)]
+    fn consume_weak(&self) -> Result<T, ConsumeError> {
+        self.consume()
+    }
+
+    #[doc(hidden)]
+    fn drain<F>(&self, limit: usize, f: F) -> usize where F: FnMut(T);
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for a bounded MPMC lock-free queue.
+///
+/// # Panics
+///
+/// * `size` is not a power of two
+pub fn channel<T>(size: usize) -> (Producer<T>, Consumer<T>) {
+    Builder::new(size).build()
+}
+
+/// Returns a producer and consumer for a bounded MPMC wait-free queue.
+///
+/// The value of `attempts` indicates the maximum number of times a producer or consumer will
+/// attempt to claim a slot before giving up and returning a `Contended` error, which bounds the
+/// number of steps taken by every operation regardless of how many other producers and consumers
+/// are operating on the queue.
+///
+/// # Panics
+///
+/// * `size` is not a power of two
+/// * `attempts` is zero
+pub fn wait_free_channel<T>(size: usize, attempts: usize) -> (Producer<T>, Consumer<T>) {
+    Builder::new(size).attempts(attempts).build()
+}
+
+/// Returns a producer and consumer for a bounded MPMC lock-free queue that uses the algorithm
+/// specified by the `B` type parameter.
+///
+/// # Panics
+///
+/// * `size` is not a power of two
+///
+/// # Example
+///
+/// ```
+/// extern crate npnc;
+///
+/// use npnc::bounded::mpmc::{self, Consumer, Producer, Scq};
+///
+/// fn main() {
+///     let (producer, consumer): (Producer<i32, Scq<i32>>, Consumer<_, _>) =
+///         mpmc::custom_channel(64);
+///     producer.produce(1).unwrap();
+///     assert_eq!(consumer.consume(), Ok(1));
+/// }
+/// ```
+pub fn custom_channel<T, B>(size: usize) -> (Producer<T, B>, Consumer<T, B>) where B: Backend<T> {
+    Builder::new(size).backend().build()
+}
+
+/// Attempts to return a producer and consumer for a bounded MPMC lock-free queue.
+///
+/// This function returns `Err` instead of panicking or aborting if `size` is not a power of two
+/// or the buffer for the queue can't be allocated.
+pub fn try_channel<T>(size: usize) -> Result<(Producer<T>, Consumer<T>), ChannelError> {
+    Builder::new(size).try_build()
+}
+
+/// Attempts to return a producer and consumer for a bounded MPMC lock-free queue that uses the
+/// algorithm specified by the `B` type parameter.
+///
+/// This function returns `Err` instead of panicking or aborting if `size` is not a power of two
+/// or the buffer for the queue can't be allocated.
+pub fn try_custom_channel<T, B>(
+    size: usize
+) -> Result<Channel<T, B>, ChannelError> where B: Backend<T> {
+    Builder::new(size).backend().try_build()
+}
+
+/// Returns a producer and consumer for a bounded MPMC lock-free queue that can contain exactly
+/// `capacity` items.
+///
+/// The buffer used by the queue is rounded up to the next power of two, but producers will
+/// receive `Full` errors once the queue contains `capacity` items.
+///
+/// # Panics
+///
+/// * `capacity` is zero
+pub fn channel_exact<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    Builder::new(capacity).exact().build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_produce_or_evict_oldest() {
+        let (producer, consumer) = channel(2);
+        assert_eq!(producer.produce_or_evict_oldest(0, |_| true), (Ok(()), None));
+        assert_eq!(producer.produce_or_evict_oldest(1, |_| true), (Ok(()), None));
+        let result = producer.produce_or_evict_oldest(2, |&i| i > 2);
+        assert_eq!(result, (Err(ProduceError::Full(2)), None));
+        assert_eq!(producer.produce_or_evict_oldest(3, |&i| i > 2), (Ok(()), Some(0)));
+        assert_eq!(consumer.consume(), Ok(1));
+        assert_eq!(consumer.consume(), Ok(3));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+    }
+
+    #[test]
+    fn test_builder_evict_oldest() {
+        let builder = Builder::new(3).exact().evict_oldest();
+        let (producer, consumer) = builder.backend::<Scq<_>>().build();
+        for item in 0..5 {
+            producer.produce(item).unwrap();
+        }
+        assert_eq!(consumer.consume(), Ok(2));
+        assert_eq!(consumer.consume(), Ok(3));
+        assert_eq!(consumer.consume(), Ok(4));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+    }
+
+    #[test]
+    fn test_builder_backend_attempts() {
+        let builder = Builder::<i32>::new(4).attempts(3).backend::<Vyukov<_>>();
+        assert_eq!(builder.attempts, 3);
+    }
+
+    fn test_backend<B>() where B: Backend<usize> + 'static {
+        use std::thread;
+
+        let (producer, consumer) = Builder::new(6).exact().backend::<B>().build();
+        for item in 0..6 {
+            producer.produce(item).unwrap();
+        }
+        assert_eq!(producer.produce(6), Err(ProduceError::Full(6)));
+        for item in 0..6 {
+            assert_eq!(consumer.consume(), Ok(item));
+        }
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        let (producer, consumer) = Builder::new(64).backend::<B>().build();
+        let producers = (0..2).map(|i| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for item in 0..5000 {
+                    while producer.produce((i * 5000) + item).is_err() {
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+        let consumers = (0..2).map(|_| {
+            let consumer = consumer.clone();
+            thread::spawn(move || {
+                let mut items = vec![];
+                let mut last = [None, None];
+                while items.len() < 5000 {
+                    match consumer.consume() {
+                        // Items from each producer are consumed in the order they were produced.
+                        Ok(item) => {
+                            assert!(last[item / 5000].is_none_or(|l| l < item));
+                            last[item / 5000] = Some(item);
+                            items.push(item);
+                        },
+                        Err(_) => thread::yield_now(),
+                    }
+                }
+                items
+            })
+        }).collect::<Vec<_>>();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut items = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, (0..10000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_vyukov() {
+        test_backend::<Vyukov<usize>>();
+    }
+
+    #[test]
+    fn test_scq() {
+        test_backend::<Scq<usize>>();
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::mem;
+use std::ptr;
+
+use {ChannelError, ConsumeError, MemoryUsage, ProduceError};
+use buffer::{Buffer};
+use ring::{Ring};
+use super::{sealed, Backend};
+
+//================================================
+// Structs
+//================================================
+
+// Scq ___________________________________________
+
+/// The scalable circular queue (SCQ) algorithm (Nikolaev).
+///
+/// Items are stored in a buffer whose free and occupied indices are kept in two rings. Producers
+/// and consumers claim entries in the rings with fetch-and-add instead of retrying a
+/// compare-and-swap, which scales better than the `Vyukov` algorithm when many threads are adding
+/// or removing items.
+#[derive(Debug)]
+pub struct Scq<T> {
+    occupied: Ring,
+    free: Ring,
+    buffer: Buffer<T>,
+}
+
+impl<T> Backend<T> for Scq<T> {
+    fn try_new(size: usize, _: usize) -> Result<Self, ChannelError> {
+        let buffer = Buffer::try_new(size)?;
+
+        // A ring with an order of zero can't distinguish an empty entry from the first index.
+        let order = cmp::max(size.trailing_zeros(), 1);
+        let free = Ring::try_new(order)?;
+        for index in 0..size {
+            free.enqueue(index);
+        }
+        Ok(Scq { occupied: Ring::try_new(order)?, free, buffer })
+    }
+
+    fn len(&self) -> usize {
+        cmp::min(self.occupied.len(), self.capacity())
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.size()
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let entries = self.occupied.size() + self.free.size();
+        let bytes = (self.capacity() * mem::size_of::<T>()) + (entries * mem::size_of::<usize>());
+        MemoryUsage { bytes, nodes: 0, retired: 0 }
+    }
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        if let Some(index) = self.free.dequeue() {
+            unsafe { self.buffer.set(index, item); }
+            self.occupied.enqueue(index);
+            Ok(())
+        } else {
+            Err(ProduceError::Full(item))
+        }
+    }
+
+    fn consume(&self) -> Result<T, ConsumeError> {
+        if let Some(index) = self.occupied.dequeue() {
+            let item = unsafe { ptr::read(self.buffer.get_ref(index)) };
+            self.free.enqueue(index);
+            Ok(item)
+        } else {
+            Err(ConsumeError::Empty)
+        }
+    }
+
+    fn drain<F>(&self, limit: usize, mut f: F) -> usize where F: FnMut(T) {
+        // The occupied ring is claimed one entry at a time, so only the items in the queue when
+        // this method is called are removed so that the producers can't keep it from returning.
+        let len = cmp::min(self.len(), limit);
+        let mut count = 0;
+        while count < len {
+            match self.consume() {
+                Ok(item) => f(item),
+                Err(_) => break,
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+impl<T> Drop for Scq<T> {
+    fn drop(&mut self) {
+        while self.consume().is_ok() { }
+    }
+}
+
+impl<T> sealed::Sealed for Scq<T> { }
@@ -0,0 +1,300 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::mem::{self, MaybeUninit};
+use std::cell::{UnsafeCell};
+use std::sync::atomic::Ordering::*;
+
+use {ChannelError, ConsumeError, MemoryUsage, ProduceError, padding};
+use buffer::{Buffer};
+use super::{sealed, Backend};
+
+/// A position in the queue or the sequence number of a slot.
+///
+/// Sequence numbers are 64 bits wide on 32-bit targets that support 64-bit atomics. A 32-bit
+/// sequence number can wrap around in minutes in a busy long-running process, after which a thread
+/// that was preempted between loading a sequence number and claiming its slot could claim the slot
+/// a full wrap later (the ABA problem).
+#[cfg(all(target_pointer_width="32", target_has_atomic="64"))]
+type Sequence = u64;
+#[cfg(all(target_pointer_width="32", target_has_atomic="64"))]
+type AtomicSequence = ::std::sync::atomic::AtomicU64;
+#[cfg(all(target_pointer_width="32", target_has_atomic="64"))]
+type Difference = i64;
+
+/// A position in the queue or the sequence number of a slot.
+#[cfg(not(all(target_pointer_width="32", target_has_atomic="64")))]
+type Sequence = usize;
+#[cfg(not(all(target_pointer_width="32", target_has_atomic="64")))]
+type AtomicSequence = ::std::sync::atomic::AtomicUsize;
+#[cfg(not(all(target_pointer_width="32", target_has_atomic="64")))]
+type Difference = isize;
+
+//================================================
+// Structs
+//================================================
+
+// Slot __________________________________________
+
+#[derive(Debug)]
+struct Slot<T> {
+    item: UnsafeCell<MaybeUninit<T>>,
+    sequence: AtomicSequence,
+}
+
+impl<T> Slot<T> {
+    //- Constructors -----------------------------
+
+    fn new(index: usize) -> Self {
+        let sequence = AtomicSequence::new(widen(index));
+        Slot { item: UnsafeCell::new(MaybeUninit::uninit()), sequence }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Moves the item out of this slot, leaving this slot uninitialized.
+    unsafe fn get(&self) -> T {
+        (*self.item.get()).as_ptr().read()
+    }
+
+    /// Moves the supplied item into this slot, which must be uninitialized.
+    unsafe fn set(&self, item: T) {
+        (*self.item.get()).as_mut_ptr().write(item);
+    }
+}
+
+// Vyukov ________________________________________
+
+/// Dmitry Vyukov's bounded queue algorithm.
+///
+/// Every slot in the buffer is stamped with a sequence number which producers and consumers use
+/// to claim the slot with a compare-and-swap. The sequence numbers are 64 bits wide on 32-bit
+/// targets that support 64-bit atomics.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Vyukov<T> {
+    write: AtomicSequence,
+    _wpadding: [usize; padding(1)],
+    read: AtomicSequence,
+    _rpadding: [usize; padding(1)],
+    buffer: Buffer<Slot<T>>,
+    attempts: usize,
+}
+
+impl<T> Vyukov<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to claim a slot up to the supplied number of times to add the supplied item.
+    fn produce_attempts(&self, item: T, attempts: usize) -> Result<(), ProduceError<T>> {
+        for _ in 0..attempts {
+            let write = self.write.load(Relaxed);
+            let slot = unsafe { self.buffer.wrapping_get_ref(narrow(write)) };
+            let sequence = slot.sequence.load(Acquire);
+            let difference = difference(sequence, write);
+
+            // Return an error if the queue is full.
+            if difference < 0 {
+                return Err(ProduceError::Full(item));
+            }
+
+            // Add the item to the back of the queue if this slot is available.
+            let next = write.wrapping_add(1);
+            if difference == 0 && exchange(&self.write, write, next) {
+                unsafe { slot.set(item); }
+                slot.sequence.store(next, Release);
+                return Ok(());
+            }
+        }
+
+        Err(ProduceError::Contended(item))
+    }
+
+    /// Attempts to claim a slot up to the supplied number of times to remove an item.
+    fn consume_attempts(&self, attempts: usize) -> Result<T, ConsumeError> {
+        for _ in 0..attempts {
+            let read = self.read.load(Relaxed);
+            let slot = unsafe { self.buffer.wrapping_get_ref(narrow(read)) };
+            let sequence = slot.sequence.load(Acquire);
+            let difference = difference(sequence, read.wrapping_add(1));
+
+            // Return an error if the queue is empty.
+            if difference < 0 {
+                return Err(ConsumeError::Empty);
+            }
+
+            // Remove and return the item at the front of the queue if this slot is available.
+            let next = read.wrapping_add(1);
+            if difference == 0 && exchange(&self.read, read, next) {
+                let item = unsafe { slot.get() };
+                slot.sequence.store(next.wrapping_add(self.lap()), Release);
+                return Ok(item);
+            }
+        }
+
+        Err(ConsumeError::Contended)
+    }
+
+    /// Returns the amount the sequence number of a slot advances by when its item is removed.
+    fn lap(&self) -> Sequence {
+        widen(self.buffer.size() - 1)
+    }
+}
+
+impl<T> Backend<T> for Vyukov<T> {
+    fn try_new(size: usize, attempts: usize) -> Result<Self, ChannelError> {
+        // A slot that was just filled has the same sequence number as an empty slot would for the
+        // next write position when the buffer has one slot, so at least two slots are used.
+        let size = cmp::max(size, 2);
+        let buffer = Buffer::try_new(size)?;
+        for index in 0..size {
+            unsafe { buffer.set(index, Slot::new(index)); }
+        }
+        Ok(Vyukov {
+            write: AtomicSequence::new(0),
+            _wpadding: [0; padding(1)],
+            read: AtomicSequence::new(0),
+            _rpadding: [0; padding(1)],
+            buffer,
+            attempts,
+        })
+    }
+
+    fn len(&self) -> usize {
+        // The read position is loaded first so that it can't be ahead of the write position.
+        let read = self.read.load(Acquire);
+        let write = self.write.load(Acquire);
+        narrow(cmp::min(write.wrapping_sub(read), widen(self.capacity())))
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.size()
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let bytes = self.buffer.size() * mem::size_of::<Slot<T>>();
+        MemoryUsage { bytes, nodes: 0, retired: 0 }
+    }
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.produce_attempts(item, self.attempts)
+    }
+
+    fn produce_weak(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.produce_attempts(item, 1)
+    }
+
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.consume_attempts(self.attempts)
+    }
+
+    fn consume_weak(&self) -> Result<T, ConsumeError> {
+        self.consume_attempts(1)
+    }
+
+    fn drain<F>(&self, limit: usize, mut f: F) -> usize where F: FnMut(T) {
+        for _ in 0..self.attempts {
+            // Find the end of the slots at the front of the queue that have been published.
+            let read = self.read.load(Relaxed);
+            let mut end = read;
+            let len = widen(cmp::min(self.buffer.size(), limit));
+            while end.wrapping_sub(read) < len {
+                let slot = unsafe { self.buffer.wrapping_get_ref(narrow(end)) };
+                if slot.sequence.load(Acquire) != end.wrapping_add(1) {
+                    break;
+                }
+                end = end.wrapping_add(1);
+            }
+
+            if end == read {
+                return 0;
+            }
+
+            // Remove the items in the slots if they could all be claimed at once.
+            if exchange(&self.read, read, end) {
+                let mut index = read;
+                while index != end {
+                    let slot = unsafe { self.buffer.wrapping_get_ref(narrow(index)) };
+                    let item = unsafe { slot.get() };
+                    let next = index.wrapping_add(1);
+                    slot.sequence.store(next.wrapping_add(self.lap()), Release);
+                    f(item);
+                    index = next;
+                }
+                return narrow(end.wrapping_sub(read));
+            }
+        }
+
+        0
+    }
+}
+
+impl<T> Drop for Vyukov<T> {
+    fn drop(&mut self) {
+        // No producers or consumers can be operating on the queue while it is being dropped, so
+        // the items are dropped by walking the slots between the read and write positions instead
+        // of with `consume`. Only the slots whose sequence numbers show that they were published
+        // are dropped, so a slot that was claimed but never written can't be dropped.
+        let (read, write) = (*self.read.get_mut(), *self.write.get_mut());
+        let mut index = read;
+        while index != write {
+            let slot = unsafe { self.buffer.wrapping_get_ref(narrow(index)) };
+            if slot.sequence.load(Relaxed) == index.wrapping_add(1) {
+                drop(unsafe { slot.get() });
+            }
+            index = index.wrapping_add(1);
+        }
+    }
+}
+
+impl<T> sealed::Sealed for Vyukov<T> { }
+
+//================================================
+// Functions
+//================================================
+
+/// Returns the signed distance from the supplied position to the supplied sequence number.
+fn difference(sequence: Sequence, position: Sequence) -> Difference {
+    sequence.wrapping_sub(position) as Difference
+}
+
+/// Converts the supplied index or length into a sequence number.
+#[cfg(all(target_pointer_width="32", target_has_atomic="64"))]
+fn widen(value: usize) -> Sequence {
+    value as Sequence
+}
+
+/// Converts the supplied index or length into a sequence number.
+#[cfg(not(all(target_pointer_width="32", target_has_atomic="64")))]
+fn widen(value: usize) -> Sequence {
+    value
+}
+
+/// Truncates the supplied sequence number into an index or length.
+///
+/// Buffer sizes are powers of two, so a truncated position still wraps to the same slot.
+#[cfg(all(target_pointer_width="32", target_has_atomic="64"))]
+fn narrow(sequence: Sequence) -> usize {
+    sequence as usize
+}
+
+/// Truncates the supplied sequence number into an index or length.
+#[cfg(not(all(target_pointer_width="32", target_has_atomic="64")))]
+fn narrow(sequence: Sequence) -> usize {
+    sequence
+}
+
+fn exchange(atomic: &AtomicSequence, current: Sequence, new: Sequence) -> bool {
+    atomic.compare_exchange_weak(current, new, Relaxed, Relaxed).is_ok()
+}
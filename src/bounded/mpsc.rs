@@ -0,0 +1,502 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded multi-producer, single-consumer lock-free queue.
+//!
+//! The producers claim slots stamped with sequence numbers with a compare-and-swap like the
+//! `Vyukov` algorithm of the bounded MPMC queue. Since there is only one consumer, the read
+//! position is only ever updated by the consumer, so the consumer removes items without a
+//! compare-and-swap.
+
+use std::cmp;
+use std::mem::{self, MaybeUninit};
+use std::cell::{Cell, UnsafeCell};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration, Instant};
+
+use {ChannelError, Consume, ConsumeError, MemoryUsage, Produce, ProduceError, padding};
+use buffer::{Buffer};
+use monitor;
+use notify::{Notifier};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for a bounded MPSC lock-free queue.
+#[derive(Debug)]
+pub struct Consumer<T>(Arc<Queue<T>>);
+
+impl<T> Consumer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the front of the queue.
+    ///
+    /// This method returns `Err` if the queue is empty, including while the producer that claimed
+    /// the slot at the front of the queue has not finished adding its item.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting in a low-power state while
+    /// the queue is empty.
+    ///
+    /// On x86-64 processors that support `umonitor` and `umwait` and on AArch64 processors, this
+    /// consumer waits for the cacheline containing the back of the queue to be written to instead
+    /// of spinning, which has nearly the latency of spinning at a fraction of the power. On other
+    /// processors, this consumer spins. This method only returns `Err` if the queue is empty and
+    /// the producers have been disconnected or the queue has been closed.
+    pub fn consume_wait(&self) -> Result<T, ConsumeError> {
+        loop {
+            match self.0.consume() {
+                Err(ConsumeError::Empty) => monitor::wait(&self.0.write, |write| {
+                    write != self.0.read.get() || self.0.is_disconnected()
+                }),
+                result => return result,
+            }
+        }
+    }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        Consume::consume_uninit(self, items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        Consume::consume_latest(self)
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        Consume::discard(self, n)
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the queue is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of items the queue can contain.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producers will receive `Disconnected` errors and the
+    /// consumer will receive a `Disconnected` error once the queue is empty, no matter how many
+    /// producers are still connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Disconnects the producers and returns the items remaining in the queue.
+    ///
+    /// Once this method has been called, the producers will receive `Disconnected` errors and this
+    /// consumer will receive a `Disconnected` error once the queue is empty. Items the producers
+    /// were in the middle of adding when this method was called may be left in the queue.
+    pub fn close_and_drain(&self) -> Vec<T> {
+        self.0.close();
+        let mut items = vec![];
+        while let Ok(item) = self.0.consume() {
+            items.push(item);
+        }
+        items
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.0.consumer.store(0, Release);
+        self.0.disconnect.notify();
+    }
+}
+
+unsafe impl<T> Send for Consumer<T> where T: Send { }
+
+// Producer ______________________________________
+
+/// A producer for a bounded MPSC lock-free queue.
+#[derive(Debug)]
+pub struct Producer<T>(Arc<Queue<T>>);
+
+impl<T> Producer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of the queue.
+    ///
+    /// This method returns `Err` if the queue is full or has no remaining consumers.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    /// Attempts to add the supplied item to the back of the queue, retrying with backoff while the
+    /// queue is full until the supplied deadline.
+    ///
+    /// This method returns `Timeout` with the item if the queue is still full once the deadline has
+    /// passed (e.g., so a pipeline can shed load) and `Disconnected` if the queue has no remaining
+    /// consumers.
+    pub fn produce_deadline(&self, item: T, deadline: Instant) -> Result<(), ProduceError<T>> {
+        ::produce_deadline(item, Some(deadline), |item| self.0.produce(item))
+    }
+
+    /// Adds the supplied item to the back of the queue, retrying with backoff while the queue is
+    /// full.
+    ///
+    /// This method only returns `Err` if the queue has no remaining consumers or has been closed.
+    /// The waiting thread spins, yields, and then sleeps briefly between attempts; the producers in
+    /// the `blocking` module sleep until they are woken by a consumer instead.
+    pub fn produce_blocking(&self, item: T) -> Result<(), ProduceError<T>> {
+        ::produce_deadline(item, None, |item| self.0.produce(item))
+    }
+
+    /// Moves items from the front of the supplied vector to the back of the queue until the vector
+    /// is empty or the queue is full and returns the number of items moved.
+    ///
+    /// The items that were not moved are left in the vector in their original order.
+    pub fn produce_many(&self, items: &mut Vec<T>) -> usize {
+        Produce::produce_many(self, items)
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the queue is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of items the queue can contain.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producers will receive `Disconnected` errors and the
+    /// consumer will receive a `Disconnected` error once the queue is empty, no matter how many
+    /// producers are still connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers or has been
+    /// closed.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        self.0.producers.fetch_add(1, Release);
+        Producer(self.0.clone())
+    }
+}
+
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.producers.fetch_sub(1, Release);
+        monitor::touch(&self.0.write);
+    }
+}
+
+unsafe impl<T> Send for Producer<T> where T: Send { }
+
+// Queue _________________________________________
+
+#[derive(Debug)]
+#[repr(C)]
+struct Queue<T> {
+    write: AtomicUsize,
+    consumer: AtomicUsize,
+    closed: AtomicBool,
+    _wpadding: [usize; padding(3)],
+    read: Cell<usize>,
+    /// The read position as last published by the consumer (for `len`).
+    consumed: AtomicUsize,
+    producers: AtomicUsize,
+    _rpadding: [usize; padding(3)],
+    buffer: Buffer<Slot<T>>,
+    disconnect: Notifier,
+}
+
+impl<T> Queue<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Queue` that uses a buffer of the supplied size.
+    fn try_new(size: usize) -> Result<Arc<Self>, ChannelError> {
+        // A slot that was just filled has the same sequence number as an empty slot would for the
+        // next write position when the buffer has one slot, so at least two slots are used.
+        let size = cmp::max(size, 2);
+        let buffer = Buffer::try_new(size)?;
+        for index in 0..size {
+            unsafe { buffer.set(index, Slot::new(index)); }
+        }
+        Ok(Arc::new(Queue {
+            write: AtomicUsize::new(0),
+            consumer: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
+            _wpadding: [0; padding(3)],
+            read: Cell::new(0),
+            consumed: AtomicUsize::new(0),
+            producers: AtomicUsize::new(1),
+            _rpadding: [0; padding(3)],
+            buffer,
+            disconnect: Notifier::new(),
+        }))
+    }
+
+    //- Accessors --------------------------------
+
+    fn len(&self) -> usize {
+        // The read position is loaded first so that it can't be ahead of the write position.
+        let read = self.consumed.load(Acquire);
+        let write = self.write.load(Acquire);
+        cmp::min(write.wrapping_sub(read), self.capacity())
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.size()
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let bytes = self.buffer.size() * mem::size_of::<Slot<T>>();
+        MemoryUsage { bytes, nodes: 0, retired: 0 }
+    }
+
+    /// Returns whether the producers have been disconnected or this queue has been closed.
+    fn is_disconnected(&self) -> bool {
+        self.producers.load(Acquire) == 0 || self.closed.load(Acquire)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Release);
+        monitor::touch(&self.write);
+        self.disconnect.notify();
+    }
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        // Return an error if the consumer has been disconnected or the queue has been closed.
+        if self.consumer.load(Acquire) == 0 || self.closed.load(Acquire) {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        loop {
+            let write = self.write.load(Relaxed);
+            let slot = unsafe { self.buffer.wrapping_get_ref(write) };
+            let sequence = slot.sequence.load(Acquire);
+            let difference = (sequence as isize).wrapping_sub(write as isize);
+
+            // Return an error if the queue is full.
+            if difference < 0 {
+                return Err(ProduceError::Full(item));
+            }
+
+            // Add the item to the back of the queue if this slot is available.
+            let next = write.wrapping_add(1);
+            if difference == 0 {
+                let result = self.write.compare_exchange_weak(write, next, Relaxed, Relaxed);
+                if result.is_ok() {
+                    unsafe { slot.set(item); }
+                    slot.sequence.store(next, Release);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn consume(&self) -> Result<T, ConsumeError> {
+        // The producers are checked first so that an item added by a producer before it was
+        // disconnected is never missed.
+        let disconnected = self.producers.load(Acquire) == 0 || self.closed.load(Acquire);
+
+        // Return an error if the queue is empty.
+        let read = self.read.get();
+        let slot = unsafe { self.buffer.wrapping_get_ref(read) };
+        let next = read.wrapping_add(1);
+        if slot.sequence.load(Acquire) != next {
+            if disconnected {
+                return Err(ConsumeError::Disconnected);
+            } else {
+                return Err(ConsumeError::Empty);
+            }
+        }
+
+        // Remove and return the item at the front of the queue.
+        let item = unsafe { slot.get() };
+        slot.sequence.store(next.wrapping_add(self.buffer.size() - 1), Release);
+        self.read.set(next);
+        self.consumed.store(next, Release);
+        Ok(item)
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        while self.consume().is_ok() { }
+    }
+}
+
+unsafe impl<T> Sync for Queue<T> where T: Send { }
+
+// Slot __________________________________________
+
+#[derive(Debug)]
+struct Slot<T> {
+    item: UnsafeCell<MaybeUninit<T>>,
+    sequence: AtomicUsize,
+}
+
+impl<T> Slot<T> {
+    //- Constructors -----------------------------
+
+    fn new(index: usize) -> Self {
+        Slot { item: UnsafeCell::new(MaybeUninit::uninit()), sequence: AtomicUsize::new(index) }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Moves the item out of this slot, leaving this slot uninitialized.
+    unsafe fn get(&self) -> T {
+        (*self.item.get()).as_ptr().read()
+    }
+
+    /// Moves the supplied item into this slot, which must be uninitialized.
+    unsafe fn set(&self, item: T) {
+        (*self.item.get()).as_mut_ptr().write(item);
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for a bounded MPSC lock-free queue.
+///
+/// # Panics
+///
+/// * `size` is not a power of two
+pub fn channel<T>(size: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(size.is_power_of_two(), "`size` is not a power of two");
+    try_channel(size).expect("failed to allocate queue")
+}
+
+/// Attempts to return a producer and consumer for a bounded MPSC lock-free queue.
+///
+/// This function returns `Err` instead of panicking or aborting if `size` is not a power of two
+/// or the buffer for the queue can't be allocated.
+pub fn try_channel<T>(size: usize) -> Result<(Producer<T>, Consumer<T>), ChannelError> {
+    if !size.is_power_of_two() {
+        return Err(ChannelError::InvalidCapacity);
+    }
+
+    let queue = Queue::try_new(size)?;
+    Ok((Producer(queue.clone()), Consumer(queue)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consume_wait() {
+        use std::thread;
+
+        let (producer, consumer) = channel(2);
+        let thread = thread::spawn(move || {
+            let items = (0..4).map(|_| consumer.consume_wait()).collect::<Vec<_>>();
+            (items, consumer.consume_wait())
+        });
+        for item in 0..4 {
+            producer.produce_blocking(item).unwrap();
+        }
+        drop(producer);
+        let (items, result) = thread.join().unwrap();
+        assert_eq!(items, (0..4).map(Ok).collect::<Vec<_>>());
+        assert_eq!(result, Err(ConsumeError::Disconnected));
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded single-producer, multi-consumer queue.
+//!
+//! The consumers claim slots stamped with sequence numbers with a compare-and-swap like the
+//! `Vyukov` algorithm of the bounded MPMC queue. Since there is only one producer, the write
+//! position is only ever updated by the producer, so adding an item is wait-free.
+//!
+//! Each position is given two sequence numbers, an even one for when the slot is empty and an odd
+//! one for when the slot is full, so that a queue with a single slot can tell a slot that was just
+//! filled apart from a slot that is ready for the next write position.
+
+use std::cmp;
+use std::mem::{self, MaybeUninit};
+use std::cell::{UnsafeCell};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration, Instant};
+
+use {ChannelError, Consume, ConsumeError, MemoryUsage, Produce, ProduceError, padding};
+use buffer::{Buffer};
+use monitor;
+use notify::{Notifier};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for a bounded SPMC queue.
+#[derive(Debug)]
+pub struct Consumer<T>(Arc<Queue<T>>);
+
+impl<T> Consumer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the front of the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting in a low-power state while
+    /// the queue is empty.
+    ///
+    /// On x86-64 processors that support `umonitor` and `umwait` and on AArch64 processors, this
+    /// consumer waits for the cacheline containing the back of the queue to be written to instead
+    /// of spinning, which has nearly the latency of spinning at a fraction of the power. On other
+    /// processors, this consumer spins. This method only returns `Err` if the queue is empty and
+    /// the producer has been disconnected or the queue has been closed.
+    pub fn consume_wait(&self) -> Result<T, ConsumeError> {
+        loop {
+            match self.0.consume() {
+                Err(ConsumeError::Empty) => monitor::wait(&self.0.write, |write| {
+                    write != self.0.read.load(Acquire) || self.0.is_disconnected()
+                }),
+                result => return result,
+            }
+        }
+    }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        Consume::consume_uninit(self, items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        Consume::consume_latest(self)
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        Consume::discard(self, n)
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the queue is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of items the queue can contain.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.0.consumers.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producer will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty, no matter how many
+    /// consumers are still connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Disconnects the producer and returns the items remaining in the queue.
+    ///
+    /// Once this method has been called, the producer will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty. An item the producer
+    /// was in the middle of adding when this method was called may be left in the queue.
+    pub fn close_and_drain(&self) -> Vec<T> {
+        self.0.close();
+        let mut items = vec![];
+        while let Ok(item) = self.0.consume() {
+            items.push(item);
+        }
+        items
+    }
+}
+
+impl<T> Clone for Consumer<T> {
+    fn clone(&self) -> Self {
+        self.0.consumers.fetch_add(1, Release);
+        Consumer(self.0.clone())
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        if self.0.consumers.fetch_sub(1, Release) == 1 {
+            self.0.disconnect.notify();
+        }
+    }
+}
+
+unsafe impl<T> Send for Consumer<T> where T: Send { }
+
+// Producer ______________________________________
+
+/// A producer for a bounded SPMC queue.
+#[derive(Debug)]
+pub struct Producer<T>(Arc<Queue<T>>);
+
+impl<T> Producer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of the queue.
+    ///
+    /// This method returns `Err` if the queue is full or has no remaining consumers.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    /// Attempts to add the supplied item to the back of the queue, retrying with backoff while the
+    /// queue is full until the supplied deadline.
+    ///
+    /// This method returns `Timeout` with the item if the queue is still full once the deadline has
+    /// passed (e.g., so a pipeline can shed load) and `Disconnected` if the queue has no remaining
+    /// consumers.
+    pub fn produce_deadline(&self, item: T, deadline: Instant) -> Result<(), ProduceError<T>> {
+        ::produce_deadline(item, Some(deadline), |item| self.0.produce(item))
+    }
+
+    /// Adds the supplied item to the back of the queue, retrying with backoff while the queue is
+    /// full.
+    ///
+    /// This method only returns `Err` if the queue has no remaining consumers or has been closed.
+    /// The waiting thread spins, yields, and then sleeps briefly between attempts; the producers in
+    /// the `blocking` module sleep until they are woken by a consumer instead.
+    pub fn produce_blocking(&self, item: T) -> Result<(), ProduceError<T>> {
+        ::produce_deadline(item, None, |item| self.0.produce(item))
+    }
+
+    /// Moves items from the front of the supplied vector to the back of the queue until the vector
+    /// is empty or the queue is full and returns the number of items moved.
+    ///
+    /// The items that were not moved are left in the vector in their original order.
+    pub fn produce_many(&self, items: &mut Vec<T>) -> usize {
+        Produce::produce_many(self, items)
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the queue is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of items the queue can contain.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.0.consumers.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producer will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty, no matter how many
+    /// consumers are still connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers or has been
+    /// closed.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
+}
+
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.producer.store(0, Release);
+        monitor::touch(&self.0.write);
+    }
+}
+
+unsafe impl<T> Send for Producer<T> where T: Send { }
+
+// Queue _________________________________________
+
+#[derive(Debug)]
+#[repr(C)]
+struct Queue<T> {
+    write: AtomicUsize,
+    consumers: AtomicUsize,
+    closed: AtomicBool,
+    _wpadding: [usize; padding(3)],
+    read: AtomicUsize,
+    producer: AtomicUsize,
+    _rpadding: [usize; padding(2)],
+    buffer: Buffer<Slot<T>>,
+    disconnect: Notifier,
+}
+
+impl<T> Queue<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Queue` that uses a buffer of the supplied size.
+    fn try_new(size: usize) -> Result<Arc<Self>, ChannelError> {
+        let buffer = Buffer::try_new(size)?;
+        for index in 0..size {
+            unsafe { buffer.set(index, Slot::new(index)); }
+        }
+        Ok(Arc::new(Queue {
+            write: AtomicUsize::new(0),
+            consumers: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
+            _wpadding: [0; padding(3)],
+            read: AtomicUsize::new(0),
+            producer: AtomicUsize::new(1),
+            _rpadding: [0; padding(2)],
+            buffer,
+            disconnect: Notifier::new(),
+        }))
+    }
+
+    //- Accessors --------------------------------
+
+    fn len(&self) -> usize {
+        // The read position is loaded first so that it can't be ahead of the write position.
+        let read = self.read.load(Acquire);
+        let write = self.write.load(Acquire);
+        cmp::min(write.wrapping_sub(read), self.capacity())
+    }
+
+    fn capacity(&self) -> usize {
+        self.buffer.size()
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let bytes = self.buffer.size() * mem::size_of::<Slot<T>>();
+        MemoryUsage { bytes, nodes: 0, retired: 0 }
+    }
+
+    /// Returns whether the producer has been disconnected or this queue has been closed.
+    fn is_disconnected(&self) -> bool {
+        self.producer.load(Acquire) == 0 || self.closed.load(Acquire)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Release);
+        monitor::touch(&self.write);
+        self.disconnect.notify();
+    }
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        // Return an error if the consumers have been disconnected or the queue has been closed.
+        if self.consumers.load(Acquire) == 0 || self.closed.load(Acquire) {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        // Return an error if the queue is full. The slot at the write position is still waiting
+        // for a consumer to remove the item added to it one lap ago.
+        let write = self.write.load(Relaxed);
+        let slot = unsafe { self.buffer.wrapping_get_ref(write) };
+        if slot.sequence.load(Acquire) != empty(write) {
+            return Err(ProduceError::Full(item));
+        }
+
+        // Add the item to the back of the queue.
+        let next = write.wrapping_add(1);
+        unsafe { slot.set(item); }
+        slot.sequence.store(full(write), Release);
+        self.write.store(next, Release);
+        Ok(())
+    }
+
+    fn consume(&self) -> Result<T, ConsumeError> {
+        // The producer is checked first so that an item added by the producer before it was
+        // disconnected is never missed.
+        let disconnected = self.producer.load(Acquire) == 0 || self.closed.load(Acquire);
+
+        loop {
+            let read = self.read.load(Relaxed);
+            let slot = unsafe { self.buffer.wrapping_get_ref(read) };
+            let sequence = slot.sequence.load(Acquire);
+            let difference = (sequence as isize).wrapping_sub(full(read) as isize);
+
+            // Return an error if the queue is empty.
+            if difference < 0 {
+                if disconnected {
+                    return Err(ConsumeError::Disconnected);
+                } else {
+                    return Err(ConsumeError::Empty);
+                }
+            }
+
+            // Remove and return the item at the front of the queue if this slot is available.
+            let next = read.wrapping_add(1);
+            if difference == 0 {
+                let result = self.read.compare_exchange_weak(read, next, Relaxed, Relaxed);
+                if result.is_ok() {
+                    let item = unsafe { slot.get() };
+                    slot.sequence.store(empty(read.wrapping_add(self.buffer.size())), Release);
+                    return Ok(item);
+                }
+            }
+        }
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        while self.consume().is_ok() { }
+    }
+}
+
+unsafe impl<T> Sync for Queue<T> where T: Send { }
+
+// Slot __________________________________________
+
+#[derive(Debug)]
+struct Slot<T> {
+    item: UnsafeCell<MaybeUninit<T>>,
+    sequence: AtomicUsize,
+}
+
+impl<T> Slot<T> {
+    //- Constructors -----------------------------
+
+    fn new(index: usize) -> Self {
+        let sequence = AtomicUsize::new(empty(index));
+        Slot { item: UnsafeCell::new(MaybeUninit::uninit()), sequence }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Moves the item out of this slot, leaving this slot uninitialized.
+    unsafe fn get(&self) -> T {
+        (*self.item.get()).as_ptr().read()
+    }
+
+    /// Moves the supplied item into this slot, which must be uninitialized.
+    unsafe fn set(&self, item: T) {
+        (*self.item.get()).as_mut_ptr().write(item);
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns the sequence number of a slot that is ready for an item at the supplied position.
+fn empty(position: usize) -> usize {
+    position.wrapping_mul(2)
+}
+
+/// Returns the sequence number of a slot that holds the item at the supplied position.
+fn full(position: usize) -> usize {
+    position.wrapping_mul(2).wrapping_add(1)
+}
+
+/// Returns a producer and consumer for a bounded SPMC queue.
+///
+/// # Panics
+///
+/// * `size` is not a power of two
+pub fn channel<T>(size: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(size.is_power_of_two(), "`size` is not a power of two");
+    try_channel(size).expect("failed to allocate queue")
+}
+
+/// Attempts to return a producer and consumer for a bounded SPMC queue.
+///
+/// This function returns `Err` instead of panicking or aborting if `size` is not a power of two
+/// or the buffer for the queue can't be allocated.
+pub fn try_channel<T>(size: usize) -> Result<(Producer<T>, Consumer<T>), ChannelError> {
+    if !size.is_power_of_two() {
+        return Err(ChannelError::InvalidCapacity);
+    }
+
+    let queue = Queue::try_new(size)?;
+    Ok((Producer(queue.clone()), Consumer(queue)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_slot() {
+        let (producer, consumer) = channel(1);
+        assert_eq!(producer.capacity(), 1);
+        for item in 0..4 {
+            producer.produce(item).unwrap();
+            assert_eq!(producer.produce(item + 1), Err(ProduceError::Full(item + 1)));
+            assert_eq!(consumer.consume(), Ok(item));
+            assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        }
+    }
+
+    #[test]
+    fn test_wrap() {
+        let (producer, consumer) = channel(4);
+        let other = consumer.clone();
+        for lap in 0..3 {
+            for item in 0..4 {
+                producer.produce(lap * 4 + item).unwrap();
+            }
+            assert_eq!(producer.produce(0), Err(ProduceError::Full(0)));
+            assert_eq!(consumer.consume(), Ok(lap * 4));
+            assert_eq!(other.consume(), Ok(lap * 4 + 1));
+            assert_eq!(consumer.consume(), Ok(lap * 4 + 2));
+            assert_eq!(other.consume(), Ok(lap * 4 + 3));
+            assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        }
+        drop(producer);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_consume_wait() {
+        use std::thread;
+
+        let (producer, consumer) = channel(2);
+        let thread = thread::spawn(move || {
+            let items = (0..4).map(|_| consumer.consume_wait()).collect::<Vec<_>>();
+            (items, consumer.consume_wait())
+        });
+        for item in 0..4 {
+            producer.produce_blocking(item).unwrap();
+        }
+        drop(producer);
+        let (items, result) = thread.join().unwrap();
+        assert_eq!(items, (0..4).map(Ok).collect::<Vec<_>>());
+        assert_eq!(result, Err(ConsumeError::Disconnected));
+    }
+}
@@ -14,18 +14,86 @@
 
 //! Bounded single-producer, single-consumer wait-free queue.
 
+use std::cmp;
+use std::marker::{PhantomData};
+use std::mem::{self, MaybeUninit};
 use std::cell::{Cell};
 use std::sync::{Arc};
-use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::atomic::Ordering::*;
+use std::time::{Duration, Instant};
 
-use {ConsumeError, ProduceError, POINTERS};
+use {ChannelError, Consume, ConsumeError, MemoryUsage, Produce, ProduceError, padding};
 use buffer::{Buffer};
+use monitor;
+use notify::{Notifier};
 
 //================================================
 // Structs
 //================================================
 
+// Builder _______________________________________
+
+/// A builder for a bounded SPSC wait-free queue.
+#[derive(Debug)]
+pub struct Builder<T> {
+    capacity: usize,
+    exact: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Builder<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Builder` for a queue that can contain the supplied number of items.
+    ///
+    /// The capacity must be a power of two unless `exact` is called.
+    pub fn new(capacity: usize) -> Self {
+        Builder { capacity, exact: false, _marker: PhantomData }
+    }
+
+    //- Consumers --------------------------------
+
+    /// Allows a capacity that is not a power of two.
+    ///
+    /// The buffer used by the queue is rounded up to the next power of two, but the producer will
+    /// receive `Full` errors once the queue contains the requested number of items.
+    pub fn exact(self) -> Self {
+        Builder { exact: true, ..self }
+    }
+
+    /// Returns a producer and consumer for the queue.
+    ///
+    /// # Panics
+    ///
+    /// * the capacity is zero
+    /// * the capacity is not a power of two and `exact` was not called
+    pub fn build(self) -> (Producer<T>, Consumer<T>) {
+        assert!(self.capacity != 0, "`capacity` is zero");
+        if !self.exact {
+            assert!(self.capacity.is_power_of_two(), "`capacity` is not a power of two");
+        }
+        self.try_build().expect("failed to allocate queue")
+    }
+
+    /// Attempts to return a producer and consumer for the queue.
+    ///
+    /// This method returns `Err` instead of panicking or aborting if the capacity is not valid or
+    /// the buffer for the queue can't be allocated.
+    pub fn try_build(self) -> Result<(Producer<T>, Consumer<T>), ChannelError> {
+        let size = if self.exact && self.capacity != 0 {
+            self.capacity.checked_next_power_of_two().ok_or(ChannelError::Overflow)?
+        } else if self.capacity.is_power_of_two() {
+            self.capacity
+        } else {
+            return Err(ChannelError::InvalidCapacity);
+        };
+
+        let queue = Queue::with_buffer(Buffer::try_new(size)?, self.capacity);
+        Ok((Producer(queue.clone()), Consumer(queue)))
+    }
+}
+
 // Consumer ______________________________________
 
 /// A consumer for a bounded SPSC wait-free queue.
@@ -42,6 +110,55 @@ impl<T> Consumer<T> {
         self.0.consume()
     }
 
+    /// Removes and returns the item at the front of the queue, waiting in a low-power state while
+    /// the queue is empty.
+    ///
+    /// On x86-64 processors that support `umonitor` and `umwait` and on AArch64 processors, this
+    /// consumer waits for the cacheline containing the back of the queue to be written to instead
+    /// of spinning, which has nearly the latency of spinning at a fraction of the power. On other
+    /// processors, this consumer spins. This method only returns `Err` if the queue is empty and
+    /// the producer has been disconnected or the queue has been closed.
+    pub fn consume_wait(&self) -> Result<T, ConsumeError> {
+        loop {
+            match self.0.consume() {
+                Err(ConsumeError::Empty) => monitor::wait(&self.0.write, |write| {
+                    write != self.0.write_copy.get() || self.0.is_disconnected()
+                }),
+                result => return result,
+            }
+        }
+    }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        self.0.consume_uninit(items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        self.0.consume_latest()
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        self.0.discard(n)
+    }
+
     /// Returns the number of items currently in the queue.
     pub fn len(&self) -> usize {
         self.0.len()
@@ -56,11 +173,63 @@ impl<T> Consumer<T> {
     pub fn capacity(&self) -> usize {
         self.0.capacity()
     }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producer will receive `Disconnected` errors and the
+    /// consumer will receive a `Disconnected` error once the queue is empty, even if both are still
+    /// connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Disconnects the producer and returns the items remaining in the queue.
+    ///
+    /// Once this method has been called, the producer will receive `Disconnected` errors and this
+    /// consumer will receive a `Disconnected` error once the queue is empty. An item the producer
+    /// was in the middle of adding when this method was called may be left in the queue.
+    pub fn close_and_drain(&self) -> Vec<T> {
+        self.0.close();
+        let mut items = vec![];
+        while let Ok(item) = self.0.consume() {
+            items.push(item);
+        }
+        items
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+
+    fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        self.0.consume_uninit(items)
+    }
+
+    fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        self.0.consume_latest()
+    }
+
+    fn discard(&self, n: usize) -> usize {
+        self.0.discard(n)
+    }
 }
 
 impl<T> Drop for Consumer<T> {
     fn drop(&mut self) {
         self.0.consumer.store(0, Release);
+        self.0.disconnect.notify();
     }
 }
 
@@ -82,6 +251,34 @@ impl<T> Producer<T> {
         self.0.produce(item)
     }
 
+    /// Attempts to add the supplied item to the back of the queue, retrying with backoff while the
+    /// queue is full until the supplied deadline.
+    ///
+    /// This method returns `Timeout` with the item if the queue is still full once the deadline has
+    /// passed (e.g., so a pipeline can shed load) and `Disconnected` if the queue has no remaining
+    /// consumers.
+    pub fn produce_deadline(&self, item: T, deadline: Instant) -> Result<(), ProduceError<T>> {
+        ::produce_deadline(item, Some(deadline), |item| self.0.produce(item))
+    }
+
+    /// Adds the supplied item to the back of the queue, retrying with backoff while the queue is
+    /// full.
+    ///
+    /// This method only returns `Err` if the queue has no remaining consumers or has been closed.
+    /// The waiting thread spins, yields, and then sleeps briefly between attempts; the producers in
+    /// the `blocking` module sleep until they are woken by a consumer instead.
+    pub fn produce_blocking(&self, item: T) -> Result<(), ProduceError<T>> {
+        ::produce_deadline(item, None, |item| self.0.produce(item))
+    }
+
+    /// Moves items from the front of the supplied vector to the back of the queue until the vector
+    /// is empty or the queue is full and returns the number of items moved.
+    ///
+    /// The items that were not moved are left in the vector in their original order.
+    pub fn produce_many(&self, items: &mut Vec<T>) -> usize {
+        self.0.produce_many(items)
+    }
+
     /// Returns the number of items currently in the queue.
     pub fn len(&self) -> usize {
         self.0.len()
@@ -96,11 +293,50 @@ impl<T> Producer<T> {
     pub fn capacity(&self) -> usize {
         self.0.capacity()
     }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producer will receive `Disconnected` errors and the
+    /// consumer will receive a `Disconnected` error once the queue is empty, even if both are still
+    /// connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers or has been
+    /// closed.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
+}
+
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    fn produce_many(&self, items: &mut Vec<T>) -> usize {
+        self.0.produce_many(items)
+    }
 }
 
 impl<T> Drop for Producer<T> {
     fn drop(&mut self) {
         self.0.producer.store(0, Release);
+        monitor::touch(&self.0.write);
     }
 }
 
@@ -114,28 +350,36 @@ struct Queue<T> {
     write: AtomicUsize,
     read_copy: Cell<usize>,
     consumer: AtomicUsize,
-    _wpadding: [usize; POINTERS - 3],
+    closed: AtomicBool,
+    _wpadding: [usize; padding(4)],
     read: AtomicUsize,
     write_copy: Cell<usize>,
     producer: AtomicUsize,
-    _rpadding: [usize; POINTERS - 3],
+    _rpadding: [usize; padding(3)],
     buffer: Buffer<T>,
+    capacity: usize,
+    disconnect: Notifier,
 }
 
 impl<T> Queue<T> {
     //- Constructors -----------------------------
 
-    fn new(size: usize) -> Arc<Self> {
+    /// Constructs a new `Queue` that uses the supplied buffer and can contain up to the supplied
+    /// number of items.
+    fn with_buffer(buffer: Buffer<T>, capacity: usize) -> Arc<Self> {
         Arc::new(Queue {
             write: AtomicUsize::new(0),
             read_copy: Cell::new(0),
             consumer: AtomicUsize::new(1),
-            _wpadding: [0; POINTERS - 3],
+            closed: AtomicBool::new(false),
+            _wpadding: [0; padding(4)],
             read: AtomicUsize::new(0),
             write_copy: Cell::new(0),
             producer: AtomicUsize::new(1),
-            _rpadding: [0; POINTERS - 3],
-            buffer: Buffer::new(size),
+            _rpadding: [0; padding(3)],
+            buffer,
+            capacity,
+            disconnect: Notifier::new(),
         })
     }
 
@@ -146,20 +390,36 @@ impl<T> Queue<T> {
     }
 
     fn capacity(&self) -> usize {
-        self.buffer.size()
+        self.capacity
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let bytes = self.buffer.size() * mem::size_of::<T>();
+        MemoryUsage { bytes, nodes: 0, retired: 0 }
+    }
+
+    /// Returns whether the producer has been disconnected or this queue has been closed.
+    fn is_disconnected(&self) -> bool {
+        self.producer.load(Acquire) == 0 || self.closed.load(Acquire)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Release);
+        monitor::touch(&self.write);
+        self.disconnect.notify();
     }
 
     fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
-        // Return an error if the consumer has been disconnected.
-        if self.consumer.load(Acquire) == 0 {
+        // Return an error if the consumer has been disconnected or the queue has been closed.
+        if self.consumer.load(Acquire) == 0 || self.closed.load(Acquire) {
             return Err(ProduceError::Disconnected(item));
         }
 
         // Return an error if the queue is full.
         let write = self.write.load(Acquire);
-        if write.wrapping_sub(self.read_copy.get()) == self.buffer.size() {
+        if write.wrapping_sub(self.read_copy.get()) >= self.capacity {
             self.read_copy.set(self.read.load(Acquire));
-            if write.wrapping_sub(self.read_copy.get()) == self.buffer.size() {
+            if write.wrapping_sub(self.read_copy.get()) >= self.capacity {
                 return Err(ProduceError::Full(item));
             }
         }
@@ -170,13 +430,33 @@ impl<T> Queue<T> {
         Ok(())
     }
 
+    fn produce_many(&self, items: &mut Vec<T>) -> usize {
+        if self.consumer.load(Acquire) == 0 || self.closed.load(Acquire) {
+            return 0;
+        }
+
+        // Add as many items as there is room for at once.
+        let write = self.write.load(Acquire);
+        self.read_copy.set(self.read.load(Acquire));
+        let free = self.capacity - write.wrapping_sub(self.read_copy.get());
+        let count = cmp::min(free, items.len());
+        for (index, item) in items.drain(..count).enumerate() {
+            unsafe { self.buffer.wrapping_set(write.wrapping_add(index), item); }
+        }
+        self.write.store(write.wrapping_add(count), Release);
+        count
+    }
+
     fn consume(&self) -> Result<T, ConsumeError> {
         // Return an error if the queue is empty.
         let read = self.read.load(Acquire);
         if read == self.write_copy.get() {
+            // The producer is checked first so that an item added by the producer before it was
+            // disconnected is never missed.
+            let disconnected = self.producer.load(Acquire) == 0 || self.closed.load(Acquire);
             self.write_copy.set(self.write.load(Acquire));
             if read == self.write_copy.get() {
-                if self.producer.load(Acquire) == 0 {
+                if disconnected {
                     return Err(ConsumeError::Disconnected);
                 } else {
                     return Err(ConsumeError::Empty);
@@ -189,6 +469,40 @@ impl<T> Queue<T> {
         self.read.store(read.wrapping_add(1), Release);
         Ok(item)
     }
+
+    fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        // Remove as many items as are available at once.
+        let read = self.read.load(Acquire);
+        self.write_copy.set(self.write.load(Acquire));
+        let count = cmp::min(self.write_copy.get().wrapping_sub(read), items.len());
+        for (index, slot) in items[..count].iter_mut().enumerate() {
+            *slot = MaybeUninit::new(unsafe { self.buffer.wrapping_get(read.wrapping_add(index)) });
+        }
+        self.read.store(read.wrapping_add(count), Release);
+        count
+    }
+
+    fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        // Only the items in the queue when this method is called are removed so that a producer
+        // can't keep this method from returning.
+        let skipped = self.len().saturating_sub(1);
+        for _ in 0..skipped {
+            self.consume()?;
+        }
+        self.consume().map(|item| (item, skipped))
+    }
+
+    fn discard(&self, n: usize) -> usize {
+        // Remove as many items as are available at once.
+        let read = self.read.load(Acquire);
+        self.write_copy.set(self.write.load(Acquire));
+        let count = cmp::min(self.write_copy.get().wrapping_sub(read), n);
+        for index in 0..count {
+            drop(unsafe { self.buffer.wrapping_get(read.wrapping_add(index)) });
+        }
+        self.read.store(read.wrapping_add(count), Release);
+        count
+    }
 }
 
 impl<T> Drop for Queue<T> {
@@ -209,7 +523,49 @@ unsafe impl<T> Sync for Queue<T> where T: Send { }
 ///
 /// * `size` is not a power of two
 pub fn channel<T>(size: usize) -> (Producer<T>, Consumer<T>) {
-    assert!(size.is_power_of_two(), "`size` is not a power of two");
-    let queue = Queue::new(size);
-    (Producer(queue.clone()), Consumer(queue))
+    Builder::new(size).build()
+}
+
+/// Attempts to return a producer and consumer for a bounded SPSC wait-free queue.
+///
+/// This function returns `Err` instead of panicking or aborting if `size` is not a power of two
+/// or the buffer for the queue can't be allocated.
+pub fn try_channel<T>(size: usize) -> Result<(Producer<T>, Consumer<T>), ChannelError> {
+    Builder::new(size).try_build()
+}
+
+/// Returns a producer and consumer for a bounded SPSC wait-free queue that can contain exactly
+/// `capacity` items.
+///
+/// The buffer used by the queue is rounded up to the next power of two, but producers will
+/// receive `Full` errors once the queue contains `capacity` items.
+///
+/// # Panics
+///
+/// * `capacity` is zero
+pub fn channel_exact<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    Builder::new(capacity).exact().build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consume_wait() {
+        use std::thread;
+
+        let (producer, consumer) = channel(2);
+        let thread = thread::spawn(move || {
+            let items = (0..4).map(|_| consumer.consume_wait()).collect::<Vec<_>>();
+            (items, consumer.consume_wait())
+        });
+        for item in 0..4 {
+            producer.produce_blocking(item).unwrap();
+        }
+        drop(producer);
+        let (items, result) = thread.join().unwrap();
+        assert_eq!(items, (0..4).map(Ok).collect::<Vec<_>>());
+        assert_eq!(result, Err(ConsumeError::Disconnected));
+    }
 }
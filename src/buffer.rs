@@ -14,6 +14,9 @@
 
 use std::mem;
 use std::ptr;
+use std::alloc::{self, Layout};
+
+use {ChannelError};
 
 //================================================
 // Structs
@@ -31,13 +34,17 @@ pub struct Buffer<T> {
 impl<T> Buffer<T> {
     //- Constructors -----------------------------
 
-    /// Constructs a new `Buffer`,
-    pub fn new(size: usize) -> Self {
+    /// Attempts to construct a new `Buffer`.
+    ///
+    /// This method returns `Err` instead of panicking or aborting if the buffer is too large or
+    /// can't be allocated.
+    pub fn try_new(size: usize) -> Result<Self, ChannelError> {
         assert!(size.is_power_of_two());
-        let mut vec = Vec::with_capacity(size);
-        let data = vec.as_mut_ptr();
-        mem::forget(vec);
-        Buffer { data: data, size: size }
+        try_vec::<T>(size).map(|mut vec| {
+            let data = vec.as_mut_ptr();
+            mem::forget(vec);
+            Buffer { data, size }
+        })
     }
 
     //- Accessors --------------------------------
@@ -49,9 +56,7 @@ impl<T> Buffer<T> {
 
     /// Returns the item at the supplied index in this buffer.
     pub unsafe fn get(&self, index: usize) -> T {
-        let mut item = mem::uninitialized();
-        ptr::swap(self.data.offset(index as isize), &mut item);
-        item
+        ptr::read(self.data.add(index))
     }
 
     /// Returns the item at the supplied index in this buffer after wrapping the index.
@@ -85,3 +90,37 @@ impl<T> Drop for Buffer<T> {
         unsafe { Vec::from_raw_parts(self.data, 0, self.size); }
     }
 }
+
+//================================================
+// Functions
+//================================================
+
+/// Attempts to move the supplied value into a new allocation that can be freed as a `Box`.
+///
+/// This function returns the value instead of aborting if the allocation fails.
+pub fn try_box<T>(value: T) -> Result<*mut T, T> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        return Ok(Box::into_raw(Box::new(value)));
+    }
+
+    let pointer = unsafe { alloc::alloc(layout) as *mut T };
+    if pointer.is_null() {
+        return Err(value);
+    }
+
+    unsafe { ptr::write(pointer, value); }
+    Ok(pointer)
+}
+
+/// Attempts to allocate an empty vector with the supplied capacity.
+pub fn try_vec<T>(capacity: usize) -> Result<Vec<T>, ChannelError> {
+    match capacity.checked_mul(mem::size_of::<T>()) {
+        Some(bytes) if bytes <= isize::MAX as usize => { },
+        _ => return Err(ChannelError::Overflow),
+    }
+
+    let mut vec = vec![];
+    vec.try_reserve_exact(capacity).map_err(|_| ChannelError::AllocationFailure)?;
+    Ok(vec)
+}
@@ -0,0 +1,388 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unbounded multi-producer, multi-consumer lock-free queue for `Bytes` buffers that is bounded by
+//! the number of bytes in flight.
+//!
+//! Only the buffer handles are moved through the queue, so the payloads are never copied. A
+//! producer receives a `Full` error if adding a buffer would put more than the limit of bytes in
+//! the queue. A buffer larger than the limit is still accepted when the queue is empty so that it
+//! can't be rejected forever.
+//!
+//! `Producer::write_vectored` gathers the contents of several `IoSlice`s into a single buffer and
+//! `Consumer::read_vectored` scatters the contents of buffers into several `IoSliceMut`s, so code
+//! that uses vectored IO (e.g., `readv` and `writev` on sockets) doesn't need a separate buffer to
+//! coalesce its slices. A buffer that does not fit in the slices supplied to `read_vectored` is
+//! kept by the consumer and the rest of it is returned first by the next read.
+//!
+//! This module requires the `bytes` feature, which also implements `MemSized` for `Bytes` and
+//! `BytesMut`.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate bytes;
+//! extern crate npnc;
+//!
+//! use bytes::{Bytes};
+//!
+//! fn main() {
+//!     let (producer, consumer) = npnc::bytes::channel(1024, 0);
+//!
+//!     producer.produce(Bytes::from(vec![0u8; 768])).unwrap();
+//!     assert!(producer.produce(Bytes::from(vec![0u8; 512])).is_err());
+//!     assert_eq!(producer.in_flight(), 768);
+//!
+//!     assert_eq!(consumer.consume().map(|b| b.len()), Ok(768));
+//!     assert_eq!(consumer.in_flight(), 0);
+//! }
+//! ```
+
+extern crate bytes;
+
+use std::cmp;
+use std::fmt;
+use std::cell::{Cell};
+use std::io::{IoSlice, IoSliceMut};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use self::bytes::{Bytes, BytesMut};
+
+use {Consume, ConsumeError, Produce, ProduceError};
+use sized::{MemSized};
+use unbounded::mpmc;
+
+//================================================
+// Structs
+//================================================
+
+// Budget ________________________________________
+
+#[derive(Debug)]
+struct Budget {
+    in_flight: AtomicUsize,
+    limit: usize,
+}
+
+impl Budget {
+    //- Accessors --------------------------------
+
+    /// Attempts to reserve the supplied number of bytes.
+    fn acquire(&self, bytes: usize) -> bool {
+        let mut current = self.in_flight.load(Relaxed);
+        loop {
+            if current != 0 && current.saturating_add(bytes) > self.limit {
+                return false;
+            }
+
+            match self.in_flight.compare_exchange_weak(current, current + bytes, Relaxed, Relaxed) {
+                Ok(_) => return true,
+                Err(value) => current = value,
+            }
+        }
+    }
+
+    /// Releases the supplied number of bytes.
+    fn release(&self, bytes: usize) {
+        self.in_flight.fetch_sub(bytes, Relaxed);
+    }
+}
+
+// Consumer ______________________________________
+
+/// A consumer for an unbounded MPMC lock-free queue for `Bytes` buffers.
+pub struct Consumer {
+    consumer: mpmc::Consumer<Bytes>,
+    budget: Arc<Budget>,
+    partial: Cell<Option<Bytes>>,
+}
+
+impl Consumer {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the buffer at the front of the queue.
+    ///
+    /// The rest of a buffer that was partially read by `read_vectored` is returned first. This
+    /// method returns `Err` if the queue is empty.
+    pub fn consume(&self) -> Result<Bytes, ConsumeError> {
+        if let Some(bytes) = self.partial.take() {
+            return Ok(bytes);
+        }
+
+        let bytes = self.consumer.consume()?;
+        self.budget.release(bytes.len());
+        Ok(bytes)
+    }
+
+    /// Removes buffers from the front of the queue and copies their contents into the supplied
+    /// slices until the slices are full or the queue is empty and returns the number of bytes
+    /// copied.
+    ///
+    /// The rest of a buffer that does not fit in the slices is kept by this consumer and returned
+    /// first by the next call to this method or `consume`. This method returns `Err` if the queue
+    /// is empty before any bytes are copied.
+    pub fn read_vectored(&self, bufs: &mut [IoSliceMut]) -> Result<usize, ConsumeError> {
+        let mut copied = 0;
+        for buf in bufs.iter_mut() {
+            let mut offset = 0;
+            while offset < buf.len() {
+                let mut bytes = match self.consume() {
+                    Ok(bytes) => bytes,
+                    Err(error) if copied == 0 => return Err(error),
+                    Err(_) => return Ok(copied),
+                };
+
+                let len = cmp::min(bytes.len(), buf.len() - offset);
+                buf[offset..offset + len].copy_from_slice(&bytes[..len]);
+                offset += len;
+                copied += len;
+                if len < bytes.len() {
+                    self.partial.set(Some(bytes.split_off(len)));
+                }
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Returns the length of the rest of the buffer that was partially read by `read_vectored`.
+    ///
+    /// These bytes are no longer counted as in flight.
+    pub fn partial_len(&self) -> usize {
+        let partial = self.partial.take();
+        let len = partial.as_ref().map_or(0, |b| b.len());
+        self.partial.set(partial);
+        len
+    }
+
+    /// Returns the total length of the buffers currently in the queue.
+    pub fn in_flight(&self) -> usize {
+        self.budget.in_flight.load(Relaxed)
+    }
+
+    /// Returns the maximum total length of the buffers the queue can contain.
+    pub fn limit(&self) -> usize {
+        self.budget.limit
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &mpmc::Consumer<Bytes> {
+        &self.consumer
+    }
+}
+
+impl Clone for Consumer {
+    fn clone(&self) -> Self {
+        let budget = self.budget.clone();
+        Consumer { consumer: self.consumer.clone(), budget, partial: Cell::new(None) }
+    }
+}
+
+impl Consume<Bytes> for Consumer {
+    fn consume(&self) -> Result<Bytes, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+impl fmt::Debug for Consumer {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("Consumer")
+            .field("consumer", &self.consumer)
+            .field("budget", &self.budget)
+            .field("partial", &self.partial_len())
+            .finish()
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer for an unbounded MPMC lock-free queue for `Bytes` buffers.
+#[derive(Debug)]
+pub struct Producer {
+    producer: mpmc::Producer<Bytes>,
+    budget: Arc<Budget>,
+}
+
+impl Producer {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied buffer to the back of the queue.
+    ///
+    /// This method returns `Err` if the queue has no remaining consumers or if adding the buffer
+    /// would put more than the limit of bytes in the queue.
+    pub fn produce(&self, bytes: Bytes) -> Result<(), ProduceError<Bytes>> {
+        if self.producer.consumer_count() == 0 {
+            return Err(ProduceError::Disconnected(bytes));
+        }
+
+        // The buffer is counted before it is added so that the count never underflows.
+        let len = bytes.len();
+        if !self.budget.acquire(len) {
+            return Err(ProduceError::Full(bytes));
+        }
+        self.producer.produce(bytes).inspect_err(|_| self.budget.release(len))
+    }
+
+    /// Attempts to freeze the supplied buffer and add it to the back of the queue.
+    ///
+    /// Freezing the buffer does not copy its contents. The frozen buffer is returned if it is
+    /// rejected.
+    pub fn produce_mut(&self, bytes: BytesMut) -> Result<(), ProduceError<Bytes>> {
+        self.produce(bytes.freeze())
+    }
+
+    /// Attempts to copy the contents of the supplied slices into a single buffer and add it to the
+    /// back of the queue and returns the number of bytes added.
+    ///
+    /// Nothing is added if the slices are empty. The buffer is returned if it is rejected.
+    pub fn write_vectored(&self, bufs: &[IoSlice]) -> Result<usize, ProduceError<Bytes>> {
+        let len = bufs.iter().map(|b| b.len()).sum();
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let mut bytes = BytesMut::with_capacity(len);
+        for buf in bufs {
+            bytes.extend_from_slice(buf);
+        }
+        self.produce_mut(bytes).map(|_| len)
+    }
+
+    /// Returns the total length of the buffers currently in the queue.
+    pub fn in_flight(&self) -> usize {
+        self.budget.in_flight.load(Relaxed)
+    }
+
+    /// Returns the maximum total length of the buffers the queue can contain.
+    pub fn limit(&self) -> usize {
+        self.budget.limit
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &mpmc::Producer<Bytes> {
+        &self.producer
+    }
+}
+
+impl Clone for Producer {
+    fn clone(&self) -> Self {
+        Producer { producer: self.producer.clone(), budget: self.budget.clone() }
+    }
+}
+
+impl Produce<Bytes> for Producer {
+    fn produce(&self, item: Bytes) -> Result<(), ProduceError<Bytes>> {
+        Producer::produce(self, item)
+    }
+}
+
+//================================================
+// Traits
+//================================================
+
+impl MemSized for Bytes {
+    fn mem_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl MemSized for BytesMut {
+    fn mem_size(&self) -> usize {
+        self.len()
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for an unbounded MPMC lock-free queue for `Bytes` buffers that
+/// can contain up to `limit` bytes.
+///
+/// The value of `clones` indicates the number of clones of the initial producer and consumer the
+/// queue reserves space for (see `unbounded::mpmc::channel`).
+///
+/// # Panics
+///
+/// * `limit` is zero
+pub fn channel(limit: usize, clones: usize) -> (Producer, Consumer) {
+    assert!(limit != 0, "`limit` is zero");
+    let budget = Arc::new(Budget { in_flight: AtomicUsize::new(0), limit });
+    let (producer, consumer) = mpmc::channel(clones);
+    let producer = Producer { producer, budget: budget.clone() };
+    (producer, Consumer { consumer, budget, partial: Cell::new(None) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit() {
+        let (producer, consumer) = channel(8, 0);
+        assert_eq!(producer.limit(), 8);
+
+        // A buffer larger than the limit is accepted when the queue is empty.
+        assert_eq!(producer.produce(Bytes::from(vec![0u8; 16])), Ok(()));
+        assert!(matches!(producer.produce(Bytes::from(vec![0u8; 1])), Err(ProduceError::Full(_))));
+        assert_eq!(consumer.consume().map(|b| b.len()), Ok(16));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        assert_eq!(producer.produce_mut(BytesMut::from(&b"abcd"[..])), Ok(()));
+        assert_eq!(producer.produce(Bytes::from_static(b"efgh")), Ok(()));
+        assert_eq!(producer.in_flight(), 8);
+        assert!(producer.produce(Bytes::from_static(b"i")).is_err());
+        assert_eq!(consumer.consume(), Ok(Bytes::from_static(b"abcd")));
+        assert_eq!(consumer.in_flight(), 4);
+    }
+
+    #[test]
+    fn test_vectored() {
+        let (producer, consumer) = channel(64, 0);
+        let bufs = [IoSlice::new(b"abc"), IoSlice::new(b""), IoSlice::new(b"de")];
+        assert_eq!(producer.write_vectored(&bufs), Ok(5));
+        assert_eq!(producer.write_vectored(&[]), Ok(0));
+        assert_eq!(producer.write_vectored(&[IoSlice::new(b"fgh")]), Ok(3));
+
+        // The rest of a buffer that does not fit is kept by the consumer.
+        let (mut a, mut b) = ([0; 2], [0; 4]);
+        let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+        assert_eq!(consumer.read_vectored(&mut bufs), Ok(6));
+        assert_eq!((&a, &b), (b"ab", b"cdef"));
+        assert_eq!(consumer.partial_len(), 2);
+        assert_eq!(consumer.in_flight(), 0);
+
+        let mut c = [0; 4];
+        assert_eq!(consumer.read_vectored(&mut [IoSliceMut::new(&mut c)]), Ok(2));
+        assert_eq!(&c[..2], b"gh");
+        let result = consumer.read_vectored(&mut [IoSliceMut::new(&mut c)]);
+        assert_eq!(result, Err(ConsumeError::Empty));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (producer, consumer) = channel(8, 0);
+        drop(consumer);
+        let result = producer.produce(Bytes::from_static(b"a"));
+        assert_eq!(result, Err(ProduceError::Disconnected(Bytes::from_static(b"a"))));
+        assert_eq!(producer.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_mem_size() {
+        assert_eq!(Bytes::from_static(b"abc").mem_size(), 3);
+        assert_eq!(BytesMut::with_capacity(16).mem_size(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Causal ordering of items across producers.
+//!
+//! Items added to an MPMC queue by different producers can be removed by consumers in an order
+//! that differs from the order in which they were stamped. Stamping items with a `Stamper` before
+//! adding them to a queue allows consumers to reconstruct a consistent total order.
+//!
+//! The stamps issued by a `Clock` are dense (every stamp from `0` up to the most recently issued
+//! stamp is issued exactly once) so a consumer that has seen stamps `0` through `n` can emit them
+//! in order without waiting for any other items.
+
+use std::cell::{Cell};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+//================================================
+// Structs
+//================================================
+
+// Clock _________________________________________
+
+/// A clock that issues global stamps to stampers.
+#[derive(Debug)]
+pub struct Clock {
+    stamp: AtomicU64,
+    producer: AtomicUsize,
+}
+
+impl Clock {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Clock`.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Clock { stamp: AtomicU64::new(0), producer: AtomicUsize::new(0) })
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns a new stamper with a unique producer identifier that uses this clock.
+    pub fn stamper(self: &Arc<Self>) -> Stamper {
+        let producer = self.producer.fetch_add(1, Relaxed);
+        Stamper { clock: self.clone(), producer, sequence: Cell::new(0) }
+    }
+}
+
+// Stamped _______________________________________
+
+/// An item stamped with its position in a causal order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Stamped<T> {
+    /// The item.
+    pub item: T,
+    /// The identifier of the producer that stamped the item.
+    pub producer: usize,
+    /// The number of items the producer stamped before the item.
+    pub sequence: u64,
+    /// The global stamp of the item.
+    pub stamp: u64,
+}
+
+// Stamper _______________________________________
+
+/// A producer-local stamper.
+#[derive(Debug)]
+pub struct Stamper {
+    clock: Arc<Clock>,
+    producer: usize,
+    sequence: Cell<u64>,
+}
+
+impl Stamper {
+    //- Accessors --------------------------------
+
+    /// Returns the unique producer identifier of this stamper.
+    pub fn producer(&self) -> usize {
+        self.producer
+    }
+
+    /// Stamps the supplied item.
+    pub fn stamp<T>(&self, item: T) -> Stamped<T> {
+        let sequence = self.sequence.get();
+        self.sequence.set(sequence + 1);
+        let stamp = self.clock.stamp.fetch_add(1, SeqCst);
+        Stamped { item, producer: self.producer, sequence, stamp }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_stamp() {
+        let clock = Clock::new();
+        let (a, b) = (clock.stamper(), clock.stamper());
+        assert_ne!(a.producer(), b.producer());
+
+        let stamped = a.stamp('a');
+        assert_eq!(stamped, Stamped { item: 'a', producer: a.producer(), sequence: 0, stamp: 0 });
+        assert_eq!((b.stamp('b').sequence, b.stamp('c').sequence), (0, 1));
+        assert_eq!(a.stamp('d').stamp, 3);
+    }
+
+    #[test]
+    fn test_dense() {
+        let clock = Clock::new();
+        let threads = (0..4).map(|_| {
+            let stamper = clock.stamper();
+            thread::spawn(move || (0..1000).map(|i| stamper.stamp(i)).collect::<Vec<_>>())
+        }).collect::<Vec<_>>();
+
+        let mut stamps = vec![];
+        for thread in threads {
+            let items = thread.join().unwrap();
+            // The sequence numbers issued by each stamper are consecutive.
+            assert!(items.iter().enumerate().all(|(i, s)| s.sequence == i as u64));
+            stamps.extend(items.iter().map(|s| s.stamp));
+        }
+        stamps.sort();
+        assert_eq!(stamps, (0..4000).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unbounded multi-producer, multi-consumer queue that conflates items by key.
+//!
+//! Items are added to this queue with a key. Adding an item with the same key as an item which is
+//! still in the queue replaces the value of that item instead of adding a new item, so the queue
+//! never contains more than one item per key. Items keep the position of the first item added
+//! with their key. Unlike the other queues in this crate, this queue is protected by a lock.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::conflate;
+//!
+//! fn main() {
+//!     let (producer, consumer) = conflate::channel();
+//!
+//!     producer.produce("a", 1).unwrap();
+//!     producer.produce("b", 2).unwrap();
+//!     producer.produce("a", 3).unwrap();
+//!     assert_eq!(consumer.len(), 2);
+//!
+//!     assert_eq!(consumer.consume(), Ok(("a", 3)));
+//!     assert_eq!(consumer.consume(), Ok(("b", 2)));
+//! }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+use notify::{Notifier};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for an unbounded conflating MPMC queue.
+#[derive(Debug)]
+pub struct Consumer<K, V>(Arc<Queue<K, V>>) where K: Clone + Eq + Hash;
+
+impl<K, V> Consumer<K, V> where K: Clone + Eq + Hash {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the front of the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume(&self) -> Result<(K, V), ConsumeError> {
+        self.0.consume()
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the queue is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.0.consumers.load(Acquire)
+    }
+}
+
+impl<K, V> Clone for Consumer<K, V> where K: Clone + Eq + Hash {
+    fn clone(&self) -> Self {
+        self.0.consumers.fetch_add(1, Release);
+        Consumer(self.0.clone())
+    }
+}
+
+impl<K, V> Consume<(K, V)> for Consumer<K, V> where K: Clone + Eq + Hash {
+    fn consume(&self) -> Result<(K, V), ConsumeError> {
+        self.0.consume()
+    }
+}
+
+impl<K, V> Drop for Consumer<K, V> where K: Clone + Eq + Hash {
+    fn drop(&mut self) {
+        if self.0.consumers.fetch_sub(1, Release) == 1 {
+            self.0.disconnect.notify();
+        }
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer for an unbounded conflating MPMC queue.
+#[derive(Debug)]
+pub struct Producer<K, V>(Arc<Queue<K, V>>) where K: Clone + Eq + Hash;
+
+impl<K, V> Producer<K, V> where K: Clone + Eq + Hash {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of the queue or to replace the value of the
+    /// item in the queue with the same key.
+    ///
+    /// This method returns `Err` if the queue has no remaining consumers.
+    pub fn produce(&self, key: K, value: V) -> Result<(), ProduceError<(K, V)>> {
+        self.0.produce(key, value)
+    }
+
+    /// Returns the number of items currently in the queue.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns whether the queue is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.0.consumers.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
+}
+
+impl<K, V> Clone for Producer<K, V> where K: Clone + Eq + Hash {
+    fn clone(&self) -> Self {
+        self.0.producers.fetch_add(1, Release);
+        Producer(self.0.clone())
+    }
+}
+
+impl<K, V> Produce<(K, V)> for Producer<K, V> where K: Clone + Eq + Hash {
+    fn produce(&self, item: (K, V)) -> Result<(), ProduceError<(K, V)>> {
+        self.0.produce(item.0, item.1)
+    }
+}
+
+impl<K, V> Drop for Producer<K, V> where K: Clone + Eq + Hash {
+    fn drop(&mut self) {
+        self.0.producers.fetch_sub(1, Release);
+    }
+}
+
+// Queue _________________________________________
+
+#[derive(Debug)]
+struct Queue<K, V> where K: Clone + Eq + Hash {
+    producers: AtomicUsize,
+    consumers: AtomicUsize,
+    items: Mutex<(VecDeque<K>, HashMap<K, V>)>,
+    disconnect: Notifier,
+}
+
+impl<K, V> Queue<K, V> where K: Clone + Eq + Hash {
+    //- Constructors -----------------------------
+
+    fn new() -> Arc<Self> {
+        Arc::new(Queue {
+            producers: AtomicUsize::new(1),
+            consumers: AtomicUsize::new(1),
+            items: Mutex::new((VecDeque::new(), HashMap::new())),
+            disconnect: Notifier::new(),
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn len(&self) -> usize {
+        self.items.lock().unwrap().0.len()
+    }
+
+    fn produce(&self, key: K, value: V) -> Result<(), ProduceError<(K, V)>> {
+        // Return an error if all of the consumers have been disconnected.
+        if self.consumers.load(Acquire) == 0 {
+            return Err(ProduceError::Disconnected((key, value)));
+        }
+
+        // Replace the value of the item with the same key or add the item to the back of the queue.
+        let mut items = self.items.lock().unwrap();
+        let (ref mut keys, ref mut values) = *items;
+        if values.insert(key.clone(), value).is_none() {
+            keys.push_back(key);
+        }
+        Ok(())
+    }
+
+    fn consume(&self) -> Result<(K, V), ConsumeError> {
+        let disconnected = self.producers.load(Acquire) == 0;
+
+        // Remove and return the item at the front of the queue.
+        let mut items = self.items.lock().unwrap();
+        let (ref mut keys, ref mut values) = *items;
+        if let Some(key) = keys.pop_front() {
+            let value = values.remove(&key).unwrap();
+            Ok((key, value))
+        } else if disconnected {
+            Err(ConsumeError::Disconnected)
+        } else {
+            Err(ConsumeError::Empty)
+        }
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for an unbounded conflating MPMC queue.
+pub fn channel<K, V>() -> (Producer<K, V>, Consumer<K, V>) where K: Clone + Eq + Hash {
+    let queue = Queue::new();
+    (Producer(queue.clone()), Consumer(queue))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool};
+
+    #[test]
+    fn test_conflate() {
+        let (producer, consumer) = channel();
+        producer.produce("a", 1).unwrap();
+        producer.produce("b", 2).unwrap();
+        producer.produce("a", 3).unwrap();
+        assert_eq!((producer.len(), consumer.is_empty()), (2, false));
+
+        // A key that was consumed is added to the back of the queue again.
+        assert_eq!(consumer.consume(), Ok(("a", 3)));
+        producer.produce("a", 4).unwrap();
+        assert_eq!(consumer.consume(), Ok(("b", 2)));
+        assert_eq!(consumer.consume(), Ok(("a", 4)));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (producer, consumer) = channel();
+        let clone = consumer.clone();
+        assert_eq!((producer.producer_count(), producer.consumer_count()), (1, 2));
+
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let flag = disconnected.clone();
+        producer.on_disconnect(move || flag.store(true, SeqCst));
+        drop(consumer);
+        assert!(!disconnected.load(SeqCst));
+        drop(clone);
+        assert!(disconnected.load(SeqCst));
+        assert_eq!(producer.produce("a", 1), Err(ProduceError::Disconnected(("a", 1))));
+
+        let (producer, consumer) = channel();
+        producer.produce("a", 1).unwrap();
+        drop(producer);
+        assert_eq!(consumer.consume(), Ok(("a", 1)));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-handle production and consumption counters.
+//!
+//! The producers and consumers returned by `wrap` count the items added or removed by each handle
+//! separately, so every clone starts counting from zero. The counts of all of the live producers
+//! or consumers can be collected from any handle with `producer_counts` or `consumer_counts`
+//! (e.g., by a supervisor thread holding a clone), which makes load imbalance between worker
+//! consumers directly observable. Each handle only ever writes to its own counter.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::bounded::mpmc;
+//! use npnc::counted;
+//!
+//! fn main() {
+//!     let (producer, consumer1) = mpmc::channel(64);
+//!     let (producer, consumer1) = counted::wrap(producer, consumer1);
+//!     let consumer2 = consumer1.clone();
+//!
+//!     for item in 0..4 {
+//!         producer.produce(item).unwrap();
+//!     }
+//!
+//!     consumer1.consume().unwrap();
+//!     consumer1.consume().unwrap();
+//!     consumer1.consume().unwrap();
+//!     consumer2.consume().unwrap();
+//!     assert_eq!(producer.count(), 4);
+//!     assert_eq!(consumer1.count(), 3);
+//!     assert_eq!(consumer2.count(), 1);
+//!     assert_eq!(producer.consumer_counts(), &[3, 1]);
+//! }
+//! ```
+
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that counts the items it removes from a queue.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+    count: Arc<AtomicUsize>,
+    registry: Arc<Registry>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        let item = self.consumer.consume()?;
+        self.count.fetch_add(1, Relaxed);
+        Ok(item)
+    }
+
+    /// Returns the number of items this consumer has removed from the queue.
+    ///
+    /// Clones of this consumer are counted separately.
+    pub fn count(&self) -> usize {
+        self.count.load(Relaxed)
+    }
+
+    /// Returns the number of items each live producer has added to the queue in the order the
+    /// producers were created.
+    pub fn producer_counts(&self) -> Vec<usize> {
+        counts(&self.registry.producers)
+    }
+
+    /// Returns the number of items each live consumer has removed from the queue in the order the
+    /// consumers were created.
+    pub fn consumer_counts(&self) -> Vec<usize> {
+        counts(&self.registry.consumers)
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        let count = register(&self.registry.consumers);
+        Consumer { consumer: self.consumer.clone(), count, registry: self.registry.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that counts the items it adds to a queue.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: P,
+    count: Arc<AtomicUsize>,
+    registry: Arc<Registry>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `Err` if the queue is full or has no remaining consumers.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        self.producer.produce(item)?;
+        self.count.fetch_add(1, Relaxed);
+        Ok(())
+    }
+
+    /// Returns the number of items this producer has added to the queue.
+    ///
+    /// Clones of this producer are counted separately.
+    pub fn count(&self) -> usize {
+        self.count.load(Relaxed)
+    }
+
+    /// Returns the number of items each live producer has added to the queue in the order the
+    /// producers were created.
+    pub fn producer_counts(&self) -> Vec<usize> {
+        counts(&self.registry.producers)
+    }
+
+    /// Returns the number of items each live consumer has removed from the queue in the order the
+    /// consumers were created.
+    pub fn consumer_counts(&self) -> Vec<usize> {
+        counts(&self.registry.consumers)
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        let count = register(&self.registry.producers);
+        Producer { producer: self.producer.clone(), count, registry: self.registry.clone() }
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// Registry ______________________________________
+
+/// The counters of the live producers and consumers of a queue.
+///
+/// The counters are held weakly so that the counters of dropped handles are skipped.
+#[derive(Debug)]
+struct Registry {
+    producers: Mutex<Vec<Weak<AtomicUsize>>>,
+    consumers: Mutex<Vec<Weak<AtomicUsize>>>,
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns the counts of the live counters in the supplied list and removes the dead counters.
+fn counts(counters: &Mutex<Vec<Weak<AtomicUsize>>>) -> Vec<usize> {
+    let mut counts = vec![];
+    counters.lock().unwrap().retain(|c| match c.upgrade() {
+        Some(c) => { counts.push(c.load(Relaxed)); true },
+        None => false,
+    });
+    counts
+}
+
+/// Adds a new counter to the supplied list and returns it.
+fn register(counters: &Mutex<Vec<Weak<AtomicUsize>>>) -> Arc<AtomicUsize> {
+    let count = Arc::new(AtomicUsize::new(0));
+    let mut counters = counters.lock().unwrap();
+    counters.retain(|c| c.upgrade().is_some());
+    counters.push(Arc::downgrade(&count));
+    count
+}
+
+/// Wraps the supplied producer and consumer so they count the items they add or remove.
+///
+/// Clones of the returned producer and consumer are counted separately.
+pub fn wrap<P, C>(producer: P, consumer: C) -> (Producer<P>, Consumer<C>) {
+    let producers = Mutex::new(vec![]);
+    let registry = Arc::new(Registry { producers, consumers: Mutex::new(vec![]) });
+    let count = register(&registry.producers);
+    let producer = Producer { producer, count, registry: registry.clone() };
+    let count = register(&registry.consumers);
+    (producer, Consumer { consumer, count, registry })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{mpmc};
+
+    #[test]
+    fn test_counts() {
+        let (producer, consumer) = mpmc::channel(2);
+        let (producer1, consumer1) = wrap(producer, consumer);
+        let (producer2, consumer2) = (producer1.clone(), consumer1.clone());
+
+        producer1.produce(1).unwrap();
+        producer2.produce(2).unwrap();
+        // Items that could not be added are not counted.
+        assert_eq!(producer2.produce(3), Err(ProduceError::Full(3)));
+        assert_eq!(producer1.producer_counts(), &[1, 1]);
+
+        consumer2.consume().unwrap();
+        consumer2.consume().unwrap();
+        assert_eq!(consumer2.consume(), Err(ConsumeError::Empty));
+        assert_eq!((consumer1.count(), consumer2.count()), (0, 2));
+        assert_eq!(producer1.consumer_counts(), &[0, 2]);
+
+        // The counts of dropped handles are skipped.
+        drop(consumer1);
+        assert_eq!(consumer2.consumer_counts(), &[2]);
+        drop(producer2);
+        assert_eq!(consumer2.producer_counts(), &[1]);
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Credit-based flow control.
+//!
+//! The producers returned by `wrap` spend a credit for every item they add to a queue and receive
+//! `NoCredit` errors once the credits have been exhausted. Credits are granted by consumers or by
+//! `Grantor`s, which can be handed to later stages of a pipeline so that producers at the start
+//! of the pipeline are throttled by how quickly items leave the end of the pipeline.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::ProduceError;
+//! use npnc::bounded::spsc;
+//! use npnc::credit;
+//!
+//! fn main() {
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let (producer, consumer) = credit::wrap(producer, consumer, 1);
+//!
+//!     producer.produce(1).unwrap();
+//!     assert_eq!(producer.produce(2), Err(ProduceError::NoCredit(2)));
+//!
+//!     consumer.grant(1);
+//!     producer.produce(2).unwrap();
+//! }
+//! ```
+
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that grants credits to producers.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+    credits: Arc<AtomicUsize>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        self.consumer.consume()
+    }
+
+    /// Grants the supplied number of credits to the producers.
+    pub fn grant(&self, credits: usize) {
+        self.credits.fetch_add(credits, Release);
+    }
+
+    /// Returns a grantor that can grant credits to the producers.
+    pub fn grantor(&self) -> Grantor {
+        Grantor(self.credits.clone())
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        Consumer { consumer: self.consumer.clone(), credits: self.credits.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.consumer.consume()
+    }
+}
+
+// Grantor _______________________________________
+
+/// A handle that grants credits to producers.
+#[derive(Clone, Debug)]
+pub struct Grantor(Arc<AtomicUsize>);
+
+impl Grantor {
+    //- Accessors --------------------------------
+
+    /// Grants the supplied number of credits to the producers.
+    pub fn grant(&self, credits: usize) {
+        self.0.fetch_add(credits, Release);
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that spends a credit for every item it adds to a queue.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: P,
+    credits: Arc<AtomicUsize>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `Err` if the queue is full, has no remaining consumers, or if the
+    /// producers have no remaining credits.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        // Spend a credit.
+        let mut credits = self.credits.load(Acquire);
+        loop {
+            if credits == 0 {
+                return Err(ProduceError::NoCredit(item));
+            }
+
+            match self.credits.compare_exchange_weak(credits, credits - 1, AcqRel, Acquire) {
+                Ok(_) => break,
+                Err(current) => credits = current,
+            }
+        }
+
+        // Refund the credit if the item could not be added.
+        self.producer.produce(item).inspect_err(|_| { self.credits.fetch_add(1, Release); })
+    }
+
+    /// Returns the number of credits currently available to the producers.
+    pub fn credits(&self) -> usize {
+        self.credits.load(Acquire)
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        Producer { producer: self.producer.clone(), credits: self.credits.clone() }
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Wraps the supplied producer and consumer so the producers spend credits granted by consumers.
+///
+/// The value of `credits` indicates the number of credits initially available to the producers.
+pub fn wrap<P, C>(producer: P, consumer: C, credits: usize) -> (Producer<P>, Consumer<C>) {
+    let credits = Arc::new(AtomicUsize::new(credits));
+    let producer = Producer { producer, credits: credits.clone() };
+    (producer, Consumer { consumer, credits })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_credits() {
+        let (producer, consumer) = spsc::channel(2);
+        let (producer, consumer) = wrap(producer, consumer, 4);
+        producer.produce(1).unwrap();
+        producer.produce(2).unwrap();
+        assert_eq!(producer.credits(), 2);
+
+        // The credit spent on an item that could not be added is refunded.
+        assert_eq!(producer.produce(3), Err(ProduceError::Full(3)));
+        assert_eq!(producer.credits(), 2);
+
+        assert_eq!(consumer.consume(), Ok(1));
+        assert_eq!(consumer.consume(), Ok(2));
+        producer.produce(3).unwrap();
+        producer.produce(4).unwrap();
+        assert_eq!(producer.produce(5), Err(ProduceError::NoCredit(5)));
+    }
+
+    #[test]
+    fn test_grantor() {
+        let (producer, consumer) = spsc::channel(8);
+        let (producer, consumer) = wrap(producer, consumer, 0);
+        assert_eq!(producer.produce(1), Err(ProduceError::NoCredit(1)));
+
+        let grantor = consumer.grantor();
+        drop(consumer);
+        grantor.grant(2);
+        assert_eq!(producer.credits(), 2);
+        assert_eq!(producer.produce(1), Err(ProduceError::Disconnected(1)));
+        assert_eq!(producer.credits(), 2);
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deferred dropping of unwanted items.
+//!
+//! A `Dropper` sends the items it is given to a background thread which drops them, keeping
+//! expensive `Drop` implementations (deallocations, file closes, etc.) out of latency-critical
+//! code. The background thread exits once every clone of the dropper has been dropped and the
+//! remaining items have been dropped.
+
+use std::thread::{self, Thread};
+
+use {ConsumeError};
+use unbounded::relaxed;
+
+//================================================
+// Structs
+//================================================
+
+// Dropper _______________________________________
+
+/// A handle that drops items on a background thread.
+#[derive(Debug)]
+pub struct Dropper<T> {
+    producer: Option<relaxed::Producer<T>>,
+    thread: Thread,
+}
+
+impl<T> Dropper<T> where T: Send + 'static {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Dropper` and spawns its background thread.
+    pub fn new() -> Self {
+        let (producer, consumer) = relaxed::channel();
+        let thread = thread::Builder::new().name("npnc-dropper".into()).spawn(move || {
+            loop {
+                match consumer.consume() {
+                    Ok(item) => drop(item),
+                    Err(ConsumeError::Empty) => thread::park(),
+                    Err(ConsumeError::Contended) | Err(ConsumeError::Timeout) => { },
+                    Err(ConsumeError::Disconnected) => break,
+                }
+            }
+        }).expect("failed to spawn dropper thread");
+        Dropper { producer: Some(producer), thread: thread.thread().clone() }
+    }
+}
+
+impl<T> Dropper<T> {
+    //- Accessors --------------------------------
+
+    /// Sends the supplied item to the background thread to be dropped.
+    pub fn defer(&self, item: T) {
+        // The background thread can't exit while this dropper exists so this can't fail.
+        let _ = self.producer.as_ref().unwrap().produce(item);
+        self.thread.unpark();
+    }
+
+    /// Sends the supplied items to the background thread to be dropped.
+    pub fn defer_all<I>(&self, items: I) where I: IntoIterator<Item=T> {
+        let producer = self.producer.as_ref().unwrap();
+        for item in items {
+            let _ = producer.produce(item);
+        }
+        self.thread.unpark();
+    }
+}
+
+impl<T> Clone for Dropper<T> {
+    fn clone(&self) -> Self {
+        Dropper { producer: self.producer.clone(), thread: self.thread.clone() }
+    }
+}
+
+impl<T> Default for Dropper<T> where T: Send + 'static {
+    fn default() -> Self {
+        Dropper::new()
+    }
+}
+
+impl<T> Drop for Dropper<T> {
+    fn drop(&mut self) {
+        // Disconnect before waking the background thread so it can exit if this was the last clone.
+        self.producer.take();
+        self.thread.unpark();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::{self, Sender};
+
+    struct Item(Sender<Option<String>>);
+
+    impl Drop for Item {
+        fn drop(&mut self) {
+            let _ = self.0.send(thread::current().name().map(|n| n.into()));
+        }
+    }
+
+    #[test]
+    fn test_defer() {
+        let (sender, receiver) = mpsc::channel();
+        let dropper = Dropper::new();
+        dropper.defer(Item(sender.clone()));
+        let clone = dropper.clone();
+        drop(dropper);
+        clone.defer_all(vec![Item(sender.clone()), Item(sender)]);
+        drop(clone);
+
+        // The items are dropped on the background thread, which exits once they have been dropped.
+        let names = receiver.iter().collect::<Vec<_>>();
+        assert_eq!(names, vec![Some("npnc-dropper".to_string()); 3]);
+    }
+}
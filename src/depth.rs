@@ -0,0 +1,436 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Periodic sampling of queue depths.
+//!
+//! The producers and consumers returned by `Sampler::wrap` keep track of the number of items in
+//! their queue. Calling `Sampler::sample` records the current depth of every queue wrapped by the
+//! sampler in the sink of the sampler, which gives a time series of the depths of a group of queues
+//! without instrumenting every place items are added or removed. The sampler can be pumped by the
+//! user (e.g., from an existing event loop) or by a background thread started with
+//! `Sampler::spawn`, which samples at a fixed interval until the returned `Handle` is dropped.
+//!
+//! `History` is a sink that keeps the most recent samples in memory.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::sync::{Arc};
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::depth::{History, Sampler};
+//!
+//! fn main() {
+//!     let history = Arc::new(History::new(64));
+//!     let sampler = Sampler::new(history.clone());
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let (producer, consumer) = sampler.wrap(producer, consumer);
+//!
+//!     producer.produce(1).unwrap();
+//!     producer.produce(2).unwrap();
+//!     sampler.sample();
+//!     consumer.consume().unwrap();
+//!     sampler.sample();
+//!
+//!     let depths = history.samples().iter().map(|s| s.depth).collect::<Vec<_>>();
+//!     assert_eq!(depths, &[2, 1]);
+//! }
+//! ```
+
+use std::cmp;
+use std::fmt;
+use std::thread;
+use std::collections::{VecDeque};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration, Instant};
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// Channel _______________________________________
+
+/// The state of a queue sampled by a sampler.
+#[derive(Debug)]
+struct Channel {
+    index: usize,
+    depth: AtomicUsize,
+}
+
+// Consumer ______________________________________
+
+/// A consumer whose queue is sampled by a sampler.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+    channel: Arc<Channel>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        let item = self.consumer.consume()?;
+        self.channel.depth.fetch_sub(1, Relaxed);
+        Ok(item)
+    }
+
+    /// Returns the index of the queue among the queues sampled by the sampler.
+    pub fn index(&self) -> usize {
+        self.channel.index
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        Consumer { consumer: self.consumer.clone(), channel: self.channel.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Handle ________________________________________
+
+/// A handle to a background thread that samples the queues of a sampler.
+///
+/// The thread is stopped when this handle is dropped.
+#[derive(Debug)]
+pub struct Handle {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.stop.store(true, Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+// History _______________________________________
+
+/// A sink that keeps the most recent samples in memory.
+#[derive(Debug)]
+pub struct History {
+    samples: Mutex<VecDeque<Sample>>,
+    capacity: usize,
+}
+
+impl History {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `History` that keeps up to the supplied number of samples.
+    pub fn new(capacity: usize) -> Self {
+        History { samples: Mutex::new(VecDeque::with_capacity(capacity)), capacity }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the samples in this history from oldest to newest.
+    pub fn samples(&self) -> Vec<Sample> {
+        self.samples.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Returns the samples in this history for the queue with the supplied index from oldest to
+    /// newest.
+    pub fn samples_for(&self, index: usize) -> Vec<Sample> {
+        self.samples.lock().unwrap().iter().filter(|s| s.index == index).cloned().collect()
+    }
+}
+
+impl Sink for History {
+    fn record(&self, sample: &Sample) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        if self.capacity != 0 {
+            samples.push_back(*sample);
+        }
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer whose queue is sampled by a sampler.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: P,
+    channel: Arc<Channel>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `Err` if the queue is full or has no remaining consumers.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        // The item is counted before it is added so that the count never underflows.
+        self.channel.depth.fetch_add(1, Relaxed);
+        self.producer.produce(item).inspect_err(|_| {
+            self.channel.depth.fetch_sub(1, Relaxed);
+        })
+    }
+
+    /// Returns the index of the queue among the queues sampled by the sampler.
+    pub fn index(&self) -> usize {
+        self.channel.index
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        Producer { producer: self.producer.clone(), channel: self.channel.clone() }
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// Sample ________________________________________
+
+/// The depth of a queue at a point in time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// The index of the queue among the queues sampled by the sampler.
+    pub index: usize,
+    /// The time the sample was taken relative to when the sampler was constructed.
+    pub time: Duration,
+    /// The number of items in the queue.
+    pub depth: usize,
+}
+
+// Sampler _______________________________________
+
+/// Samples the depths of a group of queues.
+pub struct Sampler<S>(Arc<Shared<S>>);
+
+impl<S> Sampler<S> where S: Sink + 'static {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Sampler` that records samples in the supplied sink.
+    pub fn new(sink: S) -> Self {
+        Sampler(Arc::new(Shared {
+            start: Instant::now(),
+            channels: Mutex::new((vec![], 0)),
+            sink,
+        }))
+    }
+
+    //- Accessors --------------------------------
+
+    /// Wraps the supplied producer and consumer so their queue is sampled by this sampler.
+    ///
+    /// The supplied producer and consumer should belong to the same queue and should not be used
+    /// to add or remove items once wrapped. Queues are indexed in the order they are wrapped and
+    /// are no longer sampled once their wrapped producers and consumers have all been dropped.
+    pub fn wrap<P, C>(&self, producer: P, consumer: C) -> (Producer<P>, Consumer<C>) {
+        let mut channels = self.0.channels.lock().unwrap();
+        channels.1 += 1;
+        let channel = Arc::new(Channel { index: channels.1 - 1, depth: AtomicUsize::new(0) });
+        channels.0.push(Arc::downgrade(&channel));
+        let producer = Producer { producer, channel: channel.clone() };
+        (producer, Consumer { consumer, channel })
+    }
+
+    /// Records the current depths of the sampled queues in the sink of this sampler.
+    pub fn sample(&self) {
+        self.0.sample();
+    }
+
+    /// Starts a background thread that records the depths of the sampled queues in the sink of
+    /// this sampler at the supplied interval until the returned handle is dropped.
+    pub fn spawn(&self, interval: Duration) -> Handle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (shared, flag) = (self.0.clone(), stop.clone());
+        let thread = thread::spawn(move || {
+            let mut next = Instant::now();
+            while !flag.load(Acquire) {
+                let now = Instant::now();
+                if now < next {
+                    thread::park_timeout(next - now);
+                    continue;
+                }
+                // Ticks missed while the sink was busy are skipped instead of sampled in a burst.
+                shared.sample();
+                next = cmp::max(next + interval, Instant::now());
+            }
+        });
+        Handle { stop, thread: Some(thread) }
+    }
+
+    /// Returns a reference to the sink of this sampler.
+    pub fn get_sink(&self) -> &S {
+        &self.0.sink
+    }
+}
+
+impl<S> Clone for Sampler<S> {
+    fn clone(&self) -> Self {
+        Sampler(self.0.clone())
+    }
+}
+
+impl<S> fmt::Debug for Sampler<S> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Sampler {{ start: {:?} }}", self.0.start)
+    }
+}
+
+// Shared ________________________________________
+
+struct Shared<S> {
+    start: Instant,
+    /// The sampled queues and the number of queues that have been sampled.
+    channels: Mutex<(Vec<Weak<Channel>>, usize)>,
+    sink: S,
+}
+
+impl<S> Shared<S> where S: Sink {
+    //- Accessors --------------------------------
+
+    fn sample(&self) {
+        let time = self.start.elapsed();
+        let mut channels = self.channels.lock().unwrap();
+        channels.0.retain(|c| match c.upgrade() {
+            Some(channel) => {
+                let depth = channel.depth.load(Relaxed);
+                self.sink.record(&Sample { index: channel.index, time, depth });
+                true
+            },
+            None => false,
+        });
+    }
+}
+
+//================================================
+// Traits
+//================================================
+
+// Sink __________________________________________
+
+/// A type that records the depths of sampled queues.
+///
+/// Samples are recorded on the thread that calls `Sampler::sample` or on the background thread of
+/// the sampler, so a sink that blocks only delays sampling.
+pub trait Sink: Send + Sync {
+    /// Records the supplied sample.
+    fn record(&self, sample: &Sample);
+}
+
+impl<F> Sink for F where F: Fn(&Sample) + Send + Sync {
+    fn record(&self, sample: &Sample) {
+        self(sample)
+    }
+}
+
+impl<S> Sink for Arc<S> where S: Sink {
+    fn record(&self, sample: &Sample) {
+        (**self).record(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_sample() {
+        let history = Arc::new(History::new(3));
+        let sampler = Sampler::new(history.clone());
+        let (ap, ac) = spsc::channel(1);
+        let (ap, ac) = sampler.wrap(ap, ac);
+        let (bp, bc) = spsc::channel(4);
+        let (bp, bc) = sampler.wrap(bp, bc);
+        assert_eq!((ap.index(), ac.index(), bp.index(), bc.index()), (0, 0, 1, 1));
+
+        // Rejected items are not counted.
+        ap.produce(1).unwrap();
+        assert_eq!(ap.produce(2), Err(ProduceError::Full(2)));
+        bp.produce(3).unwrap();
+        sampler.sample();
+        assert_eq!(history.samples().iter().map(|s| s.depth).collect::<Vec<_>>(), &[1, 1]);
+
+        assert_eq!(ac.consume(), Ok(1));
+        assert_eq!(ac.consume(), Err(ConsumeError::Empty));
+        sampler.sample();
+        assert_eq!(history.samples_for(0).iter().map(|s| s.depth).collect::<Vec<_>>(), &[0]);
+        assert_eq!(history.samples().len(), 3);
+
+        // Queues are no longer sampled once their producers and consumers are dropped.
+        drop((ap, ac));
+        sampler.sample();
+        let samples = history.samples();
+        assert_eq!(samples.iter().map(|s| s.index).collect::<Vec<_>>(), &[0, 1, 1]);
+    }
+
+    #[test]
+    fn test_spawn() {
+        let sampler = Sampler::new(Arc::new(History::new(1024)));
+        let (producer, consumer) = spsc::channel(4);
+        let (producer, _consumer) = sampler.wrap(producer, consumer);
+        producer.produce(1).unwrap();
+
+        let handle = sampler.spawn(Duration::from_millis(1));
+        thread::sleep(Duration::from_millis(50));
+        drop(handle);
+        let samples = sampler.get_sink().samples();
+        assert!(samples.len() > 1);
+        assert!(samples.iter().all(|s| s.depth == 1));
+        assert!(samples.windows(2).all(|w| w[0].time < w[1].time));
+
+        // The thread has stopped.
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(sampler.get_sink().samples().len(), samples.len());
+    }
+
+    #[test]
+    fn test_history_zero() {
+        let history = History::new(0);
+        history.record(&Sample { index: 0, time: Duration::from_secs(0), depth: 1 });
+        assert!(history.samples().is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Freezing queues so their contents can be inspected.
+//!
+//! While a `Freezer` is frozen, the producers returned by `Freezer::wrap` receive `Full` errors
+//! and the consumers receive `Empty` errors, so the contents of the wrapped queues stay the same
+//! (e.g., while a debugger or snapshotter takes a consistent dump of a pipeline of queues).
+//! `Freezer::freeze` waits for the operations that were in progress to finish before returning.
+//! `Consumer::snapshot` returns copies of the items in a frozen queue.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::{ConsumeError, ProduceError};
+//! use npnc::bounded::spsc;
+//! use npnc::freeze::{Freezer};
+//!
+//! fn main() {
+//!     let freezer = Freezer::new();
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let (producer, consumer) = freezer.wrap(producer, consumer);
+//!
+//!     producer.produce(1).unwrap();
+//!     producer.produce(2).unwrap();
+//!
+//!     freezer.freeze();
+//!     assert_eq!(producer.produce(3), Err(ProduceError::Full(3)));
+//!     assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+//!     assert_eq!(consumer.snapshot(&producer).items, vec![1, 2]);
+//!     freezer.thaw();
+//!
+//!     assert_eq!(consumer.consume(), Ok(1));
+//!     assert_eq!(consumer.consume(), Ok(2));
+//! }
+//! ```
+
+use std::thread;
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that receives `Empty` errors while its freezer is frozen.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+    state: Arc<State>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    ///
+    /// This method returns `Err(ConsumeError::Empty)` while the freezer is frozen.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        if !self.state.enter() {
+            return Err(ConsumeError::Empty);
+        }
+
+        let result = self.consumer.consume();
+        self.state.exit();
+        result
+    }
+
+    /// Returns copies of the items in the frozen queue from front to back.
+    ///
+    /// The queues don't support reading items without removing them, so the items are removed
+    /// with the wrapped consumer and added back in the same order with the wrapped producer. This
+    /// means that the supplied producer must belong to the same queue as this consumer and that the
+    /// queue must be a FIFO queue for the items to keep their order. Taking a snapshot is not
+    /// atomic with respect to producers and consumers of the queue that are not wrapped by this
+    /// freezer.
+    ///
+    /// If an item can't be added back (e.g., because a node could not be allocated for it), no
+    /// more items are added back so that the items in the queue stay in order, and the items that
+    /// were not added back are returned in `Snapshot::rejected` instead of being dropped.
+    ///
+    /// # Panics
+    ///
+    /// * the freezer is not frozen
+    pub fn snapshot<T, P>(&self, producer: &Producer<P>) -> Snapshot<T>
+        where T: Clone, C: Consume<T>, P: Produce<T>
+    {
+        assert!(self.state.frozen.load(SeqCst), "the freezer is not frozen");
+        let mut items = vec![];
+        while let Ok(item) = self.consumer.consume() {
+            items.push(item);
+        }
+
+        let copies = items.clone();
+        let mut rejected = vec![];
+        let mut items = items.into_iter();
+        while let Some(item) = items.next() {
+            if let Err(error) = producer.producer.produce(item) {
+                rejected.push(error.item());
+                rejected.extend(items);
+                break;
+            }
+        }
+        Snapshot { items: copies, rejected }
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        Consumer { consumer: self.consumer.clone(), state: self.state.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Freezer _______________________________________
+
+/// Freezes and thaws a group of queues.
+#[derive(Clone, Debug)]
+pub struct Freezer(Arc<State>);
+
+impl Freezer {
+    //- Constructors -----------------------------
+
+    /// Constructs a new thawed `Freezer`.
+    pub fn new() -> Self {
+        Freezer(Arc::new(State { frozen: AtomicBool::new(false), active: AtomicUsize::new(0) }))
+    }
+
+    //- Accessors --------------------------------
+
+    /// Wraps the supplied producer and consumer so they can be frozen by this freezer.
+    ///
+    /// The supplied producer and consumer should belong to the same queue and should not be used
+    /// to add or remove items once wrapped.
+    pub fn wrap<P, C>(&self, producer: P, consumer: C) -> (Producer<P>, Consumer<C>) {
+        let producer = Producer { producer, state: self.0.clone() };
+        (producer, Consumer { consumer, state: self.0.clone() })
+    }
+
+    /// Freezes the wrapped queues, waiting for the operations in progress to finish.
+    pub fn freeze(&self) {
+        self.0.frozen.store(true, SeqCst);
+        while self.0.active.load(SeqCst) != 0 {
+            thread::yield_now();
+        }
+    }
+
+    /// Thaws the wrapped queues.
+    pub fn thaw(&self) {
+        self.0.frozen.store(false, SeqCst);
+    }
+
+    /// Returns whether the wrapped queues are frozen.
+    pub fn is_frozen(&self) -> bool {
+        self.0.frozen.load(SeqCst)
+    }
+}
+
+impl Default for Freezer {
+    fn default() -> Self {
+        Freezer::new()
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that receives `Full` errors while its freezer is frozen.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: P,
+    state: Arc<State>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `Err(ProduceError::Full)` while the freezer is frozen.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        if !self.state.enter() {
+            return Err(ProduceError::Full(item));
+        }
+
+        let result = self.producer.produce(item);
+        self.state.exit();
+        result
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        Producer { producer: self.producer.clone(), state: self.state.clone() }
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// Snapshot ______________________________________
+
+/// The items in a frozen queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot<T> {
+    /// Copies of the items that were in the queue from front to back.
+    pub items: Vec<T>,
+    /// The items that were removed from the queue but could not be added back, from front to back.
+    pub rejected: Vec<T>,
+}
+
+// State _________________________________________
+
+#[derive(Debug)]
+struct State {
+    frozen: AtomicBool,
+    /// The number of operations in progress.
+    active: AtomicUsize,
+}
+
+impl State {
+    //- Accessors --------------------------------
+
+    /// Starts an operation and returns whether this state is not frozen.
+    ///
+    /// `exit` must be called once the operation has finished if this method returns `true`.
+    fn enter(&self) -> bool {
+        // The operation is counted before checking whether this state is frozen so that `freeze`
+        // will wait for the operation to finish.
+        self.active.fetch_add(1, SeqCst);
+        if self.frozen.load(SeqCst) {
+            self.active.fetch_sub(1, SeqCst);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Finishes an operation.
+    fn exit(&self) {
+        self.active.fetch_sub(1, SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    struct Reject;
+
+    impl Produce<i32> for Reject {
+        fn produce(&self, item: i32) -> Result<(), ProduceError<i32>> {
+            Err(ProduceError::Full(item))
+        }
+    }
+
+    #[test]
+    fn test_freeze() {
+        let freezer = Freezer::new();
+        let (producer, consumer) = spsc::channel(4);
+        let (producer, consumer) = freezer.wrap(producer, consumer);
+        producer.produce(1).unwrap();
+
+        freezer.freeze();
+        assert!(freezer.is_frozen());
+        assert_eq!(producer.produce(2), Err(ProduceError::Full(2)));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        assert_eq!(consumer.snapshot(&producer), Snapshot { items: vec![1], rejected: vec![] });
+
+        freezer.thaw();
+        assert_eq!(consumer.consume(), Ok(1));
+    }
+
+    #[test]
+    fn test_snapshot_rejected() {
+        let freezer = Freezer::new();
+        let (producer, consumer) = spsc::channel(4);
+        producer.produce(1).unwrap();
+        producer.produce(2).unwrap();
+        let (producer, consumer) = freezer.wrap(Reject, consumer);
+
+        freezer.freeze();
+        let snapshot = consumer.snapshot(&producer);
+        assert_eq!(snapshot, Snapshot { items: vec![1, 2], rejected: vec![1, 2] });
+        freezer.thaw();
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+    }
+
+    #[test]
+    #[should_panic(expected="the freezer is not frozen")]
+    fn test_snapshot_thawed() {
+        let freezer = Freezer::new();
+        let (producer, consumer) = freezer.wrap(Reject, spsc::channel::<i32>(4).1);
+        consumer.snapshot(&producer);
+    }
+}
@@ -0,0 +1,1180 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Asynchronous channels with the same interface as the channels in `futures::channel::mpsc`.
+//!
+//! The senders and receivers in this module have the same methods as their counterparts in
+//! `futures::channel::mpsc` and implement `Sink` and `Stream`, so code that uses those channels
+//! can switch to the channels in this module by changing the functions it constructs channels
+//! with. Bounded channels are backed by a bounded MPMC queue and unbounded channels are backed by
+//! an unbounded MPMC queue. As with `futures::channel::mpsc::channel`, every sender of a bounded
+//! channel can hold one item in addition to the items in the queue when the queue is full.
+//! Bounded channels constructed with `channel_with` can instead reject items or drop the oldest
+//! item when the queue is full (see `OnFull`).
+//!
+//! Unlike with `futures::channel::mpsc`, a receiver can report why its channel terminated once it
+//! has received `None` (see `Termination`), and `with_termination` turns a receiver into a stream
+//! that ends with the reason, so a downstream stage can tell a sender that panicked from a sender
+//! that finished.
+//!
+//! A receiver can also be turned into a stream of batches with `batches`, which yields up to a
+//! maximum number of items at once and yields a partial batch once the first item in the batch has
+//! waited for a maximum duration (e.g., for writing items to a log or a database in bulk).
+//!
+//! This module requires the `futures` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate futures;
+//! extern crate npnc;
+//!
+//! use std::thread;
+//!
+//! use futures::executor::{block_on};
+//! use futures::sink::{SinkExt};
+//! use futures::stream::{StreamExt};
+//!
+//! fn main() {
+//!     let (mut sender, receiver) = npnc::futures::channel(4);
+//!
+//!     let thread = thread::spawn(move || {
+//!         for item in 0..32 {
+//!             block_on(sender.send(item)).unwrap();
+//!         }
+//!     });
+//!
+//!     let items = block_on(receiver.collect::<Vec<_>>());
+//!     assert_eq!(items, (0..32).collect::<Vec<_>>());
+//!     thread.join().unwrap();
+//! }
+//! ```
+
+extern crate futures;
+
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::mem;
+use std::pin::{Pin};
+use std::thread;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use self::futures::sink::{Sink};
+use self::futures::stream::{FusedStream, Stream};
+use self::futures::task::{AtomicWaker};
+
+use {Consume, ConsumeError, Produce, ProduceError};
+use bounded;
+use unbounded;
+
+//================================================
+// Enums
+//================================================
+
+// OnFull ________________________________________
+
+/// What the senders of a bounded channel do when the channel is full.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnFull {
+    /// The sender holds on to the item and `poll_ready` and `poll_flush` return `Pending` until
+    /// there is room for it.
+    Wait,
+    /// `start_send` returns an error for which `SendError::is_full` returns `true` and the item is
+    /// dropped.
+    Error,
+    /// The item at the front of the channel is dropped to make room for the item.
+    DropOldest,
+}
+
+// Termination ___________________________________
+
+/// Indicates why a channel terminated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Termination {
+    /// The channel was closed by the receiver or a sender.
+    Closed,
+    /// Every sender was dropped or disconnected.
+    Disconnected,
+    /// A sender was dropped while its thread was panicking.
+    Panicked,
+}
+
+//================================================
+// Structs
+//================================================
+
+// Batches _______________________________________
+
+/// A stream that receives the items in a channel in batches.
+///
+/// A batch is yielded once it contains the maximum number of items, once the first item in the
+/// batch has waited for the maximum duration, or once the channel terminates. A batch is never
+/// empty. The maximum duration is measured by a background thread which is spawned the first time
+/// a batch has to wait for more items.
+#[derive(Debug)]
+pub struct Batches<R> where R: Stream {
+    receiver: R,
+    max: usize,
+    max_wait: Duration,
+    batch: Vec<R::Item>,
+    /// The time the current batch is yielded even if it is not full, if any.
+    deadline: Option<Instant>,
+    timer: Option<Arc<Timer>>,
+    done: bool,
+}
+
+impl<R> Batches<R> where R: Stream {
+    //- Constructors -----------------------------
+
+    fn new(receiver: R, max: usize, max_wait: Duration) -> Self {
+        assert!(max != 0, "`max` is zero");
+        Batches {
+            receiver,
+            max,
+            max_wait,
+            batch: vec![],
+            deadline: None,
+            timer: None,
+            done: false,
+        }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns a reference to the wrapped receiver.
+    pub fn get_ref(&self) -> &R {
+        &self.receiver
+    }
+
+    //- Mutators ---------------------------------
+
+    /// Returns the current batch and starts a new batch.
+    fn take(&mut self) -> Vec<R::Item> {
+        self.deadline = None;
+        mem::take(&mut self.batch)
+    }
+}
+
+impl<R> Drop for Batches<R> where R: Stream {
+    fn drop(&mut self) {
+        if let Some(ref timer) = self.timer {
+            timer.stop();
+        }
+    }
+}
+
+impl<R> FusedStream for Batches<R> where R: Stream + Unpin {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+impl<R> Stream for Batches<R> where R: Stream + Unpin {
+    type Item = Vec<R::Item>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>, context: &mut Context
+    ) -> Poll<Option<Vec<R::Item>>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        while this.batch.len() < this.max {
+            match Pin::new(&mut this.receiver).poll_next(context) {
+                Poll::Ready(Some(item)) => {
+                    if this.batch.is_empty() {
+                        // A duration too long to be represented as a deadline waits indefinitely.
+                        this.deadline = Instant::now().checked_add(this.max_wait);
+                    }
+                    this.batch.push(item);
+                },
+                Poll::Ready(None) => {
+                    this.done = true;
+                    let batch = this.take();
+                    return Poll::Ready(if batch.is_empty() { None } else { Some(batch) });
+                },
+                Poll::Pending => {
+                    let deadline = match this.deadline {
+                        Some(deadline) if !this.batch.is_empty() => deadline,
+                        _ => return Poll::Pending,
+                    };
+                    if Instant::now() >= deadline {
+                        return Poll::Ready(Some(this.take()));
+                    }
+                    let timer = this.timer.get_or_insert_with(Timer::spawn);
+                    timer.wake_at(deadline, context.waker());
+                    return Poll::Pending;
+                },
+            }
+        }
+
+        Poll::Ready(Some(this.take()))
+    }
+}
+
+impl<R> Unpin for Batches<R> where R: Stream + Unpin { }
+
+// Parked ________________________________________
+
+/// A set of tasks waiting for room in a queue.
+#[derive(Debug)]
+pub(crate) struct Parked {
+    /// The number of tasks in this set.
+    count: AtomicUsize,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Parked {
+    //- Constructors -----------------------------
+
+    pub(crate) fn new() -> Self {
+        Parked { count: AtomicUsize::new(0), wakers: Mutex::new(vec![]) }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Adds the supplied task to this set so it will be woken once there is room in the queue.
+    ///
+    /// A task should try to add its item again after parking so that room that was made in the
+    /// meantime is not missed.
+    pub(crate) fn park(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        self.count.store(wakers.len(), SeqCst);
+        drop(wakers);
+        atomic::fence(SeqCst);
+    }
+
+    /// Wakes and removes the tasks in this set.
+    ///
+    /// With the `sanitize` feature, this method always locks the set instead of checking whether
+    /// it is empty after a fence, since ThreadSanitizer does not model fences.
+    pub(crate) fn wake(&self) {
+        atomic::fence(SeqCst);
+        if cfg!(feature="sanitize") || self.count.load(SeqCst) != 0 {
+            let wakers = {
+                let mut wakers = self.wakers.lock().unwrap();
+                self.count.store(0, SeqCst);
+                mem::take(&mut *wakers)
+            };
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+    }
+}
+
+// Receiver ______________________________________
+
+/// The receiving half of a bounded channel.
+#[derive(Debug)]
+pub struct Receiver<T> {
+    consumer: bounded::mpmc::Consumer<T>,
+    shared: Arc<Shared>,
+    terminated: bool,
+}
+
+impl<T> Receiver<T> {
+    //- Accessors --------------------------------
+
+    /// Closes this channel so the senders can't send any more items.
+    ///
+    /// The items already in the channel can still be received.
+    pub fn close(&mut self) {
+        self.shared.close();
+    }
+
+    /// Attempts to receive an item without waiting.
+    ///
+    /// This method returns `Ok(None)` if the channel is closed and empty and `Err` if the channel
+    /// is empty but not closed.
+    pub fn try_next(&mut self) -> Result<Option<T>, TryRecvError> {
+        self.shared.try_next(&self.consumer, &mut self.terminated)
+    }
+
+    /// Returns why the channel terminated or `None` if the channel has not terminated.
+    ///
+    /// The channel terminates once this receiver has received `None`.
+    pub fn termination(&self) -> Option<Termination> {
+        if self.terminated { Some(self.shared.termination()) } else { None }
+    }
+
+    //- Consumers --------------------------------
+
+    /// Returns a stream that receives the items in the channel wrapped in `Ok` followed by why the
+    /// channel terminated wrapped in `Err`.
+    pub fn with_termination(self) -> WithTermination<Self> {
+        let shared = self.shared.clone();
+        WithTermination { receiver: self, shared, done: false }
+    }
+
+    /// Returns a stream that receives the items in the channel in batches of up to `max` items,
+    /// yielding a partial batch once its first item has waited for `max_wait`.
+    ///
+    /// # Panics
+    ///
+    /// * `max` is zero
+    pub fn batches(self, max: usize, max_wait: Duration) -> Batches<Self> {
+        Batches::new(self, max, max_wait)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+impl<T> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        let this = &mut *self;
+        this.shared.poll_next(&this.consumer, &mut this.terminated, context)
+    }
+}
+
+// SendError _____________________________________
+
+/// Indicates the reason an item could not be sent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SendError {
+    disconnected: bool,
+}
+
+impl SendError {
+    //- Accessors --------------------------------
+
+    /// Returns whether the item could not be sent because the channel was full.
+    pub fn is_full(&self) -> bool {
+        !self.disconnected
+    }
+
+    /// Returns whether the item could not be sent because the channel was closed or had no
+    /// receiver.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+}
+
+impl error::Error for SendError { }
+
+impl fmt::Display for SendError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let description = if self.disconnected {
+            "the channel was closed or had no receiver"
+        } else {
+            "the channel was full"
+        };
+        formatter.write_str(description)
+    }
+}
+
+// Sender ________________________________________
+
+/// The sending half of a bounded channel.
+#[derive(Debug)]
+pub struct Sender<T> {
+    producer: Option<bounded::mpmc::Producer<T>>,
+    pending: Option<T>,
+    on_full: OnFull,
+    shared: Arc<Shared>,
+}
+
+impl<T> Sender<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to send the supplied item without waiting.
+    ///
+    /// This method returns `Err` if the channel is full, closed, or has no receiver. If this
+    /// sender is holding an item because the channel was full, the held item is sent first and
+    /// this method returns `Err` if it can't be sent so that the items are received in the order
+    /// they were sent.
+    pub fn try_send(&mut self, item: T) -> Result<(), TrySendError<T>> {
+        if let Some(pending) = self.pending.take() {
+            if let Err(error) = self.shared.try_send(self.producer.as_ref(), pending) {
+                let disconnected = error.is_disconnected();
+                self.pending = Some(error.into_inner());
+                return Err(TrySendError::new(disconnected, item));
+            }
+        }
+
+        self.shared.try_send(self.producer.as_ref(), item)
+    }
+
+    /// Returns what this sender does when the channel is full.
+    pub fn on_full(&self) -> OnFull {
+        self.on_full
+    }
+
+    /// Returns whether the channel is closed or has no receiver.
+    pub fn is_closed(&self) -> bool {
+        let connected = self.producer.as_ref().is_some_and(|p| p.consumer_count() != 0);
+        !connected || self.shared.is_closed()
+    }
+
+    /// Returns whether this sender and the supplied sender send items to the same channel.
+    pub fn same_receiver(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    /// Returns whether this sender sends items to the supplied receiver.
+    pub fn is_connected_to(&self, receiver: &Receiver<T>) -> bool {
+        Arc::ptr_eq(&self.shared, &receiver.shared)
+    }
+
+    /// Closes this channel so none of the senders can send any more items.
+    pub fn close_channel(&mut self) {
+        self.shared.close();
+    }
+
+    /// Disconnects this sender from the channel.
+    ///
+    /// The receiver will receive `None` once every sender has been dropped or disconnected and the
+    /// channel is empty.
+    pub fn disconnect(&mut self) {
+        self.pending = None;
+        self.producer = None;
+        self.shared.receiver.wake();
+    }
+
+    /// Attempts to add the item this sender is holding because the channel was full.
+    fn poll_pending(&mut self, context: &mut Context) -> Poll<Result<(), SendError>> {
+        let error = match self.pending.take().map(|i| self.try_send(i)) {
+            Some(Err(error)) => error,
+            _ => return Poll::Ready(Ok(())),
+        };
+
+        if error.is_disconnected() {
+            return Poll::Ready(Err(error.into_send_error()));
+        }
+
+        // The sender is parked before trying again so that a receiver that makes room in the
+        // meantime will wake it.
+        self.shared.senders.park(context.waker());
+        match self.try_send(error.into_inner()) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(error) if error.is_disconnected() => Poll::Ready(Err(error.into_send_error())),
+            Err(error) => {
+                self.pending = Some(error.into_inner());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let (producer, shared) = (self.producer.clone(), self.shared.clone());
+        Sender { producer, pending: None, on_full: self.on_full, shared }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.shared.panicked.store(true, SeqCst);
+        }
+
+        // The producer is dropped before the receiver is woken so that the receiver can observe
+        // that the channel has been disconnected.
+        self.producer = None;
+        self.shared.receiver.wake();
+    }
+}
+
+impl<T> Sink<T> for Sender<T> {
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), SendError>> {
+        self.get_mut().poll_pending(context)
+    }
+
+    /// Attempts to send the supplied item, holding on to it if the channel is full.
+    ///
+    /// The held item is sent by `poll_ready` or `poll_flush` once there is room for it. If this
+    /// sender returns errors when the channel is full (see `OnFull`), the item is dropped and an
+    /// error is returned instead.
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), SendError> {
+        let this = self.get_mut();
+        match this.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(error) if error.is_full() && this.on_full == OnFull::Wait => {
+                this.pending = Some(error.into_inner());
+                Ok(())
+            },
+            Err(error) => Err(error.into_send_error()),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), SendError>> {
+        self.get_mut().poll_pending(context)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), SendError>> {
+        let this = self.get_mut();
+        match this.poll_pending(context) {
+            Poll::Ready(result) => {
+                this.disconnect();
+                Poll::Ready(result)
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Unpin for Sender<T> { }
+
+// Shared ________________________________________
+
+/// The state shared by the senders and receiver of a channel.
+#[derive(Debug)]
+struct Shared {
+    closed: AtomicBool,
+    /// Whether a sender was dropped while its thread was panicking.
+    panicked: AtomicBool,
+    receiver: AtomicWaker,
+    senders: Parked,
+}
+
+impl Shared {
+    //- Constructors -----------------------------
+
+    fn new() -> Arc<Self> {
+        Arc::new(Shared {
+            closed: AtomicBool::new(false),
+            panicked: AtomicBool::new(false),
+            receiver: AtomicWaker::new(),
+            senders: Parked::new(),
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(SeqCst)
+    }
+
+    /// Returns why the channel terminated, assuming that it has terminated.
+    fn termination(&self) -> Termination {
+        if self.panicked.load(SeqCst) {
+            Termination::Panicked
+        } else if self.is_closed() {
+            Termination::Closed
+        } else {
+            Termination::Disconnected
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, SeqCst);
+        self.receiver.wake();
+        self.senders.wake();
+    }
+
+    fn try_send<T, P>(
+        &self, producer: Option<&P>, item: T
+    ) -> Result<(), TrySendError<T>> where P: Produce<T> {
+        let producer = match producer {
+            Some(producer) if !self.is_closed() => producer,
+            _ => return Err(TrySendError::new(true, item)),
+        };
+
+        match producer.produce(item) {
+            Ok(()) => {
+                self.receiver.wake();
+                Ok(())
+            },
+            Err(ProduceError::Disconnected(item)) => Err(TrySendError::new(true, item)),
+            Err(error) => Err(TrySendError::new(false, error.item())),
+        }
+    }
+
+    fn try_next<T, C>(
+        &self, consumer: &C, terminated: &mut bool
+    ) -> Result<Option<T>, TryRecvError> where C: Consume<T> {
+        match consumer.consume() {
+            Ok(item) => {
+                self.senders.wake();
+                Ok(Some(item))
+            },
+            Err(ConsumeError::Disconnected) => {
+                *terminated = true;
+                Ok(None)
+            },
+            Err(_) if self.is_closed() => {
+                *terminated = true;
+                Ok(None)
+            },
+            Err(_) => Err(TryRecvError { _private: () }),
+        }
+    }
+
+    fn poll_next<T, C>(
+        &self, consumer: &C, terminated: &mut bool, context: &mut Context
+    ) -> Poll<Option<T>> where C: Consume<T> {
+        if let Ok(item) = self.try_next(consumer, terminated) {
+            return Poll::Ready(item);
+        }
+
+        // The receiver is registered before trying again so that a sender that adds an item in
+        // the meantime will wake it.
+        self.receiver.register(context.waker());
+        match self.try_next(consumer, terminated) {
+            Ok(item) => Poll::Ready(item),
+            Err(_) => Poll::Pending,
+        }
+    }
+}
+
+// Timer _________________________________________
+
+/// A background thread that wakes a task once a deadline has passed.
+#[derive(Debug)]
+struct Timer {
+    state: Mutex<TimerState>,
+    condvar: Condvar,
+}
+
+impl Timer {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Timer` and spawns its background thread.
+    fn spawn() -> Arc<Self> {
+        let state = TimerState { deadline: None, waker: None, stopped: false };
+        let timer = Arc::new(Timer { state: Mutex::new(state), condvar: Condvar::new() });
+        let shared = timer.clone();
+        thread::Builder::new().name("npnc-timer".into()).spawn(move || {
+            shared.run();
+        }).expect("failed to spawn timer thread");
+        timer
+    }
+
+    //- Accessors --------------------------------
+
+    /// Wakes the supplied task once the supplied deadline has passed.
+    ///
+    /// This replaces the deadline and task previously supplied to this method, if any.
+    fn wake_at(&self, deadline: Instant, waker: &Waker) {
+        let mut state = self.state.lock().unwrap();
+        state.deadline = Some(deadline);
+        if !state.waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            state.waker = Some(waker.clone());
+        }
+        self.condvar.notify_one();
+    }
+
+    /// Stops the background thread of this timer.
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.condvar.notify_one();
+    }
+
+    /// Wakes the supplied tasks once their deadlines have passed until this timer is stopped.
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped {
+            let now = Instant::now();
+            state = match state.deadline {
+                Some(deadline) if now >= deadline => {
+                    state.deadline = None;
+                    let waker = state.waker.take();
+                    // The task is woken without the lock held since it may be polled immediately.
+                    drop(state);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                    self.state.lock().unwrap()
+                },
+                Some(deadline) => self.condvar.wait_timeout(state, deadline - now).unwrap().0,
+                None => self.condvar.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+/// The state of a `Timer`.
+#[derive(Debug)]
+struct TimerState {
+    deadline: Option<Instant>,
+    waker: Option<Waker>,
+    /// Whether the background thread should stop.
+    stopped: bool,
+}
+
+// TryRecvError __________________________________
+
+/// Indicates that an item could not be received because the channel was empty.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TryRecvError {
+    _private: (),
+}
+
+impl error::Error for TryRecvError { }
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("the channel was empty")
+    }
+}
+
+// TrySendError __________________________________
+
+/// Indicates the reason an item could not be sent and contains the item.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct TrySendError<T> {
+    error: SendError,
+    item: T,
+}
+
+impl<T> TrySendError<T> {
+    //- Constructors -----------------------------
+
+    fn new(disconnected: bool, item: T) -> Self {
+        TrySendError { error: SendError { disconnected }, item }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns whether the item could not be sent because the channel was full.
+    pub fn is_full(&self) -> bool {
+        self.error.is_full()
+    }
+
+    /// Returns whether the item could not be sent because the channel was closed or had no
+    /// receiver.
+    pub fn is_disconnected(&self) -> bool {
+        self.error.is_disconnected()
+    }
+
+    //- Consumers --------------------------------
+
+    /// Returns the item that could not be sent.
+    pub fn into_inner(self) -> T {
+        self.item
+    }
+
+    /// Returns the reason the item could not be sent.
+    pub fn into_send_error(self) -> SendError {
+        self.error
+    }
+}
+
+impl<T> error::Error for TrySendError<T> { }
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "TrySendError {{ error: {:?}, item: .. }}", self.error)
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, formatter)
+    }
+}
+
+// UnboundedReceiver _____________________________
+
+/// The receiving half of an unbounded channel.
+#[derive(Debug)]
+pub struct UnboundedReceiver<T> {
+    consumer: unbounded::mpmc::Consumer<T>,
+    shared: Arc<Shared>,
+    terminated: bool,
+}
+
+impl<T> UnboundedReceiver<T> {
+    //- Accessors --------------------------------
+
+    /// Closes this channel so the senders can't send any more items.
+    ///
+    /// The items already in the channel can still be received.
+    pub fn close(&mut self) {
+        self.shared.close();
+    }
+
+    /// Attempts to receive an item without waiting.
+    ///
+    /// This method returns `Ok(None)` if the channel is closed and empty and `Err` if the channel
+    /// is empty but not closed.
+    pub fn try_next(&mut self) -> Result<Option<T>, TryRecvError> {
+        self.shared.try_next(&self.consumer, &mut self.terminated)
+    }
+
+    /// Returns why the channel terminated or `None` if the channel has not terminated.
+    ///
+    /// The channel terminates once this receiver has received `None`.
+    pub fn termination(&self) -> Option<Termination> {
+        if self.terminated { Some(self.shared.termination()) } else { None }
+    }
+
+    //- Consumers --------------------------------
+
+    /// Returns a stream that receives the items in the channel wrapped in `Ok` followed by why the
+    /// channel terminated wrapped in `Err`.
+    pub fn with_termination(self) -> WithTermination<Self> {
+        let shared = self.shared.clone();
+        WithTermination { receiver: self, shared, done: false }
+    }
+
+    /// Returns a stream that receives the items in the channel in batches of up to `max` items,
+    /// yielding a partial batch once its first item has waited for `max_wait`.
+    ///
+    /// # Panics
+    ///
+    /// * `max` is zero
+    pub fn batches(self, max: usize, max_wait: Duration) -> Batches<Self> {
+        Batches::new(self, max, max_wait)
+    }
+}
+
+impl<T> Drop for UnboundedReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+impl<T> FusedStream for UnboundedReceiver<T> {
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<T> Stream for UnboundedReceiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<T>> {
+        let this = &mut *self;
+        this.shared.poll_next(&this.consumer, &mut this.terminated, context)
+    }
+}
+
+// UnboundedSender _______________________________
+
+/// The sending half of an unbounded channel.
+#[derive(Debug)]
+pub struct UnboundedSender<T> {
+    producer: Option<unbounded::mpmc::Producer<T>>,
+    shared: Arc<Shared>,
+}
+
+impl<T> UnboundedSender<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to send the supplied item.
+    ///
+    /// This method returns `Err` if the channel is closed or has no receiver.
+    pub fn unbounded_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        self.shared.try_send(self.producer.as_ref(), item)
+    }
+
+    /// Returns whether the channel is closed or has no receiver.
+    pub fn is_closed(&self) -> bool {
+        let connected = self.producer.as_ref().is_some_and(|p| p.consumer_count() != 0);
+        !connected || self.shared.is_closed()
+    }
+
+    /// Returns whether this sender and the supplied sender send items to the same channel.
+    pub fn same_receiver(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    /// Returns whether this sender sends items to the supplied receiver.
+    pub fn is_connected_to(&self, receiver: &UnboundedReceiver<T>) -> bool {
+        Arc::ptr_eq(&self.shared, &receiver.shared)
+    }
+
+    /// Closes this channel so none of the senders can send any more items.
+    pub fn close_channel(&self) {
+        self.shared.close();
+    }
+
+    /// Disconnects this sender from the channel.
+    ///
+    /// The receiver will receive `None` once every sender has been dropped or disconnected and the
+    /// channel is empty.
+    pub fn disconnect(&mut self) {
+        self.producer = None;
+        self.shared.receiver.wake();
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        UnboundedSender { producer: self.producer.clone(), shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.shared.panicked.store(true, SeqCst);
+        }
+
+        // The producer is dropped before the receiver is woken so that the receiver can observe
+        // that the channel has been disconnected.
+        self.producer = None;
+        self.shared.receiver.wake();
+    }
+}
+
+impl<T> Sink<T> for UnboundedSender<T> {
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), SendError>> {
+        if self.is_closed() {
+            Poll::Ready(Err(SendError { disconnected: true }))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), SendError> {
+        self.unbounded_send(item).map_err(|e| e.into_send_error())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), SendError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), SendError>> {
+        self.get_mut().disconnect();
+        Poll::Ready(Ok(()))
+    }
+}
+
+// WithTermination _______________________________
+
+/// A stream that receives the items in a channel wrapped in `Ok` followed by why the channel
+/// terminated wrapped in `Err`.
+///
+/// This lets a downstream stage distinguish a channel that was closed or whose senders were all
+/// dropped normally from a channel whose sender was dropped by a panicking thread.
+#[derive(Debug)]
+pub struct WithTermination<R> {
+    receiver: R,
+    shared: Arc<Shared>,
+    done: bool,
+}
+
+impl<R> WithTermination<R> {
+    //- Accessors --------------------------------
+
+    /// Returns a reference to the wrapped receiver.
+    pub fn get_ref(&self) -> &R {
+        &self.receiver
+    }
+
+    //- Consumers --------------------------------
+
+    /// Returns the wrapped receiver.
+    pub fn into_inner(self) -> R {
+        self.receiver
+    }
+}
+
+impl<R> FusedStream for WithTermination<R> where R: Stream + Unpin {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+impl<R> Stream for WithTermination<R> where R: Stream + Unpin {
+    type Item = Result<R::Item, Termination>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>, context: &mut Context
+    ) -> Poll<Option<Result<R::Item, Termination>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut self.receiver).poll_next(context) {
+            Poll::Ready(Some(item)) => Poll::Ready(Some(Ok(item))),
+            Poll::Ready(None) => {
+                self.done = true;
+                Poll::Ready(Some(Err(self.shared.termination())))
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a sender and receiver for a bounded channel backed by a bounded MPMC queue.
+///
+/// The queue can contain up to `buffer` items (or one item if `buffer` is zero). Each sender can
+/// hold one more item while the queue is full.
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    channel_with(buffer, OnFull::Wait)
+}
+
+/// Returns a sender and receiver for a bounded channel backed by a bounded MPMC queue whose
+/// senders do the supplied thing when the channel is full.
+///
+/// The queue can contain up to `buffer` items (or one item if `buffer` is zero). Each sender can
+/// only hold one more item while the queue is full if `on_full` is `OnFull::Wait`.
+pub fn channel_with<T>(buffer: usize, on_full: OnFull) -> (Sender<T>, Receiver<T>) {
+    let builder = bounded::mpmc::Builder::new(cmp::max(buffer, 1)).exact();
+    let builder = if on_full == OnFull::DropOldest { builder.evict_oldest() } else { builder };
+    let (producer, consumer) = builder.build();
+    let shared = Shared::new();
+    let sender = Sender {
+        producer: Some(producer),
+        pending: None,
+        on_full,
+        shared: shared.clone(),
+    };
+    (sender, Receiver { consumer, shared, terminated: false })
+}
+
+/// Returns a sender and receiver for an unbounded channel backed by an unbounded MPMC queue.
+pub fn unbounded<T>() -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+    let (producer, consumer) = unbounded::mpmc::channel(0);
+    let shared = Shared::new();
+    let sender = UnboundedSender { producer: Some(producer), shared: shared.clone() };
+    (sender, UnboundedReceiver { consumer, shared, terminated: false })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use self::futures::executor::{block_on};
+    use self::futures::stream::{StreamExt};
+    use self::futures::task::{noop_waker_ref};
+
+    #[test]
+    fn test_try_send_after_held_item() {
+        let (mut sender, mut receiver) = channel(1);
+        sender.try_send(0).unwrap();
+
+        // The channel is full, so the sender holds on to the item.
+        Pin::new(&mut sender).start_send(1).unwrap();
+        assert!(sender.try_send(2).unwrap_err().is_full());
+
+        // The held item is sent before the next item.
+        assert_eq!(receiver.try_next(), Ok(Some(0)));
+        sender.try_send(3).unwrap_err();
+        assert_eq!(receiver.try_next(), Ok(Some(1)));
+        sender.try_send(4).unwrap();
+        drop(sender);
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), [4]);
+    }
+
+    #[test]
+    fn test_poll_ready_sends_held_item() {
+        let mut context = Context::from_waker(noop_waker_ref());
+        let (mut sender, mut receiver) = channel(1);
+        sender.try_send(0).unwrap();
+        Pin::new(&mut sender).start_send(1).unwrap();
+        assert!(Pin::new(&mut sender).poll_ready(&mut context).is_pending());
+        assert_eq!(receiver.try_next(), Ok(Some(0)));
+        assert_eq!(Pin::new(&mut sender).poll_ready(&mut context), Poll::Ready(Ok(())));
+        assert_eq!(receiver.try_next(), Ok(Some(1)));
+    }
+
+    #[test]
+    fn test_termination() {
+        let (sender, receiver) = channel::<i32>(1);
+        drop(sender);
+        let items = block_on(receiver.with_termination().collect::<Vec<_>>());
+        assert_eq!(items, [Err(Termination::Disconnected)]);
+
+        let (sender, mut receiver) = unbounded::<i32>();
+        sender.unbounded_send(1).unwrap();
+        receiver.close();
+        assert!(sender.unbounded_send(2).unwrap_err().is_disconnected());
+        assert_eq!(receiver.try_next(), Ok(Some(1)));
+        assert_eq!(receiver.try_next(), Ok(None));
+        assert_eq!(receiver.termination(), Some(Termination::Closed));
+    }
+
+    #[test]
+    fn test_batches() {
+        let (sender, receiver) = unbounded();
+        for item in 0..5 {
+            sender.unbounded_send(item).unwrap();
+        }
+        drop(sender);
+        let batches = receiver.batches(2, Duration::from_secs(3600));
+        assert_eq!(block_on(batches.collect::<Vec<_>>()), [vec![0, 1], vec![2, 3], vec![4]]);
+    }
+
+    #[test]
+    fn test_batches_max_wait() {
+        let (mut sender, receiver) = channel(8);
+        for item in 0..3 {
+            sender.try_send(item).unwrap();
+        }
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            sender.try_send(3).unwrap();
+        });
+        let mut batches = receiver.batches(8, Duration::from_millis(50));
+        assert_eq!(block_on(batches.next()), Some(vec![0, 1, 2]));
+        assert_eq!(block_on(batches.next()), Some(vec![3]));
+        assert_eq!(block_on(batches.next()), None);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected="`max` is zero")]
+    fn test_batches_zero() {
+        let (_sender, receiver) = channel::<i32>(1);
+        let _ = receiver.batches(0, Duration::from_secs(1));
+    }
+
+
+    #[test]
+    fn test_on_full_error() {
+        let mut context = Context::from_waker(noop_waker_ref());
+        let (mut sender, mut receiver) = channel_with(1, OnFull::Error);
+        Pin::new(&mut sender).start_send(0).unwrap();
+        assert!(Pin::new(&mut sender).start_send(1).unwrap_err().is_full());
+        assert_eq!(Pin::new(&mut sender).poll_ready(&mut context), Poll::Ready(Ok(())));
+        assert_eq!(receiver.try_next(), Ok(Some(0)));
+        assert!(receiver.try_next().is_err());
+    }
+
+    #[test]
+    fn test_on_full_drop_oldest() {
+        let (mut sender, mut receiver) = channel_with(2, OnFull::DropOldest);
+        for item in 0..5 {
+            Pin::new(&mut sender).start_send(item).unwrap();
+        }
+        drop(sender);
+        assert_eq!(receiver.try_next(), Ok(Some(3)));
+        assert_eq!(block_on(receiver.collect::<Vec<_>>()), [4]);
+    }
+
+}
@@ -58,28 +58,142 @@
 
 #[cfg(feature="valgrind")]
 extern crate alloc_system;
+#[cfg(any(feature="affinity", feature="futex", feature="readiness"))]
+extern crate libc;
 
-extern crate hazard;
-
+use std::cmp;
 use std::error;
 use std::fmt;
+use std::hint;
+use std::mem::{self, MaybeUninit};
+use std::time::{Duration, Instant};
+
+use utility::{Backoff};
 
 #[macro_use]
 mod utility;
 mod buffer;
+mod monitor;
+mod notify;
+mod ring;
+pub mod adaptive;
+#[cfg(feature="affinity")]
+pub mod affinity;
+pub mod audio;
+pub mod batch;
+pub mod blocking;
 pub mod bounded;
+#[cfg(feature="bytes")]
+pub mod bytes;
+#[cfg(target_has_atomic="64")]
+pub mod causal;
+pub mod conflate;
+pub mod counted;
+pub mod credit;
+pub mod defer;
+pub mod depth;
+pub mod freeze;
+#[cfg(feature="futures")]
+pub mod futures;
+#[cfg(feature="futures")]
+pub mod mailbox;
+pub mod multi;
+pub mod oneshot;
+pub mod pool;
+pub mod quota;
+#[cfg(feature="rayon")]
+pub mod rayon;
+#[cfg(all(unix, feature="readiness"))]
+pub mod readiness;
+pub mod reclaim;
+pub mod rendezvous;
+pub mod reorder;
+#[cfg(feature="futures")]
+pub mod rpc;
+pub mod sample;
+pub mod select;
+pub mod sized;
+pub mod spill;
+pub mod stack;
+pub mod trace;
 pub mod unbounded;
+#[cfg(target_has_atomic="64")]
+pub mod watchdog;
+
+/// The size of a cacheline in bytes.
+///
+/// This is `64` with the `cacheline-64` feature and `128` otherwise, which covers CPUs that fetch
+/// cachelines in adjacent pairs. The fields of a queue that are written by different threads are
+/// padded to this size unless the `compact` feature is enabled.
+pub const CACHELINE: usize = if cfg!(feature="cacheline-64") { 64 } else { 128 };
+
+/// The number of pointers that fit in a cacheline.
+///
+/// This is at least `1` so that the padding is well-defined on targets with any pointer width
+/// (e.g., 16-bit targets or targets with pointers wider than a cacheline).
+const POINTERS: usize = if mem::size_of::<usize>() < CACHELINE {
+    CACHELINE / mem::size_of::<usize>()
+} else {
+    1
+};
 
-/// The number of pointers that fit in a 128 byte cacheline.
-#[cfg(target_pointer_width="32")]
-const POINTERS: usize = 32;
-#[cfg(target_pointer_width="64")]
-const POINTERS: usize = 16;
+/// The maximum number of items `transfer` moves between queues at once.
+const BATCH: usize = 64;
 
 //================================================
 // Enums
 //================================================
 
+// ChannelError __________________________________
+
+/// Indicates the reason a queue could not be constructed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelError {
+    /// The requested capacity was not valid for the queue (e.g., not a power of two).
+    InvalidCapacity,
+    /// The size of the buffer for the requested capacity overflowed.
+    Overflow,
+    /// The buffer for the requested capacity could not be allocated.
+    AllocationFailure,
+}
+
+impl error::Error for ChannelError { }
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            ChannelError::InvalidCapacity => "the capacity was not valid for the queue",
+            ChannelError::Overflow => "the size of the buffer overflowed",
+            ChannelError::AllocationFailure => "the buffer could not be allocated",
+        };
+        formatter.write_str(description)
+    }
+}
+
+// CloneError ____________________________________
+
+/// Indicates the reason a producer or consumer could not be cloned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CloneError {
+    /// The queue had no remaining producers or consumers on the other side or had been closed.
+    Disconnected,
+    /// The queue had no space left for another producer or consumer and was constructed to refuse
+    /// clones instead of allocating space for more of them.
+    LimitReached,
+}
+
+impl error::Error for CloneError { }
+
+impl fmt::Display for CloneError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            CloneError::Disconnected => "the queue was disconnected",
+            CloneError::LimitReached => "the queue had no space left for another clone",
+        };
+        formatter.write_str(description)
+    }
+}
+
 // ConsumeError __________________________________
 
 /// Indicates the reason a `consume` operation could not return an item.
@@ -89,6 +203,10 @@ pub enum ConsumeError {
     Disconnected,
     /// The queue was empty.
     Empty,
+    /// The consumer gave up after failing to claim an item too many times.
+    Contended,
+    /// The queue was empty until the timeout elapsed.
+    Timeout,
 }
 
 impl error::Error for ConsumeError {
@@ -96,6 +214,8 @@ impl error::Error for ConsumeError {
         match *self {
             ConsumeError::Disconnected => "the queue was empty and had no remaining producers",
             ConsumeError::Empty => "the queue was empty",
+            ConsumeError::Contended => "the consumer failed to claim an item too many times",
+            ConsumeError::Timeout => "the queue was empty until the timeout elapsed",
         }
     }
 }
@@ -115,6 +235,14 @@ pub enum ProduceError<T> {
     Disconnected(T),
     /// The queue was full.
     Full(T),
+    /// The producer had no remaining credits.
+    NoCredit(T),
+    /// The producer gave up after failing to claim a slot too many times.
+    Contended(T),
+    /// The queue could not allocate memory for the item.
+    AllocFailed(T),
+    /// The queue was full until the deadline passed.
+    Timeout(T),
 }
 
 impl<T> ProduceError<T> {
@@ -122,7 +250,26 @@ impl<T> ProduceError<T> {
 
     /// Returns the rejected item.
     pub fn item(self) -> T {
-        match self { ProduceError::Disconnected(item) | ProduceError::Full(item) => item }
+        match self {
+            ProduceError::Disconnected(item) |
+            ProduceError::Full(item) |
+            ProduceError::NoCredit(item) |
+            ProduceError::Contended(item) |
+            ProduceError::AllocFailed(item) |
+            ProduceError::Timeout(item) => item,
+        }
+    }
+
+    /// Returns this error with the rejected item replaced by the result of the supplied function.
+    pub(crate) fn map<U, F>(self, f: F) -> ProduceError<U> where F: FnOnce(T) -> U {
+        match self {
+            ProduceError::Disconnected(item) => ProduceError::Disconnected(f(item)),
+            ProduceError::Full(item) => ProduceError::Full(f(item)),
+            ProduceError::NoCredit(item) => ProduceError::NoCredit(f(item)),
+            ProduceError::Contended(item) => ProduceError::Contended(f(item)),
+            ProduceError::AllocFailed(item) => ProduceError::AllocFailed(f(item)),
+            ProduceError::Timeout(item) => ProduceError::Timeout(f(item)),
+        }
     }
 }
 
@@ -131,6 +278,10 @@ impl<T> error::Error for ProduceError<T> {
         match *self {
             ProduceError::Disconnected(_) => "the queue had no remaining consumers",
             ProduceError::Full(_) => "the queue was full",
+            ProduceError::NoCredit(_) => "the producer had no remaining credits",
+            ProduceError::Contended(_) => "the producer failed to claim a slot too many times",
+            ProduceError::AllocFailed(_) => "the queue could not allocate memory for the item",
+            ProduceError::Timeout(_) => "the queue was full until the deadline passed",
         }
     }
 }
@@ -140,6 +291,10 @@ impl<T> fmt::Debug for ProduceError<T> {
         match *self {
             ProduceError::Disconnected(_) => write!(formatter, "ProduceError::Disconnected(..)"),
             ProduceError::Full(_) => write!(formatter, "ProduceError::Full(..)"),
+            ProduceError::NoCredit(_) => write!(formatter, "ProduceError::NoCredit(..)"),
+            ProduceError::Contended(_) => write!(formatter, "ProduceError::Contended(..)"),
+            ProduceError::AllocFailed(_) => write!(formatter, "ProduceError::AllocFailed(..)"),
+            ProduceError::Timeout(_) => write!(formatter, "ProduceError::Timeout(..)"),
         }
     }
 }
@@ -149,3 +304,369 @@ impl<T> fmt::Display for ProduceError<T> {
         write!(formatter, "{}", error::Error::description(self))
     }
 }
+
+//================================================
+// Structs
+//================================================
+
+// MemoryUsage ___________________________________
+
+/// The memory used by a queue.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The number of bytes used by the buffer or nodes of the queue.
+    ///
+    /// This does not include any memory owned by the items in the queue.
+    pub bytes: usize,
+    /// The number of nodes in the queue (always `0` for bounded queues).
+    pub nodes: usize,
+    /// The number of nodes removed from the queue that have not been freed (always `0` for bounded
+    /// queues).
+    pub retired: usize,
+}
+
+//================================================
+// Traits
+//================================================
+
+// Consume _______________________________________
+
+/// A type that can remove items from a queue.
+pub trait Consume<T> {
+    /// Attempts to remove and return an item from the queue.
+    fn consume(&self) -> Result<T, ConsumeError>;
+
+    /// Attempts to remove and return an item from the queue without retrying if another consumer
+    /// interferes.
+    ///
+    /// This method may fail with `Contended` even if the queue contains items, which is cheaper for
+    /// callers that retry at a higher level anyway (e.g., a scheduler polling many queues). By
+    /// default this method is the same as `consume`.
+    fn consume_weak(&self) -> Result<T, ConsumeError> {
+        self.consume()
+    }
+
+    /// Moves items from the queue into the supplied slice until the slice is full or an item
+    /// can't be removed and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        for (index, slot) in items.iter_mut().enumerate() {
+            match self.consume() {
+                Ok(item) => { *slot = MaybeUninit::new(item); },
+                Err(_) => return index,
+            }
+        }
+        items.len()
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if no items could be removed.
+    fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        let mut latest = self.consume()?;
+        let mut skipped = 0;
+        while let Ok(item) = self.consume() {
+            latest = item;
+            skipped += 1;
+        }
+        Ok((latest, skipped))
+    }
+
+    /// Removes and drops up to the supplied number of items from the queue and returns the number
+    /// of items dropped.
+    fn discard(&self, n: usize) -> usize {
+        let mut count = 0;
+        while count < n && self.consume().is_ok() {
+            count += 1;
+        }
+        count
+    }
+
+    /// Removes and returns an item from the queue, retrying with backoff for up to the supplied
+    /// duration while the queue is empty or contended.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed. A
+    /// duration too long to be represented as a deadline waits indefinitely. The waiting thread
+    /// spins, yields, and then sleeps briefly between attempts; the consumers in the `blocking`
+    /// module sleep until they are woken by a producer instead.
+    fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        let deadline = Instant::now().checked_add(timeout);
+        let mut backoff = Backoff::new();
+        loop {
+            match self.consume() {
+                Err(ConsumeError::Empty) | Err(ConsumeError::Contended) => { },
+                result => return result,
+            }
+
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(ConsumeError::Timeout);
+            }
+            backoff.snooze(deadline);
+        }
+    }
+
+    /// Removes and returns an item from the queue, spinning while the queue is empty or contended.
+    ///
+    /// This method only returns `Err` if the queue is empty and has no remaining producers or has
+    /// been closed. Unlike `consume_timeout`, the waiting thread never yields or sleeps and only
+    /// calls `hint::spin_loop` between attempts, so this method can wait for items where threads
+    /// can't be parked (e.g., in an interrupt handler or on a single-purpose core).
+    fn consume_spin(&self) -> Result<T, ConsumeError> {
+        loop {
+            match self.consume() {
+                Err(ConsumeError::Empty) | Err(ConsumeError::Contended) => hint::spin_loop(),
+                result => return result,
+            }
+        }
+    }
+}
+
+// Produce _______________________________________
+
+/// A type that can add items to a queue.
+pub trait Produce<T> {
+    /// Attempts to add the supplied item to the queue.
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>>;
+
+    /// Attempts to add the supplied item to the queue without retrying if another producer
+    /// interferes.
+    ///
+    /// This method may fail with `Contended` even if the queue has room for the item, which is
+    /// cheaper for callers that retry at a higher level anyway. By default this method is the same
+    /// as `produce`.
+    fn produce_weak(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.produce(item)
+    }
+
+    /// Moves items from the front of the supplied vector into the queue until the vector is empty
+    /// or an item can't be added and returns the number of items moved.
+    ///
+    /// The items that were not moved are left in the vector in their original order.
+    fn produce_many(&self, items: &mut Vec<T>) -> usize {
+        items.reverse();
+        let mut count = 0;
+        while let Some(item) = items.pop() {
+            if let Err(error) = self.produce(item) {
+                items.push(error.item());
+                break;
+            }
+            count += 1;
+        }
+        items.reverse();
+        count
+    }
+
+    /// Adds the supplied item to the queue, spinning while the queue is full or contended.
+    ///
+    /// This method only returns `Err` if the queue has no remaining consumers or has been closed
+    /// or if the queue rejects the item for another reason. Unlike the `produce_blocking` methods,
+    /// the waiting thread never yields or sleeps and only calls `hint::spin_loop` between attempts.
+    fn produce_spin(&self, item: T) -> Result<(), ProduceError<T>> {
+        let mut item = item;
+        loop {
+            match self.produce(item) {
+                Err(ProduceError::Full(returned)) | Err(ProduceError::Contended(returned)) => {
+                    item = returned;
+                    hint::spin_loop();
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns the number of pointers needed to pad the supplied number of pointers to a multiple of
+/// a cacheline.
+///
+/// The supplied pointers may span more than one cacheline on targets with wide pointers or small
+/// cachelines, in which case they are padded to the next cacheline boundary. This is always `0`
+/// with the `compact` feature.
+const fn padding(pointers: usize) -> usize {
+    if cfg!(feature="compact") { 0 } else { (POINTERS - (pointers % POINTERS)) % POINTERS }
+}
+
+/// Repeatedly attempts to add the supplied item with the supplied function while the queue is
+/// full or contended, backing off between attempts, until the supplied deadline passes, if any.
+fn produce_deadline<T, F>(
+    item: T, deadline: Option<Instant>, mut f: F
+) -> Result<(), ProduceError<T>> where F: FnMut(T) -> Result<(), ProduceError<T>> {
+    let mut item = item;
+    let mut backoff = Backoff::new();
+    loop {
+        match f(item) {
+            Err(ProduceError::Full(returned)) | Err(ProduceError::Contended(returned)) => {
+                item = returned;
+            },
+            result => return result,
+        }
+
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(ProduceError::Timeout(item));
+        }
+        backoff.snooze(deadline);
+    }
+}
+
+/// Moves up to `max` items from the queue of the supplied consumer to the queue of the supplied
+/// producer and returns the number of items moved.
+///
+/// The items are removed with `consume_uninit` and added with `produce_many` in batches, so queues
+/// that implement those methods with a single update of their indices (e.g., the bounded SPSC
+/// queue) only update their indices once per batch. The items are staged in the supplied buffer,
+/// which can be reused between calls to avoid allocating. If the destination queue rejects an
+/// item (e.g., because it is full), this function returns and the items that were removed from the
+/// source queue but not moved are left in the buffer in their original order. The items in the
+/// buffer are moved first (and count towards `max`) the next time it is supplied, and any of them
+/// past `max` are left in the buffer.
+///
+/// # Example
+///
+/// ```
+/// extern crate npnc;
+///
+/// use npnc::bounded::spsc;
+///
+/// fn main() {
+///     let (producer1, consumer1) = spsc::channel(64);
+///     let (producer2, consumer2) = spsc::channel(4);
+///     for index in 0..6 {
+///         producer1.produce(index).unwrap();
+///     }
+///
+///     let mut buffer = vec![];
+///     assert_eq!(npnc::transfer(&consumer1, &producer2, 8, &mut buffer), 4);
+///     assert_eq!(buffer, vec![4, 5]);
+///
+///     assert_eq!(consumer2.consume(), Ok(0));
+///     assert_eq!(npnc::transfer(&consumer1, &producer2, 8, &mut buffer), 1);
+///     assert_eq!(buffer, vec![5]);
+/// }
+/// ```
+pub fn transfer<T, C, P>(consumer: &C, producer: &P, max: usize, buffer: &mut Vec<T>) -> usize
+    where C: Consume<T>, P: Produce<T>
+{
+    let mut moved = 0;
+    loop {
+        // Add up to `max` of the items in the buffer and stop if any of them are rejected or the
+        // limit is reached. The items past the limit are kept behind any rejected items.
+        if !buffer.is_empty() {
+            let rest = buffer.split_off(cmp::min(max - moved, buffer.len()));
+            moved += producer.produce_many(buffer);
+            let stop = !buffer.is_empty() || !rest.is_empty();
+            buffer.extend(rest);
+            if stop {
+                return moved;
+            }
+        }
+
+        // Remove the next batch of items.
+        if moved >= max {
+            return moved;
+        }
+        let batch = cmp::min(max - moved, BATCH);
+        buffer.reserve(batch);
+        let removed = consumer.consume_uninit(&mut buffer.spare_capacity_mut()[..batch]);
+        if removed == 0 {
+            return moved;
+        }
+        unsafe { buffer.set_len(removed); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_transfer_limits_buffered_items() {
+        let (producer1, consumer1) = spsc::channel::<i32>(4);
+        let (producer2, consumer2) = spsc::channel(4);
+
+        let mut buffer = vec![0, 1, 2];
+        assert_eq!(transfer(&consumer1, &producer2, 0, &mut buffer), 0);
+        assert_eq!(buffer, vec![0, 1, 2]);
+        assert_eq!(consumer2.len(), 0);
+
+        producer1.produce(3).unwrap();
+        assert_eq!(transfer(&consumer1, &producer2, 2, &mut buffer), 2);
+        assert_eq!(buffer, vec![2]);
+        assert_eq!(consumer1.len(), 1);
+
+        assert_eq!(transfer(&consumer1, &producer2, 8, &mut buffer), 2);
+        assert!(buffer.is_empty());
+        assert_eq!((0..4).map(|_| consumer2.consume().unwrap()).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_transfer_keeps_rejected_items_in_order() {
+        let (producer1, consumer1) = spsc::channel(8);
+        let (producer2, consumer2) = spsc::channel(2);
+        for index in 0..4 {
+            producer1.produce(index).unwrap();
+        }
+
+        let mut buffer = vec![];
+        assert_eq!(transfer(&consumer1, &producer2, 8, &mut buffer), 2);
+        assert_eq!(buffer, vec![2, 3]);
+        assert_eq!(consumer2.consume(), Ok(0));
+        assert_eq!(transfer(&consumer1, &producer2, 8, &mut buffer), 1);
+        assert_eq!(buffer, vec![3]);
+    }
+
+    #[test]
+    fn test_consume_timeout() {
+        let (producer, consumer) = spsc::channel(4);
+        let timeout = Duration::from_millis(10);
+        assert_eq!(consumer.consume_timeout(timeout), Err(ConsumeError::Timeout));
+        producer.produce(1).unwrap();
+        assert_eq!(consumer.consume_timeout(timeout), Ok(1));
+        drop(producer);
+        let forever = Duration::new(u64::MAX, 0);
+        assert_eq!(consumer.consume_timeout(forever), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_produce_blocking() {
+        use std::thread;
+
+        let (producer, consumer) = spsc::channel(2);
+        let thread = thread::spawn(move || {
+            for item in 0..8 {
+                producer.produce_blocking(item).unwrap();
+            }
+            producer
+        });
+        let items = (0..8).map(|_| consumer.consume_timeout(Duration::from_secs(10)));
+        assert_eq!(items.collect::<Vec<_>>(), (0..8).map(Ok).collect::<Vec<_>>());
+
+        let producer = thread.join().unwrap();
+        drop(consumer);
+        assert_eq!(producer.produce_blocking(8), Err(ProduceError::Disconnected(8)));
+    }
+
+    #[test]
+    fn test_spin() {
+        use std::thread;
+
+        let (producer, consumer) = spsc::channel(2);
+        let thread = thread::spawn(move || {
+            for item in 0..8 {
+                producer.produce_spin(item).unwrap();
+            }
+        });
+        let items = (0..8).map(|_| consumer.consume_spin()).collect::<Vec<_>>();
+        assert_eq!(items, (0..8).map(Ok).collect::<Vec<_>>());
+
+        thread.join().unwrap();
+        assert_eq!(consumer.consume_spin(), Err(ConsumeError::Disconnected));
+    }
+
+}
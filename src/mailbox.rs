@@ -0,0 +1,476 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mailboxes for actors.
+//!
+//! A mailbox holds the messages sent to an actor through its addresses. Ordinary messages are
+//! added to an unbounded MPMC queue that only the mailbox removes messages from. Control messages
+//! (e.g., requests to stop or restart an actor) are added to a separate bounded queue, the
+//! priority lane, which the mailbox always empties first so that control messages overtake the
+//! ordinary messages that are waiting.
+//!
+//! The mailbox is overloaded while it contains its limit of ordinary messages. An overloaded
+//! mailbox rejects the messages passed to `Address::try_send` and suspends the tasks awaiting
+//! `Address::send` until the actor has caught up. `Address::force_send` ignores the limit for
+//! senders that can't wait (e.g., a supervisor forwarding a message it has already accepted).
+//!
+//! This module requires the `futures` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate futures;
+//! extern crate npnc;
+//!
+//! use futures::executor::{block_on};
+//! use npnc::mailbox;
+//!
+//! #[derive(Debug, PartialEq, Eq)]
+//! enum Message {
+//!     Add(i32),
+//!     Stop,
+//! }
+//!
+//! fn main() {
+//!     let (address, mailbox) = mailbox::mailbox(2, 1);
+//!
+//!     address.try_send(Message::Add(1)).unwrap();
+//!     address.try_send(Message::Add(2)).unwrap();
+//!     assert!(address.is_overloaded());
+//!     assert!(address.try_send(Message::Add(3)).is_err());
+//!     address.try_send_control(Message::Stop).unwrap();
+//!
+//!     assert_eq!(block_on(mailbox.recv()), Some(Message::Stop));
+//!     assert_eq!(block_on(mailbox.recv()), Some(Message::Add(1)));
+//!     block_on(address.send(Message::Add(3))).unwrap();
+//!     assert_eq!(mailbox.len(), 2);
+//!
+//!     drop(address);
+//!     assert_eq!(block_on(mailbox.recv()), Some(Message::Add(2)));
+//!     assert_eq!(block_on(mailbox.recv()), Some(Message::Add(3)));
+//!     assert_eq!(block_on(mailbox.recv()), None);
+//! }
+//! ```
+
+extern crate futures;
+
+use std::future::{Future};
+use std::pin::{Pin};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::task::{Context, Poll};
+
+use self::futures::stream::{Stream};
+use self::futures::task::{AtomicWaker};
+
+use {ConsumeError, ProduceError};
+use bounded;
+use futures::{Parked};
+use unbounded;
+
+//================================================
+// Structs
+//================================================
+
+// Address _______________________________________
+
+/// A handle used to send messages to a mailbox.
+#[derive(Debug)]
+pub struct Address<M> {
+    normal: Option<unbounded::mpmc::Producer<M>>,
+    control: Option<bounded::mpmc::Producer<M>>,
+    shared: Arc<Shared>,
+}
+
+impl<M> Address<M> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied message to the mailbox without waiting.
+    ///
+    /// This method returns `Err(ProduceError::Full)` if the mailbox is overloaded.
+    pub fn try_send(&self, message: M) -> Result<(), ProduceError<M>> {
+        self.produce(message, false)
+    }
+
+    /// Returns a future that adds the supplied message to the mailbox, waiting while the mailbox
+    /// is overloaded.
+    ///
+    /// The future resolves to `Err(ProduceError::Disconnected)` if the mailbox has been closed or
+    /// dropped.
+    pub fn send<'a>(&'a self, message: M) -> Sending<'a, M> {
+        Sending { address: self, message: Some(message) }
+    }
+
+    /// Adds the supplied message to the mailbox even if the mailbox is overloaded.
+    pub fn force_send(&self, message: M) -> Result<(), ProduceError<M>> {
+        self.produce(message, true)
+    }
+
+    /// Attempts to add the supplied message to the priority lane of the mailbox.
+    ///
+    /// This method returns `Err(ProduceError::Full)` if the priority lane is full.
+    pub fn try_send_control(&self, message: M) -> Result<(), ProduceError<M>> {
+        let control = match self.control {
+            Some(ref control) if !self.shared.is_closed() => control,
+            _ => return Err(ProduceError::Disconnected(message)),
+        };
+
+        control.produce(message)?;
+        self.shared.receiver.wake();
+        Ok(())
+    }
+
+    /// Returns whether the mailbox is overloaded.
+    pub fn is_overloaded(&self) -> bool {
+        self.shared.len.load(SeqCst) >= self.shared.limit
+    }
+
+    /// Returns whether the mailbox has been closed or dropped.
+    pub fn is_closed(&self) -> bool {
+        let connected = self.normal.as_ref().is_some_and(|p| p.consumer_count() != 0);
+        !connected || self.shared.is_closed()
+    }
+
+    /// Returns whether this address and the supplied address send messages to the same mailbox.
+    pub fn same_mailbox(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    fn produce(&self, message: M, force: bool) -> Result<(), ProduceError<M>> {
+        let normal = match self.normal {
+            Some(ref normal) if !self.shared.is_closed() => normal,
+            _ => return Err(ProduceError::Disconnected(message)),
+        };
+
+        // The message is counted before it is added so that concurrent senders can't exceed the
+        // limit of the mailbox.
+        let len = self.shared.len.fetch_add(1, SeqCst);
+        if !force && len >= self.shared.limit {
+            self.shared.len.fetch_sub(1, SeqCst);
+            return Err(ProduceError::Full(message));
+        }
+
+        if let Err(error) = normal.produce(message) {
+            self.shared.len.fetch_sub(1, SeqCst);
+            return Err(error);
+        }
+
+        self.shared.receiver.wake();
+        Ok(())
+    }
+}
+
+impl<M> Clone for Address<M> {
+    fn clone(&self) -> Self {
+        let normal = self.normal.clone();
+        Address { normal, control: self.control.clone(), shared: self.shared.clone() }
+    }
+}
+
+impl<M> Drop for Address<M> {
+    fn drop(&mut self) {
+        // The producers are dropped before the mailbox is woken so that the mailbox can observe
+        // that it has been disconnected.
+        self.normal = None;
+        self.control = None;
+        self.shared.receiver.wake();
+    }
+}
+
+// Mailbox _______________________________________
+
+/// A queue of messages for an actor with a priority lane for control messages.
+///
+/// The mailbox is closed when it is dropped.
+#[derive(Debug)]
+pub struct Mailbox<M> {
+    normal: unbounded::mpmc::Consumer<M>,
+    control: bounded::mpmc::Consumer<M>,
+    shared: Arc<Shared>,
+}
+
+impl<M> Mailbox<M> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return a message from this mailbox without waiting.
+    ///
+    /// Control messages are always returned before ordinary messages. This method returns
+    /// `Err(ConsumeError::Disconnected)` if this mailbox is empty and has been closed or has no
+    /// remaining addresses.
+    pub fn try_recv(&self) -> Result<M, ConsumeError> {
+        if let Ok(message) = self.control.consume() {
+            return Ok(message);
+        }
+
+        match self.normal.consume() {
+            Ok(message) => {
+                self.shared.len.fetch_sub(1, SeqCst);
+                self.shared.senders.wake();
+                Ok(message)
+            },
+            Err(ConsumeError::Empty) if !self.shared.is_closed() => Err(ConsumeError::Empty),
+            // A control message may have been sent just before this mailbox was disconnected.
+            Err(ConsumeError::Empty) | Err(ConsumeError::Disconnected) => {
+                self.control.consume().map_err(|_| ConsumeError::Disconnected)
+            },
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns a future that removes and returns a message from this mailbox, waiting while this
+    /// mailbox is empty.
+    ///
+    /// The future resolves to `None` if this mailbox is empty and has been closed or has no
+    /// remaining addresses.
+    pub fn recv<'a>(&'a self) -> Receiving<'a, M> {
+        Receiving { mailbox: self }
+    }
+
+    /// Closes this mailbox so the addresses can't send any more messages.
+    ///
+    /// The messages already in this mailbox can still be received.
+    pub fn close(&self) {
+        self.shared.closed.store(true, SeqCst);
+        self.shared.senders.wake();
+    }
+
+    /// Returns the number of ordinary messages currently in this mailbox.
+    pub fn len(&self) -> usize {
+        self.shared.len.load(SeqCst)
+    }
+
+    /// Returns whether this mailbox currently contains no ordinary messages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of ordinary messages this mailbox can contain before it is overloaded.
+    pub fn limit(&self) -> usize {
+        self.shared.limit
+    }
+
+    fn poll_recv(&self, context: &mut Context) -> Poll<Option<M>> {
+        match self.try_recv() {
+            Ok(message) => return Poll::Ready(Some(message)),
+            Err(ConsumeError::Disconnected) => return Poll::Ready(None),
+            Err(_) => { },
+        }
+
+        // The mailbox is registered before trying again so that an address that adds a message
+        // in the meantime will wake it.
+        self.shared.receiver.register(context.waker());
+        match self.try_recv() {
+            Ok(message) => Poll::Ready(Some(message)),
+            Err(ConsumeError::Disconnected) => Poll::Ready(None),
+            Err(_) => Poll::Pending,
+        }
+    }
+}
+
+impl<M> Drop for Mailbox<M> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<M> Stream for Mailbox<M> {
+    type Item = M;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<M>> {
+        self.poll_recv(context)
+    }
+}
+
+// Receiving _____________________________________
+
+/// A future that removes and returns a message from a mailbox.
+#[derive(Debug)]
+pub struct Receiving<'a, M> where M: 'a {
+    mailbox: &'a Mailbox<M>,
+}
+
+impl<'a, M> Future for Receiving<'a, M> {
+    type Output = Option<M>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<M>> {
+        self.mailbox.poll_recv(context)
+    }
+}
+
+// Sending _______________________________________
+
+/// A future that adds a message to a mailbox once the mailbox is not overloaded.
+#[derive(Debug)]
+pub struct Sending<'a, M> where M: 'a {
+    address: &'a Address<M>,
+    message: Option<M>,
+}
+
+impl<'a, M> Future for Sending<'a, M> {
+    type Output = Result<(), ProduceError<M>>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), ProduceError<M>>> {
+        let this = self.get_mut();
+        let message = this.message.take().expect("`Sending` polled after completion");
+        let message = match this.address.try_send(message) {
+            Err(ProduceError::Full(message)) => message,
+            result => return Poll::Ready(result),
+        };
+
+        // The task is parked before trying again so that a mailbox that makes room in the
+        // meantime will wake it.
+        this.address.shared.senders.park(context.waker());
+        match this.address.try_send(message) {
+            Err(ProduceError::Full(message)) => {
+                this.message = Some(message);
+                Poll::Pending
+            },
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+impl<'a, M> Unpin for Sending<'a, M> { }
+
+// Shared ________________________________________
+
+/// The state shared by the addresses of a mailbox and the mailbox.
+#[derive(Debug)]
+struct Shared {
+    /// The number of ordinary messages in the mailbox.
+    len: AtomicUsize,
+    limit: usize,
+    closed: AtomicBool,
+    receiver: AtomicWaker,
+    senders: Parked,
+}
+
+impl Shared {
+    //- Accessors --------------------------------
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(SeqCst)
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns an address and mailbox for a mailbox that is overloaded once it contains `limit`
+/// ordinary messages and whose priority lane can contain up to `control` control messages.
+///
+/// # Panics
+///
+/// * `limit` is zero
+/// * `control` is zero
+pub fn mailbox<M>(limit: usize, control: usize) -> (Address<M>, Mailbox<M>) {
+    assert!(limit != 0, "`limit` is zero");
+    assert!(control != 0, "`control` is zero");
+    let (normal, normal_consumer) = unbounded::mpmc::channel(0);
+    let (control, control_consumer) = bounded::mpmc::channel_exact(control);
+    let shared = Arc::new(Shared {
+        len: AtomicUsize::new(0),
+        limit,
+        closed: AtomicBool::new(false),
+        receiver: AtomicWaker::new(),
+        senders: Parked::new(),
+    });
+    let address = Address { normal: Some(normal), control: Some(control), shared: shared.clone() };
+    (address, Mailbox { normal: normal_consumer, control: control_consumer, shared })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::time::{Duration};
+
+    use self::futures::executor::{block_on};
+    use self::futures::task::{noop_waker_ref};
+
+    #[test]
+    fn test_control() {
+        let (address, mailbox) = mailbox(4, 1);
+        address.try_send(1).unwrap();
+        address.try_send_control(2).unwrap();
+        assert_eq!(address.try_send_control(3), Err(ProduceError::Full(3)));
+        assert_eq!(mailbox.len(), 1);
+
+        assert_eq!(mailbox.try_recv(), Ok(2));
+        assert_eq!(mailbox.try_recv(), Ok(1));
+        assert_eq!(mailbox.try_recv(), Err(ConsumeError::Empty));
+
+        // A control message sent just before the addresses are dropped is still received.
+        address.try_send_control(4).unwrap();
+        drop(address);
+        assert_eq!(mailbox.try_recv(), Ok(4));
+        assert_eq!(mailbox.try_recv(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_overload() {
+        let (address, mailbox) = mailbox(1, 1);
+        assert_eq!(mailbox.limit(), 1);
+        address.try_send(1).unwrap();
+        assert!(address.is_overloaded());
+        assert_eq!(address.try_send(2), Err(ProduceError::Full(2)));
+        address.force_send(2).unwrap();
+        assert_eq!(mailbox.len(), 2);
+
+        // A sender waiting for room is woken when the mailbox catches up.
+        let sender = address.clone();
+        assert!(sender.same_mailbox(&address));
+        let thread = thread::spawn(move || block_on(sender.send(3)));
+        assert_eq!(block_on(mailbox.recv()), Some(1));
+        assert_eq!(block_on(mailbox.recv()), Some(2));
+        assert_eq!(thread.join().unwrap(), Ok(()));
+        assert_eq!(block_on(mailbox.recv()), Some(3));
+        assert!(mailbox.is_empty());
+    }
+
+    #[test]
+    fn test_close() {
+        let (address, mailbox) = mailbox(1, 1);
+        address.try_send(1).unwrap();
+
+        // A sender waiting for room is woken when the mailbox is closed.
+        let sender = address.clone();
+        let thread = thread::spawn(move || block_on(sender.send(2)));
+        thread::sleep(Duration::from_millis(10));
+        mailbox.close();
+        assert_eq!(thread.join().unwrap(), Err(ProduceError::Disconnected(2)));
+
+        assert!(address.is_closed());
+        assert_eq!(address.try_send(3), Err(ProduceError::Disconnected(3)));
+        assert_eq!(address.try_send_control(4), Err(ProduceError::Disconnected(4)));
+        assert_eq!(block_on(mailbox.recv()), Some(1));
+        assert_eq!(mailbox.try_recv(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_wake() {
+        let (address, mailbox) = mailbox(4, 1);
+        let mut context = Context::from_waker(noop_waker_ref());
+        let mut receiving = mailbox.recv();
+        assert_eq!(Pin::new(&mut receiving).poll(&mut context), Poll::Pending);
+
+        let thread = thread::spawn(move || address.try_send(1));
+        assert_eq!(block_on(mailbox.recv()), Some(1));
+        thread.join().unwrap().unwrap();
+        assert_eq!(block_on(mailbox.recv()), None);
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+//================================================
+// Functions
+//================================================
+
+/// Writes to the cacheline containing the supplied atomic without changing its value, which wakes
+/// any threads waiting on the cacheline with `wait`.
+pub fn touch(atomic: &AtomicUsize) {
+    // An idempotent read-modify-write like `fetch_or(0)` may be compiled to a fenced load, so a
+    // compare-and-swap that stores the value it loaded is used instead. If it fails, the cacheline
+    // was written to by another thread anyway.
+    let value = atomic.load(Relaxed);
+    let _ = atomic.compare_exchange(value, value, Release, Relaxed);
+}
+
+/// Waits in a low-power state until the cacheline containing the supplied atomic is written to,
+/// unless the supplied function returns `true`.
+///
+/// On x86-64 processors that support `umonitor` and `umwait`, this waits for the cacheline
+/// containing the atomic to be written to (or for a timeout of about 100,000 cycles). On AArch64
+/// processors, this waits for an event with `wfe` after loading the atomic exclusively, which is
+/// generated when the cacheline is written to (or by the event stream of the generic timer).
+/// Otherwise, this is a spin loop hint.
+///
+/// The function is passed the value of the atomic and is called after the cacheline starts being
+/// monitored, so a write that makes it return `true` (e.g., a write that disconnects the queue
+/// followed by a write to the cacheline) is never missed. This may return before anything has
+/// changed, so the condition being waited for should be checked again.
+#[cfg(target_arch="x86_64")]
+pub fn wait<F>(atomic: &AtomicUsize, ready: F) where F: FnOnce(usize) -> bool {
+    use std::arch::asm;
+    use std::arch::x86_64::{__cpuid, __cpuid_count, _rdtsc};
+    use std::hint;
+
+    /// The number of cycles to wait for before giving up.
+    const TIMEOUT: u64 = 100_000;
+
+    /// Whether `umonitor` and `umwait` are supported (`0` if unknown, `1` if not, or `2` if so).
+    static SUPPORTED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut supported = SUPPORTED.load(Relaxed);
+    if supported == 0 {
+        // The `waitpkg` feature is bit 5 of `ecx` for leaf 7.
+        let waitpkg = __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ecx & (1 << 5) != 0;
+        supported = if waitpkg { 2 } else { 1 };
+        SUPPORTED.store(supported, Relaxed);
+    }
+
+    if supported != 2 {
+        hint::spin_loop();
+        return;
+    }
+
+    unsafe {
+        asm!("umonitor {0}", in(reg) atomic.as_ptr(), options(nostack, preserves_flags));
+        if ready(atomic.load(Acquire)) {
+            return;
+        }
+
+        // The deadline is passed in `edx:eax` and `0` requests the lighter C0.2 state.
+        let deadline = _rdtsc().wrapping_add(TIMEOUT);
+        asm!(
+            "umwait {0:e}",
+            in(reg) 0u32,
+            in("edx") (deadline >> 32) as u32,
+            in("eax") deadline as u32,
+            options(nostack),
+        );
+    }
+}
+
+/// Waits in a low-power state until the cacheline containing the supplied atomic is written to,
+/// unless the supplied function returns `true`.
+///
+/// See the x86-64 version of this function for details.
+#[cfg(target_arch="aarch64")]
+pub fn wait<F>(atomic: &AtomicUsize, ready: F) where F: FnOnce(usize) -> bool {
+    use std::arch::asm;
+
+    unsafe {
+        // Loading the atomic exclusively arms the exclusive monitor, which generates an event when
+        // another core writes to the cacheline containing the atomic.
+        let current: usize;
+        asm!("ldaxr {0}, [{1}]", out(reg) current, in(reg) atomic.as_ptr(), options(nostack));
+        if !ready(current) {
+            asm!("wfe", options(nomem, nostack));
+        }
+        asm!("clrex", options(nomem, nostack));
+    }
+}
+
+/// Waits until the cacheline containing the supplied atomic might have been written to.
+///
+/// See the x86-64 version of this function for details.
+#[cfg(not(any(target_arch="x86_64", target_arch="aarch64")))]
+pub fn wait<F>(_: &AtomicUsize, _: F) where F: FnOnce(usize) -> bool {
+    ::std::hint::spin_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::sync::{Arc};
+    use std::sync::atomic::{AtomicBool};
+    use std::time::{Duration};
+
+    #[test]
+    fn test_wait() {
+        let atomic = AtomicUsize::new(1);
+        touch(&atomic);
+        assert_eq!(atomic.load(Relaxed), 1);
+
+        // The function is only called when the cacheline can be monitored.
+        let mut value = None;
+        wait(&atomic, |v| { value = Some(v); true });
+        assert!(value.is_none() || value == Some(1));
+    }
+
+    #[test]
+    fn test_wake() {
+        let (atomic, stop) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicBool::new(false)));
+        let thread = {
+            let (atomic, stop) = (atomic.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Acquire) {
+                    wait(&atomic, |_| stop.load(Acquire));
+                }
+            })
+        };
+
+        thread::sleep(Duration::from_millis(10));
+        stop.store(true, Release);
+        touch(&atomic);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,526 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Removing items from several queues that contain the same type of item.
+//!
+//! A `MultiConsumer` owns several consumers and removes items from them in round-robin order, so a
+//! queue that always contains items can't keep the items in the other queues from being removed.
+//!
+//! A `Scheduler` owns several consumers and passes the items it removes from them to a handler on
+//! a single thread (e.g., a dispatcher thread that serves many connections). Each round of the
+//! scheduler visits every queue and removes up to the weight of the queue in items, stopping early
+//! if the time slice of the queue runs out, so busy queues get more of the thread than quiet ones
+//! without starving them.
+//!
+//! A `MergeConsumer` owns several consumers for queues whose items are each in order by a key
+//! (e.g., per-core event streams stamped with timestamps) and removes the items from them in order
+//! by that key. It buffers at most one item per queue, and it only removes an item once every queue
+//! that still has producers has an item buffered, since an empty queue could still receive an item
+//! with a smaller key.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::multi::{MultiConsumer};
+//!
+//! fn main() {
+//!     let (a, ac) = spsc::channel(64);
+//!     let (b, bc) = spsc::channel(64);
+//!     let consumer = MultiConsumer::new(vec![ac, bc]);
+//!
+//!     a.produce(1).unwrap();
+//!     a.produce(2).unwrap();
+//!     b.produce(3).unwrap();
+//!
+//!     assert_eq!(consumer.consume(), Ok(1));
+//!     assert_eq!(consumer.consume(), Ok(3));
+//!     assert_eq!(consumer.consume(), Ok(2));
+//!     assert_eq!(consumer.stats()[0].consumed, 2);
+//! }
+//! ```
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::time::{Duration};
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::multi::{Scheduler};
+//!
+//! fn main() {
+//!     let (a, ac) = spsc::channel(64);
+//!     let (b, bc) = spsc::channel(64);
+//!     let mut scheduler = Scheduler::new();
+//!     scheduler.add(ac, 3, Duration::from_millis(1));
+//!     scheduler.add(bc, 1, Duration::from_millis(1));
+//!
+//!     for item in 0..4 {
+//!         a.produce(item).unwrap();
+//!         b.produce(item + 10).unwrap();
+//!     }
+//!
+//!     let mut items = vec![];
+//!     assert_eq!(scheduler.pump(|_, i| items.push(i)), Ok(4));
+//!     assert_eq!(items, &[0, 1, 2, 10]);
+//! }
+//! ```
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::ConsumeError;
+//! use npnc::bounded::spsc;
+//! use npnc::multi::{MergeConsumer};
+//!
+//! fn main() {
+//!     let (a, ac) = spsc::channel(64);
+//!     let (b, bc) = spsc::channel(64);
+//!     let consumer = MergeConsumer::new(vec![ac, bc], |&(timestamp, _): &(u64, char)| timestamp);
+//!
+//!     a.produce((1, 'a')).unwrap();
+//!     a.produce((4, 'b')).unwrap();
+//!     assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+//!
+//!     b.produce((2, 'c')).unwrap();
+//!     b.produce((3, 'd')).unwrap();
+//!     drop((a, b));
+//!
+//!     let mut items = vec![];
+//!     while let Ok((_, item)) = consumer.consume() {
+//!         items.push(item);
+//!     }
+//!     assert_eq!(items, &['a', 'c', 'd', 'b']);
+//! }
+//! ```
+
+use std::fmt;
+use std::cell::{Cell};
+use std::marker::{PhantomData};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use {Consume, ConsumeError};
+
+//================================================
+// Structs
+//================================================
+
+// MergeConsumer _________________________________
+
+/// A consumer that removes items from several queues in order by a key.
+///
+/// The items in each queue must already be in order by the key. Items with equal keys are removed
+/// from the queue with the lowest index first.
+pub struct MergeConsumer<T, C, K> {
+    consumers: Vec<C>,
+    heads: Vec<Cell<Option<T>>>,
+    stats: Vec<Cell<Stats>>,
+    key: K,
+}
+
+impl<T, C, K, U> MergeConsumer<T, C, K> where C: Consume<T>, K: Fn(&T) -> U, U: Ord {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `MergeConsumer` that removes items from the supplied consumers in order by
+    /// the key returned by the supplied function.
+    pub fn new(consumers: Vec<C>, key: K) -> Self {
+        let heads = consumers.iter().map(|_| Cell::new(None)).collect();
+        let stats = consumers.iter().map(|_| Cell::new(Stats::default())).collect();
+        MergeConsumer { consumers, heads, stats, key }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item with the smallest key.
+    ///
+    /// This method returns `Err` if any queue that still has producers is empty, since it could
+    /// still receive an item with a smaller key, and returns `Disconnected` if every queue is empty
+    /// and has no remaining producers.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.consume_indexed().map(|(_, item)| item)
+    }
+
+    /// Attempts to remove and return the item with the smallest key and the index of the consumer
+    /// it was removed with.
+    ///
+    /// This method returns `Err` if any queue that still has producers is empty, since it could
+    /// still receive an item with a smaller key, and returns `Disconnected` if every queue is empty
+    /// and has no remaining producers.
+    pub fn consume_indexed(&self) -> Result<(usize, T), ConsumeError> {
+        let mut ready = true;
+        let mut disconnected = 0;
+        let mut smallest: Option<(usize, U)> = None;
+        for (index, consumer) in self.consumers.iter().enumerate() {
+            // Buffer an item from this queue if one is not already buffered.
+            let head = match self.heads[index].take() {
+                Some(item) => item,
+                None => match consumer.consume() {
+                    Ok(item) => item,
+                    Err(ConsumeError::Disconnected) => {
+                        let mut stats = self.stats[index].get();
+                        stats.disconnected = true;
+                        self.stats[index].set(stats);
+                        disconnected += 1;
+                        continue;
+                    },
+                    Err(_) => {
+                        ready = false;
+                        continue;
+                    },
+                },
+            };
+
+            let key = (self.key)(&head);
+            let smaller = match smallest {
+                Some((_, ref smallest)) => key < *smallest,
+                None => true,
+            };
+            if smaller {
+                smallest = Some((index, key));
+            }
+            self.heads[index].set(Some(head));
+        }
+
+        match smallest {
+            Some((index, _)) if ready => {
+                let mut stats = self.stats[index].get();
+                stats.consumed += 1;
+                stats.disconnected = false;
+                self.stats[index].set(stats);
+                Ok((index, self.heads[index].take().unwrap()))
+            },
+            None if !self.consumers.is_empty() && disconnected == self.consumers.len() => {
+                Err(ConsumeError::Disconnected)
+            },
+            _ => Err(ConsumeError::Empty),
+        }
+    }
+
+    /// Returns the statistics for each of the consumers.
+    pub fn stats(&self) -> Vec<Stats> {
+        self.stats.iter().map(|s| s.get()).collect()
+    }
+
+    /// Returns the consumers.
+    pub fn get_ref(&self) -> &[C] {
+        &self.consumers
+    }
+
+    //- Consumers --------------------------------
+
+    /// Returns the consumers and the items that have been removed from their queues but not yet
+    /// returned by this consumer.
+    pub fn into_inner(self) -> (Vec<C>, Vec<Option<T>>) {
+        (self.consumers, self.heads.into_iter().map(|h| h.into_inner()).collect())
+    }
+}
+
+impl<T, C, K> fmt::Debug for MergeConsumer<T, C, K> where C: fmt::Debug {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("MergeConsumer")
+            .field("consumers", &self.consumers)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl<T, C, K, U> Consume<T> for MergeConsumer<T, C, K>
+    where C: Consume<T>, K: Fn(&T) -> U, U: Ord
+{
+    fn consume(&self) -> Result<T, ConsumeError> {
+        MergeConsumer::consume(self)
+    }
+}
+
+// MultiConsumer _________________________________
+
+/// A consumer that removes items from several queues in round-robin order.
+#[derive(Debug)]
+pub struct MultiConsumer<T, C> {
+    consumers: Vec<C>,
+    stats: Vec<Cell<Stats>>,
+    next: Cell<usize>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, C> MultiConsumer<T, C> where C: Consume<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `MultiConsumer` that removes items from the supplied consumers.
+    pub fn new(consumers: Vec<C>) -> Self {
+        let stats = consumers.iter().map(|_| Cell::new(Stats::default())).collect();
+        let next = Cell::new(0);
+        MultiConsumer { consumers, stats, next, _marker: PhantomData }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the next queue that contains an item.
+    ///
+    /// This method returns `Err` if every queue is empty, and returns `Disconnected` if every queue
+    /// is empty and has no remaining producers.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.consume_indexed().map(|(_, item)| item)
+    }
+
+    /// Attempts to remove and return an item and the index of the consumer it was removed with
+    /// from the next queue that contains an item.
+    ///
+    /// This method returns `Err` if every queue is empty, and returns `Disconnected` if every queue
+    /// is empty and has no remaining producers.
+    pub fn consume_indexed(&self) -> Result<(usize, T), ConsumeError> {
+        let len = self.consumers.len();
+        let mut disconnected = 0;
+        for offset in 0..len {
+            let index = (self.next.get() + offset) % len;
+            let mut stats = self.stats[index].get();
+            match self.consumers[index].consume() {
+                Ok(item) => {
+                    stats.consumed += 1;
+                    stats.disconnected = false;
+                    self.stats[index].set(stats);
+                    self.next.set((index + 1) % len);
+                    return Ok((index, item));
+                },
+                Err(ConsumeError::Disconnected) => {
+                    stats.disconnected = true;
+                    self.stats[index].set(stats);
+                    disconnected += 1;
+                },
+                Err(_) => { },
+            }
+        }
+
+        if len != 0 && disconnected == len {
+            Err(ConsumeError::Disconnected)
+        } else {
+            Err(ConsumeError::Empty)
+        }
+    }
+
+    /// Returns the statistics for each of the consumers.
+    pub fn stats(&self) -> Vec<Stats> {
+        self.stats.iter().map(|s| s.get()).collect()
+    }
+
+    /// Returns the consumers.
+    pub fn get_ref(&self) -> &[C] {
+        &self.consumers
+    }
+
+    //- Consumers --------------------------------
+
+    /// Returns the consumers.
+    pub fn into_inner(self) -> Vec<C> {
+        self.consumers
+    }
+}
+
+impl<T, C> Consume<T> for MultiConsumer<T, C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        MultiConsumer::consume(self)
+    }
+}
+
+// Scheduler _____________________________________
+
+/// A consumer that passes the items in several queues to a handler in weighted, time-sliced
+/// rounds.
+#[derive(Debug)]
+pub struct Scheduler<T, C> {
+    consumers: Vec<C>,
+    weights: Vec<(usize, Duration)>,
+    stats: Vec<Cell<Stats>>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, C> Scheduler<T, C> where C: Consume<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Scheduler` without any consumers.
+    pub fn new() -> Self {
+        Scheduler { consumers: vec![], weights: vec![], stats: vec![], _marker: PhantomData }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Runs one round, passing the items removed from the queues and the indices of the consumers
+    /// they were removed with to the supplied handler, and returns the number of items removed.
+    ///
+    /// This method returns `Disconnected` if this scheduler has no consumers or if every queue is
+    /// empty and has no remaining producers.
+    pub fn pump<F>(&self, mut f: F) -> Result<usize, ConsumeError> where F: FnMut(usize, T) {
+        let mut count = 0;
+        let mut disconnected = 0;
+        for (index, consumer) in self.consumers.iter().enumerate() {
+            let (weight, slice) = self.weights[index];
+            let mut stats = self.stats[index].get();
+            let mut start = None;
+            for _ in 0..weight {
+                match consumer.consume() {
+                    Ok(item) => {
+                        let start = *start.get_or_insert_with(Instant::now);
+                        stats.consumed += 1;
+                        stats.disconnected = false;
+                        count += 1;
+                        f(index, item);
+                        if start.elapsed() >= slice {
+                            break;
+                        }
+                    },
+                    Err(ConsumeError::Disconnected) => {
+                        stats.disconnected = true;
+                        disconnected += 1;
+                        break;
+                    },
+                    Err(_) => break,
+                }
+            }
+            self.stats[index].set(stats);
+        }
+
+        if disconnected == self.consumers.len() {
+            Err(ConsumeError::Disconnected)
+        } else {
+            Ok(count)
+        }
+    }
+
+    /// Runs rounds until every queue is empty and has no remaining producers, yielding the thread
+    /// after rounds in which no items were removed.
+    ///
+    /// This method returns immediately if this scheduler has no consumers.
+    pub fn run<F>(&self, mut f: F) where F: FnMut(usize, T) {
+        while let Ok(count) = self.pump(&mut f) {
+            if count == 0 {
+                thread::yield_now();
+            }
+        }
+    }
+
+    /// Returns the statistics for each of the consumers.
+    pub fn stats(&self) -> Vec<Stats> {
+        self.stats.iter().map(|s| s.get()).collect()
+    }
+
+    /// Returns the consumers.
+    pub fn get_ref(&self) -> &[C] {
+        &self.consumers
+    }
+
+    //- Mutators ---------------------------------
+
+    /// Adds the supplied consumer to this scheduler and returns its index.
+    ///
+    /// Each round, up to `weight` items are removed with the consumer, fewer if `slice` has passed
+    /// since the first item was removed.
+    ///
+    /// # Panics
+    ///
+    /// * `weight` is zero
+    pub fn add(&mut self, consumer: C, weight: usize, slice: Duration) -> usize {
+        assert!(weight != 0, "`weight` is zero");
+        self.consumers.push(consumer);
+        self.weights.push((weight, slice));
+        self.stats.push(Cell::new(Stats::default()));
+        self.consumers.len() - 1
+    }
+
+    //- Consumers --------------------------------
+
+    /// Returns the consumers.
+    pub fn into_inner(self) -> Vec<C> {
+        self.consumers
+    }
+}
+
+impl<T, C> Default for Scheduler<T, C> where C: Consume<T> {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+// Stats _________________________________________
+
+/// The statistics for one of the consumers of a `MergeConsumer`, `MultiConsumer`, or `Scheduler`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of items removed with the consumer.
+    pub consumed: usize,
+    /// Whether the queue was empty and had no remaining producers the last time the consumer was
+    /// used.
+    pub disconnected: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_multi_consumer_disconnected() {
+        let (a, ac) = spsc::channel(4);
+        let (b, bc) = spsc::channel(4);
+        let consumer = MultiConsumer::new(vec![ac, bc]);
+        b.produce(1).unwrap();
+        drop(a);
+        assert_eq!(consumer.consume_indexed(), Ok((1, 1)));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        drop(b);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+        assert!(consumer.stats().iter().all(|s| s.disconnected));
+    }
+
+    #[test]
+    fn test_merge_consumer_waits_for_every_queue() {
+        let (a, ac) = spsc::channel(4);
+        let (b, bc) = spsc::channel(4);
+        let consumer = MergeConsumer::new(vec![ac, bc], |&i: &i32| i);
+        a.produce(2).unwrap();
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        b.produce(1).unwrap();
+        assert_eq!(consumer.consume_indexed(), Ok((1, 1)));
+        drop(b);
+        assert_eq!(consumer.consume_indexed(), Ok((0, 2)));
+        drop(a);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_scheduler_run() {
+        let scheduler = Scheduler::<i32, spsc::Consumer<i32>>::new();
+        scheduler.run(|_, _| unreachable!());
+        assert_eq!(scheduler.pump(|_, _| unreachable!()), Err(ConsumeError::Disconnected));
+
+        let (a, ac) = spsc::channel(8);
+        let (b, bc) = spsc::channel(8);
+        let mut scheduler = Scheduler::new();
+        scheduler.add(ac, 2, Duration::from_secs(1));
+        scheduler.add(bc, 1, Duration::from_secs(1));
+        for item in 0..4 {
+            a.produce(item).unwrap();
+            b.produce(item + 10).unwrap();
+        }
+        drop((a, b));
+
+        let mut items = vec![];
+        scheduler.run(|index, item| items.push((index, item)));
+        assert_eq!(items, [(0, 0), (0, 1), (1, 10), (0, 2), (0, 3), (1, 11), (1, 12), (1, 13)]);
+        assert_eq!(scheduler.stats()[0], Stats { consumed: 4, disconnected: true });
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::sync::{Mutex};
+
+/// A function called once a queue has been disconnected from its consumers.
+type Callback = Box<dyn FnOnce() + Send>;
+
+//================================================
+// Structs
+//================================================
+
+// Notifier ______________________________________
+
+/// A set of callbacks that are called once when a queue is disconnected from its consumers.
+pub struct Notifier {
+    callbacks: Mutex<Option<Vec<Callback>>>,
+}
+
+impl Notifier {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Notifier`.
+    pub fn new() -> Self {
+        Notifier { callbacks: Mutex::new(Some(vec![])) }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Adds the supplied callback to this notifier.
+    ///
+    /// The callback is called immediately if this notifier has already been notified.
+    pub fn register(&self, callback: Box<dyn FnOnce() + Send>) {
+        let mut callbacks = self.callbacks.lock().unwrap();
+        if let Some(ref mut callbacks) = *callbacks {
+            callbacks.push(callback);
+            return;
+        }
+
+        drop(callbacks);
+        callback();
+    }
+
+    /// Calls the callbacks added to this notifier if it has not already been notified.
+    pub fn notify(&self) {
+        let callbacks = self.callbacks.lock().unwrap().take();
+        for callback in callbacks.into_iter().flatten() {
+            callback();
+        }
+    }
+
+    /// Adds the callbacks added to the supplied notifier to this notifier.
+    ///
+    /// This notifier is notified if the supplied notifier has already been notified.
+    pub fn extend(&self, other: Notifier) {
+        match other.callbacks.into_inner().unwrap() {
+            Some(callbacks) => for callback in callbacks { self.register(callback); },
+            None => self.notify(),
+        }
+    }
+
+    /// Moves the callbacks added to this notifier into a new notifier.
+    ///
+    /// This is used to keep the callbacks when a queue is converted into another type of queue.
+    pub fn take(&self) -> Self {
+        let mut callbacks = self.callbacks.lock().unwrap();
+        let taken = callbacks.replace(vec![]);
+        Notifier { callbacks: Mutex::new(taken) }
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let callbacks = self.callbacks.lock().unwrap();
+        let len = callbacks.as_ref().map_or(0, |c| c.len());
+        write!(formatter, "Notifier {{ callbacks: {}, notified: {} }}", len, callbacks.is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc};
+    use std::sync::atomic::{AtomicUsize};
+    use std::sync::atomic::Ordering::*;
+
+    fn counter(count: &Arc<AtomicUsize>) -> Callback {
+        let count = count.clone();
+        Box::new(move || { count.fetch_add(1, SeqCst); })
+    }
+
+    #[test]
+    fn test_notify() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let notifier = Notifier::new();
+        notifier.register(counter(&count));
+        notifier.register(counter(&count));
+        assert_eq!(count.load(SeqCst), 0);
+
+        // The callbacks are only called once.
+        notifier.notify();
+        notifier.notify();
+        assert_eq!(count.load(SeqCst), 2);
+
+        // A callback registered once the notifier has been notified is called immediately.
+        notifier.register(counter(&count));
+        assert_eq!(count.load(SeqCst), 3);
+    }
+
+    #[test]
+    fn test_take_extend() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let notifier = Notifier::new();
+        notifier.register(counter(&count));
+        let taken = notifier.take();
+        notifier.notify();
+        assert_eq!(count.load(SeqCst), 0);
+
+        let other = Notifier::new();
+        other.extend(taken);
+        other.notify();
+        assert_eq!(count.load(SeqCst), 1);
+
+        // Extending with a notified notifier notifies this notifier.
+        let notified = Notifier::new();
+        notified.notify();
+        let notifier = Notifier::new();
+        notifier.register(counter(&count));
+        notifier.extend(notified);
+        assert_eq!(count.load(SeqCst), 2);
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lock-free oneshot channel.
+//!
+//! A oneshot channel transfers exactly one item from its producer to its consumer, which makes it
+//! suitable for sending the reply to a request (e.g., by adding the producer to a queue along with
+//! the request). Adding the item consumes the producer, and the handoff is a single atomic
+//! read-modify-write on each side. `Consumer::try_consume` distinguishes an item that has not been
+//! sent yet (`Empty`) from a producer that was dropped without sending an item (`Disconnected`).
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::thread;
+//!
+//! use npnc::ConsumeError;
+//! use npnc::oneshot;
+//!
+//! fn main() {
+//!     let (producer, consumer) = oneshot::channel();
+//!     assert_eq!(consumer.try_consume(), Err(ConsumeError::Empty));
+//!
+//!     thread::spawn(move || producer.produce(42).unwrap()).join().unwrap();
+//!     assert_eq!(consumer.try_consume(), Ok(42));
+//!     assert_eq!(consumer.try_consume(), Err(ConsumeError::Disconnected));
+//!
+//!     let (producer, consumer) = oneshot::channel::<u32>();
+//!     drop(producer);
+//!     assert_eq!(consumer.try_consume(), Err(ConsumeError::Disconnected));
+//! }
+//! ```
+
+use std::fmt;
+use std::cell::{Cell, UnsafeCell};
+use std::marker::{PhantomData};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, ProduceError};
+
+/// Indicates that the item has been sent.
+const SENT: usize = 1;
+/// Indicates that the producer has been dropped.
+const PRODUCER: usize = 2;
+/// Indicates that the consumer has been dropped.
+const CONSUMER: usize = 4;
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for a lock-free oneshot channel.
+///
+/// A consumer can be sent to another thread but can't be shared between threads, since removing
+/// the item is not synchronized with other calls to `try_consume`.
+#[derive(Debug)]
+pub struct Consumer<T>(Arc<Slot<T>>, PhantomData<Cell<()>>);
+
+impl<T> Consumer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item.
+    ///
+    /// This method returns `Empty` if the item has not been sent yet and `Disconnected` if the
+    /// producer was dropped without sending an item or if the item has already been removed.
+    pub fn try_consume(&self) -> Result<T, ConsumeError> {
+        let state = self.0.state.load(Acquire);
+        if state & SENT != 0 {
+            // Only this consumer accesses the item once it has been sent.
+            unsafe { (*self.0.item.get()).take().ok_or(ConsumeError::Disconnected) }
+        } else if state & PRODUCER != 0 {
+            Err(ConsumeError::Disconnected)
+        } else {
+            Err(ConsumeError::Empty)
+        }
+    }
+
+    /// Returns whether the item has been sent.
+    ///
+    /// This method continues to return `true` after the item has been removed.
+    pub fn is_sent(&self) -> bool {
+        self.0.state.load(Acquire) & SENT != 0
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.try_consume()
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.0.state.fetch_or(CONSUMER, Release);
+    }
+}
+
+unsafe impl<T> Send for Consumer<T> where T: Send { }
+
+// Producer ______________________________________
+
+/// A producer for a lock-free oneshot channel.
+#[derive(Debug)]
+pub struct Producer<T>(Arc<Slot<T>>);
+
+impl<T> Producer<T> {
+    //- Accessors --------------------------------
+
+    /// Returns whether the consumer has been dropped.
+    ///
+    /// If this method returns `true`, the item would be rejected, so any work to compute it can be
+    /// skipped.
+    pub fn is_disconnected(&self) -> bool {
+        self.0.state.load(Acquire) & CONSUMER != 0
+    }
+
+    //- Consumers --------------------------------
+
+    /// Attempts to send the supplied item to the consumer.
+    ///
+    /// This method returns `Err` if the consumer has been dropped.
+    pub fn produce(self, item: T) -> Result<(), ProduceError<T>> {
+        if self.is_disconnected() {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        // The consumer does not access the item until it has been marked as sent.
+        unsafe { *self.0.item.get() = Some(item); }
+        if self.0.state.fetch_or(SENT, AcqRel) & CONSUMER != 0 {
+            // The consumer was dropped before the item was sent, so it will never access it.
+            let item = unsafe { (*self.0.item.get()).take().unwrap() };
+            return Err(ProduceError::Disconnected(item));
+        }
+        Ok(())
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.state.fetch_or(PRODUCER, Release);
+    }
+}
+
+unsafe impl<T> Send for Producer<T> where T: Send { }
+
+// Slot __________________________________________
+
+struct Slot<T> {
+    state: AtomicUsize,
+    item: UnsafeCell<Option<T>>,
+}
+
+impl<T> fmt::Debug for Slot<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Slot {{ state: {:?} }}", self.state.load(Relaxed))
+    }
+}
+
+unsafe impl<T> Sync for Slot<T> where T: Send { }
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for a lock-free oneshot channel.
+pub fn channel<T>() -> (Producer<T>, Consumer<T>) {
+    let slot = Arc::new(Slot { state: AtomicUsize::new(0), item: UnsafeCell::new(None) });
+    (Producer(slot.clone()), Consumer(slot, PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oneshot() {
+        let (producer, consumer) = channel();
+        assert_eq!(consumer.try_consume(), Err(ConsumeError::Empty));
+        assert!(!consumer.is_sent());
+        producer.produce(1).unwrap();
+        assert!(consumer.is_sent());
+        assert_eq!(consumer.try_consume(), Ok(1));
+        assert_eq!(consumer.try_consume(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_oneshot_disconnected() {
+        let (producer, consumer) = channel::<i32>();
+        drop(producer);
+        assert_eq!(consumer.try_consume(), Err(ConsumeError::Disconnected));
+
+        let (producer, consumer) = channel();
+        drop(consumer);
+        assert!(producer.is_disconnected());
+        assert_eq!(producer.produce(1), Err(ProduceError::Disconnected(1)));
+    }
+}
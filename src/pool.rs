@@ -0,0 +1,457 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sharing a capacity budget between several queues and pools of consumer threads.
+//!
+//! A `CapacityPool` contains a fixed number of slots. The producers returned by
+//! `CapacityPool::wrap` take a slot from the pool for every item they add to a queue and receive
+//! `Full` errors once the pool is empty, and the consumers return a slot for every item they
+//! remove. This bounds the total number of items in all of the wrapped queues (e.g., the
+//! per-connection queues of a server) in addition to the capacity of each queue. The slots held by
+//! the items left in a queue are returned to the pool once the wrapped producers and consumers for
+//! the queue have all been dropped.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::ProduceError;
+//! use npnc::bounded::spsc;
+//! use npnc::pool::{CapacityPool};
+//!
+//! fn main() {
+//!     let pool = CapacityPool::new(3);
+//!     let (ap, ac) = spsc::channel(64);
+//!     let (ap, ac) = pool.wrap(ap, ac);
+//!     let (bp, bc) = spsc::channel(64);
+//!     let (bp, bc) = pool.wrap(bp, bc);
+//!
+//!     ap.produce(1).unwrap();
+//!     ap.produce(2).unwrap();
+//!     bp.produce(3).unwrap();
+//!     assert_eq!(bp.produce(4), Err(ProduceError::Full(4)));
+//!
+//!     assert_eq!(ac.consume(), Ok(1));
+//!     bp.produce(4).unwrap();
+//!     assert_eq!(bc.consume(), Ok(3));
+//!     assert_eq!(pool.available(), 1);
+//! }
+//! ```
+//!
+//! `spawn_consumers` spawns a number of threads which each remove items from a queue with their
+//! own clone of a consumer and pass them to a shared closure. The threads exit once the queue has
+//! no remaining producers and is empty or once they are told to stop.
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use std::sync::{Arc};
+//!
+//! use npnc::bounded::mpmc;
+//! use npnc::pool;
+//!
+//! fn main() {
+//!     let (producer, consumer) = mpmc::channel(64);
+//!     let sum = Arc::new(AtomicUsize::new(0));
+//!     let total = sum.clone();
+//!     let workers = pool::spawn_consumers(consumer, 4, move |i| {
+//!         total.fetch_add(i, Ordering::Relaxed);
+//!     });
+//!
+//!     for item in 0..32 {
+//!         producer.produce(item).unwrap();
+//!     }
+//!     drop(producer);
+//!
+//!     workers.join().unwrap();
+//!     assert_eq!(sum.load(Ordering::Relaxed), 496);
+//! }
+//! ```
+
+use std::thread::{self, JoinHandle};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// CapacityPool __________________________________
+
+/// A number of slots shared by several queues.
+#[derive(Clone, Debug)]
+pub struct CapacityPool(Arc<Slots>);
+
+impl CapacityPool {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `CapacityPool` that contains the supplied number of slots.
+    pub fn new(size: usize) -> Self {
+        CapacityPool(Arc::new(Slots { available: AtomicUsize::new(size), size }))
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the number of slots currently available in this pool.
+    pub fn available(&self) -> usize {
+        self.0.available.load(Acquire)
+    }
+
+    /// Returns the number of slots in this pool.
+    pub fn size(&self) -> usize {
+        self.0.size
+    }
+
+    /// Wraps the supplied producer and consumer so they take slots from and return slots to this
+    /// pool.
+    ///
+    /// The supplied producer and consumer should belong to the same queue and should not be used
+    /// to add or remove items once wrapped.
+    pub fn wrap<P, C>(&self, producer: P, consumer: C) -> (Producer<P>, Consumer<C>) {
+        let share = Arc::new(Share { pool: self.clone(), items: AtomicUsize::new(0) });
+        let producer = Producer { producer, share: share.clone() };
+        (producer, Consumer { consumer, share })
+    }
+
+    /// Attempts to take a slot from this pool.
+    fn acquire(&self) -> bool {
+        let slots = &self.0.available;
+        let mut available = slots.load(Acquire);
+        loop {
+            if available == 0 {
+                return false;
+            }
+
+            match slots.compare_exchange_weak(available, available - 1, AcqRel, Acquire) {
+                Ok(_) => return true,
+                Err(current) => available = current,
+            }
+        }
+    }
+
+    /// Returns the supplied number of slots to this pool.
+    fn release(&self, slots: usize) {
+        self.0.available.fetch_add(slots, Release);
+    }
+}
+
+// Consumer ______________________________________
+
+/// A consumer that returns a slot to a pool for every item it removes from a queue.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+    share: Arc<Share>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        let item = self.consumer.consume()?;
+        self.share.remove();
+        Ok(item)
+    }
+
+    /// Returns the number of slots currently held by the items in the queue.
+    pub fn len(&self) -> usize {
+        self.share.items.load(Acquire)
+    }
+
+    /// Returns whether the queue currently holds no slots.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the pool the queue takes slots from.
+    pub fn pool(&self) -> &CapacityPool {
+        &self.share.pool
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        Consumer { consumer: self.consumer.clone(), share: self.share.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that takes a slot from a pool for every item it adds to a queue.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: P,
+    share: Arc<Share>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `Err` if the queue is full, has no remaining consumers, or if the pool
+    /// has no available slots.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        if !self.share.pool.acquire() {
+            return Err(ProduceError::Full(item));
+        }
+
+        // The slot is counted before the item is added so that the count never underflows.
+        self.share.items.fetch_add(1, AcqRel);
+        self.producer.produce(item).inspect_err(|_| self.share.remove())
+    }
+
+    /// Returns the number of slots currently held by the items in the queue.
+    pub fn len(&self) -> usize {
+        self.share.items.load(Acquire)
+    }
+
+    /// Returns whether the queue currently holds no slots.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the pool the queue takes slots from.
+    pub fn pool(&self) -> &CapacityPool {
+        &self.share.pool
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        Producer { producer: self.producer.clone(), share: self.share.clone() }
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// Share _________________________________________
+
+/// The slots held by the items in one of the queues that take slots from a pool.
+#[derive(Debug)]
+struct Share {
+    pool: CapacityPool,
+    items: AtomicUsize,
+}
+
+impl Share {
+    //- Accessors --------------------------------
+
+    /// Returns the slot held by an item to the pool.
+    fn remove(&self) {
+        self.items.fetch_sub(1, AcqRel);
+        self.pool.release(1);
+    }
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        // The queue can no longer be used, so the slots held by the items left in it are returned.
+        self.pool.release(*self.items.get_mut());
+    }
+}
+
+// Slots _________________________________________
+
+#[derive(Debug)]
+struct Slots {
+    available: AtomicUsize,
+    size: usize,
+}
+
+// Workers _______________________________________
+
+/// The threads spawned by `spawn_consumers`.
+#[derive(Debug)]
+pub struct Workers {
+    threads: Vec<JoinHandle<()>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Workers {
+    //- Accessors --------------------------------
+
+    /// Returns the number of threads.
+    pub fn len(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Returns whether there are no threads.
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    /// Tells the threads to exit once they have finished processing their current items.
+    ///
+    /// The items left in the queue are not removed.
+    pub fn stop(&self) {
+        self.stopped.store(true, Release);
+    }
+
+    //- Consumers --------------------------------
+
+    /// Waits for the threads to exit.
+    ///
+    /// This method returns `Err` containing the panic of the first thread that panicked, if any.
+    pub fn join(self) -> thread::Result<()> {
+        let mut result = Ok(());
+        for thread in self.threads {
+            if let Err(error) = thread.join() {
+                result = result.and(Err(error));
+            }
+        }
+        result
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Spawns the supplied number of threads which remove items from a queue with clones of the
+/// supplied consumer and call the supplied closure with each item.
+///
+/// The threads exit once the queue has no remaining producers and is empty or once `stop` is
+/// called on the returned `Workers`. The threads yield while the queue is empty, so they are best
+/// suited to queues that are rarely empty.
+///
+/// # Panics
+///
+/// * `n` is zero
+pub fn spawn_consumers<T, C, F>(consumer: C, n: usize, f: F) -> Workers
+    where C: Consume<T> + Clone + Send + 'static, F: Fn(T) + Send + Sync + 'static
+{
+    assert!(n != 0, "`n` is zero");
+    let f = Arc::new(f);
+    let stopped = Arc::new(AtomicBool::new(false));
+    let mut consumers = (1..n).map(|_| consumer.clone()).collect::<Vec<_>>();
+    consumers.push(consumer);
+
+    let threads = consumers.into_iter().enumerate().map(|(index, consumer)| {
+        let f = f.clone();
+        let stopped = stopped.clone();
+        let name = format!("npnc-consumer-{}", index);
+        thread::Builder::new().name(name).spawn(move || {
+            while !stopped.load(Acquire) {
+                match consumer.consume() {
+                    Ok(item) => f(item),
+                    Err(ConsumeError::Empty) => thread::yield_now(),
+                    Err(ConsumeError::Contended) | Err(ConsumeError::Timeout) => { },
+                    Err(ConsumeError::Disconnected) => break,
+                }
+            }
+        }).expect("failed to spawn consumer thread")
+    }).collect();
+    Workers { threads, stopped }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Mutex};
+
+    use bounded::{mpmc, spsc};
+
+    #[test]
+    fn test_pool() {
+        let pool = CapacityPool::new(2);
+        let (ap, ac) = spsc::channel(1);
+        let (ap, ac) = pool.wrap(ap, ac);
+        let (bp, bc) = spsc::channel(4);
+        let (bp, bc) = pool.wrap(bp, bc);
+        assert_eq!(ap.pool().size(), 2);
+
+        // A slot is returned if the queue rejects the item.
+        ap.produce(1).unwrap();
+        assert_eq!(ap.produce(2), Err(ProduceError::Full(2)));
+        assert_eq!((ap.len(), pool.available()), (1, 1));
+
+        bp.produce(3).unwrap();
+        assert_eq!(bp.produce(4), Err(ProduceError::Full(4)));
+        assert_eq!(ac.consume(), Ok(1));
+        assert!(ac.is_empty());
+        assert_eq!(ac.consume(), Err(ConsumeError::Empty));
+        assert_eq!(pool.available(), 1);
+
+        // The slots held by the items left in a queue are returned once it is dropped.
+        bp.produce(4).unwrap();
+        assert_eq!((bc.len(), pool.available()), (2, 0));
+        drop((bp, bc));
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    fn test_spawn_consumers() {
+        let (producer, consumer) = mpmc::channel(64);
+        let items = Arc::new(Mutex::new(vec![]));
+        let sink = items.clone();
+        let workers = spawn_consumers(consumer, 3, move |i| sink.lock().unwrap().push(i));
+        assert_eq!(workers.len(), 3);
+
+        for item in 0..100 {
+            while producer.produce(item).is_err() { }
+        }
+        drop(producer);
+        workers.join().unwrap();
+
+        let mut items = items.lock().unwrap().clone();
+        items.sort();
+        assert_eq!(items, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_stop() {
+        let (producer, consumer) = mpmc::channel::<i32>(64);
+        let workers = spawn_consumers(consumer, 2, |_| { });
+        workers.stop();
+        workers.join().unwrap();
+        drop(producer);
+    }
+
+    #[test]
+    fn test_panic() {
+        let (producer, consumer) = mpmc::channel(64);
+        let workers = spawn_consumers(consumer, 2, |i: i32| assert!(i != 1));
+        producer.produce(1).unwrap();
+        drop(producer);
+        assert!(workers.join().is_err());
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-producer quotas.
+//!
+//! The producers returned by `wrap` limit the number of items each producer handle has in a queue
+//! (its outstanding items), so one misbehaving component can't fill a queue shared with other
+//! components. A producer receives a `NoCredit` error if adding an item would put it over its
+//! quota and can add items again once the consumers have removed some of its outstanding items.
+//! Every clone of a producer has its own quota and its own outstanding items.
+//!
+//! The wrapped queue contains `Item`s, which attribute each item to the producer that added it so
+//! that the consumers returned by `wrap` can return the quota to that producer. An `Item` also
+//! returns its quota when it is dropped, so items discarded through the wrapped consumer or left
+//! in the queue when it is dropped don't leave the producers over their quotas. The wrapped
+//! producer should not be used to add items once wrapped.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::ProduceError;
+//! use npnc::bounded::mpmc;
+//! use npnc::quota;
+//!
+//! fn main() {
+//!     let (producer1, consumer) = mpmc::channel(64);
+//!     let (producer1, consumer) = quota::wrap(producer1, consumer, 2);
+//!     let producer2 = producer1.with_quota(1);
+//!
+//!     producer1.produce(1).unwrap();
+//!     producer1.produce(2).unwrap();
+//!     assert_eq!(producer1.produce(3), Err(ProduceError::NoCredit(3)));
+//!     producer2.produce(4).unwrap();
+//!     assert_eq!(producer2.produce(5), Err(ProduceError::NoCredit(5)));
+//!
+//!     assert_eq!(consumer.consume(), Ok(1));
+//!     assert_eq!(producer1.outstanding(), 1);
+//!     producer1.produce(3).unwrap();
+//! }
+//! ```
+
+use std::ptr;
+use std::mem::{ManuallyDrop};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that returns quota to the producers that added the items it removes.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<Item<T>> {
+        self.consumer.consume().map(Item::into_inner)
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        Consumer { consumer: self.consumer.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<Item<T>> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Item __________________________________________
+
+/// An item in a queue attributed to the producer that added it.
+#[derive(Debug)]
+pub struct Item<T> {
+    item: T,
+    outstanding: Arc<AtomicUsize>,
+}
+
+impl<T> Item<T> {
+    //- Consumers --------------------------------
+
+    /// Returns the item and returns its quota to the producer that added it.
+    pub fn into_inner(self) -> T {
+        // The item is moved out without running the destructor of this item, which would return
+        // the quota a second time.
+        let this = ManuallyDrop::new(self);
+        this.outstanding.fetch_sub(1, Release);
+        unsafe {
+            drop(ptr::read(&this.outstanding));
+            ptr::read(&this.item)
+        }
+    }
+}
+
+impl<T> Drop for Item<T> {
+    fn drop(&mut self) {
+        self.outstanding.fetch_sub(1, Release);
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that limits the number of items it has in a queue.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: P,
+    outstanding: Arc<AtomicUsize>,
+    quota: usize,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `NoCredit` if this producer already has as many items in the queue as
+    /// its quota allows and `Err` if the queue is full or has no remaining consumers.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<Item<T>> {
+        // The item is counted before it is added so that the count never underflows.
+        if self.outstanding.fetch_add(1, Acquire) >= self.quota {
+            self.outstanding.fetch_sub(1, Relaxed);
+            return Err(ProduceError::NoCredit(item));
+        }
+
+        let item = Item { item, outstanding: self.outstanding.clone() };
+        self.producer.produce(item).map_err(|e| e.map(Item::into_inner))
+    }
+
+    /// Returns the number of items this producer currently has in the queue.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Acquire)
+    }
+
+    /// Returns the maximum number of items this producer can have in the queue.
+    pub fn quota(&self) -> usize {
+        self.quota
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+
+    /// Returns a clone of this producer with the supplied quota.
+    ///
+    /// # Panics
+    ///
+    /// * `quota` is zero
+    pub fn with_quota(&self, quota: usize) -> Self where P: Clone {
+        assert!(quota != 0, "`quota` is zero");
+        let outstanding = Arc::new(AtomicUsize::new(0));
+        Producer { producer: self.producer.clone(), outstanding, quota }
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        self.with_quota(self.quota)
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<Item<T>> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Wraps the supplied producer and consumer so the producer can have at most `quota` items in the
+/// queue.
+///
+/// Clones of the returned producer have their own quotas (see `Producer::with_quota`).
+///
+/// # Panics
+///
+/// * `quota` is zero
+pub fn wrap<P, C>(producer: P, consumer: C, quota: usize) -> (Producer<P>, Consumer<C>) {
+    assert!(quota != 0, "`quota` is zero");
+    let outstanding = Arc::new(AtomicUsize::new(0));
+    let producer = Producer { producer, outstanding, quota };
+    (producer, Consumer { consumer })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{mpmc};
+
+    #[test]
+    fn test_quota() {
+        let (producer1, consumer) = mpmc::channel(4);
+        let (producer1, consumer) = wrap(producer1, consumer, 2);
+        let producer2 = producer1.clone();
+
+        producer1.produce(1).unwrap();
+        producer1.produce(2).unwrap();
+        assert_eq!(producer1.produce(3), Err(ProduceError::NoCredit(3)));
+        producer2.produce(4).unwrap();
+        assert_eq!(producer2.outstanding(), 1);
+
+        assert_eq!(consumer.consume(), Ok(1));
+        assert_eq!(producer1.outstanding(), 1);
+        producer2.produce(5).unwrap();
+
+        // The quota is returned if the queue rejects the item.
+        producer1.clone().produce(7).unwrap();
+        assert_eq!(producer1.produce(6), Err(ProduceError::Full(6)));
+        assert_eq!(producer1.outstanding(), 1);
+    }
+
+    #[test]
+    fn test_quota_returned_on_drop() {
+        let (producer, consumer) = mpmc::channel(4);
+        let (producer, consumer) = wrap(producer, consumer, 2);
+        producer.produce(1).unwrap();
+        producer.produce(2).unwrap();
+        assert_eq!(consumer.get_ref().discard(2), 2);
+        assert_eq!(producer.outstanding(), 0);
+        producer.produce(3).unwrap();
+
+        drop(consumer);
+        assert_eq!(producer.produce(4), Err(ProduceError::Disconnected(4)));
+        assert_eq!(producer.outstanding(), 1);
+    }
+}
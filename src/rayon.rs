@@ -0,0 +1,122 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Processing the items in a queue in parallel with `rayon`.
+//!
+//! The `par_drain` method of the `ParDrain` trait, which is implemented for every consumer,
+//! returns a parallel iterator over the items currently in a queue. The items are removed by the
+//! threads in the `rayon` thread pool as they need more work, so the backlog of a queue can be
+//! processed across the pool without splitting it into chunks by hand. The iterator ends once the
+//! queue is empty.
+//!
+//! This module requires the `rayon` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//! extern crate rayon;
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::rayon::{ParDrain};
+//! use rayon::prelude::*;
+//!
+//! fn main() {
+//!     let (producer, mut consumer) = spsc::channel(64);
+//!
+//!     for item in 0..32 {
+//!         producer.produce(item).unwrap();
+//!     }
+//!
+//!     let sum: i32 = consumer.par_drain().map(|i| i * 2).sum();
+//!     assert_eq!(sum, 992);
+//!     assert!(consumer.is_empty());
+//! }
+//! ```
+
+extern crate rayon;
+
+use std::marker::{PhantomData};
+
+use self::rayon::iter::{IterBridge, ParallelBridge};
+
+use {Consume};
+
+//================================================
+// Structs
+//================================================
+
+// Drain _________________________________________
+
+/// An iterator that removes the items in a queue until the queue is empty.
+#[derive(Debug)]
+pub struct Drain<'c, T, C> where C: 'c {
+    consumer: &'c mut C,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'c, T, C> Iterator for Drain<'c, T, C> where C: Consume<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.consumer.consume().ok()
+    }
+}
+
+//================================================
+// Traits
+//================================================
+
+// ParDrain ______________________________________
+
+/// A consumer whose queue can be drained by the threads in a `rayon` thread pool.
+pub trait ParDrain<T>: Consume<T> + Send + Sized where T: Send {
+    /// Returns a parallel iterator that removes the items in the queue until the queue is empty.
+    ///
+    /// The order the items are processed in is not specified.
+    fn par_drain<'c>(&'c mut self) -> IterBridge<Drain<'c, T, Self>> {
+        Drain { consumer: self, _marker: PhantomData }.par_bridge()
+    }
+}
+
+impl<T, C> ParDrain<T> for C where T: Send, C: Consume<T> + Send { }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use self::rayon::prelude::*;
+
+    use bounded::{spsc};
+    use unbounded::{mpmc};
+
+    #[test]
+    fn test_par_drain() {
+        let (producer, mut consumer) = mpmc::channel(0);
+        for item in 0..1000 {
+            producer.produce(item).unwrap();
+        }
+
+        let mut items = consumer.par_drain().collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, (0..1000).collect::<Vec<_>>());
+        assert!(consumer.consume().is_err());
+    }
+
+    #[test]
+    fn test_par_drain_empty() {
+        let (_producer, mut consumer) = spsc::channel::<i32>(4);
+        assert_eq!(consumer.par_drain().count(), 0);
+    }
+}
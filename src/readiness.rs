@@ -0,0 +1,319 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Readiness notifications for event loops.
+//!
+//! The consumers returned by `wrap` implement `AsRawFd`, so they can be registered with `epoll`,
+//! `kqueue`, `poll`, or `mio` like a socket. The file descriptor is an `eventfd` on Linux and the
+//! read end of a pipe on other Unix platforms. It becomes readable when an item is added to an
+//! empty queue or when a producer is dropped (so that the consumer can observe that the queue has
+//! been disconnected) and stays readable until a consumer finds the queue empty, so an event loop
+//! should remove items until `consume` returns `Err` whenever the file descriptor is readable.
+//!
+//! The producers only write to the file descriptor after a consumer has found the queue empty, so
+//! a busy queue costs at most one system call per time the queue is drained instead of one per
+//! item.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::os::unix::io::{AsRawFd};
+//!
+//! use npnc::ConsumeError;
+//! use npnc::bounded::spsc;
+//! use npnc::readiness;
+//!
+//! fn main() {
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let (producer, consumer) = readiness::wrap(producer, consumer).unwrap();
+//!     let fd = consumer.as_raw_fd();
+//!
+//!     // Register `fd` with an event loop here.
+//!
+//!     producer.produce(1).unwrap();
+//!     producer.produce(2).unwrap();
+//!
+//!     // Once `fd` is readable...
+//!     assert_eq!(consumer.consume(), Ok(1));
+//!     assert_eq!(consumer.consume(), Ok(2));
+//!     assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+//! #   let _ = fd;
+//! }
+//! ```
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::atomic::Ordering::*;
+
+use libc;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer whose queue can be registered with an event loop.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+    event: Arc<Event>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    ///
+    /// This method returns `Err` if the queue is empty, in which case the file descriptor is no
+    /// longer readable until another item is added or a producer is dropped.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        match self.consumer.consume() {
+            Err(ConsumeError::Empty) => {
+                // The queue is checked again after the producers are asked to write to the file
+                // descriptor so that an item added in the meantime is never missed.
+                self.event.clear();
+                self.event.armed.store(true, Relaxed);
+                atomic::fence(SeqCst);
+                self.consumer.consume()
+            },
+            result => result,
+        }
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> AsRawFd for Consumer<C> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.event.read
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        Consumer { consumer: self.consumer.clone(), event: self.event.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Event _________________________________________
+
+/// A file descriptor that is readable while a queue may have items for an event loop.
+#[derive(Debug)]
+struct Event {
+    read: RawFd,
+    write: RawFd,
+    /// Whether a consumer has found the queue empty since the last notification.
+    armed: AtomicBool,
+}
+
+impl Event {
+    //- Constructors -----------------------------
+
+    #[cfg(target_os="linux")]
+    fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Event { read: fd, write: fd, armed: AtomicBool::new(true) })
+    }
+
+    #[cfg(not(target_os="linux"))]
+    fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let event = Event { read: fds[0], write: fds[1], armed: AtomicBool::new(true) };
+        for &fd in &fds {
+            unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(event)
+    }
+
+    //- Accessors --------------------------------
+
+    /// Makes the file descriptor readable.
+    fn notify(&self) {
+        // A full pipe or counter is already readable, so failed writes are ignored.
+        let value = 1u64;
+        let size = if self.read == self.write { 8 } else { 1 };
+        unsafe { libc::write(self.write, &value as *const u64 as *const libc::c_void, size); }
+    }
+
+    /// Makes the file descriptor unreadable.
+    fn clear(&self) {
+        let mut buffer = [0u8; 64];
+        loop {
+            let pointer = buffer.as_mut_ptr() as *mut libc::c_void;
+            if unsafe { libc::read(self.read, pointer, buffer.len()) } <= 0 {
+                return;
+            }
+        }
+    }
+
+    /// Makes the file descriptor readable if a consumer has found the queue empty.
+    fn produced(&self) {
+        atomic::fence(SeqCst);
+        if self.armed.load(Relaxed) && self.armed.swap(false, Relaxed) {
+            self.notify();
+        }
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            if self.write != self.read {
+                libc::close(self.write);
+            }
+        }
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that makes the file descriptor of its consumers readable.
+///
+/// The file descriptor is also made readable when this producer is dropped so that the consumers
+/// can observe that the queue has been disconnected.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: Option<P>,
+    event: Arc<Event>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `Err` if the queue is full or has no remaining consumers.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        self.get_ref().produce(item)?;
+        self.event.produced();
+        Ok(())
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        self.producer.as_ref().unwrap()
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        Producer { producer: self.producer.clone(), event: self.event.clone() }
+    }
+}
+
+impl<P> Drop for Producer<P> {
+    fn drop(&mut self) {
+        // The producer is dropped before the file descriptor is made readable so that the
+        // consumers can observe that the queue has been disconnected.
+        self.producer = None;
+        self.event.notify();
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Wraps the supplied producer and consumer so the consumer can be registered with an event loop.
+///
+/// The supplied producer and consumer should belong to the same queue and should not be used to
+/// add or remove items once wrapped. This function returns `Err` if the file descriptor could not
+/// be created.
+pub fn wrap<P, C>(producer: P, consumer: C) -> io::Result<(Producer<P>, Consumer<C>)> {
+    let event = Arc::new(Event::new()?);
+    let producer = Producer { producer: Some(producer), event: event.clone() };
+    Ok((producer, Consumer { consumer, event }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    fn readable(fd: RawFd) -> bool {
+        let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        unsafe { libc::poll(&mut pollfd, 1, 0) == 1 }
+    }
+
+    #[test]
+    fn test_readiness() {
+        let (producer, consumer) = spsc::channel(64);
+        let (producer, consumer) = wrap(producer, consumer).unwrap();
+        let fd = consumer.as_raw_fd();
+        assert!(!readable(fd));
+
+        producer.produce(1).unwrap();
+        assert!(readable(fd));
+
+        // The file descriptor stays readable until a consumer finds the queue empty.
+        producer.produce(2).unwrap();
+        assert_eq!(consumer.consume(), Ok(1));
+        assert!(readable(fd));
+        assert_eq!(consumer.consume(), Ok(2));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        assert!(!readable(fd));
+
+        producer.produce(3).unwrap();
+        assert!(readable(fd));
+        assert_eq!(consumer.consume(), Ok(3));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (producer, consumer) = spsc::channel::<i32>(64);
+        let (producer, consumer) = wrap(producer, consumer).unwrap();
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        drop(producer);
+        assert!(readable(consumer.as_raw_fd()));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp;
+use std::sync::{Arc, Mutex};
+
+use super::{Hazard, Reclaim};
+
+/// The number of hazard pointers each thread needs for any unbounded MPMC queue algorithm.
+const HAZARDS: usize = 3;
+
+//================================================
+// Structs
+//================================================
+
+// Domain ________________________________________
+
+/// A memory reclamation domain, which can be shared by multiple unbounded MPMC queues.
+///
+/// A domain contains the per-thread state of a memory reclamation scheme (e.g., the hazard
+/// pointers and retired nodes of each thread) for every producer and consumer of the queues that
+/// use it. Every queue normally has a domain of its own, so an application with many queues pays
+/// for the per-thread state of every queue and scans each domain separately. Queues constructed
+/// with a shared domain (see `Builder::domain`) instead reserve slots for their producers and
+/// consumers in the shared domain.
+///
+/// The nodes retired by a queue that uses a shared domain may not be freed until the domain is
+/// dropped, which happens once every clone of the domain and every queue that uses it is dropped.
+/// With `Qsbr`, removed nodes can't be freed until every producer and consumer of every queue that
+/// uses the domain has called `quiescent`, and `memory_usage` counts the retired nodes of every
+/// queue that uses the domain.
+///
+/// # Example
+///
+/// ```
+/// extern crate npnc;
+///
+/// use npnc::reclaim::{Domain, Hazard};
+/// use npnc::unbounded::mpmc::{Builder};
+///
+/// fn main() {
+///     let domain = Domain::<Hazard>::new(4);
+///     let (producer1, consumer1) = Builder::new().domain(&domain).build();
+///     let (producer2, consumer2) = Builder::new().domain(&domain).build();
+///     assert_eq!(domain.remaining_threads(), 0);
+///
+///     producer1.produce(1).unwrap();
+///     producer2.produce(2).unwrap();
+///     assert_eq!(consumer1.consume(), Ok(1));
+///     assert_eq!(consumer2.consume(), Ok(2));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Domain<R=Hazard>(Arc<Inner<R>>) where R: Reclaim;
+
+impl<R> Domain<R> where R: Reclaim {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Domain` that reserves space for the supplied number of producers and
+    /// consumers.
+    ///
+    /// More producers and consumers can use the domain than this number, but the domain has to
+    /// allocate space for them when they are made.
+    pub fn new(threads: usize) -> Self {
+        Domain::with_hazards(threads, HAZARDS)
+    }
+
+    /// Constructs a new `Domain` that reserves space for the supplied number of producers and
+    /// consumers with the supplied number of hazard pointers each.
+    pub(crate) fn with_hazards(threads: usize, hazards: usize) -> Self {
+        let threads = cmp::max(threads, 1);
+        Domain(Arc::new(Inner {
+            reclaim: R::new(threads, hazards),
+            hazards,
+            threads: Mutex::new(((0..threads).rev().collect(), threads)),
+        }))
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the number of additional producers and consumers that can use this domain before it
+    /// has to allocate space for more of them.
+    pub fn remaining_threads(&self) -> usize {
+        self.0.threads.lock().unwrap().0.len()
+    }
+
+    /// Returns the memory reclamation scheme of this domain.
+    pub(crate) fn reclaim(&self) -> &R {
+        &self.0.reclaim
+    }
+
+    /// Returns the number of hazard pointers each thread in this domain has.
+    pub(crate) fn hazards(&self) -> usize {
+        self.0.hazards
+    }
+
+    /// Reserves and returns an unused thread index.
+    pub(crate) fn attach(&self) -> usize {
+        self.try_attach(true).unwrap()
+    }
+
+    /// Reserves and returns an unused thread index, allocating space for a new one if there are no
+    /// unused thread indices and `grow` is `true`.
+    pub(crate) fn try_attach(&self, grow: bool) -> Option<usize> {
+        let mut threads = self.0.threads.lock().unwrap();
+        let thread = match threads.0.pop() {
+            Some(thread) => thread,
+            None if grow => { threads.1 += 1; threads.1 - 1 },
+            None => return None,
+        };
+        self.0.reclaim.attach(thread);
+        Some(thread)
+    }
+
+    /// Releases the supplied thread index so it can be reused.
+    pub(crate) fn detach(&self, thread: usize) {
+        self.0.reclaim.detach(thread);
+        self.0.threads.lock().unwrap().0.push(thread);
+    }
+}
+
+impl<R> Clone for Domain<R> where R: Reclaim {
+    fn clone(&self) -> Self {
+        Domain(self.0.clone())
+    }
+}
+
+impl<R> Default for Domain<R> where R: Reclaim {
+    fn default() -> Self {
+        Domain::new(0)
+    }
+}
+
+// Inner _________________________________________
+
+#[derive(Debug)]
+struct Inner<R> {
+    reclaim: R,
+    hazards: usize,
+    /// The unused thread indices and the number of thread indices that have been handed out.
+    threads: Mutex<(Vec<usize>, usize)>,
+}
+
+// The per-thread state of the reclamation scheme is only accessed by the producer or consumer that
+// reserved its thread index, and thread indices are reserved and released while holding a lock, so
+// a domain can be shared between threads like the queues that use it.
+unsafe impl<R> Send for Inner<R> where R: Reclaim { }
+unsafe impl<R> Sync for Inner<R> where R: Reclaim { }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use reclaim::{Qsbr};
+    use unbounded::mpmc::{Builder};
+
+    fn test_threads<R>() where R: Reclaim + 'static {
+        let domain = Domain::<R>::new(2);
+        let threads = (0..4).map(|index| {
+            let domain = domain.clone();
+            thread::spawn(move || {
+                let (producer, consumer) = Builder::new().domain(&domain).build();
+                let producer = thread::spawn(move || {
+                    for item in 0..1000 {
+                        producer.produce(index * 1000 + item).unwrap();
+                    }
+                });
+                let mut items = vec![];
+                while items.len() < 1000 {
+                    if let Ok(item) = consumer.consume() {
+                        items.push(item);
+                    }
+                }
+                producer.join().unwrap();
+                assert_eq!(items, (index * 1000..(index + 1) * 1000).collect::<Vec<_>>());
+            })
+        }).collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(domain.remaining_threads() >= 2);
+    }
+
+    #[test]
+    fn test_hazard_threads() {
+        test_threads::<Hazard>();
+    }
+
+    #[test]
+    fn test_qsbr_threads() {
+        test_threads::<Qsbr>();
+    }
+}
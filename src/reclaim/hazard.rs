@@ -0,0 +1,225 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ptr;
+use std::cell::{UnsafeCell};
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use super::{sealed, Reclaim, Retired, Table};
+
+/// The number of pointers a thread retires before attempting to free them.
+const THRESHOLD: usize = 512;
+/// The maximum number of retired pointers a thread checks each time it retires a pointer.
+const STEP: usize = 4;
+
+//================================================
+// Structs
+//================================================
+
+// Hazard ________________________________________
+
+/// Hazard-pointer-based memory reclamation.
+#[derive(Debug)]
+pub struct Hazard {
+    domains: usize,
+    locals: Table<Local>,
+}
+
+impl Hazard {
+    //- Accessors --------------------------------
+
+    fn hazard(&self, thread: usize, domain: usize) -> &AtomicPtr<u8> {
+        &self.locals.get(thread).hazards[domain]
+    }
+
+    /// Returns whether the supplied pointer is marked as hazardous by any thread.
+    fn hazardous(&self, pointer: *mut u8) -> bool {
+        self.locals.iter().any(|l| l.hazards.iter().any(|h| h.load(SeqCst) == pointer))
+    }
+
+    /// Frees the pointers retired by the supplied thread that are not marked as hazardous.
+    fn scan(&self, thread: usize) {
+        let hazardous = self.locals.iter()
+            .flat_map(|l| l.hazards.iter().map(|h| h.load(SeqCst)))
+            .collect::<Vec<_>>();
+        let local = self.locals.get(thread);
+        let retired = unsafe { &mut *local.retired.get() };
+        retired.retain(|r| {
+            if hazardous.contains(&r.pointer) {
+                true
+            } else {
+                unsafe { r.free(); }
+                false
+            }
+        });
+        unsafe { *local.cursor.get() = 0; }
+        local.count.store(retired.len(), Relaxed);
+    }
+
+    /// Frees up to `STEP` of the pointers retired by the supplied thread that are not marked as
+    /// hazardous.
+    ///
+    /// Each call resumes checking where the previous call stopped, so the retired pointers are
+    /// scanned incrementally instead of all at once.
+    fn step(&self, thread: usize) {
+        let local = self.locals.get(thread);
+        let retired = unsafe { &mut *local.retired.get() };
+        let cursor = unsafe { &mut *local.cursor.get() };
+        for _ in 0..STEP {
+            if *cursor >= retired.len() {
+                *cursor = 0;
+            }
+
+            if self.hazardous(retired[*cursor].pointer) {
+                *cursor += 1;
+            } else {
+                unsafe { retired.swap_remove(*cursor).free(); }
+                if retired.is_empty() {
+                    break;
+                }
+            }
+        }
+        local.count.store(retired.len(), Relaxed);
+    }
+}
+
+impl Drop for Hazard {
+    fn drop(&mut self) {
+        for local in self.locals.iter() {
+            for retired in unsafe { &*local.retired.get() } {
+                unsafe { retired.free(); }
+            }
+        }
+    }
+}
+
+impl Reclaim for Hazard {
+    fn new(threads: usize, domains: usize) -> Self {
+        Hazard { domains, locals: Table::new(threads, || Local::new(domains)) }
+    }
+
+    fn attach(&self, thread: usize) {
+        self.locals.reserve(thread, || Local::new(self.domains));
+    }
+
+    fn detach(&self, _: usize) { }
+
+    fn mark<N>(&self, thread: usize, domain: usize, pointer: &AtomicPtr<N>) -> *mut N {
+        let hazard = self.hazard(thread, domain);
+        let mut value = pointer.load(Acquire);
+        loop {
+            hazard.store(value as *mut u8, SeqCst);
+            let current = pointer.load(SeqCst);
+            if current == value {
+                return value;
+            }
+            value = current;
+        }
+    }
+
+    fn mark_ptr<N>(&self, thread: usize, domain: usize, pointer: *mut N) -> *mut N {
+        self.hazard(thread, domain).store(pointer as *mut u8, SeqCst);
+        pointer
+    }
+
+    fn clear(&self, thread: usize, domain: usize) {
+        self.hazard(thread, domain).store(ptr::null_mut(), Release);
+    }
+
+    unsafe fn retire<N>(&self, thread: usize, pointer: *mut N) {
+        let local = self.locals.get(thread);
+        let retired = &mut *local.retired.get();
+        retired.push(Retired::new(pointer));
+        if retired.len() >= THRESHOLD {
+            self.step(thread);
+        } else {
+            local.count.store(retired.len(), Relaxed);
+        }
+    }
+
+    fn retired(&self) -> usize {
+        self.locals.iter().map(|l| l.count.load(Relaxed)).sum()
+    }
+
+    fn shrink(&self, thread: usize) {
+        self.scan(thread);
+        unsafe { (*self.locals.get(thread).retired.get()).shrink_to_fit(); }
+    }
+}
+
+impl sealed::Sealed for Hazard { }
+
+// Local _________________________________________
+
+#[derive(Debug)]
+struct Local {
+    hazards: Vec<AtomicPtr<u8>>,
+    retired: UnsafeCell<Vec<Retired>>,
+    cursor: UnsafeCell<usize>,
+    count: AtomicUsize,
+}
+
+impl Local {
+    //- Constructors -----------------------------
+
+    fn new(domains: usize) -> Self {
+        let hazards = (0..domains).map(|_| AtomicPtr::new(ptr::null_mut())).collect();
+        let retired = UnsafeCell::new(vec![]);
+        let cursor = UnsafeCell::new(0);
+        Local { hazards, retired, cursor, count: AtomicUsize::new(0) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc};
+
+    #[test]
+    fn test_hazardous() {
+        let hazard = Hazard::new(2, 1);
+        hazard.attach(0);
+        hazard.attach(1);
+
+        let item = Arc::new(());
+        let pointer = AtomicPtr::new(Box::into_raw(Box::new(item.clone())));
+        let marked = hazard.mark(1, 0, &pointer);
+        unsafe { hazard.retire(0, marked); }
+
+        // A pointer marked as hazardous by another thread is not freed.
+        hazard.shrink(0);
+        assert_eq!((hazard.retired(), Arc::strong_count(&item)), (1, 2));
+
+        hazard.clear(1, 0);
+        hazard.shrink(0);
+        assert_eq!((hazard.retired(), Arc::strong_count(&item)), (0, 1));
+    }
+
+    #[test]
+    fn test_threshold() {
+        let hazard = Hazard::new(1, 1);
+        hazard.attach(0);
+        let item = Arc::new(());
+        for _ in 0..THRESHOLD * 2 {
+            unsafe { hazard.retire(0, Box::into_raw(Box::new(item.clone()))); }
+        }
+
+        // Retired pointers are freed incrementally once the threshold is reached.
+        assert!(hazard.retired() < THRESHOLD);
+        drop(hazard);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Memory reclamation schemes for unbounded lock-free queues.
+//!
+//! * `Hazard` &ndash; hazard pointers, which bound the amount of unreclaimed memory but require
+//!   extra stores on every operation
+//! * `Qsbr` &ndash; quiescent-state-based reclamation, which has no per-operation overhead but
+//!   requires every handle to periodically call `quiescent`
+//!
+//! The per-thread state of a scheme lives in a `Domain`, which can be shared by multiple queues.
+
+use std::fmt;
+use std::sync::atomic::{AtomicPtr};
+
+mod domain;
+mod hazard;
+mod qsbr;
+mod table;
+
+pub use self::domain::{Domain};
+pub use self::hazard::{Hazard};
+pub use self::qsbr::{Qsbr};
+
+pub(crate) use self::table::{Table};
+
+//================================================
+// Structs
+//================================================
+
+// Retired _______________________________________
+
+/// A type-erased pointer to a boxed value that has been removed from a data structure.
+#[derive(Copy, Clone, Debug)]
+struct Retired {
+    pointer: *mut u8,
+    free: unsafe fn(*mut u8),
+}
+
+impl Retired {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Retired`.
+    fn new<N>(pointer: *mut N) -> Self {
+        Retired { pointer: pointer as *mut u8, free: free::<N> }
+    }
+
+    //- Consumers --------------------------------
+
+    /// Frees the boxed value.
+    unsafe fn free(self) {
+        (self.free)(self.pointer);
+    }
+}
+
+//================================================
+// Traits
+//================================================
+
+mod sealed {
+    pub trait Sealed { }
+}
+
+// Reclaim _______________________________________
+
+/// A memory reclamation scheme.
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+pub trait Reclaim: sealed::Sealed + fmt::Debug + Sized {
+    #[doc(hidden)]
+    fn new(threads: usize, domains: usize) -> Self;
+
+    #[doc(hidden)]
+    fn attach(&self, thread: usize);
+
+    #[doc(hidden)]
+    fn detach(&self, thread: usize);
+
+    #[doc(hidden)]
+    fn mark<N>(&self, thread: usize, domain: usize, pointer: &AtomicPtr<N>) -> *mut N;
+
+    #[doc(hidden)]
+    fn mark_ptr<N>(&self, thread: usize, domain: usize, pointer: *mut N) -> *mut N;
+
+    #[doc(hidden)]
+    fn clear(&self, thread: usize, domain: usize);
+
+    #[doc(hidden)]
+    unsafe fn retire<N>(&self, thread: usize, pointer: *mut N);
+
+    #[doc(hidden)]
+    fn retired(&self) -> usize;
+
+    #[doc(hidden)]
+    fn shrink(&self, thread: usize);
+}
+
+//================================================
+// Functions
+//================================================
+
+unsafe fn free<N>(pointer: *mut u8) {
+    drop(Box::from_raw(pointer as *mut N));
+}
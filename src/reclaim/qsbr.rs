@@ -0,0 +1,183 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cell::{UnsafeCell};
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use super::{sealed, Reclaim, Retired, Table};
+
+/// The epoch announced by threads that are not attached.
+const OFFLINE: usize = usize::MAX;
+
+//================================================
+// Structs
+//================================================
+
+// Local _________________________________________
+
+#[derive(Debug)]
+struct Local {
+    epoch: AtomicUsize,
+    retired: UnsafeCell<Vec<(usize, Retired)>>,
+    count: AtomicUsize,
+}
+
+impl Local {
+    //- Constructors -----------------------------
+
+    fn new() -> Self {
+        let retired = UnsafeCell::new(vec![]);
+        Local { epoch: AtomicUsize::new(OFFLINE), retired, count: AtomicUsize::new(0) }
+    }
+}
+
+// Qsbr __________________________________________
+
+/// Quiescent-state-based memory reclamation.
+///
+/// A pointer retired during an epoch is freed once every attached thread has passed through a
+/// quiescent state (by calling `quiescent`) twice since the epoch began. Threads that never pass
+/// through a quiescent state prevent any retired pointers from being freed.
+#[derive(Debug)]
+pub struct Qsbr {
+    epoch: AtomicUsize,
+    locals: Table<Local>,
+}
+
+impl Qsbr {
+    //- Accessors --------------------------------
+
+    /// Announces that the supplied thread holds no references to shared nodes.
+    pub(crate) fn quiescent(&self, thread: usize) {
+        // Announce the current epoch and advance the epoch if every attached thread has announced
+        // the current epoch.
+        let epoch = self.epoch.load(SeqCst);
+        let local = self.locals.get(thread);
+        local.epoch.store(epoch, SeqCst);
+        let current = |l: &Local| { let e = l.epoch.load(SeqCst); e == epoch || e == OFFLINE };
+        if self.locals.iter().all(current) {
+            let _ = self.epoch.compare_exchange(epoch, epoch + 1, SeqCst, SeqCst);
+        }
+
+        // Free the pointers retired at least two epochs ago.
+        let epoch = self.epoch.load(SeqCst);
+        let retired = unsafe { &mut *local.retired.get() };
+        retired.retain(|&(e, r)| {
+            if e + 2 <= epoch {
+                unsafe { r.free(); }
+                false
+            } else {
+                true
+            }
+        });
+        local.count.store(retired.len(), Relaxed);
+    }
+}
+
+impl Drop for Qsbr {
+    fn drop(&mut self) {
+        for local in self.locals.iter() {
+            for &(_, retired) in unsafe { &*local.retired.get() } {
+                unsafe { retired.free(); }
+            }
+        }
+    }
+}
+
+impl Reclaim for Qsbr {
+    fn new(threads: usize, _: usize) -> Self {
+        Qsbr { epoch: AtomicUsize::new(0), locals: Table::new(threads, Local::new) }
+    }
+
+    fn attach(&self, thread: usize) {
+        self.locals.reserve(thread, Local::new);
+        self.locals.get(thread).epoch.store(self.epoch.load(SeqCst), SeqCst);
+    }
+
+    fn detach(&self, thread: usize) {
+        self.quiescent(thread);
+        self.locals.get(thread).epoch.store(OFFLINE, SeqCst);
+    }
+
+    fn mark<N>(&self, _: usize, _: usize, pointer: &AtomicPtr<N>) -> *mut N {
+        pointer.load(Acquire)
+    }
+
+    fn mark_ptr<N>(&self, _: usize, _: usize, pointer: *mut N) -> *mut N {
+        pointer
+    }
+
+    fn clear(&self, _: usize, _: usize) { }
+
+    unsafe fn retire<N>(&self, thread: usize, pointer: *mut N) {
+        let local = self.locals.get(thread);
+        let retired = &mut *local.retired.get();
+        retired.push((self.epoch.load(SeqCst), Retired::new(pointer)));
+        local.count.store(retired.len(), Relaxed);
+    }
+
+    fn retired(&self) -> usize {
+        self.locals.iter().map(|l| l.count.load(Relaxed)).sum()
+    }
+
+    fn shrink(&self, thread: usize) {
+        self.quiescent(thread);
+        unsafe { (*self.locals.get(thread).retired.get()).shrink_to_fit(); }
+    }
+}
+
+impl sealed::Sealed for Qsbr { }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc};
+
+    #[test]
+    fn test_quiescent() {
+        let qsbr = Qsbr::new(2, 0);
+        qsbr.attach(0);
+        qsbr.attach(1);
+
+        let item = Arc::new(());
+        unsafe { qsbr.retire(0, Box::into_raw(Box::new(item.clone()))); }
+
+        // A thread that never passes through a quiescent state prevents the pointer being freed.
+        for _ in 0..4 {
+            qsbr.quiescent(0);
+        }
+        assert_eq!((qsbr.retired(), Arc::strong_count(&item)), (1, 2));
+
+        qsbr.quiescent(1);
+        qsbr.quiescent(0);
+        assert_eq!((qsbr.retired(), Arc::strong_count(&item)), (0, 1));
+    }
+
+    #[test]
+    fn test_detach() {
+        let qsbr = Qsbr::new(2, 0);
+        qsbr.attach(0);
+        qsbr.attach(1);
+        qsbr.detach(1);
+
+        // A detached thread does not prevent pointers from being freed.
+        let item = Arc::new(());
+        unsafe { qsbr.retire(0, Box::into_raw(Box::new(item.clone()))); }
+        qsbr.quiescent(0);
+        qsbr.quiescent(0);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::marker::{PhantomData};
+use std::sync::atomic::{AtomicPtr};
+use std::sync::atomic::Ordering::*;
+
+/// The number of bits in a `usize`.
+const BITS: usize = mem::size_of::<usize>() * 8;
+
+//================================================
+// Structs
+//================================================
+
+// Table _________________________________________
+
+/// A growable array of per-thread values.
+///
+/// The values are stored in chunks that double in size and are allocated when a value in them is
+/// first reserved. A chunk is added with a single compare-and-swap, so the table can grow without
+/// a lock and values never move once they have been constructed.
+pub struct Table<T> {
+    shift: usize,
+    chunks: Vec<AtomicPtr<T>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Table<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Table` with room for at least the supplied number of values.
+    pub fn new<F>(len: usize, init: F) -> Self where F: Fn() -> T {
+        let shift = len.next_power_of_two().trailing_zeros() as usize;
+        let chunks = (0..BITS - shift).map(|_| AtomicPtr::new(ptr::null_mut())).collect();
+        let table = Table { shift, chunks, _marker: PhantomData };
+        table.reserve(0, init);
+        table
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the chunk and offset in the chunk for the supplied index.
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let index = index + (1 << self.shift);
+        let bit = BITS - 1 - index.leading_zeros() as usize;
+        (bit - self.shift, index - (1 << bit))
+    }
+
+    /// Returns the number of values in the supplied chunk.
+    fn size(&self, chunk: usize) -> usize {
+        1 << (self.shift + chunk)
+    }
+
+    /// Allocates the chunk that contains the supplied index if it has not already been allocated.
+    pub fn reserve<F>(&self, index: usize, init: F) where F: Fn() -> T {
+        let (chunk, _) = self.locate(index);
+        if !self.chunks[chunk].load(SeqCst).is_null() {
+            return;
+        }
+
+        let values = (0..self.size(chunk)).map(|_| init()).collect::<Vec<_>>();
+        let pointer = Box::into_raw(values.into_boxed_slice()) as *mut T;
+        let null = ptr::null_mut();
+        if self.chunks[chunk].compare_exchange(null, pointer, SeqCst, SeqCst).is_err() {
+            unsafe { self.free(chunk, pointer); }
+        }
+    }
+
+    /// Returns the value at the supplied index.
+    ///
+    /// The chunk that contains the index must have been reserved.
+    pub fn get(&self, index: usize) -> &T {
+        let (chunk, offset) = self.locate(index);
+        let pointer = self.chunks[chunk].load(SeqCst);
+        debug_assert!(!pointer.is_null(), "index has not been reserved");
+        unsafe { &*pointer.add(offset) }
+    }
+
+    /// Returns an iterator over the values in the allocated chunks.
+    pub fn iter(&self) -> impl Iterator<Item=&T> {
+        self.chunks.iter().enumerate().flat_map(move |(chunk, pointer)| {
+            let pointer = pointer.load(SeqCst);
+            let len = if pointer.is_null() { 0 } else { self.size(chunk) };
+            (0..len).map(move |offset| unsafe { &*pointer.add(offset) })
+        })
+    }
+
+    /// Frees the supplied chunk.
+    unsafe fn free(&self, chunk: usize, pointer: *mut T) {
+        let slice = ptr::slice_from_raw_parts_mut(pointer, self.size(chunk));
+        drop(Box::from_raw(slice));
+    }
+}
+
+impl<T> Drop for Table<T> {
+    fn drop(&mut self) {
+        for (chunk, pointer) in self.chunks.iter().enumerate() {
+            let pointer = pointer.load(SeqCst);
+            if !pointer.is_null() {
+                unsafe { self.free(chunk, pointer); }
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Table<T> where T: fmt::Debug {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,389 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Zero-capacity multi-producer, single-consumer rendezvous channel.
+//!
+//! A rendezvous channel never buffers an item that the consumer has not asked for. Adding an item
+//! only succeeds while the consumer is waiting for one, otherwise the producer receives a `Full`
+//! error and keeps the item. The consumer only waits for an item during a call to `consume`,
+//! `consume_blocking`, or `consume_timeout`, and at most one producer can hand an item to it during
+//! each call. `consume` stops waiting before it returns, so it only removes an item if a producer
+//! hands one over while it is running. `consume_blocking` spins briefly and then parks the consumer
+//! thread until a producer hands it an item and `consume_timeout` does the same but stops waiting
+//! once a duration has elapsed.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::thread;
+//!
+//! use npnc::ProduceError;
+//! use npnc::rendezvous;
+//!
+//! fn main() {
+//!     let (producer, consumer) = rendezvous::channel();
+//!     assert_eq!(producer.produce(0), Err(ProduceError::Full(0)));
+//!
+//!     let thread = thread::spawn(move || {
+//!         let mut item = 1;
+//!         loop {
+//!             match producer.produce(item) {
+//!                 Ok(()) => return,
+//!                 Err(error) => item = error.item(),
+//!             }
+//!             thread::yield_now();
+//!         }
+//!     });
+//!
+//!     assert_eq!(consumer.consume_blocking(), Ok(1));
+//!     thread.join().unwrap();
+//! }
+//! ```
+
+use std::fmt;
+use std::hint;
+use std::cell::{Cell, UnsafeCell};
+use std::marker::{PhantomData};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+/// Indicates that the consumer is not waiting for an item.
+const IDLE: usize = 0;
+/// Indicates that the consumer is waiting for an item.
+const WAITING: usize = 1;
+/// Indicates that a producer is handing an item to the consumer.
+const WRITING: usize = 2;
+/// Indicates that a producer has handed an item to the consumer.
+const FULL: usize = 3;
+
+/// The number of times `consume_blocking` and `consume_timeout` check for an item before parking.
+const SPIN: usize = 100;
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for a rendezvous channel.
+///
+/// A consumer can be sent to another thread but can't be shared between threads, since only one
+/// thread can wait for an item at a time.
+#[derive(Debug)]
+pub struct Consumer<T>(Arc<Slot<T>>, PhantomData<Cell<()>>);
+
+impl<T> Consumer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item handed to this consumer without waiting.
+    ///
+    /// This consumer waits for an item only while this method is running, so a producer can only
+    /// hand it an item during the call. This method returns `Err` if no item was handed to this
+    /// consumer.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+
+    /// Removes and returns an item that has been handed to this consumer, waiting for one if
+    /// necessary.
+    ///
+    /// This method returns `Err` if the channel has no remaining producers.
+    pub fn consume_blocking(&self) -> Result<T, ConsumeError> {
+        *self.0.thread.lock().unwrap() = Some(thread::current());
+        let mut spin = 0;
+        loop {
+            match self.0.poll() {
+                Err(ConsumeError::Empty) if spin < SPIN => { spin += 1; hint::spin_loop(); },
+                Err(ConsumeError::Empty) => thread::park(),
+                result => return result,
+            }
+        }
+    }
+
+    /// Removes and returns an item that has been handed to this consumer, waiting up to the
+    /// supplied duration for one if necessary.
+    ///
+    /// This method returns `Timeout` if no item has been handed to this consumer once the duration
+    /// has elapsed, in which case this consumer stops waiting for an item. A duration too long to
+    /// be represented as a deadline waits indefinitely.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        let deadline = match Instant::now().checked_add(timeout) {
+            Some(deadline) => deadline,
+            None => return self.consume_blocking(),
+        };
+        *self.0.thread.lock().unwrap() = Some(thread::current());
+        let mut spin = 0;
+        loop {
+            match self.0.poll() {
+                Err(ConsumeError::Empty) if spin < SPIN => { spin += 1; hint::spin_loop(); },
+                Err(ConsumeError::Empty) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return self.0.cancel().ok_or(ConsumeError::Timeout);
+                    }
+                    thread::park_timeout(deadline - now);
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// Returns the number of producers currently connected to the channel.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+
+    fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consumer::consume_timeout(self, timeout)
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.0.cancel();
+        self.0.consumer.store(false, Release);
+    }
+}
+
+unsafe impl<T> Send for Consumer<T> where T: Send { }
+
+// Producer ______________________________________
+
+/// A producer for a rendezvous channel.
+#[derive(Debug)]
+pub struct Producer<T>(Arc<Slot<T>>);
+
+impl<T> Producer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to hand the supplied item to the consumer.
+    ///
+    /// This method returns `Full` if the consumer is not waiting for an item or another producer
+    /// has already handed it one and `Disconnected` if the consumer has been dropped.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    /// Returns whether the consumer is waiting for an item.
+    pub fn is_consumer_waiting(&self) -> bool {
+        self.0.state.load(Acquire) == WAITING
+    }
+
+    /// Returns the number of producers currently connected to the channel.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        self.0.producers.fetch_add(1, Release);
+        Producer(self.0.clone())
+    }
+}
+
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        if self.0.producers.fetch_sub(1, AcqRel) == 1 {
+            self.0.unpark();
+        }
+    }
+}
+
+unsafe impl<T> Send for Producer<T> where T: Send { }
+
+// Slot __________________________________________
+
+struct Slot<T> {
+    state: AtomicUsize,
+    item: UnsafeCell<Option<T>>,
+    producers: AtomicUsize,
+    consumer: AtomicBool,
+    thread: Mutex<Option<Thread>>,
+}
+
+impl<T> Slot<T> {
+    //- Accessors --------------------------------
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        if !self.consumer.load(Acquire) {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        // Claim the slot if the consumer is waiting for an item.
+        if self.state.compare_exchange(WAITING, WRITING, Acquire, Relaxed).is_err() {
+            return Err(ProduceError::Full(item));
+        }
+
+        // The consumer does not access the item until the slot is full.
+        unsafe { *self.item.get() = Some(item); }
+        self.state.store(FULL, Release);
+        self.unpark();
+        Ok(())
+    }
+
+    /// Removes an item that has been handed to the consumer, stopping waiting for an item before
+    /// returning `Err`.
+    fn consume(&self) -> Result<T, ConsumeError> {
+        match self.poll() {
+            Err(ConsumeError::Empty) => self.cancel().ok_or(ConsumeError::Empty),
+            result => result,
+        }
+    }
+
+    /// Removes an item that has been handed to the consumer, starting or continuing to wait for an
+    /// item if none has been handed over.
+    fn poll(&self) -> Result<T, ConsumeError> {
+        // The producers are checked first so that an item handed off by the last producer before it
+        // was dropped is never missed.
+        let disconnected = self.producers.load(Acquire) == 0;
+
+        match self.state.load(Acquire) {
+            FULL => Ok(self.take()),
+            IDLE if !disconnected => {
+                self.state.store(WAITING, Release);
+                Err(ConsumeError::Empty)
+            },
+            WAITING if disconnected => {
+                match self.state.compare_exchange(WAITING, IDLE, Acquire, Acquire) {
+                    Ok(_) => Err(ConsumeError::Disconnected),
+                    Err(FULL) => Ok(self.take()),
+                    Err(_) => Err(ConsumeError::Empty),
+                }
+            },
+            IDLE => Err(ConsumeError::Disconnected),
+            _ => Err(ConsumeError::Empty),
+        }
+    }
+
+    fn cancel(&self) -> Option<T> {
+        loop {
+            match self.state.compare_exchange(WAITING, IDLE, Acquire, Acquire) {
+                Ok(_) | Err(IDLE) => return None,
+                Err(FULL) => return Some(self.take()),
+                // A producer is handing an item to the consumer.
+                Err(_) => hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Removes the item that has been handed to the consumer once the slot is full.
+    fn take(&self) -> T {
+        let item = unsafe { (*self.item.get()).take().unwrap() };
+        self.state.store(IDLE, Release);
+        item
+    }
+
+    /// Wakes the consumer if it is parked in `consume_blocking`.
+    fn unpark(&self) {
+        if let Some(ref thread) = *self.thread.lock().unwrap() {
+            thread.unpark();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Slot<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("Slot")
+            .field("state", &self.state.load(Relaxed))
+            .field("producers", &self.producers.load(Relaxed))
+            .field("consumer", &self.consumer.load(Relaxed))
+            .finish()
+    }
+}
+
+unsafe impl<T> Sync for Slot<T> where T: Send { }
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for a zero-capacity rendezvous channel.
+pub fn channel<T>() -> (Producer<T>, Consumer<T>) {
+    let slot = Arc::new(Slot {
+        state: AtomicUsize::new(IDLE),
+        item: UnsafeCell::new(None),
+        producers: AtomicUsize::new(1),
+        consumer: AtomicBool::new(true),
+        thread: Mutex::new(None),
+    });
+    (Producer(slot.clone()), Consumer(slot, PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consume_stops_waiting() {
+        let (producer, consumer) = channel();
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        assert!(!producer.is_consumer_waiting());
+        assert_eq!(producer.produce(1), Err(ProduceError::Full(1)));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        drop(producer);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_consume_blocking() {
+        let (producer, consumer) = channel();
+        let thread = thread::spawn(move || {
+            for index in 0..16 {
+                let mut item = index;
+                while let Err(ProduceError::Full(i)) = producer.produce(item) {
+                    item = i;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        for index in 0..16 {
+            assert_eq!(consumer.consume_blocking(), Ok(index));
+        }
+        thread.join().unwrap();
+        assert_eq!(consumer.consume_blocking(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    fn test_consume_timeout() {
+        let (producer, consumer) = channel::<i32>();
+        let timeout = Duration::from_millis(10);
+        assert_eq!(consumer.consume_timeout(timeout), Err(ConsumeError::Timeout));
+        assert_eq!(producer.produce(1), Err(ProduceError::Full(1)));
+
+        drop(consumer);
+        assert_eq!(producer.produce(1), Err(ProduceError::Disconnected(1)));
+    }
+}
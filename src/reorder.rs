@@ -0,0 +1,473 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Removing sequenced items from a queue in sequence order.
+//!
+//! A `ReorderConsumer` removes items tagged with sequence numbers (e.g., by the workers of a
+//! pipeline that processes items in parallel) from a queue and returns them strictly in sequence
+//! order. Items that are removed before the items that precede them are held in a local buffer
+//! until the gap is filled. The buffer only holds the items whose sequence numbers are within a
+//! fixed window of the next sequence number, so the producers must not get more than the window
+//! ahead of the slowest producer.
+//!
+//! A `WindowConsumer` instead removes items that are only slightly out of order by a key (e.g., a
+//! sequence number or a timestamp from one of several sharded producers) and returns them in order
+//! by that key. It holds up to a fixed number of items in a local buffer and returns the item with
+//! the smallest key once the buffer is full, so an item is returned in order as long as no more
+//! than that number of items with larger keys are removed from the queue before it. Unlike a
+//! `ReorderConsumer`, the keys do not have to be contiguous.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::ConsumeError;
+//! use npnc::bounded::mpmc;
+//! use npnc::reorder::{ReorderConsumer};
+//!
+//! fn main() {
+//!     let (producer, consumer) = mpmc::channel(64);
+//!     let consumer = ReorderConsumer::new(consumer, 16);
+//!
+//!     producer.produce((1, "b")).unwrap();
+//!     producer.produce((2, "c")).unwrap();
+//!     assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+//!     assert_eq!(consumer.pending(), 2);
+//!
+//!     producer.produce((0, "a")).unwrap();
+//!     assert_eq!(consumer.consume(), Ok((0, "a")));
+//!     assert_eq!(consumer.consume(), Ok((1, "b")));
+//!     assert_eq!(consumer.consume(), Ok((2, "c")));
+//! }
+//! ```
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::ConsumeError;
+//! use npnc::bounded::mpmc;
+//! use npnc::reorder::{WindowConsumer};
+//!
+//! fn main() {
+//!     let (producer, consumer) = mpmc::channel(64);
+//!     let consumer = WindowConsumer::new(consumer, 2, |&(timestamp, _): &(u64, char)| timestamp);
+//!
+//!     producer.produce((20, 'b')).unwrap();
+//!     producer.produce((10, 'a')).unwrap();
+//!     producer.produce((40, 'd')).unwrap();
+//!     producer.produce((30, 'c')).unwrap();
+//!     assert_eq!(consumer.consume(), Ok((10, 'a')));
+//!     assert_eq!(consumer.consume(), Ok((20, 'b')));
+//!     assert_eq!(consumer.consume(), Ok((30, 'c')));
+//!     assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+//!
+//!     drop(producer);
+//!     assert_eq!(consumer.consume(), Ok((40, 'd')));
+//!     assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+//! }
+//! ```
+
+use std::fmt;
+use std::cell::{Cell, UnsafeCell};
+use std::cmp::{Ordering};
+use std::collections::{BinaryHeap};
+use std::marker::{PhantomData};
+
+use {Consume, ConsumeError};
+#[cfg(target_has_atomic="64")]
+use causal::{Stamped};
+
+//================================================
+// Structs
+//================================================
+
+// Entry _________________________________________
+
+/// An item in the local buffer of a `WindowConsumer`, which is ordered so that the greatest entry
+/// is the one with the smallest key that was removed from the queue first.
+struct Entry<T, K> {
+    key: K,
+    order: u64,
+    item: T,
+}
+
+impl<T, K> Ord for Entry<T, K> where K: Ord {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.cmp(&self.key).then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl<T, K> PartialOrd for Entry<T, K> where K: Ord {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, K> PartialEq for Entry<T, K> where K: Ord {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, K> Eq for Entry<T, K> where K: Ord { }
+
+// ReorderConsumer _______________________________
+
+/// A consumer that returns sequenced items in sequence order.
+///
+/// The items in the local buffer are dropped when this consumer is dropped.
+pub struct ReorderConsumer<T, C> {
+    consumer: C,
+    buffer: UnsafeCell<Vec<Option<T>>>,
+    next: Cell<u64>,
+    pending: Cell<usize>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, C> ReorderConsumer<T, C> where T: Sequenced, C: Consume<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `ReorderConsumer` that returns items starting with sequence number `0`
+    /// and can hold the items with up to the supplied number of sequence numbers at once.
+    ///
+    /// # Panics
+    ///
+    /// * `window` is `0`
+    pub fn new(consumer: C, window: usize) -> Self {
+        assert!(window != 0, "`window` is zero");
+        ReorderConsumer {
+            consumer,
+            buffer: UnsafeCell::new((0..window).map(|_| None).collect()),
+            next: Cell::new(0),
+            pending: Cell::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item with the next sequence number.
+    ///
+    /// Items are removed from the queue and held in the local buffer until the item with the next
+    /// sequence number is found. This method returns `Err` if the queue is empty or disconnected
+    /// before the item with the next sequence number is found.
+    ///
+    /// # Panics
+    ///
+    /// * an item has a sequence number that has already been returned or skipped, that is held in
+    ///   the local buffer, or that is not within the window of the next sequence number
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        let buffer = unsafe { &mut *self.buffer.get() };
+        let next = self.next.get();
+        if let Some(item) = buffer[self.index(next)].take() {
+            self.pending.set(self.pending.get() - 1);
+            self.next.set(next + 1);
+            return Ok(item);
+        }
+
+        loop {
+            let item = self.consumer.consume()?;
+            let sequence = item.sequence();
+            if sequence == next {
+                self.next.set(next + 1);
+                return Ok(item);
+            }
+
+            let index = self.index(sequence);
+            let valid = sequence > next && sequence - next < buffer.len() as u64;
+            assert!(valid && buffer[index].is_none(), "an item has an unexpected sequence number");
+            buffer[index] = Some(item);
+            self.pending.set(self.pending.get() + 1);
+        }
+    }
+
+    /// Gives up on the item with the next sequence number (e.g., because the producer that would
+    /// have added it failed) and returns the item if it is in the local buffer.
+    pub fn skip(&self) -> Option<T> {
+        let buffer = unsafe { &mut *self.buffer.get() };
+        let next = self.next.get();
+        let item = buffer[self.index(next)].take();
+        if item.is_some() {
+            self.pending.set(self.pending.get() - 1);
+        }
+        self.next.set(next + 1);
+        item
+    }
+
+    /// Returns the sequence number of the next item this consumer will return.
+    pub fn next_sequence(&self) -> u64 {
+        self.next.get()
+    }
+
+    /// Returns the number of items in the local buffer.
+    pub fn pending(&self) -> usize {
+        self.pending.get()
+    }
+
+    /// Returns the number of sequence numbers the local buffer can hold items for.
+    pub fn window(&self) -> usize {
+        unsafe { (*self.buffer.get()).len() }
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+
+    /// Returns the index in the local buffer for the supplied sequence number.
+    fn index(&self, sequence: u64) -> usize {
+        (sequence % self.window() as u64) as usize
+    }
+}
+
+impl<T, C> Consume<T> for ReorderConsumer<T, C> where T: Sequenced, C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        ReorderConsumer::consume(self)
+    }
+}
+
+impl<T, C> fmt::Debug for ReorderConsumer<T, C> where C: fmt::Debug {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("ReorderConsumer")
+            .field("consumer", &self.consumer)
+            .field("next", &self.next.get())
+            .field("pending", &self.pending.get())
+            .finish()
+    }
+}
+
+// WindowConsumer ________________________________
+
+/// A consumer that returns items that are slightly out of order in order by a key.
+///
+/// The items in the local buffer are dropped when this consumer is dropped.
+pub struct WindowConsumer<T, C, F, K> {
+    consumer: C,
+    buffer: UnsafeCell<BinaryHeap<Entry<T, K>>>,
+    window: usize,
+    key: F,
+    last: UnsafeCell<Option<K>>,
+    order: Cell<u64>,
+    late: Cell<usize>,
+}
+
+impl<T, C, F, K> WindowConsumer<T, C, F, K> where C: Consume<T>, F: Fn(&T) -> K, K: Ord {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `WindowConsumer` that orders items by the key returned by the supplied
+    /// function and can hold up to the supplied number of items at once.
+    ///
+    /// # Panics
+    ///
+    /// * `window` is `0`
+    pub fn new(consumer: C, window: usize, key: F) -> Self {
+        assert!(window != 0, "`window` is zero");
+        WindowConsumer {
+            consumer,
+            buffer: UnsafeCell::new(BinaryHeap::with_capacity(window)),
+            window,
+            key,
+            last: UnsafeCell::new(None),
+            order: Cell::new(0),
+            late: Cell::new(0),
+        }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item with the smallest key.
+    ///
+    /// Items are removed from the queue and held in the local buffer until the buffer is full.
+    /// This method returns `Err` if the queue is empty before the buffer is full. Once the queue
+    /// is empty and disconnected, the items in the buffer are returned before `Disconnected`.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        let buffer = unsafe { &mut *self.buffer.get() };
+        while buffer.len() < self.window {
+            match self.consumer.consume() {
+                Ok(item) => {
+                    let order = self.order.get();
+                    self.order.set(order.wrapping_add(1));
+                    buffer.push(Entry { key: (self.key)(&item), order, item });
+                },
+                Err(ConsumeError::Disconnected) if !buffer.is_empty() => break,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(self.pop().unwrap())
+    }
+
+    /// Removes and returns the item in the local buffer with the smallest key without waiting for
+    /// the buffer to be full (e.g., to avoid holding items indefinitely while the queue is idle).
+    pub fn flush(&self) -> Option<T> {
+        self.pop()
+    }
+
+    /// Returns the number of items in the local buffer.
+    pub fn pending(&self) -> usize {
+        unsafe { (*self.buffer.get()).len() }
+    }
+
+    /// Returns the number of items the local buffer can hold.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Returns the number of items that have been returned out of order because they arrived too
+    /// late for the window to reorder them.
+    pub fn late(&self) -> usize {
+        self.late.get()
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+
+    /// Removes and returns the item in the local buffer with the smallest key.
+    fn pop(&self) -> Option<T> {
+        let entry = unsafe { (*self.buffer.get()).pop()? };
+        let last = unsafe { &mut *self.last.get() };
+        match *last {
+            Some(ref last) if entry.key < *last => self.late.set(self.late.get() + 1),
+            _ => *last = Some(entry.key),
+        }
+        Some(entry.item)
+    }
+}
+
+impl<T, C, F, K> Consume<T> for WindowConsumer<T, C, F, K>
+    where C: Consume<T>, F: Fn(&T) -> K, K: Ord
+{
+    fn consume(&self) -> Result<T, ConsumeError> {
+        WindowConsumer::consume(self)
+    }
+}
+
+impl<T, C, F, K> fmt::Debug for WindowConsumer<T, C, F, K> where C: fmt::Debug {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("WindowConsumer")
+            .field("consumer", &self.consumer)
+            .field("pending", &unsafe { (*self.buffer.get()).len() })
+            .field("window", &self.window)
+            .field("late", &self.late.get())
+            .finish()
+    }
+}
+
+//================================================
+// Traits
+//================================================
+
+// Sequenced _____________________________________
+
+/// A type of item that is tagged with a sequence number.
+pub trait Sequenced {
+    /// Returns the sequence number of this item.
+    fn sequence(&self) -> u64;
+}
+
+impl<T> Sequenced for (u64, T) {
+    fn sequence(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(target_has_atomic="64")]
+impl<T> Sequenced for Stamped<T> {
+    /// Returns the global stamp of this item.
+    ///
+    /// The global stamp orders items across producers, unlike the per-producer sequence number.
+    #[allow(clippy::misnamed_getters)]
+    fn sequence(&self) -> u64 {
+        self.stamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{mpmc};
+
+    #[test]
+    fn test_reorder() {
+        let (producer, consumer) = mpmc::channel(64);
+        let consumer = ReorderConsumer::new(consumer, 4);
+        for &sequence in &[3, 1, 2, 0] {
+            producer.produce((sequence, sequence * 10)).unwrap();
+        }
+
+        let items = (0..4).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+        assert_eq!(items, &[(0, 0), (1, 10), (2, 20), (3, 30)]);
+        assert_eq!((consumer.next_sequence(), consumer.pending()), (4, 0));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+    }
+
+    #[test]
+    fn test_skip() {
+        let (producer, consumer) = mpmc::channel(64);
+        let consumer = ReorderConsumer::new(consumer, 4);
+        producer.produce((1, 'b')).unwrap();
+        producer.produce((2, 'c')).unwrap();
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        // The item with sequence number `0` is never added.
+        assert_eq!(consumer.skip(), None);
+        assert_eq!(consumer.skip(), Some((1, 'b')));
+        assert_eq!(consumer.consume(), Ok((2, 'c')));
+        assert_eq!(consumer.pending(), 0);
+
+        drop(producer);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+    }
+
+    #[test]
+    #[should_panic(expected = "an item has an unexpected sequence number")]
+    fn test_outside_window() {
+        let (producer, consumer) = mpmc::channel(64);
+        let consumer = ReorderConsumer::new(consumer, 4);
+        producer.produce((4, ())).unwrap();
+        let _ = consumer.consume();
+    }
+
+    #[test]
+    #[should_panic(expected = "an item has an unexpected sequence number")]
+    fn test_duplicate() {
+        let (producer, consumer) = mpmc::channel(64);
+        let consumer = ReorderConsumer::new(consumer, 4);
+        producer.produce((1, ())).unwrap();
+        producer.produce((1, ())).unwrap();
+        let _ = consumer.consume();
+    }
+
+    #[test]
+    fn test_window() {
+        let (producer, consumer) = mpmc::channel(64);
+        let consumer = WindowConsumer::new(consumer, 2, |&(key, _): &(u32, u32)| key);
+        for (order, &key) in [2, 1, 1, 4, 0, 3].iter().enumerate() {
+            producer.produce((key, order as u32)).unwrap();
+        }
+
+        // Items with the same key are returned in the order they were removed.
+        let items = (0..4).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+        assert_eq!(items, &[(1, 1), (1, 2), (2, 0), (0, 4)]);
+        assert_eq!(consumer.late(), 1);
+        assert_eq!(consumer.pending(), 1);
+        assert_eq!(consumer.consume(), Ok((3, 5)));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        assert_eq!(consumer.flush(), Some((4, 3)));
+        assert_eq!(consumer.flush(), None);
+    }
+}
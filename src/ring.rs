@@ -0,0 +1,333 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicIsize, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {ChannelError, POINTERS, padding};
+use buffer;
+
+/// The bit of the tail position of a ring that is set once the ring has been finalized.
+const FINALIZED: usize = !(usize::MAX >> 1);
+
+//================================================
+// Structs
+//================================================
+
+// Ring __________________________________________
+
+/// A ring of buffer indices.
+///
+/// A ring with an order of `n` contains up to `2^n` indices in `2^(n + 1)` entries. Each entry
+/// packs a cycle, a safe bit, and an index into one word. This is the ring of the scalable
+/// circular queue (SCQ) algorithm (Nikolaev), which is used by the `Scq` algorithm of the bounded
+/// MPMC queue and, with finalization, by the `Lscq` algorithm of the unbounded MPMC queue.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Ring {
+    tail: AtomicUsize,
+    _tpadding: [usize; padding(1)],
+    head: AtomicUsize,
+    _hpadding: [usize; padding(1)],
+    threshold: AtomicIsize,
+    _rpadding: [usize; padding(1)],
+    entries: Vec<AtomicUsize>,
+    order: u32,
+}
+
+impl Ring {
+    //- Constructors -----------------------------
+
+    /// Attempts to construct a new empty `Ring` with the supplied order.
+    pub fn try_new(order: u32) -> Result<Self, ChannelError> {
+        let size = 1usize.checked_shl(order + 1).ok_or(ChannelError::Overflow)?;
+        let mut entries = buffer::try_vec(size)?;
+        entries.extend((0..size).map(|_| AtomicUsize::new(0)));
+        let ring = Ring {
+            tail: AtomicUsize::new(size),
+            _tpadding: [0; padding(1)],
+            head: AtomicUsize::new(size),
+            _hpadding: [0; padding(1)],
+            threshold: AtomicIsize::new(-1),
+            _rpadding: [0; padding(1)],
+            entries,
+            order,
+        };
+        let empty = ring.pack(0, 1, ring.bottom());
+        for entry in &ring.entries {
+            entry.store(empty, Relaxed);
+        }
+        Ok(ring)
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the number of entries in this ring.
+    pub fn size(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the number of indices in this ring.
+    ///
+    /// This may overestimate the number of indices while indices are being added or removed.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Acquire);
+        let tail = self.tail.load(Acquire) & !FINALIZED;
+        tail.saturating_sub(head)
+    }
+
+    /// Returns the index that indicates an entry is empty.
+    fn bottom(&self) -> usize {
+        self.size() - 1
+    }
+
+    /// Returns the threshold that allows consumers to search for an index after an index is added.
+    fn reset(&self) -> isize {
+        (3 * (self.size() as isize / 2)) - 1
+    }
+
+    /// Returns whether the supplied index indicates an entry is empty.
+    ///
+    /// Consumers set the bits of `bottom - 1` in an index to mark an entry as consumed, which
+    /// leaves either `bottom - 1` or `bottom` in the entry.
+    fn is_empty(&self, index: usize) -> bool {
+        index >= self.bottom() - 1
+    }
+
+    fn pack(&self, cycle: usize, safe: usize, index: usize) -> usize {
+        (cycle << (self.order + 2)) | (safe << (self.order + 1)) | index
+    }
+
+    /// Returns the cycle of the supplied head or tail position.
+    fn turn(&self, position: usize) -> usize {
+        position >> (self.order + 1)
+    }
+
+    fn cycle(&self, entry: usize) -> usize {
+        entry >> (self.order + 2)
+    }
+
+    fn safe(&self, entry: usize) -> usize {
+        (entry >> (self.order + 1)) & 1
+    }
+
+    fn index(&self, entry: usize) -> usize {
+        entry & (self.size() - 1)
+    }
+
+    /// Returns the entry for the supplied position.
+    ///
+    /// Consecutive positions are spread across cachelines to reduce false sharing.
+    fn entry(&self, position: usize) -> &AtomicUsize {
+        let index = position & (self.size() - 1);
+        if self.size() < POINTERS {
+            &self.entries[index]
+        } else {
+            let index = ((index % POINTERS) * (self.size() / POINTERS)) + (index / POINTERS);
+            &self.entries[index]
+        }
+    }
+
+    /// Adds the supplied index to the back of this ring.
+    ///
+    /// This method returns `false` instead of adding the index if this ring has been finalized.
+    pub fn enqueue(&self, index: usize) -> bool {
+        loop {
+            let tail = self.tail.fetch_add(1, SeqCst);
+            if tail & FINALIZED != 0 {
+                return false;
+            }
+
+            let cycle = self.turn(tail);
+            let entry = self.entry(tail);
+            let mut current = entry.load(SeqCst);
+            loop {
+                let available = self.cycle(current) < cycle && self.is_empty(self.index(current));
+                if !available || (self.safe(current) == 0 && self.head.load(SeqCst) > tail) {
+                    break;
+                }
+
+                let new = self.pack(cycle, 1, index);
+                match entry.compare_exchange(current, new, SeqCst, SeqCst) {
+                    Ok(_) => {
+                        let reset = self.reset();
+                        if self.threshold.load(SeqCst) != reset {
+                            self.threshold.store(reset, SeqCst);
+                        }
+                        return true;
+                    },
+                    Err(value) => current = value,
+                }
+            }
+        }
+    }
+
+    /// Removes and returns the index at the front of this ring.
+    pub fn dequeue(&self) -> Option<usize> {
+        if self.threshold.load(SeqCst) < 0 {
+            return None;
+        }
+
+        loop {
+            let head = self.head.fetch_add(1, SeqCst);
+            let cycle = self.turn(head);
+            let entry = self.entry(head);
+            let mut current = entry.load(SeqCst);
+            loop {
+                // Return the index if it was added during the current cycle.
+                if self.cycle(current) == cycle {
+                    entry.fetch_or(self.bottom() - 1, SeqCst);
+                    return Some(self.index(current));
+                }
+
+                // Otherwise, prevent a producer that is behind from adding an index to the entry.
+                let new = if self.is_empty(self.index(current)) {
+                    self.pack(cycle, self.safe(current), self.bottom())
+                } else {
+                    self.pack(self.cycle(current), 0, self.index(current))
+                };
+
+                if self.cycle(current) >= cycle {
+                    break;
+                }
+
+                match entry.compare_exchange(current, new, SeqCst, SeqCst) {
+                    Ok(_) => break,
+                    Err(value) => current = value,
+                }
+            }
+
+            // Return `None` if this ring is empty.
+            let tail = self.tail.load(SeqCst) & !FINALIZED;
+            if tail <= head + 1 {
+                self.catchup(tail, head + 1);
+                self.threshold.fetch_sub(1, SeqCst);
+                return None;
+            }
+
+            if self.threshold.fetch_sub(1, SeqCst) <= 0 {
+                return None;
+            }
+        }
+    }
+
+    /// Prevents any more indices from being added to this ring.
+    ///
+    /// The producers that are adding indices when this ring is finalized either finish adding them
+    /// or fail to add them.
+    pub fn finalize(&self) {
+        self.tail.fetch_or(FINALIZED, SeqCst);
+    }
+
+    /// Allows consumers to search this ring for indices again after they have given up.
+    ///
+    /// Consumers give up once they fail to find an index too many times, which may leave an index
+    /// that a producer was in the middle of adding in this ring. Searching again after this ring
+    /// has been finalized finds any such index.
+    pub fn reset_threshold(&self) {
+        self.threshold.store(self.reset(), SeqCst);
+    }
+
+    /// Moves the tail of this ring forward to the head of this ring.
+    ///
+    /// The tail of a finalized ring is not moved.
+    fn catchup(&self, tail: usize, head: usize) {
+        let (mut tail, mut head) = (tail, head);
+        while self.tail.compare_exchange(tail, head, SeqCst, SeqCst).is_err() {
+            head = self.head.load(SeqCst);
+            tail = self.tail.load(SeqCst);
+            if tail >= head {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::sync::{Arc};
+
+    #[test]
+    fn test_fifo() {
+        let ring = Ring::try_new(3).unwrap();
+        assert_eq!(ring.size(), 16);
+        assert_eq!(ring.dequeue(), None);
+
+        // The ring wraps around its entries several times.
+        for round in 0..8 {
+            for index in 0..8 {
+                assert!(ring.enqueue((index + round) % 8));
+            }
+            assert_eq!(ring.len(), 8);
+            let indices = (0..8).map(|_| ring.dequeue().unwrap()).collect::<Vec<_>>();
+            assert_eq!(indices, (0..8).map(|i| (i + round) % 8).collect::<Vec<_>>());
+            assert_eq!(ring.dequeue(), None);
+        }
+    }
+
+    #[test]
+    fn test_finalize() {
+        let ring = Ring::try_new(2).unwrap();
+        assert!(ring.enqueue(1));
+        ring.finalize();
+        assert!(!ring.enqueue(2));
+        ring.reset_threshold();
+        assert_eq!(ring.dequeue(), Some(1));
+        assert_eq!(ring.dequeue(), None);
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(Ring::try_new(usize::BITS).err(), Some(ChannelError::Overflow));
+    }
+
+    #[test]
+    fn test_threads() {
+        // The indices in a pair of rings are moved between them like the free and occupied rings
+        // of the `Scq` algorithm, so no index is ever lost or duplicated.
+        let free = Arc::new(Ring::try_new(4).unwrap());
+        let occupied = Arc::new(Ring::try_new(4).unwrap());
+        for index in 0..16 {
+            assert!(free.enqueue(index));
+        }
+
+        let threads = (0..4).map(|_| {
+            let (free, occupied) = (free.clone(), occupied.clone());
+            thread::spawn(move || {
+                for _ in 0..10000 {
+                    if let Some(index) = free.dequeue() {
+                        assert!(occupied.enqueue(index));
+                    }
+                    if let Some(index) = occupied.dequeue() {
+                        assert!(free.enqueue(index));
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        free.reset_threshold();
+        occupied.reset_threshold();
+        let mut indices = vec![];
+        indices.extend((0..).map(|_| free.dequeue()).take_while(|i| i.is_some()).flatten());
+        indices.extend((0..).map(|_| occupied.dequeue()).take_while(|i| i.is_some()).flatten());
+        indices.sort();
+        assert_eq!(indices, (0..16).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,368 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request/response channels with embedded replies.
+//!
+//! Each request added to the queue by `Client::call` carries a lock-free oneshot slot for its
+//! response, and `call` returns a future that resolves once a server has responded through the
+//! slot. This saves managing a second queue for the responses and matching responses to requests
+//! with correlation ids. The requests are added to an unbounded MPMC queue, so any number of
+//! clients and servers can share a channel. The future resolves to `Err(Canceled)` if the request
+//! is dropped without a response (e.g., because the channel has no remaining servers).
+//!
+//! This module requires the `futures` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate futures;
+//! extern crate npnc;
+//!
+//! use std::thread;
+//!
+//! use futures::executor::{block_on};
+//! use npnc::rpc;
+//!
+//! fn main() {
+//!     let (client, server) = rpc::channel::<u32, u32>();
+//!
+//!     let thread = thread::spawn(move || {
+//!         while server.handle(|request| request * 2).is_ok() { }
+//!     });
+//!
+//!     assert_eq!(block_on(client.call(21)), Ok(42));
+//!     assert_eq!(block_on(client.call(64)), Ok(128));
+//!
+//!     drop(client);
+//!     thread.join().unwrap();
+//! }
+//! ```
+
+extern crate futures;
+
+use std::error;
+use std::fmt;
+use std::cell::{UnsafeCell};
+use std::future::{Future};
+use std::pin::{Pin};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::task::{Context, Poll};
+
+use self::futures::task::{AtomicWaker};
+
+use {Consume, ConsumeError};
+use unbounded;
+
+/// The slot contains a response.
+const SENT: usize = 1;
+/// The reply was dropped without a response.
+const CLOSED: usize = 2;
+/// The call was dropped.
+const DROPPED: usize = 4;
+
+//================================================
+// Structs
+//================================================
+
+// Call __________________________________________
+
+/// A future that resolves to the response to a request.
+#[derive(Debug)]
+pub struct Call<R> {
+    slot: Arc<Slot<R>>,
+}
+
+impl<R> Drop for Call<R> {
+    fn drop(&mut self) {
+        self.slot.state.fetch_or(DROPPED, AcqRel);
+    }
+}
+
+impl<R> Future for Call<R> {
+    type Output = Result<R, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<R, Canceled>> {
+        if let Some(result) = self.slot.take() {
+            return Poll::Ready(result);
+        }
+
+        // The call is registered before checking again so that a reply that is sent in the
+        // meantime will wake it.
+        self.slot.waker.register(context.waker());
+        match self.slot.take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<R> Unpin for Call<R> { }
+
+// Canceled ______________________________________
+
+/// Indicates that a request was dropped without a response.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Canceled;
+
+impl error::Error for Canceled { }
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("the request was dropped without a response")
+    }
+}
+
+// Client ________________________________________
+
+/// The sending half of a request/response channel.
+#[derive(Debug)]
+pub struct Client<Q, R> {
+    producer: unbounded::mpmc::Producer<(Q, Reply<R>)>,
+}
+
+impl<Q, R> Client<Q, R> {
+    //- Accessors --------------------------------
+
+    /// Adds the supplied request to the channel and returns a future that resolves to the
+    /// response.
+    ///
+    /// The future resolves to `Err(Canceled)` immediately if the channel has no remaining servers.
+    pub fn call(&self, request: Q) -> Call<R> {
+        let slot = Arc::new(Slot::new());
+        let reply = Reply { slot: Some(slot.clone()) };
+        let _ = self.producer.produce((request, reply));
+        Call { slot }
+    }
+
+    /// Returns the number of servers for the channel.
+    pub fn server_count(&self) -> usize {
+        self.producer.consumer_count()
+    }
+}
+
+impl<Q, R> Clone for Client<Q, R> {
+    fn clone(&self) -> Self {
+        Client { producer: self.producer.clone() }
+    }
+}
+
+// Reply _________________________________________
+
+/// The slot for the response to a request.
+///
+/// The request is canceled if this reply is dropped without a response.
+#[derive(Debug)]
+pub struct Reply<R> {
+    slot: Option<Arc<Slot<R>>>,
+}
+
+impl<R> Reply<R> {
+    //- Accessors --------------------------------
+
+    /// Returns whether the future waiting for the response has been dropped.
+    pub fn is_canceled(&self) -> bool {
+        self.slot.as_ref().is_none_or(|s| s.state.load(Acquire) & DROPPED != 0)
+    }
+
+    //- Consumers --------------------------------
+
+    /// Sends the supplied response to the future waiting for it.
+    ///
+    /// This method returns `Err` if the future has been dropped.
+    pub fn send(mut self, response: R) -> Result<(), R> {
+        let slot = self.slot.take().unwrap();
+        unsafe { *slot.value.get() = Some(response); }
+        if slot.state.fetch_or(SENT, AcqRel) & DROPPED != 0 {
+            // The call has been dropped so the response will never be read.
+            return Err(unsafe { (*slot.value.get()).take().unwrap() });
+        }
+
+        slot.waker.wake();
+        Ok(())
+    }
+}
+
+impl<R> Drop for Reply<R> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            slot.state.fetch_or(CLOSED, AcqRel);
+            slot.waker.wake();
+        }
+    }
+}
+
+// Server ________________________________________
+
+/// The receiving half of a request/response channel.
+#[derive(Debug)]
+pub struct Server<Q, R> {
+    consumer: unbounded::mpmc::Consumer<(Q, Reply<R>)>,
+}
+
+impl<Q, R> Server<Q, R> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return a request and the slot for its response from the channel.
+    pub fn consume(&self) -> Result<(Q, Reply<R>), ConsumeError> {
+        self.consumer.consume()
+    }
+
+    /// Attempts to remove a request from the channel and responds to it with the response
+    /// returned by the supplied function.
+    ///
+    /// Responses to requests whose futures have been dropped are dropped.
+    pub fn handle<F>(&self, f: F) -> Result<(), ConsumeError> where F: FnOnce(Q) -> R {
+        let (request, reply) = self.consumer.consume()?;
+        let _ = reply.send(f(request));
+        Ok(())
+    }
+
+    /// Returns the number of clients for the channel.
+    pub fn client_count(&self) -> usize {
+        self.consumer.producer_count()
+    }
+}
+
+impl<Q, R> Clone for Server<Q, R> {
+    fn clone(&self) -> Self {
+        Server { consumer: self.consumer.clone() }
+    }
+}
+
+impl<Q, R> Consume<(Q, Reply<R>)> for Server<Q, R> {
+    fn consume(&self) -> Result<(Q, Reply<R>), ConsumeError> {
+        Server::consume(self)
+    }
+}
+
+// Slot __________________________________________
+
+/// A lock-free oneshot slot for a response.
+struct Slot<R> {
+    state: AtomicUsize,
+    value: UnsafeCell<Option<R>>,
+    waker: AtomicWaker,
+}
+
+impl<R> Slot<R> {
+    //- Constructors -----------------------------
+
+    fn new() -> Self {
+        Slot { state: AtomicUsize::new(0), value: UnsafeCell::new(None), waker: AtomicWaker::new() }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the response if it has been sent or `Err(Canceled)` if it never will be.
+    fn take(&self) -> Option<Result<R, Canceled>> {
+        let state = self.state.load(Acquire);
+        if state & SENT != 0 {
+            // Only the call reads the response and it is not polled again once it is ready.
+            unsafe { (*self.value.get()).take().map(Ok) }
+        } else if state & CLOSED != 0 {
+            Some(Err(Canceled))
+        } else {
+            None
+        }
+    }
+}
+
+impl<R> fmt::Debug for Slot<R> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Slot {{ state: {:?} }}", self.state.load(Relaxed))
+    }
+}
+
+unsafe impl<R> Sync for Slot<R> where R: Send { }
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a client and server for a request/response channel backed by an unbounded MPMC queue.
+pub fn channel<Q, R>() -> (Client<Q, R>, Server<Q, R>) {
+    let (producer, consumer) = unbounded::mpmc::channel(0);
+    (Client { producer }, Server { consumer })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use self::futures::executor::{block_on};
+
+    #[test]
+    fn test_call() {
+        let (client, server) = channel::<u32, u32>();
+        assert_eq!((client.server_count(), server.client_count()), (1, 1));
+        let a = client.call(1);
+        let b = client.call(2);
+
+        // Responses are matched with their requests regardless of the order they are sent in.
+        let (_, first) = server.consume().unwrap();
+        let (request, second) = server.consume().unwrap();
+        assert_eq!(request, 2);
+        second.send(20).unwrap();
+        first.send(10).unwrap();
+        assert_eq!(block_on(b), Ok(20));
+        assert_eq!(block_on(a), Ok(10));
+        assert_eq!(server.consume().map(|_| ()), Err(ConsumeError::Empty));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (client, server) = channel::<u32, u32>();
+
+        // The call is canceled if the reply is dropped without a response.
+        let call = client.call(1);
+        drop(server.consume().unwrap());
+        assert_eq!(block_on(call), Err(Canceled));
+
+        // The response is returned if the call has been dropped.
+        drop(client.call(2));
+        let (_, reply) = server.consume().unwrap();
+        assert!(reply.is_canceled());
+        assert_eq!(reply.send(20), Err(20));
+
+        // The call is canceled immediately if the channel has no remaining servers.
+        drop(server);
+        assert_eq!(client.server_count(), 0);
+        assert_eq!(block_on(client.call(3)), Err(Canceled));
+    }
+
+    #[test]
+    fn test_threads() {
+        let (client, server) = channel::<u32, u32>();
+        let threads = (0..2).map(|_| {
+            let server = server.clone();
+            thread::spawn(move || {
+                while server.handle(|request| request + 1).is_ok() || server.client_count() != 0 { }
+            })
+        }).collect::<Vec<_>>();
+        drop(server);
+
+        let calls = (0..100).map(|i| client.call(i)).collect::<Vec<_>>();
+        for (i, call) in calls.into_iter().enumerate() {
+            assert_eq!(block_on(call), Ok(i as u32 + 1));
+        }
+        drop(client);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
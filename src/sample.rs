@@ -0,0 +1,241 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sampling of the items removed from a queue.
+//!
+//! The consumers returned by `every` and `random` only return a sample of the items in a queue.
+//! The items which are not part of the sample are removed from the queue and dropped before the
+//! next item is removed, so a consumer never has to remove and drop these items itself. Clones of
+//! a sampling consumer share the same sample.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::sample;
+//!
+//! fn main() {
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let consumer = sample::every(consumer, 3);
+//!
+//!     for item in 0..8 {
+//!         producer.produce(item).unwrap();
+//!     }
+//!
+//!     assert_eq!(consumer.consume(), Ok(0));
+//!     assert_eq!(consumer.consume(), Ok(3));
+//!     assert_eq!(consumer.consume(), Ok(6));
+//!     assert_eq!(consumer.discarded(), 4);
+//! }
+//! ```
+
+use std::marker::{PhantomData};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError};
+
+//================================================
+// Enums
+//================================================
+
+// Mode __________________________________________
+
+#[derive(Copy, Clone, Debug)]
+enum Mode {
+    Every(usize),
+    Random(f64, u64),
+}
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that only returns a sample of the items in a queue.
+#[derive(Debug)]
+pub struct Consumer<T, C> {
+    consumer: C,
+    counters: Arc<Counters>,
+    mode: Mode,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, C> Consumer<T, C> where C: Consume<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the next sampled item from the queue.
+    ///
+    /// The items removed from the queue before the next sampled item are dropped. This method
+    /// returns `Err` if the queue runs out of items before a sampled item is found.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        loop {
+            let item = self.consumer.consume()?;
+            if self.counters.keep(self.mode) {
+                return Ok(item);
+            }
+        }
+    }
+
+    /// Returns the number of items that have been removed from the queue and dropped.
+    pub fn discarded(&self) -> usize {
+        self.counters.discarded.load(Relaxed)
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<T, C> Clone for Consumer<T, C> where C: Clone {
+    fn clone(&self) -> Self {
+        let counters = self.counters.clone();
+        let consumer = self.consumer.clone();
+        Consumer { consumer, counters, mode: self.mode, _marker: PhantomData }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<T, C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Counters ______________________________________
+
+#[derive(Debug)]
+struct Counters {
+    consumed: AtomicUsize,
+    discarded: AtomicUsize,
+}
+
+impl Counters {
+    //- Accessors --------------------------------
+
+    /// Returns whether the item that was just removed from the queue is part of the sample.
+    fn keep(&self, mode: Mode) -> bool {
+        let index = self.consumed.fetch_add(1, Relaxed);
+        let keep = match mode {
+            Mode::Every(n) => index.is_multiple_of(n),
+            Mode::Random(probability, seed) => {
+                let bits = mix(seed.wrapping_add(index as u64)) >> 11;
+                (bits as f64 / (1u64 << 53) as f64) < probability
+            },
+        };
+
+        if !keep {
+            self.discarded.fetch_add(1, Relaxed);
+        }
+
+        keep
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a pseudorandom value for the supplied value (SplitMix64).
+fn mix(value: u64) -> u64 {
+    let mut value = value.wrapping_add(0x9E3779B97F4A7C15);
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D049BB133111EB);
+    value ^ (value >> 31)
+}
+
+fn wrap<T, C>(consumer: C, mode: Mode) -> Consumer<T, C> where C: Consume<T> {
+    let counters = Counters { consumed: AtomicUsize::new(0), discarded: AtomicUsize::new(0) };
+    Consumer { consumer, counters: Arc::new(counters), mode, _marker: PhantomData }
+}
+
+/// Wraps the supplied consumer so it only returns every `n`th item in the queue, starting with
+/// the first item.
+///
+/// # Panics
+///
+/// * `n` is zero
+pub fn every<T, C>(consumer: C, n: usize) -> Consumer<T, C> where C: Consume<T> {
+    assert!(n != 0, "`n` is zero");
+    wrap(consumer, Mode::Every(n))
+}
+
+/// Wraps the supplied consumer so it only returns each item in the queue with the supplied
+/// probability.
+///
+/// The sample is pseudorandom and determined by the supplied seed.
+///
+/// # Panics
+///
+/// * `probability` is not between 0 and 1
+pub fn random<T, C>(consumer: C, probability: f64, seed: u64) -> Consumer<T, C>
+    where C: Consume<T>
+{
+    assert!((0.0..=1.0).contains(&probability), "`probability` is not between 0 and 1");
+    wrap(consumer, Mode::Random(probability, seed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_every() {
+        let (producer, consumer) = spsc::channel(16);
+        let consumer = every(consumer, 4);
+        for item in 0..10 {
+            producer.produce(item).unwrap();
+        }
+        assert_eq!(consumer.consume(), Ok(0));
+        assert_eq!(consumer.consume(), Ok(4));
+        assert_eq!(consumer.consume(), Ok(8));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        assert_eq!(consumer.discarded(), 7);
+    }
+
+    #[test]
+    fn test_random() {
+        let (producer, consumer) = spsc::channel(1024);
+        for item in 0..1000 {
+            producer.produce(item).unwrap();
+        }
+        let consumer = random(consumer, 0.25, 42);
+        let mut kept = 0;
+        while consumer.consume().is_ok() {
+            kept += 1;
+        }
+        assert_eq!(kept + consumer.discarded(), 1000);
+        assert!(kept > 150 && kept < 350);
+    }
+
+    #[test]
+    #[should_panic(expected = "`n` is zero")]
+    fn test_every_zero() {
+        let (_, consumer) = spsc::channel::<i32>(16);
+        every(consumer, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "`probability` is not between 0 and 1")]
+    fn test_random_invalid() {
+        let (_, consumer) = spsc::channel::<i32>(16);
+        random(consumer, 1.5, 0);
+    }
+}
@@ -0,0 +1,496 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Waiting on several consumers at once.
+//!
+//! The `select!` macro waits until any of several consumers (of any queues, with any item types)
+//! has an item and then evaluates the arm for that consumer. Each arm has the form
+//! `result = consumer => body`, where `result` is bound to the `Result` returned by the consumer.
+//! An arm is selected once its consumer removes an item or reports that its queue is disconnected
+//! (so an arm for a disconnected queue is selected every time, like in `crossbeam-channel`).
+//!
+//! The consumers are polled in the order of the arms, so earlier arms take priority over later arms
+//! when several consumers have items. The waiting thread backs off between rounds of polling by
+//! spinning, then yielding, and finally sleeping for short periods.
+//!
+//! The arms may be followed by a `default => body` arm, which is evaluated if none of the
+//! consumers has an item instead of waiting, or by a `default(timeout) => body` arm, which is
+//! evaluated if none of the consumers has an item once the timeout (a `Duration`) has elapsed.
+//!
+//! The body of the selected arm is evaluated after the macro has stopped waiting, so `break`,
+//! `continue`, `return`, and `?` in a body affect the surrounding code as expected.
+//!
+//! A `Selector` waits on a set of consumers and producers determined at runtime instead. The
+//! consumers and producers added to a selector must share an item type and are identified by the
+//! indices returned when they are added. `Selector::wait` returns the index of the consumer that
+//! removed an item along with the result and `Selector::wait_produce` hands an item to the first
+//! producer that accepts it. A selector starts polling after the last selected consumer or
+//! producer, so the consumers and producers in a busy set are selected in turn.
+//!
+//! # Examples
+//!
+//! ```
+//! #[macro_use]
+//! extern crate npnc;
+//!
+//! use std::time::{Duration};
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::unbounded::mpsc;
+//!
+//! fn main() {
+//!     let (producer1, consumer1) = spsc::channel::<u32>(64);
+//!     let (producer2, consumer2) = mpsc::channel();
+//!     producer2.produce("b").unwrap();
+//!
+//!     let mut received = vec![];
+//!     loop {
+//!         select! {
+//!             number = consumer1 => received.push(number.unwrap().to_string()),
+//!             string = consumer2 => {
+//!                 received.push(string.unwrap().to_string());
+//!                 producer1.produce(1).unwrap();
+//!             },
+//!             default(Duration::from_millis(10)) => break,
+//!         }
+//!     }
+//!
+//!     assert_eq!(received, &["b", "1"]);
+//! }
+//! ```
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::select::{Selector};
+//!
+//! fn main() {
+//!     let channels = (0..4).map(|_| spsc::channel(1)).collect::<Vec<_>>();
+//!
+//!     let mut selector = Selector::new();
+//!     for &(ref producer, _) in &channels {
+//!         selector.add_producer(producer);
+//!     }
+//!     for &(_, ref consumer) in &channels {
+//!         selector.add(consumer);
+//!     }
+//!
+//!     channels[0].0.produce(0).unwrap();
+//!     assert_eq!(selector.wait_produce(42), (1, Ok(())));
+//!     assert_eq!(selector.wait(), (4, Ok(0)));
+//!     assert_eq!(selector.wait(), (5, Ok(42)));
+//!     assert_eq!(selector.try_wait(), None);
+//! }
+//! ```
+
+use std::fmt;
+use std::cell::{Cell};
+use std::time::{Duration, Instant};
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+#[doc(hidden)]
+pub use utility::{Backoff};
+
+//================================================
+// Macros
+//================================================
+
+// select! _______________________________________
+
+/// Waits until any of several consumers has an item and evaluates the corresponding arm.
+///
+/// See the `select` module for details.
+#[macro_export]
+macro_rules! select {
+    (@poll $consumer:expr) => (
+        $crate::select::poll(&$consumer)
+            .map($crate::select::Selected::<_, $crate::select::Never>::Ready)
+    );
+    (@poll $consumer:expr, $($rest:expr),+) => (
+        match $crate::select::poll(&$consumer) {
+            Some(result) => Some($crate::select::Selected::Ready(result)),
+            None => $crate::select!(@poll $($rest),+).map($crate::select::Selected::Next),
+        }
+    );
+    (@dispatch $selected:ident; ($pattern:pat, $body:expr)) => (
+        match $selected {
+            $crate::select::Selected::Ready($pattern) => $body,
+            $crate::select::Selected::Next(never) => match never { },
+        }
+    );
+    (@dispatch $selected:ident; ($pattern:pat, $body:expr) $($rest:tt)+) => (
+        match $selected {
+            $crate::select::Selected::Ready($pattern) => $body,
+            $crate::select::Selected::Next($selected) => {
+                $crate::select!(@dispatch $selected; $($rest)+)
+            },
+        }
+    );
+    (@parse [$(($pattern:pat, $consumer:expr, $body:expr))+]) => ({
+        let mut backoff = $crate::select::Backoff::new();
+        let selected = loop {
+            if let Some(selected) = $crate::select!(@poll $($consumer),+) {
+                break selected;
+            }
+            backoff.snooze(None);
+        };
+        $crate::select!(@dispatch selected; $(($pattern, $body))+)
+    });
+    (@parse [$(($pattern:pat, $consumer:expr, $body:expr))+] default => $default:expr $(,)*) => ({
+        match $crate::select!(@poll $($consumer),+) {
+            Some(selected) => $crate::select!(@dispatch selected; $(($pattern, $body))+),
+            None => $default,
+        }
+    });
+    (
+        @parse [$(($pattern:pat, $consumer:expr, $body:expr))+]
+        default($timeout:expr) => $default:expr $(,)*
+    ) => ({
+        let deadline = ::std::time::Instant::now().checked_add($timeout);
+        let mut backoff = $crate::select::Backoff::new();
+        let selected = loop {
+            if let Some(selected) = $crate::select!(@poll $($consumer),+) {
+                break Some(selected);
+            }
+            if deadline.map_or(false, |d| ::std::time::Instant::now() >= d) {
+                break None;
+            }
+            backoff.snooze(deadline);
+        };
+        match selected {
+            Some(selected) => $crate::select!(@dispatch selected; $(($pattern, $body))+),
+            None => $default,
+        }
+    });
+    (@parse [$($arms:tt)*] $pattern:pat = $consumer:expr => $body:expr, $($rest:tt)*) => (
+        $crate::select!(@parse [$($arms)* ($pattern, $consumer, $body)] $($rest)*)
+    );
+    (@parse [$($arms:tt)*] $pattern:pat = $consumer:expr => $body:expr) => (
+        $crate::select!(@parse [$($arms)* ($pattern, $consumer, $body)])
+    );
+    ($($tokens:tt)+) => ($crate::select!(@parse [] $($tokens)+));
+}
+
+//================================================
+// Enums
+//================================================
+
+// Entry _________________________________________
+
+/// A consumer or producer in a `Selector`.
+enum Entry<'a, T> {
+    Consumer(&'a dyn Consume<T>),
+    Producer(&'a dyn Produce<T>),
+}
+
+// Never _________________________________________
+
+/// A type with no values that ends a chain of `Selected`s.
+#[doc(hidden)]
+#[derive(Copy, Clone, Debug)]
+pub enum Never { }
+
+// Selected ______________________________________
+
+/// The result for the selected arm of a `select!` invocation.
+///
+/// The result for the first arm is `Ready(result)`, the result for the second arm is
+/// `Next(Ready(result))`, and so on.
+#[doc(hidden)]
+#[derive(Copy, Clone, Debug)]
+pub enum Selected<R, N> {
+    /// The result for this arm.
+    Ready(R),
+    /// The result for a later arm.
+    Next(N),
+}
+
+//================================================
+// Structs
+//================================================
+
+// Selector ______________________________________
+
+/// Waits on a set of consumers and producers determined at runtime.
+pub struct Selector<'a, T> {
+    entries: Vec<Entry<'a, T>>,
+    next: Cell<usize>,
+}
+
+impl<'a, T> Selector<'a, T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new empty `Selector`.
+    pub fn new() -> Self {
+        Selector { entries: vec![], next: Cell::new(0) }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the number of consumers and producers in this selector.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether this selector contains no consumers or producers.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Attempts to remove an item with any of the consumers in this selector without waiting.
+    ///
+    /// This method returns the index of the consumer and the result if a consumer removed an item
+    /// or reported that its queue is disconnected and `None` otherwise.
+    pub fn try_wait(&self) -> Option<(usize, Result<T, ConsumeError>)> {
+        let (start, len) = (self.next.get(), self.entries.len());
+        for index in (0..len).map(|i| (start + i) % len) {
+            if let Entry::Consumer(consumer) = self.entries[index] {
+                if let Some(result) = poll(consumer) {
+                    self.next.set(index + 1);
+                    return Some((index, result));
+                }
+            }
+        }
+        None
+    }
+
+    /// Removes an item with any of the consumers in this selector, waiting until one has an item.
+    ///
+    /// This method returns the index of the consumer that removed the item or reported that its
+    /// queue is disconnected along with the result.
+    ///
+    /// # Panics
+    ///
+    /// * this selector contains no consumers
+    pub fn wait(&self) -> (usize, Result<T, ConsumeError>) {
+        self.wait_deadline(None).unwrap()
+    }
+
+    /// Removes an item with any of the consumers in this selector, waiting up to the supplied
+    /// duration until one has an item.
+    ///
+    /// This method returns `None` if none of the consumers had an item once the duration elapsed.
+    ///
+    /// # Panics
+    ///
+    /// * this selector contains no consumers
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<(usize, Result<T, ConsumeError>)> {
+        self.wait_deadline(Instant::now().checked_add(timeout))
+    }
+
+    fn wait_deadline(&self, deadline: Option<Instant>) -> Option<(usize, Result<T, ConsumeError>)> {
+        let consumers = self.entries.iter().any(|e| matches!(*e, Entry::Consumer(_)));
+        assert!(consumers, "this selector contains no consumers");
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(selected) = self.try_wait() {
+                return Some(selected);
+            }
+            match deadline {
+                Some(deadline) if Instant::now() >= deadline => return None,
+                _ => backoff.snooze(deadline),
+            }
+        }
+    }
+
+    /// Adds the supplied item with the first of the producers in this selector that accepts it,
+    /// waiting while all of the producers are full.
+    ///
+    /// This method returns the index of the producer that accepted the item or reported that its
+    /// queue is disconnected (or rejected the item for another reason) along with the result.
+    ///
+    /// # Panics
+    ///
+    /// * this selector contains no producers
+    pub fn wait_produce(&self, item: T) -> (usize, Result<(), ProduceError<T>>) {
+        let producers = self.entries.iter().any(|e| matches!(*e, Entry::Producer(_)));
+        assert!(producers, "this selector contains no producers");
+        let mut item = item;
+        let mut backoff = Backoff::new();
+        loop {
+            let (start, len) = (self.next.get(), self.entries.len());
+            for index in (0..len).map(|i| (start + i) % len) {
+                if let Entry::Producer(producer) = self.entries[index] {
+                    match producer.produce(item) {
+                        Err(ProduceError::Full(returned)) |
+                        Err(ProduceError::Contended(returned)) => item = returned,
+                        result => {
+                            self.next.set(index + 1);
+                            return (index, result);
+                        },
+                    }
+                }
+            }
+            backoff.snooze(None);
+        }
+    }
+
+    //- Mutators ---------------------------------
+
+    /// Adds the supplied consumer to this selector and returns its index.
+    pub fn add<C>(&mut self, consumer: &'a C) -> usize where C: Consume<T> {
+        self.entries.push(Entry::Consumer(consumer));
+        self.entries.len() - 1
+    }
+
+    /// Adds the supplied producer to this selector and returns its index.
+    pub fn add_producer<P>(&mut self, producer: &'a P) -> usize where P: Produce<T> {
+        self.entries.push(Entry::Producer(producer));
+        self.entries.len() - 1
+    }
+}
+
+impl<'a, T> Default for Selector<'a, T> {
+    fn default() -> Self {
+        Selector::new()
+    }
+}
+
+impl<'a, T> fmt::Debug for Selector<'a, T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("Selector")
+            .field("entries", &self.entries.len())
+            .field("next", &self.next.get())
+            .finish()
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Attempts to remove an item with the supplied consumer and returns the result if the arm for the
+/// consumer should be selected.
+#[doc(hidden)]
+pub fn poll<T, C>(consumer: &C) -> Option<Result<T, ConsumeError>>
+    where C: Consume<T> + ?Sized
+{
+    match consumer.consume() {
+        Ok(item) => Some(Ok(item)),
+        Err(ConsumeError::Disconnected) => Some(Err(ConsumeError::Disconnected)),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_select() {
+        let (p1, c1) = spsc::channel::<u32>(4);
+        let (p2, c2) = spsc::channel::<&str>(4);
+        p1.produce(1).unwrap();
+        p2.produce("a").unwrap();
+
+        // Earlier arms take priority over later arms.
+        let mut received = vec![];
+        for _ in 0..3 {
+            select! {
+                number = c1 => received.push(number.unwrap().to_string()),
+                string = c2 => received.push(string.unwrap().to_string()),
+                default => received.push("default".into()),
+            }
+        }
+        assert_eq!(received, &["1", "a", "default"]);
+
+        // A disconnected queue is selected every time.
+        drop(p2);
+        let selected = select! {
+            _ = c1 => 1,
+            result = c2 => { assert_eq!(result, Err(ConsumeError::Disconnected)); 2 },
+        };
+        assert_eq!(selected, 2);
+    }
+
+    #[test]
+    fn test_select_timeout() {
+        let (_producer, consumer) = spsc::channel::<u32>(4);
+        let start = Instant::now();
+        let selected = select! {
+            _ = consumer => false,
+            default(Duration::from_millis(20)) => true,
+        };
+        assert!(selected && start.elapsed() >= Duration::from_millis(20));
+
+        let (producer, consumer) = spsc::channel::<u32>(4);
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            producer.produce(1).unwrap();
+        });
+        let selected = select! {
+            number = consumer => number,
+            default(Duration::from_secs(10)) => Err(ConsumeError::Timeout),
+        };
+        assert_eq!(selected, Ok(1));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_selector() {
+        let channels = (0..3).map(|_| spsc::channel(4)).collect::<Vec<_>>();
+        let mut selector = Selector::new();
+        for (_, consumer) in &channels {
+            selector.add(consumer);
+        }
+        assert_eq!(selector.len(), 3);
+        assert_eq!(selector.try_wait(), None);
+        assert_eq!(selector.wait_timeout(Duration::from_millis(1)), None);
+
+        // The consumers in a busy set are selected in turn.
+        for (producer, _) in &channels {
+            producer.produce(0).unwrap();
+            producer.produce(1).unwrap();
+        }
+        let selected = (0..6).map(|_| selector.wait()).collect::<Vec<_>>();
+        let expected = [(0, Ok(0)), (1, Ok(0)), (2, Ok(0)), (0, Ok(1)), (1, Ok(1)), (2, Ok(1))];
+        assert_eq!(selected, &expected);
+    }
+
+    #[test]
+    fn test_selector_produce() {
+        let channels = (0..2).map(|_| spsc::channel(1)).collect::<Vec<_>>();
+        let mut selector = Selector::new();
+        for (producer, _) in &channels {
+            selector.add_producer(producer);
+        }
+
+        // Full producers are skipped.
+        assert_eq!(selector.wait_produce(1), (0, Ok(())));
+        assert_eq!(selector.wait_produce(2), (1, Ok(())));
+        assert_eq!(channels[1].1.consume(), Ok(2));
+        assert_eq!(selector.wait_produce(3), (1, Ok(())));
+
+        // A producer whose queue is disconnected is selected.
+        let (producer, consumer) = spsc::channel(1);
+        selector.add_producer(&producer);
+        drop(consumer);
+        assert_eq!(selector.wait_produce(4), (2, Err(ProduceError::Disconnected(4))));
+    }
+
+    #[test]
+    #[should_panic(expected = "this selector contains no consumers")]
+    fn test_selector_no_consumers() {
+        let (producer, _consumer) = spsc::channel::<u32>(4);
+        let mut selector = Selector::new();
+        selector.add_producer(&producer);
+        let _ = selector.wait();
+    }
+}
@@ -0,0 +1,362 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Accounting for the number of bytes queued.
+//!
+//! The producers and consumers returned by `wrap` and `wrap_with` track the total size of the
+//! items in a queue in addition to the number of items in the queue. The wrapped producer and
+//! consumer should belong to the same queue and should not be used to add or remove items once
+//! wrapped, otherwise the tracked number of bytes will be inaccurate.
+//!
+//! The producers and consumers returned by `wrap_limited` and `wrap_limited_with` also limit the
+//! total size of the items in a queue instead of only the number of items. A producer receives a
+//! `Full` error if adding an item would put more than the limit of bytes in the queue. An item
+//! larger than the limit is still accepted when the queue is empty so that it can't be rejected
+//! forever.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::sized;
+//!
+//! fn main() {
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let (producer, consumer) = sized::wrap(producer, consumer);
+//!
+//!     producer.produce(vec![0u8; 512]).unwrap();
+//!     producer.produce(vec![0u8; 256]).unwrap();
+//!     assert_eq!(producer.stats(), sized::Stats { items: 2, bytes: 768 });
+//!
+//!     consumer.consume().unwrap();
+//!     assert_eq!(consumer.stats(), sized::Stats { items: 1, bytes: 256 });
+//! }
+//! ```
+
+use std::mem;
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Traits
+//================================================
+
+// MemSized ______________________________________
+
+/// A type that occupies a variable number of bytes.
+pub trait MemSized {
+    /// Returns the number of bytes occupied by this value.
+    fn mem_size(&self) -> usize;
+}
+
+impl<T> MemSized for [T] {
+    fn mem_size(&self) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+impl MemSized for str {
+    fn mem_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> MemSized for Vec<T> {
+    fn mem_size(&self) -> usize {
+        self[..].mem_size()
+    }
+}
+
+impl MemSized for String {
+    fn mem_size(&self) -> usize {
+        self[..].mem_size()
+    }
+}
+
+impl<T> MemSized for Box<T> where T: MemSized + ?Sized {
+    fn mem_size(&self) -> usize {
+        (**self).mem_size()
+    }
+}
+
+impl<T> MemSized for Arc<T> where T: MemSized + ?Sized {
+    fn mem_size(&self) -> usize {
+        (**self).mem_size()
+    }
+}
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that tracks the number of bytes queued.
+#[derive(Debug)]
+pub struct Consumer<T, C> {
+    consumer: C,
+    counters: Arc<Counters>,
+    size: fn(&T) -> usize,
+}
+
+impl<T, C> Consumer<T, C> where C: Consume<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        let item = self.consumer.consume()?;
+        self.counters.remove((self.size)(&item));
+        Ok(item)
+    }
+
+    /// Returns the number of items and bytes currently in the queue.
+    pub fn stats(&self) -> Stats {
+        self.counters.stats()
+    }
+
+    /// Returns the maximum total size of the items the queue can contain in bytes.
+    ///
+    /// This is `usize::MAX` for queues wrapped with `wrap` or `wrap_with`.
+    pub fn limit(&self) -> usize {
+        self.counters.limit
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<T, C> Clone for Consumer<T, C> where C: Clone {
+    fn clone(&self) -> Self {
+        let counters = self.counters.clone();
+        Consumer { consumer: self.consumer.clone(), counters, size: self.size }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<T, C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Counters ______________________________________
+
+#[derive(Debug)]
+struct Counters {
+    items: AtomicUsize,
+    bytes: AtomicUsize,
+    limit: usize,
+}
+
+impl Counters {
+    //- Constructors -----------------------------
+
+    fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Counters { items: AtomicUsize::new(0), bytes: AtomicUsize::new(0), limit })
+    }
+
+    //- Accessors --------------------------------
+
+    /// Attempts to count an item of the supplied size without exceeding the limit.
+    fn add(&self, bytes: usize) -> bool {
+        let mut current = self.bytes.load(Relaxed);
+        loop {
+            if current != 0 && current.saturating_add(bytes) > self.limit {
+                return false;
+            }
+
+            match self.bytes.compare_exchange_weak(current, current + bytes, Relaxed, Relaxed) {
+                Ok(_) => break,
+                Err(value) => current = value,
+            }
+        }
+
+        self.items.fetch_add(1, Relaxed);
+        true
+    }
+
+    fn remove(&self, bytes: usize) {
+        self.items.fetch_sub(1, Relaxed);
+        self.bytes.fetch_sub(bytes, Relaxed);
+    }
+
+    fn stats(&self) -> Stats {
+        Stats { items: self.items.load(Relaxed), bytes: self.bytes.load(Relaxed) }
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that tracks the number of bytes queued.
+#[derive(Debug)]
+pub struct Producer<T, P> {
+    producer: P,
+    counters: Arc<Counters>,
+    size: fn(&T) -> usize,
+}
+
+impl<T, P> Producer<T, P> where P: Produce<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    ///
+    /// This method returns `Err` if the queue is full, has no remaining consumers, or if adding the
+    /// item would put more than the limit of bytes in the queue.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        // The item is counted before it is added so that the counters never underflow.
+        let bytes = (self.size)(&item);
+        if !self.counters.add(bytes) {
+            return Err(ProduceError::Full(item));
+        }
+        self.producer.produce(item).inspect_err(|_| self.counters.remove(bytes))
+    }
+
+    /// Returns the number of items and bytes currently in the queue.
+    pub fn stats(&self) -> Stats {
+        self.counters.stats()
+    }
+
+    /// Returns the maximum total size of the items the queue can contain in bytes.
+    ///
+    /// This is `usize::MAX` for queues wrapped with `wrap` or `wrap_with`.
+    pub fn limit(&self) -> usize {
+        self.counters.limit
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<T, P> Clone for Producer<T, P> where P: Clone {
+    fn clone(&self) -> Self {
+        let counters = self.counters.clone();
+        Producer { producer: self.producer.clone(), counters, size: self.size }
+    }
+}
+
+impl<T, P> Produce<T> for Producer<T, P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// Stats _________________________________________
+
+/// The number of items and bytes in a queue.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of items in the queue.
+    pub items: usize,
+    /// The total size of the items in the queue in bytes.
+    pub bytes: usize,
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Wraps the supplied producer and consumer so they track the number of bytes queued.
+///
+/// The size of an item is determined with its `MemSized` implementation.
+pub fn wrap<T, P, C>(producer: P, consumer: C) -> (Producer<T, P>, Consumer<T, C>)
+    where T: MemSized, P: Produce<T>, C: Consume<T>
+{
+    wrap_with(producer, consumer, T::mem_size)
+}
+
+/// Wraps the supplied producer and consumer so they track the number of bytes queued.
+///
+/// The size of an item is determined with the supplied function.
+pub fn wrap_with<T, P, C>(
+    producer: P, consumer: C, size: fn(&T) -> usize
+) -> (Producer<T, P>, Consumer<T, C>) where P: Produce<T>, C: Consume<T> {
+    wrap_limited_with(producer, consumer, usize::MAX, size)
+}
+
+/// Wraps the supplied producer and consumer so they track the number of bytes queued and limit it
+/// to the supplied number of bytes.
+///
+/// The size of an item is determined with its `MemSized` implementation.
+pub fn wrap_limited<T, P, C>(
+    producer: P, consumer: C, limit: usize
+) -> (Producer<T, P>, Consumer<T, C>) where T: MemSized, P: Produce<T>, C: Consume<T> {
+    wrap_limited_with(producer, consumer, limit, T::mem_size)
+}
+
+/// Wraps the supplied producer and consumer so they track the number of bytes queued and limit it
+/// to the supplied number of bytes.
+///
+/// The size of an item is determined with the supplied function.
+pub fn wrap_limited_with<T, P, C>(
+    producer: P, consumer: C, limit: usize, size: fn(&T) -> usize
+) -> (Producer<T, P>, Consumer<T, C>) where P: Produce<T>, C: Consume<T> {
+    let counters = Counters::new(limit);
+    let producer = Producer { producer, counters: counters.clone(), size };
+    (producer, Consumer { consumer, counters, size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_mem_size() {
+        assert_eq!(vec![0u32; 4].mem_size(), 16);
+        assert_eq!("abc".mem_size(), 3);
+        assert_eq!(String::from("abcd").mem_size(), 4);
+        assert_eq!(Box::new(vec![0u16; 3]).mem_size(), 6);
+        assert_eq!(Arc::<str>::from("ab").mem_size(), 2);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let (producer, consumer) = spsc::channel(1);
+        let (producer, consumer) = wrap(producer, consumer);
+        assert_eq!(producer.limit(), usize::MAX);
+
+        // An item rejected by the queue is not counted.
+        producer.produce(vec![0u8; 8]).unwrap();
+        assert_eq!(producer.produce(vec![0u8; 4]), Err(ProduceError::Full(vec![0u8; 4])));
+        assert_eq!(consumer.stats(), Stats { items: 1, bytes: 8 });
+        assert_eq!(consumer.consume().map(|v| v.len()), Ok(8));
+        assert_eq!(producer.stats(), Stats { items: 0, bytes: 0 });
+    }
+
+    #[test]
+    fn test_wrap_limited() {
+        let (producer, consumer) = spsc::channel(64);
+        let (producer, consumer) = wrap_limited_with(producer, consumer, 10, |s: &String| s.len());
+        assert_eq!(consumer.limit(), 10);
+
+        // An item larger than the limit is accepted when the queue is empty.
+        producer.produce("a".repeat(16)).unwrap();
+        assert!(producer.produce("b".into()).is_err());
+        consumer.consume().unwrap();
+
+        producer.produce("a".repeat(6)).unwrap();
+        producer.produce("b".repeat(4)).unwrap();
+        assert_eq!(producer.produce("c".into()), Err(ProduceError::Full("c".into())));
+        assert_eq!(producer.stats(), Stats { items: 2, bytes: 10 });
+    }
+}
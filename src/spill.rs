@@ -0,0 +1,356 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded queues that spill into an overflow queue instead of rejecting items.
+//!
+//! The producers returned by `wrap` add items to a primary queue (usually bounded) and only add
+//! items to an overflow queue (usually unbounded) when the primary queue is full, so occasional
+//! bursts are absorbed instead of being rejected with `Full` errors while the fast path stays on
+//! the primary queue. Once an item has spilled, the producers keep adding items to the overflow
+//! queue until the consumers have emptied it, and the consumers empty the primary queue before
+//! the overflow queue, so the items are returned in the order they were added apart from races
+//! between producers and consumers at the moment the overflow queue empties. `Stats` records how
+//! often items spill.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::spill;
+//!
+//! fn main() {
+//!     let (producer, consumer) = spill::channel(2, 0);
+//!
+//!     for item in 0..5 {
+//!         producer.produce(item).unwrap();
+//!     }
+//!     assert_eq!(producer.stats().spilled, 3);
+//!     assert_eq!(producer.stats().bursts, 1);
+//!
+//!     let items = (0..5).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+//!     assert_eq!(items, &[0, 1, 2, 3, 4]);
+//!     assert_eq!(consumer.stats().overflow, 0);
+//! }
+//! ```
+
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+use bounded;
+use unbounded;
+
+/// The producer and consumer of a bounded MPMC queue that spills into an unbounded MPMC queue.
+type Channel<T> = (
+    Producer<bounded::mpmc::Producer<T>, unbounded::mpmc::Producer<T>>,
+    Consumer<bounded::mpmc::Consumer<T>, unbounded::mpmc::Consumer<T>>,
+);
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer that removes items from a primary queue and then from an overflow queue.
+#[derive(Debug)]
+pub struct Consumer<C, D> {
+    consumer: C,
+    overflow: D,
+    state: Arc<State>,
+}
+
+impl<C, D> Consumer<C, D> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the primary queue or, if it is empty, from the
+    /// overflow queue.
+    ///
+    /// This method returns `Disconnected` if both queues are empty and disconnected.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T>, D: Consume<T> {
+        let error = match self.consumer.consume() {
+            Ok(item) => return Ok(item),
+            Err(error) => error,
+        };
+
+        if self.state.overflow.load(Acquire) == 0 {
+            return Err(error);
+        }
+
+        match self.overflow.consume() {
+            Ok(item) => {
+                self.state.overflow.fetch_sub(1, Release);
+                Ok(item)
+            },
+            Err(ConsumeError::Disconnected) => Err(error),
+            Err(other) if error == ConsumeError::Disconnected => Err(other),
+            Err(_) => Err(error),
+        }
+    }
+
+    /// Returns statistics on how often items have spilled into the overflow queue.
+    pub fn stats(&self) -> Stats {
+        self.state.stats()
+    }
+
+    /// Returns a reference to the wrapped consumer for the primary queue.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+
+    /// Returns a reference to the wrapped consumer for the overflow queue.
+    pub fn get_overflow_ref(&self) -> &D {
+        &self.overflow
+    }
+}
+
+impl<C, D> Clone for Consumer<C, D> where C: Clone, D: Clone {
+    fn clone(&self) -> Self {
+        let overflow = self.overflow.clone();
+        Consumer { consumer: self.consumer.clone(), overflow, state: self.state.clone() }
+    }
+}
+
+impl<T, C, D> Consume<T> for Consumer<C, D> where C: Consume<T>, D: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer that adds items to a primary queue and spills them into an overflow queue.
+#[derive(Debug)]
+pub struct Producer<P, Q> {
+    producer: P,
+    overflow: Q,
+    state: Arc<State>,
+}
+
+impl<P, Q> Producer<P, Q> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the primary queue or, if it is full or the overflow
+    /// queue is not empty, to the overflow queue.
+    ///
+    /// This method returns `Err` if the queue the item would be added to has no remaining
+    /// consumers or if the overflow queue rejects the item.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>>
+        where P: Produce<T>, Q: Produce<T>
+    {
+        // Keep adding items to the overflow queue until it is empty to preserve the order of the
+        // items.
+        let item = if self.state.overflow.load(Acquire) == 0 {
+            match self.producer.produce(item) {
+                Ok(()) => {
+                    self.state.produced.fetch_add(1, Relaxed);
+                    return Ok(());
+                },
+                Err(ProduceError::Full(item)) => item,
+                Err(error) => return Err(error),
+            }
+        } else {
+            item
+        };
+
+        // The item is counted before it is added so that the count never underflows.
+        if self.state.overflow.fetch_add(1, AcqRel) == 0 {
+            self.state.bursts.fetch_add(1, Relaxed);
+        }
+        match self.overflow.produce(item) {
+            Ok(()) => {
+                self.state.produced.fetch_add(1, Relaxed);
+                self.state.spilled.fetch_add(1, Relaxed);
+                Ok(())
+            },
+            Err(error) => {
+                self.state.overflow.fetch_sub(1, Release);
+                Err(error)
+            },
+        }
+    }
+
+    /// Returns statistics on how often items have spilled into the overflow queue.
+    pub fn stats(&self) -> Stats {
+        self.state.stats()
+    }
+
+    /// Returns a reference to the wrapped producer for the primary queue.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+
+    /// Returns a reference to the wrapped producer for the overflow queue.
+    pub fn get_overflow_ref(&self) -> &Q {
+        &self.overflow
+    }
+}
+
+impl<P, Q> Clone for Producer<P, Q> where P: Clone, Q: Clone {
+    fn clone(&self) -> Self {
+        let overflow = self.overflow.clone();
+        Producer { producer: self.producer.clone(), overflow, state: self.state.clone() }
+    }
+}
+
+impl<T, P, Q> Produce<T> for Producer<P, Q> where P: Produce<T>, Q: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// State _________________________________________
+
+#[derive(Debug)]
+struct State {
+    produced: AtomicUsize,
+    spilled: AtomicUsize,
+    bursts: AtomicUsize,
+    overflow: AtomicUsize,
+}
+
+impl State {
+    //- Accessors --------------------------------
+
+    fn stats(&self) -> Stats {
+        Stats {
+            produced: self.produced.load(Relaxed),
+            spilled: self.spilled.load(Relaxed),
+            bursts: self.bursts.load(Relaxed),
+            overflow: self.overflow.load(Relaxed),
+        }
+    }
+}
+
+// Stats _________________________________________
+
+/// Statistics on how often items have spilled into the overflow queue.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of items that have been added to either queue.
+    pub produced: usize,
+    /// The number of items that have been added to the overflow queue.
+    pub spilled: usize,
+    /// The number of times items started spilling into an empty overflow queue.
+    pub bursts: usize,
+    /// The number of items currently in the overflow queue.
+    pub overflow: usize,
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for a bounded MPMC lock-free queue that can contain `size`
+/// items and spills into an unbounded MPMC lock-free queue.
+///
+/// The value of `clones` indicates the number of clones of the initial producer and consumer the
+/// overflow queue reserves space for (see `unbounded::mpmc::channel`).
+///
+/// # Panics
+///
+/// * `size` is not a power of two
+pub fn channel<T>(size: usize, clones: usize) -> Channel<T> {
+    let (producer, consumer) = bounded::mpmc::channel(size);
+    let (overflow_producer, overflow_consumer) = unbounded::mpmc::channel(clones);
+    wrap(producer, consumer, overflow_producer, overflow_consumer)
+}
+
+/// Wraps the supplied producers and consumers so that items spill from the primary queue into the
+/// overflow queue instead of being rejected when the primary queue is full.
+pub fn wrap<P, C, Q, D>(
+    producer: P, consumer: C, overflow_producer: Q, overflow_consumer: D
+) -> (Producer<P, Q>, Consumer<C, D>) {
+    let state = Arc::new(State {
+        produced: AtomicUsize::new(0),
+        spilled: AtomicUsize::new(0),
+        bursts: AtomicUsize::new(0),
+        overflow: AtomicUsize::new(0),
+    });
+    let overflow = overflow_producer;
+    let producer = Producer { producer, overflow, state: state.clone() };
+    (producer, Consumer { consumer, overflow: overflow_consumer, state })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_spill() {
+        let (producer, consumer) = channel(2, 0);
+        for item in 0..4 {
+            producer.produce(item).unwrap();
+        }
+
+        // Items keep spilling until the overflow queue is empty, even once the primary queue has
+        // room.
+        assert_eq!(consumer.consume(), Ok(0));
+        producer.produce(4).unwrap();
+        assert_eq!(consumer.stats(), Stats { produced: 5, spilled: 3, bursts: 1, overflow: 3 });
+
+        let items = (0..4).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+        assert_eq!(items, &[1, 2, 3, 4]);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        // Another burst starts once the overflow queue is empty.
+        for item in 5..8 {
+            producer.produce(item).unwrap();
+        }
+        assert_eq!(producer.stats().bursts, 2);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (producer, consumer) = channel(2, 0);
+        for item in 0..3 {
+            producer.produce(item).unwrap();
+        }
+        drop(producer);
+
+        let items = (0..3).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+        assert_eq!(items, &[0, 1, 2]);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+
+        let (producer, consumer) = channel(2, 0);
+        drop(consumer);
+        assert_eq!(producer.produce(0), Err(ProduceError::Disconnected(0)));
+    }
+
+    #[test]
+    fn test_threads() {
+        let (producer, consumer) = channel(4, 0);
+        let thread = thread::spawn(move || {
+            for item in 0..10000 {
+                producer.produce(item).unwrap();
+            }
+        });
+
+        let mut items = vec![];
+        loop {
+            match consumer.consume() {
+                Ok(item) => items.push(item),
+                Err(ConsumeError::Disconnected) => break,
+                Err(_) => { },
+            }
+        }
+        thread.join().unwrap();
+        items.sort();
+        assert_eq!(items, (0..10000).collect::<Vec<_>>());
+    }
+}
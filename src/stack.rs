@@ -0,0 +1,453 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unbounded multi-producer, multi-consumer lock-free stack.
+//!
+//! Items are removed in the reverse of the order they were added (LIFO), which suits workloads
+//! such as object reuse where the most recently returned object is the most likely to still be in
+//! the cache. The stack is a Treiber stack: the producers push nodes onto the top of the stack and
+//! the consumers pop nodes off the top of the stack with a compare-and-swap. The consumers use
+//! hazard pointers to free the nodes they pop like the unbounded MPMC queue, which also prevents
+//! the ABA problem. The producers never dereference a node they did not allocate, so they don't
+//! need hazard pointers.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use npnc::stack;
+//!
+//! fn main() {
+//!     let (producer, consumer) = stack::channel(0);
+//!
+//!     producer.produce(1).unwrap();
+//!     producer.produce(2).unwrap();
+//!     producer.produce(3).unwrap();
+//!     assert_eq!(consumer.len(), 3);
+//!
+//!     assert_eq!(consumer.consume(), Ok(3));
+//!     assert_eq!(consumer.consume(), Ok(2));
+//!     assert_eq!(consumer.consume(), Ok(1));
+//! }
+//! ```
+
+use std::cell::{Cell};
+use std::marker::{PhantomData};
+use std::mem;
+use std::ptr;
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, MemoryUsage, Produce, ProduceError};
+use buffer;
+use notify::{Notifier};
+use reclaim::{Domain, Hazard, Reclaim};
+
+const TOP: usize = 0;
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for an unbounded MPMC lock-free stack.
+///
+/// A consumer can be sent to another thread but can't be shared between threads, since it holds
+/// the thread index it reserved in the reclamation domain of the stack.
+///
+/// ```compile_fail,E0277
+/// extern crate npnc;
+///
+/// use std::sync::{Arc};
+/// use std::thread;
+///
+/// use npnc::stack;
+///
+/// fn main() {
+///     let (producer, consumer) = stack::channel(0);
+///     producer.produce(1).unwrap();
+///     let consumer = Arc::new(consumer);
+///     thread::spawn(move || consumer.consume());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Consumer<T>(usize, Arc<Stack<T>>, PhantomData<Cell<()>>);
+
+impl<T> Consumer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the top of the stack.
+    ///
+    /// This method returns `Err` if the stack is empty.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.1.consume(self.0)
+    }
+
+    /// Returns the number of items currently in the stack.
+    pub fn len(&self) -> usize {
+        self.1.len.load(Relaxed)
+    }
+
+    /// Returns whether the stack is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the memory currently used by the stack.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.1.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the stack.
+    pub fn producer_count(&self) -> usize {
+        self.1.producers.load(Acquire)
+    }
+
+    /// Returns the number of consumers currently connected to the stack.
+    pub fn consumer_count(&self) -> usize {
+        self.1.consumers.load(Acquire)
+    }
+
+    /// Frees the nodes retired by this consumer that are no longer in use and returns the unused
+    /// capacity of its list of retired nodes to the allocator.
+    pub fn shrink_to_fit(&self) {
+        self.1.domain.reclaim().shrink(self.0);
+    }
+}
+
+impl<T> Clone for Consumer<T> {
+    fn clone(&self) -> Self {
+        let thread = self.1.domain.attach();
+        self.1.consumers.fetch_add(1, Release);
+        Consumer(thread, self.1.clone(), PhantomData)
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.1.consume(self.0)
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.1.domain.detach(self.0);
+        if self.1.consumers.fetch_sub(1, Release) == 1 {
+            self.1.disconnect.notify();
+        }
+    }
+}
+
+unsafe impl<T> Send for Consumer<T> where T: Send { }
+
+// Node __________________________________________
+
+#[derive(Debug)]
+struct Node<T> {
+    item: Option<T>,
+    next: AtomicPtr<Node<T>>,
+}
+
+// Producer ______________________________________
+
+/// A producer for an unbounded MPMC lock-free stack.
+#[derive(Debug)]
+pub struct Producer<T>(Arc<Stack<T>>);
+
+impl<T> Producer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the top of the stack.
+    ///
+    /// This method returns `Err` if the stack has no remaining consumers or if a node for the item
+    /// could not be allocated.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    /// Returns the number of items currently in the stack.
+    pub fn len(&self) -> usize {
+        self.0.len.load(Relaxed)
+    }
+
+    /// Returns whether the stack is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the memory currently used by the stack.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the stack.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+
+    /// Returns the number of consumers currently connected to the stack.
+    pub fn consumer_count(&self) -> usize {
+        self.0.consumers.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the stack has no remaining consumers.
+    ///
+    /// The callback is called on the thread that disconnects the stack or immediately if the stack
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        self.0.producers.fetch_add(1, Release);
+        Producer(self.0.clone())
+    }
+}
+
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.producers.fetch_sub(1, Release);
+    }
+}
+
+unsafe impl<T> Send for Producer<T> where T: Send { }
+
+// Stack _________________________________________
+
+#[derive(Debug)]
+struct Stack<T> {
+    top: AtomicPtr<Node<T>>,
+    len: AtomicUsize,
+    producers: AtomicUsize,
+    consumers: AtomicUsize,
+    domain: Domain<Hazard>,
+    disconnect: Notifier,
+}
+
+impl<T> Stack<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Stack` that reserves space for the supplied number of consumers.
+    fn new(consumers: usize) -> Arc<Self> {
+        Arc::new(Stack {
+            top: AtomicPtr::new(ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            producers: AtomicUsize::new(1),
+            consumers: AtomicUsize::new(1),
+            domain: Domain::with_hazards(consumers, 1),
+            disconnect: Notifier::new(),
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let nodes = self.len.load(Relaxed);
+        let retired = self.domain.reclaim().retired();
+        let bytes = (nodes + retired) * mem::size_of::<Node<T>>();
+        MemoryUsage { bytes, nodes, retired }
+    }
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        // Return an error if all of the consumers have been disconnected.
+        if self.consumers.load(Acquire) == 0 {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        let node = Node { item: Some(item), next: AtomicPtr::new(ptr::null_mut()) };
+        let node = match buffer::try_box(node) {
+            Ok(node) => node,
+            Err(node) => return Err(ProduceError::AllocFailed(node.item.unwrap())),
+        };
+
+        // The item is counted before it is added so that the count never underflows.
+        self.len.fetch_add(1, Relaxed);
+
+        // Push the node onto the top of the stack. The top node is never dereferenced here, so it
+        // does not matter if it is freed or reused in the meantime.
+        let mut top = self.top.load(Relaxed);
+        loop {
+            deref!(node).next.store(top, Relaxed);
+            match self.top.compare_exchange_weak(top, node, Release, Relaxed) {
+                Ok(_) => return Ok(()),
+                Err(current) => top = current,
+            }
+        }
+    }
+
+    fn consume(&self, thread: usize) -> Result<T, ConsumeError> {
+        // The producers are checked first so that an item added by the last producer before it was
+        // disconnected is never missed.
+        let disconnected = self.producers.load(Acquire) == 0;
+
+        let reclaim = self.domain.reclaim();
+        loop {
+            // Return an error if the stack is empty.
+            let top = reclaim.mark(thread, TOP, &self.top);
+            if top.is_null() {
+                reclaim.clear(thread, TOP);
+                if disconnected {
+                    return Err(ConsumeError::Disconnected);
+                } else {
+                    return Err(ConsumeError::Empty);
+                }
+            }
+
+            // Pop the top node and return its item if no other consumer popped it first. The
+            // hazard pointer keeps the top node from being freed and reused, so the next node read
+            // here is still the next node if the compare-and-swap succeeds.
+            let next = deref!(top).next.load(Relaxed);
+            if self.top.compare_exchange(top, next, Acquire, Relaxed).is_ok() {
+                let item = deref_mut!(top).item.take().unwrap();
+                reclaim.clear(thread, TOP);
+                unsafe { reclaim.retire(thread, top); }
+                self.len.fetch_sub(1, Relaxed);
+                return Ok(item);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        // No other threads can be operating on this stack so the nodes can be freed directly.
+        let mut node = self.top.load(Relaxed);
+        while !node.is_null() {
+            let next = deref!(node).next.load(Relaxed);
+            unsafe { drop(Box::from_raw(node)); }
+            node = next;
+        }
+    }
+}
+
+unsafe impl<T> Sync for Stack<T> where T: Send { }
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for an unbounded MPMC lock-free stack.
+///
+/// The value of `clones` indicates the number of clones of the initial consumer the stack reserves
+/// space for. More clones can be made at the cost of allocating space for them.
+pub fn channel<T>(clones: usize) -> (Producer<T>, Consumer<T>) {
+    let stack = Stack::new(clones + 1);
+    let thread = stack.domain.attach();
+    (Producer(stack.clone()), Consumer(thread, stack, PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_lifo() {
+        let (producer, consumer) = channel(0);
+        for item in 0..4 {
+            producer.produce(item).unwrap();
+        }
+        assert_eq!(producer.len(), 4);
+        assert_eq!(consumer.consume(), Ok(3));
+        producer.produce(4).unwrap();
+
+        let items = (0..4).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+        assert_eq!(items, &[4, 2, 1, 0]);
+        assert!(consumer.is_empty());
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+        assert!(consumer.memory_usage().retired > 0);
+        consumer.shrink_to_fit();
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (producer, consumer) = channel(0);
+        producer.produce(1).unwrap();
+        drop(producer);
+        assert_eq!(consumer.producer_count(), 0);
+        assert_eq!(consumer.consume(), Ok(1));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+
+        let (producer, consumer) = channel(1);
+        let (sender, receiver) = mpsc::channel();
+        producer.on_disconnect(move || sender.send(()).unwrap());
+        let clone = consumer.clone();
+        assert_eq!(producer.consumer_count(), 2);
+        drop(consumer);
+        assert!(receiver.try_recv().is_err());
+        drop(clone);
+        assert_eq!(receiver.try_recv(), Ok(()));
+        assert_eq!(producer.produce(2), Err(ProduceError::Disconnected(2)));
+    }
+
+    #[test]
+    fn test_drop() {
+        let item = Arc::new(());
+        let (producer, consumer) = channel(0);
+        for _ in 0..100 {
+            producer.produce(item.clone()).unwrap();
+        }
+        drop(consumer.consume());
+        drop((producer, consumer));
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn test_threads() {
+        let (producer, consumer) = channel(4);
+        let producers = (0..2).map(|i| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for item in 0..5000 {
+                    producer.produce((i * 5000) + item).unwrap();
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(producer);
+        let consumers = (0..2).map(|_| {
+            let consumer = consumer.clone();
+            thread::spawn(move || {
+                let mut items = vec![];
+                loop {
+                    match consumer.consume() {
+                        Ok(item) => items.push(item),
+                        Err(ConsumeError::Disconnected) => return items,
+                        Err(_) => { },
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(consumer);
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut items = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, (0..10000).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,451 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording the operations performed on a queue.
+//!
+//! The producers and consumers returned by `trace` record every `produce` and `consume` operation
+//! they perform as an `Event` in a `Sink`. Every event is stamped with the thread that performed
+//! the operation and a sequence number that is shared by all of the producers and consumers for
+//! the queue, which shows the order the operations were performed in. Only the queues that are
+//! traced pay for recording events.
+//!
+//! A `Schedule` is a sink that keeps the order the producers and consumers of a queue performed
+//! their operations in. The operations can be performed again in the same order on a single thread
+//! with `replay` to reproduce a bug that depends on a particular interleaving of operations.
+//! Sequence numbers are taken after each operation completes, so operations that overlapped may be
+//! replayed in a different order than they took effect in and `replay` reports where the outcomes
+//! first differ.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::sync::{Arc, Mutex};
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::trace::{self, Event, Operation};
+//!
+//! fn main() {
+//!     let events = Arc::new(Mutex::new(vec![]));
+//!     let sink = events.clone();
+//!     let (producer, consumer) = spsc::channel(64);
+//!     let (producer, consumer) = trace::trace(producer, consumer, move |e: &Event| {
+//!         sink.lock().unwrap().push(e.operation);
+//!     });
+//!
+//!     producer.produce(1).unwrap();
+//!     assert_eq!(consumer.consume(), Ok(1));
+//!     assert!(consumer.consume().is_err());
+//!
+//!     let events = events.lock().unwrap();
+//!     assert_eq!(events[0], Operation::Produce(Ok(())));
+//!     assert_eq!(events[1], Operation::Consume(Ok(())));
+//!     assert!(events[2].is_err());
+//! }
+//! ```
+
+use std::fmt;
+use std::thread::{self, ThreadId};
+use std::marker::{PhantomData};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+//================================================
+// Enums
+//================================================
+
+// Kind __________________________________________
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Producer,
+    Consumer,
+}
+
+// Operation _____________________________________
+
+/// An operation performed on a queue and its outcome.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// An item was or was not added to the queue.
+    Produce(Result<(), ProduceError<()>>),
+    /// An item was or was not removed from the queue.
+    Consume(Result<(), ConsumeError>),
+}
+
+impl Operation {
+    //- Accessors --------------------------------
+
+    /// Returns whether the operation failed.
+    pub fn is_err(&self) -> bool {
+        match *self {
+            Operation::Produce(result) => result.is_err(),
+            Operation::Consume(result) => result.is_err(),
+        }
+    }
+}
+
+//================================================
+// Structs
+//================================================
+
+// Divergence ____________________________________
+
+/// Indicates that a replayed operation had a different outcome than the recorded operation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The index of the step in the schedule.
+    pub index: usize,
+    /// The recorded operation.
+    pub expected: Operation,
+    /// The replayed operation.
+    pub actual: Operation,
+}
+
+// Event _________________________________________
+
+/// An operation performed on a traced queue.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// The position of the operation in the order the operations on the queue were performed in.
+    pub sequence: usize,
+    /// The thread that performed the operation.
+    pub thread: ThreadId,
+    /// The index of the producer or consumer that performed the operation.
+    ///
+    /// Producers and consumers are numbered separately in the order they were created, starting
+    /// with `0` for the producer and consumer returned by `trace`.
+    pub handle: usize,
+    /// The operation and its outcome.
+    pub operation: Operation,
+}
+
+// Schedule ______________________________________
+
+/// A sink that keeps the order the operations on a queue were performed in.
+///
+/// A schedule is usually shared with `trace` through an `Arc` so it can be read once the
+/// operations have been performed.
+#[derive(Debug, Default)]
+pub struct Schedule {
+    events: Mutex<Vec<Event>>,
+}
+
+impl Schedule {
+    //- Constructors -----------------------------
+
+    /// Constructs a new empty `Schedule`.
+    pub fn new() -> Self {
+        Schedule { events: Mutex::new(vec![]) }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the operations recorded by this schedule in the order they were performed in.
+    pub fn steps(&self) -> Vec<Step> {
+        let mut events = self.events.lock().unwrap().clone();
+        events.sort_by_key(|e| e.sequence);
+        events.iter().map(|e| Step { handle: e.handle, operation: e.operation }).collect()
+    }
+}
+
+impl Sink for Schedule {
+    fn record(&self, event: &Event) {
+        self.events.lock().unwrap().push(*event);
+    }
+}
+
+// Shared ________________________________________
+
+struct Shared {
+    sequence: AtomicUsize,
+    producers: AtomicUsize,
+    consumers: AtomicUsize,
+    sink: Box<dyn Sink>,
+}
+
+impl Shared {
+    //- Accessors --------------------------------
+
+    /// Returns the index for a new producer or consumer.
+    fn add(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Producer => self.producers.fetch_add(1, Relaxed),
+            Kind::Consumer => self.consumers.fetch_add(1, Relaxed),
+        }
+    }
+
+    fn record(&self, handle: usize, operation: Operation) {
+        let sequence = self.sequence.fetch_add(1, SeqCst);
+        let thread = thread::current().id();
+        self.sink.record(&Event { sequence, thread, handle, operation });
+    }
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Shared {{ sequence: {:?} }}", self.sequence)
+    }
+}
+
+// Step __________________________________________
+
+/// An operation in a schedule.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// The index of the producer or consumer that performed the operation.
+    pub handle: usize,
+    /// The operation and its outcome.
+    pub operation: Operation,
+}
+
+// Traced ________________________________________
+
+/// A producer or consumer that records the operations it performs.
+#[derive(Debug)]
+pub struct Traced<T, H> {
+    handle: H,
+    index: usize,
+    kind: Kind,
+    shared: Arc<Shared>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, H> Traced<T, H> {
+    //- Constructors -----------------------------
+
+    fn new(handle: H, kind: Kind, shared: Arc<Shared>) -> Self {
+        let index = shared.add(kind);
+        Traced { handle, index, kind, shared, _marker: PhantomData }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the index of this producer or consumer among the producers or consumers for the
+    /// queue.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns a reference to the wrapped producer or consumer.
+    pub fn get_ref(&self) -> &H {
+        &self.handle
+    }
+}
+
+impl<T, H> Traced<T, H> where H: Produce<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue and records the outcome.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        let result = self.handle.produce(item);
+        let outcome = result.as_ref().map(|_| ()).map_err(strip);
+        self.shared.record(self.index, Operation::Produce(outcome));
+        result
+    }
+}
+
+impl<T, H> Traced<T, H> where H: Consume<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue and records the outcome.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        let result = self.handle.consume();
+        let outcome = result.as_ref().map(|_| ()).map_err(|e| *e);
+        self.shared.record(self.index, Operation::Consume(outcome));
+        result
+    }
+}
+
+impl<T, H> Clone for Traced<T, H> where H: Clone {
+    fn clone(&self) -> Self {
+        Traced::new(self.handle.clone(), self.kind, self.shared.clone())
+    }
+}
+
+impl<T, H> Consume<T> for Traced<T, H> where H: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Traced::consume(self)
+    }
+}
+
+impl<T, H> Produce<T> for Traced<T, H> where H: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Traced::produce(self, item)
+    }
+}
+
+//================================================
+// Traits
+//================================================
+
+// Sink __________________________________________
+
+/// A type that records the operations performed on a traced queue.
+///
+/// Events are recorded on the threads that perform the operations, so a sink that blocks will slow
+/// down the producers and consumers of the queue.
+pub trait Sink: Send + Sync {
+    /// Records the supplied event.
+    fn record(&self, event: &Event);
+}
+
+impl<F> Sink for F where F: Fn(&Event) + Send + Sync {
+    fn record(&self, event: &Event) {
+        self(event)
+    }
+}
+
+impl<S> Sink for Arc<S> where S: Sink {
+    fn record(&self, event: &Event) {
+        (**self).record(event)
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns the supplied error without the rejected item.
+fn strip<T>(error: &ProduceError<T>) -> ProduceError<()> {
+    match *error {
+        ProduceError::Disconnected(_) => ProduceError::Disconnected(()),
+        ProduceError::Full(_) => ProduceError::Full(()),
+        ProduceError::NoCredit(_) => ProduceError::NoCredit(()),
+        ProduceError::Contended(_) => ProduceError::Contended(()),
+        ProduceError::AllocFailed(_) => ProduceError::AllocFailed(()),
+        ProduceError::Timeout(_) => ProduceError::Timeout(()),
+    }
+}
+
+/// Wraps the supplied producer and consumer so they record the operations they perform in the
+/// supplied sink.
+///
+/// Clones of the returned producer and consumer record their operations in the same sink.
+pub fn trace<T, P, C, S>(
+    producer: P, consumer: C, sink: S
+) -> (Traced<T, P>, Traced<T, C>) where P: Produce<T>, C: Consume<T>, S: Sink + 'static {
+    let shared = Arc::new(Shared {
+        sequence: AtomicUsize::new(0),
+        producers: AtomicUsize::new(0),
+        consumers: AtomicUsize::new(0),
+        sink: Box::new(sink),
+    });
+    let producer = Traced::new(producer, Kind::Producer, shared.clone());
+    (producer, Traced::new(consumer, Kind::Consumer, shared))
+}
+
+/// Performs the operations in the supplied schedule in order on the current thread with the
+/// supplied producers and consumers.
+///
+/// The producers and consumers are indexed by the `handle` of each step and the items added by the
+/// producers are returned by `item`. This function returns `Err` at the first operation whose
+/// outcome differs from the recorded outcome. The removed items are dropped.
+///
+/// # Panics
+///
+/// * a step refers to a producer or consumer that was not supplied
+pub fn replay<T, P, C, F>(
+    steps: &[Step], producers: &[P], consumers: &[C], mut item: F
+) -> Result<(), Divergence> where P: Produce<T>, C: Consume<T>, F: FnMut() -> T {
+    for (index, step) in steps.iter().enumerate() {
+        let actual = match step.operation {
+            Operation::Produce(_) => {
+                let result = producers[step.handle].produce(item());
+                Operation::Produce(result.as_ref().map(|_| ()).map_err(strip))
+            },
+            Operation::Consume(_) => {
+                Operation::Consume(consumers[step.handle].consume().map(|_| ()))
+            },
+        };
+
+        if actual != step.operation {
+            return Err(Divergence { index, expected: step.operation, actual });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+    use unbounded::{mpmc};
+
+    #[test]
+    fn test_trace() {
+        let schedule = Arc::new(Schedule::new());
+        let (producer, consumer) = spsc::channel(1);
+        let (producer, consumer) = trace(producer, consumer, schedule.clone());
+        assert_eq!((producer.index(), consumer.index()), (0, 0));
+
+        producer.produce(1).unwrap();
+        assert_eq!(producer.produce(2), Err(ProduceError::Full(2)));
+        assert_eq!(consumer.consume(), Ok(1));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        let steps = schedule.steps();
+        let operations = steps.iter().map(|s| s.operation).collect::<Vec<_>>();
+        assert_eq!(operations, &[
+            Operation::Produce(Ok(())),
+            Operation::Produce(Err(ProduceError::Full(()))),
+            Operation::Consume(Ok(())),
+            Operation::Consume(Err(ConsumeError::Empty)),
+        ]);
+        assert!(!operations[0].is_err() && operations[1].is_err());
+    }
+
+    #[test]
+    fn test_clone() {
+        let schedule = Arc::new(Schedule::new());
+        let (producer, consumer) = mpmc::channel(1);
+        let (producer, consumer) = trace(producer, consumer, schedule.clone());
+        let clone = producer.clone();
+        assert_eq!((clone.index(), consumer.clone().index()), (1, 1));
+
+        clone.produce(1).unwrap();
+        producer.produce(2).unwrap();
+        let steps = schedule.steps();
+        assert_eq!(steps.iter().map(|s| s.handle).collect::<Vec<_>>(), &[1, 0]);
+    }
+
+    #[test]
+    fn test_replay() {
+        let schedule = Arc::new(Schedule::new());
+        let (producer, consumer) = spsc::channel(2);
+        let (producer, consumer) = trace(producer, consumer, schedule.clone());
+        for item in 0..3 {
+            let _ = producer.produce(item);
+        }
+        while consumer.consume().is_ok() { }
+        let steps = schedule.steps();
+
+        let (producer, consumer) = spsc::channel(2);
+        assert_eq!(replay(&steps, &[producer], &[consumer], || 0), Ok(()));
+
+        // The replayed queue is larger, so the third item is accepted instead of rejected.
+        let (producer, consumer) = spsc::channel(4);
+        let divergence = replay(&steps, &[producer], &[consumer], || 0).unwrap_err();
+        assert_eq!(divergence, Divergence {
+            index: 2,
+            expected: Operation::Produce(Err(ProduceError::Full(()))),
+            actual: Operation::Produce(Ok(())),
+        });
+    }
+}
@@ -14,5 +14,47 @@
 
 //! Unbounded lock-free queues.
 
+use std::ptr;
+use std::sync::atomic::{AtomicPtr};
+
 pub mod mpmc;
+pub mod mpsc;
+pub mod relaxed;
+pub mod spmc;
 pub mod spsc;
+
+//================================================
+// Structs
+//================================================
+
+// Node __________________________________________
+
+/// A node in an unbounded queue.
+///
+/// The node at the front of a queue is a sentinel which contains no item. An item is moved out of
+/// the node after the sentinel when it is consumed and that node becomes the new sentinel, so the
+/// queue never keeps a consumed item alive. The sentinel itself can't be removed without a
+/// different algorithm because the consumers would have to race the producers for the last node.
+///
+/// `Option<T>` is no larger than `T` when `T` is a non-null pointer such as `Box<U>` or `Arc<U>`,
+/// so items of these types are already stored in a node as a single pointer.
+///
+/// The SPSC and MPMC queues share this node type so that their nodes can be moved between queues.
+#[derive(Debug)]
+struct Node<T> {
+    item: Option<T>,
+    next: AtomicPtr<Node<T>>,
+}
+
+impl<T> Node<T> {
+    //- Constructors -----------------------------
+
+    fn new(item: Option<T>) -> Self {
+        Node { item, next: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    /// Allocates a new sentinel node.
+    fn sentinel() -> *mut Self {
+        Box::into_raw(Box::new(Node::new(None)))
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::hint;
+use std::cell::{UnsafeCell};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {padding};
+
+/// The number of times a producer checks whether its item has been taken before withdrawing it.
+const SPINS: usize = 128;
+
+/// The slot does not contain an item.
+const EMPTY: usize = 0;
+/// A producer is adding an item to or withdrawing an item from the slot.
+const BUSY: usize = 1;
+/// The slot contains an item that can be taken by a consumer.
+const WAITING: usize = 2;
+/// A consumer is checking whether it can take the item in the slot.
+const CLAIMED: usize = 3;
+/// A consumer has taken the item in the slot.
+const TAKEN: usize = 4;
+
+//================================================
+// Structs
+//================================================
+
+// Elimination ___________________________________
+
+/// An array of slots where producers and consumers can exchange items without using the queue.
+///
+/// A producer that finds the queue empty places its item in a slot and waits briefly for a
+/// consumer to take it. A consumer that finds the queue empty takes an item from a slot only if
+/// the queue is still empty once it has claimed the slot. The exchange can then be treated as the
+/// item being added to and immediately removed from the empty queue, so the order of the items in
+/// the queue is preserved.
+pub struct Elimination<T> {
+    slots: Vec<Slot<T>>,
+}
+
+impl<T> Elimination<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Elimination` with the supplied number of slots.
+    pub fn new(slots: usize) -> Self {
+        Elimination { slots: (0..slots).map(|_| Slot::new()).collect() }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns whether this array has any slots.
+    pub fn enabled(&self) -> bool {
+        !self.slots.is_empty()
+    }
+
+    /// Attempts to hand the supplied item to a consumer.
+    ///
+    /// This method returns `Err` containing the item if no consumer took it.
+    pub fn offer(&self, thread: usize, item: T) -> Result<(), T> {
+        if !self.enabled() {
+            return Err(item);
+        }
+
+        // Place the item in the slot for this thread if the slot is not in use.
+        let slot = &self.slots[thread % self.slots.len()];
+        if slot.state.compare_exchange(EMPTY, BUSY, Acquire, Relaxed).is_err() {
+            return Err(item);
+        }
+        unsafe { *slot.item.get() = Some(item); }
+        slot.state.store(WAITING, Release);
+
+        for _ in 0..SPINS {
+            if slot.state.load(Acquire) == TAKEN {
+                slot.state.store(EMPTY, Release);
+                return Ok(());
+            }
+            hint::spin_loop();
+        }
+
+        // Withdraw the item unless a consumer has claimed it.
+        loop {
+            match slot.state.compare_exchange(WAITING, BUSY, Acquire, Acquire) {
+                Ok(_) => {
+                    let item = unsafe { (*slot.item.get()).take().unwrap() };
+                    slot.state.store(EMPTY, Release);
+                    return Err(item);
+                },
+                Err(TAKEN) => {
+                    slot.state.store(EMPTY, Release);
+                    return Ok(());
+                },
+                Err(_) => hint::spin_loop(),
+            }
+        }
+    }
+
+    /// Attempts to take an item offered by a producer.
+    ///
+    /// The supplied function is called after a slot has been claimed and must return whether the
+    /// queue is empty. This method returns `None` if there were no offered items or if the queue
+    /// was not empty.
+    pub fn accept<F>(&self, thread: usize, empty: F) -> Option<T> where F: Fn() -> bool {
+        let len = self.slots.len();
+        for offset in 0..len {
+            let slot = &self.slots[(thread + offset) % len];
+            if slot.state.load(Relaxed) != WAITING {
+                continue;
+            }
+
+            if slot.state.compare_exchange(WAITING, CLAIMED, Acquire, Relaxed).is_ok() {
+                // Return the item to the producer if the queue contains older items.
+                if !empty() {
+                    slot.state.store(WAITING, Release);
+                    return None;
+                }
+
+                let item = unsafe { (*slot.item.get()).take() };
+                slot.state.store(TAKEN, Release);
+                return item;
+            }
+        }
+        None
+    }
+}
+
+impl<T> fmt::Debug for Elimination<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "Elimination {{ slots: {} }}", self.slots.len())
+    }
+}
+
+// Slot __________________________________________
+
+#[repr(C)]
+struct Slot<T> {
+    _padding: [usize; padding(1)],
+    state: AtomicUsize,
+    item: UnsafeCell<Option<T>>,
+}
+
+impl<T> Slot<T> {
+    //- Constructors -----------------------------
+
+    fn new() -> Self {
+        let item = UnsafeCell::new(None);
+        Slot { _padding: [0; padding(1)], state: AtomicUsize::new(EMPTY), item }
+    }
+}
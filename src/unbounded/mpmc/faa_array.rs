@@ -0,0 +1,257 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::mem;
+use std::ptr;
+use std::alloc::{self, Layout};
+use std::cell::{UnsafeCell};
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {MemoryUsage, padding};
+use buffer;
+use reclaim::{Reclaim};
+use super::{sealed, Backend};
+
+/// The number of slots in a segment.
+const SEGMENT: usize = 1024;
+
+const HEAD: usize = 0;
+const TAIL: usize = 1;
+
+const EMPTY: usize = 0;
+const FULL: usize = 1;
+const TAKEN: usize = 2;
+
+//================================================
+// Structs
+//================================================
+
+// FaaArray ______________________________________
+
+/// The FAA array queue algorithm (Correia and Ramalhete).
+///
+/// Items are stored in linked segments of slots which producers and consumers claim with
+/// fetch-and-add instead of retrying a compare-and-swap, which scales better than the
+/// Michael-Scott algorithm when many threads are adding or removing items. Segments are allocated
+/// and freed as a whole so this algorithm also performs far fewer allocations.
+#[derive(Debug)]
+#[repr(C)]
+pub struct FaaArray<T> {
+    tail: AtomicPtr<Segment<T>>,
+    _tpadding: [usize; padding(1)],
+    head: AtomicPtr<Segment<T>>,
+    segments: AtomicUsize,
+    _hpadding: [usize; padding(2)],
+}
+
+impl<T> Backend<T> for FaaArray<T> {
+    fn domains() -> usize {
+        2
+    }
+
+    fn new() -> Self {
+        let segment = Segment::try_new(None).unwrap_or_else(|_| {
+            alloc::handle_alloc_error(Layout::new::<Segment<T>>())
+        });
+        FaaArray {
+            tail: AtomicPtr::new(segment),
+            _tpadding: [0; padding(1)],
+            head: AtomicPtr::new(segment),
+            segments: AtomicUsize::new(1),
+            _hpadding: [0; padding(2)],
+        }
+    }
+
+    fn produce<R>(&self, reclaim: &R, thread: usize, item: T) -> Result<(), T> where R: Reclaim {
+        let mut item = item;
+        loop {
+            let tail = reclaim.mark(thread, TAIL, &self.tail);
+            let index = deref!(tail).enqueue.fetch_add(1, AcqRel);
+            if index < SEGMENT {
+                // Add the item to the claimed slot unless a consumer has given up on the slot.
+                let slot = &deref!(tail).slots[index];
+                unsafe { *slot.item.get() = Some(item); }
+                if slot.state.compare_exchange(EMPTY, FULL, AcqRel, Acquire).is_ok() {
+                    reclaim.clear(thread, TAIL);
+                    return Ok(());
+                }
+                item = unsafe { (*slot.item.get()).take().unwrap() };
+            } else if tail == self.tail.load(Acquire) {
+                let next = deref!(tail).next.load(Acquire);
+                if next.is_null() {
+                    // Add a new segment containing the item to the back of the queue.
+                    let segment = match Segment::try_new(Some(item)) {
+                        Ok(segment) => segment,
+                        Err(item) => {
+                            reclaim.clear(thread, TAIL);
+                            return Err(item.unwrap());
+                        },
+                    };
+                    if exchange(&deref!(tail).next, ptr::null_mut(), segment) {
+                        self.segments.fetch_add(1, Relaxed);
+                        exchange(&self.tail, tail, segment);
+                        reclaim.clear(thread, TAIL);
+                        return Ok(());
+                    }
+                    let segment = unsafe { Box::from_raw(segment) };
+                    item = unsafe { (*segment.slots[0].item.get()).take().unwrap() };
+                } else {
+                    // Attempt to update the tail pointer.
+                    exchange(&self.tail, tail, next);
+                }
+            }
+        }
+    }
+
+    fn consume<R>(&self, reclaim: &R, thread: usize) -> Option<T> where R: Reclaim {
+        loop {
+            // Return `None` if the queue is empty.
+            let head = reclaim.mark(thread, HEAD, &self.head);
+            let segment = deref!(head);
+            let empty = segment.dequeue.load(Acquire) >= segment.enqueue.load(Acquire);
+            if empty && segment.next.load(Acquire).is_null() {
+                reclaim.clear(thread, HEAD);
+                return None;
+            }
+
+            let index = segment.dequeue.fetch_add(1, AcqRel);
+            if index < SEGMENT {
+                // Remove and return the item in the claimed slot unless the producer that claimed
+                // the slot has not added its item yet, in which case the producer will try again.
+                let slot = &segment.slots[index];
+                if slot.state.swap(TAKEN, AcqRel) == FULL {
+                    let item = unsafe { (*slot.item.get()).take() };
+                    reclaim.clear(thread, HEAD);
+                    return item;
+                }
+            } else {
+                let next = segment.next.load(Acquire);
+                if next.is_null() {
+                    reclaim.clear(thread, HEAD);
+                    return None;
+                }
+
+                // Move the tail pointer past this segment before it is retired.
+                if head == self.tail.load(Acquire) {
+                    exchange(&self.tail, head, next);
+                }
+
+                // Remove this segment from the front of the queue.
+                if exchange(&self.head, head, next) {
+                    self.segments.fetch_sub(1, Relaxed);
+                    reclaim.clear(thread, HEAD);
+                    unsafe { reclaim.retire(thread, head); }
+                }
+            }
+        }
+    }
+
+    fn memory_usage(&self, _: usize, retired: usize) -> MemoryUsage {
+        let nodes = self.segments.load(Relaxed);
+        let bytes = (nodes + retired) * Segment::<T>::size();
+        MemoryUsage { bytes, nodes, retired }
+    }
+}
+
+impl<T> Drop for FaaArray<T> {
+    fn drop(&mut self) {
+        // No other threads can be operating on this queue so the segments can be freed directly.
+        let mut segment = self.head.load(Relaxed);
+        while !segment.is_null() {
+            let next = deref!(segment).next.load(Relaxed);
+            unsafe { drop(Box::from_raw(segment)); }
+            segment = next;
+        }
+    }
+}
+
+impl<T> sealed::Sealed for FaaArray<T> { }
+
+// Segment _______________________________________
+
+#[derive(Debug)]
+#[repr(C)]
+struct Segment<T> {
+    enqueue: AtomicUsize,
+    _epadding: [usize; padding(1)],
+    dequeue: AtomicUsize,
+    _dpadding: [usize; padding(1)],
+    next: AtomicPtr<Segment<T>>,
+    slots: Vec<Slot<T>>,
+}
+
+impl<T> Segment<T> {
+    //- Constructors -----------------------------
+
+    /// Attempts to allocate a new segment which contains the supplied item, if any, in its first
+    /// slot.
+    ///
+    /// This method returns the item instead of aborting if the segment can't be allocated.
+    fn try_new(item: Option<T>) -> Result<*mut Self, Option<T>> {
+        let mut slots = match buffer::try_vec(SEGMENT) {
+            Ok(slots) => slots,
+            Err(_) => return Err(item),
+        };
+        slots.extend((0..SEGMENT).map(|_| Slot::new()));
+        let enqueue = if item.is_some() {
+            slots[0] = Slot { item: UnsafeCell::new(item), state: AtomicUsize::new(FULL) };
+            1
+        } else {
+            0
+        };
+        let segment = Segment {
+            enqueue: AtomicUsize::new(enqueue),
+            _epadding: [0; padding(1)],
+            dequeue: AtomicUsize::new(0),
+            _dpadding: [0; padding(1)],
+            next: AtomicPtr::new(ptr::null_mut()),
+            slots,
+        };
+        buffer::try_box(segment).map_err(|s| unsafe { (*s.slots[0].item.get()).take() })
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the number of bytes used by a segment.
+    fn size() -> usize {
+        mem::size_of::<Self>() + (SEGMENT * mem::size_of::<Slot<T>>())
+    }
+}
+
+// Slot __________________________________________
+
+#[derive(Debug)]
+struct Slot<T> {
+    item: UnsafeCell<Option<T>>,
+    state: AtomicUsize,
+}
+
+impl<T> Slot<T> {
+    //- Constructors -----------------------------
+
+    fn new() -> Self {
+        Slot { item: UnsafeCell::new(None), state: AtomicUsize::new(EMPTY) }
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+fn exchange<T>(
+    atomic: &AtomicPtr<Segment<T>>, current: *mut Segment<T>, new: *mut Segment<T>
+) -> bool {
+    atomic.compare_exchange(current, new, AcqRel, Acquire).is_ok()
+}
@@ -0,0 +1,272 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::mem;
+use std::ptr;
+use std::alloc::{self, Layout};
+use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {MemoryUsage, padding};
+use buffer::{self, Buffer};
+use reclaim::{Reclaim};
+use ring::{Ring};
+use super::{sealed, Backend};
+
+/// The base two logarithm of the number of slots in a segment.
+const ORDER: u32 = 10;
+
+const HEAD: usize = 0;
+const TAIL: usize = 1;
+
+//================================================
+// Structs
+//================================================
+
+// Lscq __________________________________________
+
+/// The linked scalable circular queue (LSCQ) algorithm (Nikolaev).
+///
+/// Items are stored in linked segments that are each a scalable circular queue, whose slots
+/// producers and consumers claim with fetch-and-add like the `Scq` algorithm of the bounded MPMC
+/// queue. This is the portable form of the LCRQ algorithm (Morrison and Afek), which links rings
+/// in the same way but needs a double-width compare-and-swap. A producer that finds the segment at
+/// the back of the queue full finalizes it so that no more items can be added to it and appends a
+/// new segment. Segments are reused until they fill up, so a queue that stays short never
+/// allocates after it is constructed.
+#[derive(Debug)]
+#[repr(C)]
+pub struct Lscq<T> {
+    tail: AtomicPtr<Segment<T>>,
+    _tpadding: [usize; padding(1)],
+    head: AtomicPtr<Segment<T>>,
+    segments: AtomicUsize,
+    _hpadding: [usize; padding(2)],
+}
+
+impl<T> Backend<T> for Lscq<T> {
+    fn domains() -> usize {
+        2
+    }
+
+    fn new() -> Self {
+        let segment = Segment::try_new(None).unwrap_or_else(|_| {
+            alloc::handle_alloc_error(Layout::new::<Segment<T>>())
+        });
+        Lscq {
+            tail: AtomicPtr::new(segment),
+            _tpadding: [0; padding(1)],
+            head: AtomicPtr::new(segment),
+            segments: AtomicUsize::new(1),
+            _hpadding: [0; padding(2)],
+        }
+    }
+
+    fn produce<R>(&self, reclaim: &R, thread: usize, item: T) -> Result<(), T> where R: Reclaim {
+        let mut item = item;
+        loop {
+            let tail = reclaim.mark(thread, TAIL, &self.tail);
+            let next = deref!(tail).next.load(Acquire);
+            if !next.is_null() {
+                // Attempt to update the tail pointer.
+                exchange(&self.tail, tail, next);
+                continue;
+            }
+
+            // Add the item to the segment at the back of the queue unless it has been finalized.
+            item = match deref!(tail).produce(item) {
+                Ok(()) => {
+                    reclaim.clear(thread, TAIL);
+                    return Ok(());
+                },
+                Err(item) => item,
+            };
+
+            // Add a new segment containing the item to the back of the queue.
+            let segment = match Segment::try_new(Some(item)) {
+                Ok(segment) => segment,
+                Err(item) => {
+                    reclaim.clear(thread, TAIL);
+                    return Err(item.unwrap());
+                },
+            };
+            if exchange(&deref!(tail).next, ptr::null_mut(), segment) {
+                self.segments.fetch_add(1, Relaxed);
+                exchange(&self.tail, tail, segment);
+                reclaim.clear(thread, TAIL);
+                return Ok(());
+            }
+            let segment = unsafe { Box::from_raw(segment) };
+            item = segment.consume().unwrap();
+        }
+    }
+
+    fn consume<R>(&self, reclaim: &R, thread: usize) -> Option<T> where R: Reclaim {
+        loop {
+            let head = reclaim.mark(thread, HEAD, &self.head);
+            let segment = deref!(head);
+            if let Some(item) = segment.consume() {
+                reclaim.clear(thread, HEAD);
+                return Some(item);
+            }
+
+            // Return `None` if the queue is empty.
+            let next = segment.next.load(Acquire);
+            if next.is_null() {
+                reclaim.clear(thread, HEAD);
+                return None;
+            }
+
+            // The segment was finalized before the next segment was appended, but a producer may
+            // have been adding an item to it while the consumers were giving up on it, so it is
+            // searched once more before it is removed.
+            segment.occupied.reset_threshold();
+            if let Some(item) = segment.consume() {
+                reclaim.clear(thread, HEAD);
+                return Some(item);
+            }
+
+            // Move the tail pointer past this segment before it is retired.
+            if head == self.tail.load(Acquire) {
+                exchange(&self.tail, head, next);
+            }
+
+            // Remove this segment from the front of the queue.
+            if exchange(&self.head, head, next) {
+                self.segments.fetch_sub(1, Relaxed);
+                reclaim.clear(thread, HEAD);
+                unsafe { reclaim.retire(thread, head); }
+            }
+        }
+    }
+
+    fn memory_usage(&self, _: usize, retired: usize) -> MemoryUsage {
+        let nodes = self.segments.load(Relaxed);
+        let bytes = (nodes + retired) * Segment::<T>::size();
+        MemoryUsage { bytes, nodes, retired }
+    }
+}
+
+impl<T> Drop for Lscq<T> {
+    fn drop(&mut self) {
+        // No other threads can be operating on this queue so the segments can be freed directly.
+        let mut segment = self.head.load(Relaxed);
+        while !segment.is_null() {
+            let next = deref!(segment).next.load(Relaxed);
+            unsafe { drop(Box::from_raw(segment)); }
+            segment = next;
+        }
+    }
+}
+
+impl<T> sealed::Sealed for Lscq<T> { }
+
+// Segment _______________________________________
+
+#[derive(Debug)]
+struct Segment<T> {
+    occupied: Ring,
+    free: Ring,
+    buffer: Buffer<T>,
+    next: AtomicPtr<Segment<T>>,
+}
+
+impl<T> Segment<T> {
+    //- Constructors -----------------------------
+
+    /// Attempts to allocate a new segment which contains the supplied item, if any.
+    ///
+    /// This method returns the item instead of aborting if the segment can't be allocated.
+    fn try_new(item: Option<T>) -> Result<*mut Self, Option<T>> {
+        let (occupied, free) = match (Ring::try_new(ORDER), Ring::try_new(ORDER)) {
+            (Ok(occupied), Ok(free)) => (occupied, free),
+            _ => return Err(item),
+        };
+        let buffer = match Buffer::try_new(1 << ORDER) {
+            Ok(buffer) => buffer,
+            Err(_) => return Err(item),
+        };
+        for index in 0..buffer.size() {
+            free.enqueue(index);
+        }
+
+        let segment = Segment {
+            occupied,
+            free,
+            buffer,
+            next: AtomicPtr::new(ptr::null_mut()),
+        };
+        if let Some(item) = item {
+            // A new segment has room for the item and has not been finalized.
+            assert!(segment.produce(item).is_ok());
+        }
+        buffer::try_box(segment).map_err(|s| s.consume())
+    }
+
+    //- Accessors --------------------------------
+
+    /// Returns the number of bytes used by a segment.
+    fn size() -> usize {
+        let entries = 2 * (2 << ORDER) * mem::size_of::<usize>();
+        mem::size_of::<Self>() + ((1 << ORDER) * mem::size_of::<T>()) + entries
+    }
+
+    /// Attempts to add the supplied item to this segment.
+    ///
+    /// This method returns the item if this segment is full, in which case this segment is
+    /// finalized, or if this segment has already been finalized.
+    fn produce(&self, item: T) -> Result<(), T> {
+        let index = match self.free.dequeue() {
+            Some(index) => index,
+            None => {
+                self.occupied.finalize();
+                return Err(item);
+            },
+        };
+
+        // The index is not returned to the free ring if this segment has been finalized since no
+        // more items will be added to it.
+        unsafe { self.buffer.set(index, item); }
+        if self.occupied.enqueue(index) {
+            Ok(())
+        } else {
+            Err(unsafe { self.buffer.get(index) })
+        }
+    }
+
+    /// Attempts to remove and return the item at the front of this segment.
+    fn consume(&self) -> Option<T> {
+        self.occupied.dequeue().map(|index| {
+            let item = unsafe { self.buffer.get(index) };
+            self.free.enqueue(index);
+            item
+        })
+    }
+}
+
+impl<T> Drop for Segment<T> {
+    fn drop(&mut self) {
+        self.occupied.reset_threshold();
+        while self.consume().is_some() { }
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+fn exchange<T>(
+    atomic: &AtomicPtr<Segment<T>>, current: *mut Segment<T>, new: *mut Segment<T>
+) -> bool {
+    atomic.compare_exchange(current, new, AcqRel, Acquire).is_ok()
+}
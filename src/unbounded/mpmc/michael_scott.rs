@@ -0,0 +1,204 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr};
+use std::sync::atomic::Ordering::*;
+
+use {ConsumeError, MemoryUsage, ProduceError, padding};
+use buffer;
+use reclaim::{Reclaim};
+use unbounded::{Node};
+use super::{sealed, Backend};
+
+const READ: usize = 0;
+const WRITE: usize = 1;
+const NEXT: usize = 2;
+
+//================================================
+// Structs
+//================================================
+
+// MichaelScott __________________________________
+
+/// The Michael-Scott queue algorithm.
+///
+/// Every item is stored in a separately allocated node and producers and consumers retry a
+/// compare-and-swap until it succeeds. The nodes of this queue can be moved to and from unbounded
+/// SPSC queues without being copied.
+#[derive(Debug)]
+#[repr(C)]
+pub struct MichaelScott<T> {
+    write: AtomicPtr<Node<T>>,
+    _wpadding: [usize; padding(1)],
+    read: AtomicPtr<Node<T>>,
+    _rpadding: [usize; padding(1)],
+}
+
+impl<T> MichaelScott<T> {
+    //- Constructors -----------------------------
+
+    pub(super) fn from_nodes(read: *mut Node<T>, write: *mut Node<T>) -> Self {
+        MichaelScott {
+            write: AtomicPtr::new(write),
+            _wpadding: [0; padding(1)],
+            read: AtomicPtr::new(read),
+            _rpadding: [0; padding(1)],
+        }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Removes the nodes from this queue and returns the read and write nodes.
+    ///
+    /// This queue is left empty with a new sentinel node. This method must not be called while
+    /// other threads are operating on this queue.
+    pub(super) fn take(&self) -> (*mut Node<T>, *mut Node<T>) {
+        let sentinel = Node::sentinel();
+        (self.read.swap(sentinel, AcqRel), self.write.swap(sentinel, AcqRel))
+    }
+}
+
+impl<T> Backend<T> for MichaelScott<T> {
+    fn domains() -> usize {
+        3
+    }
+
+    fn new() -> Self {
+        let sentinel = Node::sentinel();
+        MichaelScott::from_nodes(sentinel, sentinel)
+    }
+
+    fn produce<R>(&self, reclaim: &R, thread: usize, item: T) -> Result<(), T> where R: Reclaim {
+        let node = buffer::try_box(Node::new(Some(item))).map_err(|n| n.item.unwrap())?;
+        loop {
+            let write = reclaim.mark_ptr(thread, WRITE, self.write.load(Acquire));
+            if write == self.write.load(Acquire) {
+                let next = deref!(write).next.load(Acquire);
+                if next.is_null() {
+                    // Add the item to the back of the queue if this node is available.
+                    if exchange(&deref!(write).next, ptr::null_mut(), node) {
+                        exchange(&self.write, write, node);
+                        reclaim.clear(thread, WRITE);
+                        return Ok(());
+                    }
+                } else {
+                    // Attempt to update the write pointer.
+                    exchange(&self.write, write, next);
+                }
+            }
+        }
+    }
+
+    fn produce_weak<R>(
+        &self, reclaim: &R, thread: usize, item: T
+    ) -> Result<(), ProduceError<T>> where R: Reclaim {
+        let node = buffer::try_box(Node::new(Some(item)))
+            .map_err(|n| ProduceError::AllocFailed(n.item.unwrap()))?;
+        let write = reclaim.mark_ptr(thread, WRITE, self.write.load(Acquire));
+        if write == self.write.load(Acquire) {
+            let next = deref!(write).next.load(Acquire);
+            if next.is_null() {
+                // Add the item to the back of the queue if this node is available.
+                if exchange(&deref!(write).next, ptr::null_mut(), node) {
+                    exchange(&self.write, write, node);
+                    reclaim.clear(thread, WRITE);
+                    return Ok(());
+                }
+            } else {
+                // Attempt to update the write pointer for the next producer.
+                exchange(&self.write, write, next);
+            }
+        }
+
+        // The node was never shared with other threads so it can be freed directly.
+        reclaim.clear(thread, WRITE);
+        let mut node = unsafe { Box::from_raw(node) };
+        Err(ProduceError::Contended(node.item.take().unwrap()))
+    }
+
+    fn consume<R>(&self, reclaim: &R, thread: usize) -> Option<T> where R: Reclaim {
+        loop {
+            // Return `None` if the queue is empty.
+            let read = reclaim.mark(thread, READ, &self.read);
+            if read == self.write.load(Acquire) {
+                return None;
+            }
+
+            // Remove and return the item at the front of the queue if this node is available.
+            let next = reclaim.mark(thread, NEXT, &deref!(read).next);
+            if exchange(&self.read, read, next) {
+                let item = deref_mut!(next).item.take();
+                reclaim.clear(thread, READ);
+                reclaim.clear(thread, NEXT);
+                unsafe { reclaim.retire(thread, read); }
+                return item;
+            }
+        }
+    }
+
+    fn consume_weak<R>(&self, reclaim: &R, thread: usize) -> Result<T, ConsumeError>
+        where R: Reclaim
+    {
+        // Return an error if the queue is empty.
+        let read = reclaim.mark(thread, READ, &self.read);
+        if read == self.write.load(Acquire) {
+            reclaim.clear(thread, READ);
+            return Err(ConsumeError::Empty);
+        }
+
+        // Remove and return the item at the front of the queue if this node is available.
+        let next = reclaim.mark(thread, NEXT, &deref!(read).next);
+        if exchange(&self.read, read, next) {
+            let item = deref_mut!(next).item.take();
+            reclaim.clear(thread, READ);
+            reclaim.clear(thread, NEXT);
+            unsafe { reclaim.retire(thread, read); }
+            item.ok_or(ConsumeError::Empty)
+        } else {
+            reclaim.clear(thread, READ);
+            reclaim.clear(thread, NEXT);
+            Err(ConsumeError::Contended)
+        }
+    }
+
+    fn memory_usage(&self, len: usize, retired: usize) -> MemoryUsage {
+        let nodes = len + 1;
+        let bytes = (nodes + retired) * mem::size_of::<Node<T>>();
+        MemoryUsage { bytes, nodes, retired }
+    }
+}
+
+impl<T> Drop for MichaelScott<T> {
+    fn drop(&mut self) {
+        // No other threads can be operating on this queue so the nodes can be freed directly.
+        let mut node = self.read.load(Relaxed);
+        while !node.is_null() {
+            let next = deref!(node).next.load(Relaxed);
+            unsafe { drop(Box::from_raw(node)); }
+            node = next;
+        }
+    }
+}
+
+impl<T> sealed::Sealed for MichaelScott<T> { }
+
+//================================================
+// Functions
+//================================================
+
+fn exchange<T>(atomic: &AtomicPtr<Node<T>>, current: *mut Node<T>, new: *mut Node<T>) -> bool {
+    atomic.compare_exchange(current, new, AcqRel, Acquire).is_ok()
+}
@@ -0,0 +1,1017 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unbounded multi-producer, multi-consumer lock-free queue.
+//!
+//! The algorithm used by a queue is selected with the `B` type parameter of its producers and
+//! consumers:
+//!
+//! * `MichaelScott` &ndash; the default, which allocates a node for every item and whose nodes can
+//!   be moved to and from unbounded SPSC queues (see `downgrade`)
+//! * `FaaArray` &ndash; stores items in segments of slots claimed with fetch-and-add, which has
+//!   higher throughput under contention but can't be converted to an SPSC queue
+//! * `Lscq` &ndash; stores items in linked rings whose entries are claimed with fetch-and-add, the
+//!   portable form of the LCRQ algorithm, which reuses its rings and so rarely allocates
+//!
+//! Queues that use a non-default algorithm are constructed with `custom_channel` or a `Builder`.
+//!
+//! A queue constructed with a `Builder` can also be given an elimination array (see
+//! `Builder::elimination`), where producers and consumers that find the queue empty exchange items
+//! directly instead of contending for the front and back of the queue.
+//!
+//! Queues constructed with a `Builder` can also share a memory reclamation domain (see
+//! `Builder::domain`), which saves an application with many queues from paying for the per-thread
+//! state of the memory reclamation scheme of every queue.
+//!
+//! A `SharedProducer` can be stored in a global and used from any thread, since it clones its
+//! producer for each thread that uses it.
+
+use std::cell::{Cell};
+use std::marker::{PhantomData};
+use std::mem::{MaybeUninit};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration};
+
+use {CloneError, Consume, ConsumeError, MemoryUsage, Produce, ProduceError, padding};
+use reclaim::{Domain, Hazard, Qsbr, Reclaim};
+use notify::{Notifier};
+use super::{spsc, Node};
+
+use self::elimination::{Elimination};
+
+mod elimination;
+mod faa_array;
+mod lscq;
+mod michael_scott;
+mod shared;
+
+pub use self::faa_array::{FaaArray};
+pub use self::lscq::{Lscq};
+pub use self::michael_scott::{MichaelScott};
+pub use self::shared::{SharedProducer};
+
+/// The producer and consumer of a queue.
+type Channel<T, R, B=MichaelScott<T>> = (Producer<T, R, B>, Consumer<T, R, B>);
+
+//================================================
+// Structs
+//================================================
+
+// Builder _______________________________________
+
+/// A builder for an unbounded MPMC lock-free queue.
+///
+/// # Example
+///
+/// ```
+/// extern crate npnc;
+///
+/// use npnc::reclaim::{Qsbr};
+/// use npnc::unbounded::mpmc::{Builder, FaaArray};
+///
+/// fn main() {
+///     let (producer, consumer) = Builder::new()
+///         .clones(4)
+///         .reclaim::<Qsbr>()
+///         .backend::<FaaArray<i32>>()
+///         .build();
+///     producer.produce(1).unwrap();
+///     assert_eq!(consumer.consume(), Ok(1));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Builder<T, R=Hazard, B=MichaelScott<T>> where R: Reclaim, B: Backend<T> {
+    clones: usize,
+    limited: bool,
+    elimination: usize,
+    domain: Option<Domain<R>>,
+    _marker: PhantomData<fn(R, B) -> T>,
+}
+
+impl<T> Builder<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Builder` for a queue that reserves space for no clones of the initial
+    /// producer and consumer.
+    pub fn new() -> Self {
+        Builder { clones: 0, limited: false, elimination: 0, domain: None, _marker: PhantomData }
+    }
+}
+
+impl<T, R, B> Builder<T, R, B> where R: Reclaim, B: Backend<T> {
+    //- Consumers --------------------------------
+
+    /// Sets the number of clones of the initial producer and consumer the queue reserves space for.
+    ///
+    /// Both types of clones count towards this total. More clones can be made than this number,
+    /// but the queue has to allocate space for them when they are made.
+    pub fn clones(self, clones: usize) -> Self {
+        Builder { clones, ..self }
+    }
+
+    /// Makes `try_clone` return `LimitReached` errors instead of allocating space for more
+    /// producers and consumers once the space reserved for clones has been used up.
+    ///
+    /// Space is reused once a producer or consumer is dropped. The space reserved for clones is set
+    /// with `clones`, or by the domain if one is set with `domain`.
+    ///
+    /// # Panics
+    ///
+    /// Once the limit is reached, cloning the producers and consumers with `clone` panics, as does
+    /// adding items with a `SharedProducer` from a thread that has no producer yet.
+    pub fn limit_clones(self) -> Self {
+        Builder { limited: true, ..self }
+    }
+
+    /// Sets the number of slots in the elimination array of the queue (the default is `0`, which
+    /// disables elimination).
+    ///
+    /// A producer that finds the queue empty offers its item in one of the slots and waits briefly
+    /// for a consumer to take it, and a consumer that finds the queue empty takes an offered item
+    /// if the queue is still empty. This preserves the order of the items in the queue and improves
+    /// throughput when the queue is usually close to empty, but adds latency to producers when no
+    /// consumers are waiting for items.
+    pub fn elimination(self, slots: usize) -> Self {
+        Builder { elimination: slots, ..self }
+    }
+
+    /// Sets the memory reclamation scheme used by the queue.
+    pub fn reclaim<S>(self) -> Builder<T, S, B> where S: Reclaim {
+        Builder {
+            clones: self.clones,
+            limited: self.limited,
+            elimination: self.elimination,
+            domain: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the memory reclamation domain used by the queue, which can be shared with other queues.
+    ///
+    /// The memory reclamation scheme of the queue is set to that of the domain. The producers and
+    /// consumers of the queue reserve space in the domain instead of in a domain of their own, so
+    /// the number of clones set with `clones` is ignored.
+    pub fn domain<S>(self, domain: &Domain<S>) -> Builder<T, S, B> where S: Reclaim {
+        Builder {
+            clones: self.clones,
+            limited: self.limited,
+            elimination: self.elimination,
+            domain: Some(domain.clone()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the algorithm used by the queue.
+    pub fn backend<C>(self) -> Builder<T, R, C> where C: Backend<T> {
+        Builder {
+            clones: self.clones,
+            limited: self.limited,
+            elimination: self.elimination,
+            domain: self.domain,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a producer and consumer for the queue.
+    pub fn build(self) -> (Producer<T, R, B>, Consumer<T, R, B>) {
+        let clones = self.clones;
+        let domain = self.domain.unwrap_or_else(|| Domain::with_hazards(clones + 2, B::domains()));
+        let elimination = Elimination::new(self.elimination);
+        let queue = Queue::new(domain, B::new(), elimination, 0, false, self.limited);
+        let producer = Producer(queue.attach(), queue.clone(), PhantomData);
+        (producer, Consumer(queue.attach(), queue, PhantomData))
+    }
+}
+
+impl<T> Default for Builder<T> {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+// Consumer ______________________________________
+
+/// A consumer for an unbounded MPMC lock-free queue.
+///
+/// A consumer can be sent to another thread but can't be shared between threads, since it holds
+/// the thread index it reserved in the reclamation domain of the queue.
+///
+/// ```compile_fail,E0277
+/// extern crate npnc;
+///
+/// use std::sync::{Arc};
+/// use std::thread;
+///
+/// use npnc::unbounded::mpmc;
+///
+/// fn main() {
+///     let (producer, consumer) = mpmc::channel(0);
+///     producer.produce(1).unwrap();
+///     let consumer = Arc::new(consumer);
+///     thread::spawn(move || consumer.consume());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Consumer<T, R=Hazard, B=MichaelScott<T>>(
+    usize, Arc<Queue<T, R, B>>, PhantomData<Cell<()>>
+) where R: Reclaim, B: Backend<T>;
+
+impl<T, R, B> Consumer<T, R, B> where R: Reclaim, B: Backend<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the front of the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.1.consume(self.0)
+    }
+
+    /// Attempts to remove and return the item at the front of the queue without retrying if
+    /// another thread removes the item first.
+    ///
+    /// This method returns `Err` if the queue is empty, and may return `Contended` even if the
+    /// queue is not empty. Items are not taken from the elimination array by this method. With
+    /// the `FaaArray` algorithm, this method is otherwise the same as `consume`.
+    pub fn consume_weak(&self) -> Result<T, ConsumeError> {
+        self.1.consume_weak(self.0)
+    }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        Consume::consume_uninit(self, items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        Consume::consume_latest(self)
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        Consume::discard(self, n)
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.1.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.1.producers.load(Acquire)
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.1.consumers.load(Acquire)
+    }
+
+    /// Frees the nodes retired by this handle that are no longer in use and returns the unused
+    /// capacity of its list of retired nodes to the allocator.
+    ///
+    /// Retired nodes are otherwise only freed once enough of them have accumulated, so this method
+    /// can be called during idle periods to release memory held after a burst of activity.
+    pub fn shrink_to_fit(&self) {
+        self.1.domain.reclaim().shrink(self.0);
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producers will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty, no matter how many
+    /// producers and consumers are still connected. The items already in the queue can still be
+    /// removed.
+    pub fn close(&self) {
+        self.1.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.1.closed.load(Acquire)
+    }
+
+    /// Disconnects the producers and returns the items remaining in the queue.
+    ///
+    /// Once this method has been called, the producers will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty. Items the producers
+    /// were in the middle of adding when this method was called may be left in the queue.
+    pub fn close_and_drain(&self) -> Vec<T> {
+        self.1.close();
+        let mut items = vec![];
+        while let Ok(item) = self.1.consume(self.0) {
+            items.push(item);
+        }
+        items
+    }
+
+    /// Returns the number of additional producers and consumers that can be cloned before the queue
+    /// has to allocate space for more of them.
+    pub fn remaining_clones(&self) -> usize {
+        self.1.remaining_clones()
+    }
+
+    /// Attempts to clone this consumer.
+    ///
+    /// This method returns `Disconnected` if the queue has no remaining producers or has been
+    /// closed and `LimitReached` if the queue limits clones and has no space left for another one.
+    pub fn try_clone(&self) -> Result<Self, CloneError> {
+        if self.1.producers.load(Acquire) == 0 || self.1.closed.load(Acquire) {
+            return Err(CloneError::Disconnected);
+        }
+
+        let thread = self.1.try_attach()?;
+        self.1.consumers.fetch_add(1, Release);
+        Ok(Consumer(thread, self.1.clone(), PhantomData))
+    }
+}
+
+impl<T, B> Consumer<T, Qsbr, B> where B: Backend<T> {
+    //- Accessors --------------------------------
+
+    /// Announces that this consumer is in a quiescent state.
+    ///
+    /// Removed nodes can't be freed until every producer and consumer has called this method.
+    pub fn quiescent(&self) {
+        self.1.domain.reclaim().quiescent(self.0);
+    }
+}
+
+impl<T, R, B> Clone for Consumer<T, R, B> where R: Reclaim, B: Backend<T> {
+    fn clone(&self) -> Self {
+        let thread = self.1.try_attach().expect("the queue has no space left for another clone");
+        self.1.consumers.fetch_add(1, Release);
+        Consumer(thread, self.1.clone(), PhantomData)
+    }
+}
+
+impl<T, R, B> Consume<T> for Consumer<T, R, B> where R: Reclaim, B: Backend<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.1.consume(self.0)
+    }
+
+    fn consume_weak(&self) -> Result<T, ConsumeError> {
+        self.1.consume_weak(self.0)
+    }
+}
+
+impl<T, R, B> Drop for Consumer<T, R, B> where R: Reclaim, B: Backend<T> {
+    fn drop(&mut self) {
+        self.1.detach(self.0);
+        if self.1.consumers.fetch_sub(1, Release) == 1 {
+            self.1.disconnect.notify();
+        }
+    }
+}
+
+unsafe impl<T, R, B> Send for Consumer<T, R, B> where T: Send, R: Reclaim, B: Backend<T> { }
+
+// Producer __________________________________
+
+/// A producer for an unbounded MPMC lock-free queue.
+///
+/// A producer can be sent to another thread but can't be shared between threads, since it holds
+/// the thread index it reserved in the reclamation domain of the queue. Use a `SharedProducer` to
+/// add items from threads that share a single handle.
+///
+/// ```compile_fail,E0277
+/// extern crate npnc;
+///
+/// use std::sync::{Arc};
+/// use std::thread;
+///
+/// use npnc::unbounded::mpmc;
+///
+/// fn main() {
+///     let (producer, consumer) = mpmc::channel(0);
+///     let producer = Arc::new(producer);
+///     thread::spawn(move || producer.produce(1));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Producer<T, R=Hazard, B=MichaelScott<T>>(
+    usize, Arc<Queue<T, R, B>>, PhantomData<Cell<()>>
+) where R: Reclaim, B: Backend<T>;
+
+impl<T, R, B> Producer<T, R, B> where R: Reclaim, B: Backend<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of the queue.
+    ///
+    /// This method returns `Err` if the queue has no remaining consumers or if a node for the item
+    /// could not be allocated.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.1.produce(self.0, item)
+    }
+
+    /// Attempts to add the supplied item to the back of the queue without retrying if another
+    /// thread adds an item first.
+    ///
+    /// This method returns `Err` if the queue has no remaining consumers or if a node for the item
+    /// could not be allocated, and may return `Contended` even if no other error occurred. Items
+    /// are not offered to the elimination array by this method. With the `FaaArray` algorithm,
+    /// this method is otherwise the same as `produce`.
+    pub fn produce_weak(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.1.produce_weak(self.0, item)
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.1.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.1.producers.load(Acquire)
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.1.consumers.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producers will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty, no matter how many
+    /// producers and consumers are still connected. The items already in the queue can still be
+    /// removed.
+    pub fn close(&self) {
+        self.1.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.1.closed.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers or has been
+    /// closed.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.1.disconnect.register(Box::new(callback));
+    }
+
+    /// Frees the nodes retired by this handle that are no longer in use and returns the unused
+    /// capacity of its list of retired nodes to the allocator.
+    ///
+    /// Retired nodes are otherwise only freed once enough of them have accumulated, so this method
+    /// can be called during idle periods to release memory held after a burst of activity.
+    pub fn shrink_to_fit(&self) {
+        self.1.domain.reclaim().shrink(self.0);
+    }
+
+    /// Returns the number of additional producers and consumers that can be cloned before the queue
+    /// has to allocate space for more of them.
+    pub fn remaining_clones(&self) -> usize {
+        self.1.remaining_clones()
+    }
+
+    /// Attempts to clone this producer.
+    ///
+    /// This method returns `Disconnected` if the queue has no remaining consumers or has been
+    /// closed and `LimitReached` if the queue limits clones and has no space left for another one.
+    pub fn try_clone(&self) -> Result<Self, CloneError> {
+        if self.1.consumers.load(Acquire) == 0 || self.1.closed.load(Acquire) {
+            return Err(CloneError::Disconnected);
+        }
+
+        let thread = self.1.try_attach()?;
+        self.1.producers.fetch_add(1, Release);
+        Ok(Producer(thread, self.1.clone(), PhantomData))
+    }
+}
+
+impl<T, B> Producer<T, Qsbr, B> where B: Backend<T> {
+    //- Accessors --------------------------------
+
+    /// Announces that this producer is in a quiescent state.
+    ///
+    /// Removed nodes can't be freed until every producer and consumer has called this method.
+    pub fn quiescent(&self) {
+        self.1.domain.reclaim().quiescent(self.0);
+    }
+}
+
+impl<T, R, B> Clone for Producer<T, R, B> where R: Reclaim, B: Backend<T> {
+    fn clone(&self) -> Self {
+        let thread = self.1.try_attach().expect("the queue has no space left for another clone");
+        self.1.producers.fetch_add(1, Release);
+        Producer(thread, self.1.clone(), PhantomData)
+    }
+}
+
+impl<T, R, B> Produce<T> for Producer<T, R, B> where R: Reclaim, B: Backend<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.1.produce(self.0, item)
+    }
+
+    fn produce_weak(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.1.produce_weak(self.0, item)
+    }
+}
+
+impl<T, R, B> Drop for Producer<T, R, B> where R: Reclaim, B: Backend<T> {
+    fn drop(&mut self) {
+        self.1.detach(self.0);
+        self.1.producers.fetch_sub(1, Release);
+    }
+}
+
+unsafe impl<T, R, B> Send for Producer<T, R, B> where T: Send, R: Reclaim, B: Backend<T> { }
+
+// Queue _________________________________________
+
+#[derive(Debug)]
+#[repr(C)]
+struct Queue<T, R, B> where R: Reclaim, B: Backend<T> {
+    producers: AtomicUsize,
+    produced: AtomicUsize,
+    _ppadding: [usize; padding(2)],
+    consumers: AtomicUsize,
+    closed: AtomicBool,
+    consumed: AtomicUsize,
+    _cpadding: [usize; padding(3)],
+    backend: B,
+    domain: Domain<R>,
+    /// Whether clones fail instead of allocating space in the domain once it has none left.
+    limited: bool,
+    elimination: Elimination<T>,
+    disconnect: Notifier,
+    _marker: PhantomData<T>,
+}
+
+impl<T, R, B> Queue<T, R, B> where R: Reclaim, B: Backend<T> {
+    //- Constructors -----------------------------
+
+    fn new(
+        domain: Domain<R>,
+        backend: B,
+        elimination: Elimination<T>,
+        len: usize,
+        closed: bool,
+        limited: bool,
+    ) -> Arc<Self> {
+        assert!(B::domains() <= domain.hazards(), "the domain has too few hazard pointers");
+        Arc::new(Queue {
+            producers: AtomicUsize::new(1),
+            produced: AtomicUsize::new(len),
+            _ppadding: [0; padding(2)],
+            consumers: AtomicUsize::new(1),
+            closed: AtomicBool::new(closed),
+            consumed: AtomicUsize::new(0),
+            _cpadding: [0; padding(3)],
+            backend,
+            domain,
+            limited,
+            elimination,
+            disconnect: Notifier::new(),
+            _marker: PhantomData,
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn attach(&self) -> usize {
+        self.domain.attach()
+    }
+
+    fn try_attach(&self) -> Result<usize, CloneError> {
+        self.domain.try_attach(!self.limited).ok_or(CloneError::LimitReached)
+    }
+
+    /// Returns whether this queue is empty.
+    ///
+    /// This may return `false` when the queue is empty but never returns `true` when it isn't.
+    fn is_empty(&self) -> bool {
+        // An item is counted as produced before it is added to the queue and as consumed after it
+        // is removed, so loading the consumed count first can only overestimate the length.
+        let consumed = self.consumed.load(SeqCst);
+        self.produced.load(SeqCst) == consumed
+    }
+
+    fn remaining_clones(&self) -> usize {
+        self.domain.remaining_threads()
+    }
+
+    fn detach(&self, thread: usize) {
+        self.domain.detach(thread);
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let consumed = self.consumed.load(Relaxed);
+        let len = self.produced.load(Relaxed).saturating_sub(consumed);
+        self.backend.memory_usage(len, self.domain.reclaim().retired())
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Release);
+        self.disconnect.notify();
+    }
+
+    fn produce(&self, thread: usize, item: T) -> Result<(), ProduceError<T>> {
+        // Return an error if all of the consumers have been disconnected or the queue has been
+        // closed.
+        if self.consumers.load(Acquire) == 0 || self.closed.load(Acquire) {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        // Attempt to hand the item directly to a consumer if the queue is empty.
+        let item = if self.elimination.enabled() && self.is_empty() {
+            match self.elimination.offer(thread, item) {
+                Ok(()) => return Ok(()),
+                Err(item) => item,
+            }
+        } else {
+            item
+        };
+
+        self.produced.fetch_add(1, Relaxed);
+        self.backend.produce(self.domain.reclaim(), thread, item).map_err(|item| {
+            self.produced.fetch_sub(1, Relaxed);
+            ProduceError::AllocFailed(item)
+        })
+    }
+
+    fn produce_weak(&self, thread: usize, item: T) -> Result<(), ProduceError<T>> {
+        // Return an error if all of the consumers have been disconnected or the queue has been
+        // closed.
+        if self.consumers.load(Acquire) == 0 || self.closed.load(Acquire) {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        self.produced.fetch_add(1, Relaxed);
+        self.backend.produce_weak(self.domain.reclaim(), thread, item).inspect_err(|_| {
+            self.produced.fetch_sub(1, Relaxed);
+        })
+    }
+
+    fn consume(&self, thread: usize) -> Result<T, ConsumeError> {
+        // The producers are checked first so that an item added by a producer before it was
+        // disconnected is never missed.
+        let disconnected = self.producers.load(Acquire) == 0 || self.closed.load(Acquire);
+        match self.backend.consume(self.domain.reclaim(), thread) {
+            Some(item) => {
+                self.consumed.fetch_add(1, Relaxed);
+                Ok(item)
+            },
+            None => match self.elimination.accept(thread, || self.is_empty()) {
+                Some(item) => Ok(item),
+                None if disconnected => Err(ConsumeError::Disconnected),
+                None => Err(ConsumeError::Empty),
+            },
+        }
+    }
+
+    fn consume_weak(&self, thread: usize) -> Result<T, ConsumeError> {
+        // The producers are checked first so that an item added by a producer before it was
+        // disconnected is never missed.
+        let disconnected = self.producers.load(Acquire) == 0 || self.closed.load(Acquire);
+        match self.backend.consume_weak(self.domain.reclaim(), thread) {
+            Ok(item) => {
+                self.consumed.fetch_add(1, Relaxed);
+                Ok(item)
+            },
+            Err(ConsumeError::Empty) if disconnected => Err(ConsumeError::Disconnected),
+            result => result,
+        }
+    }
+}
+
+impl<T, R> Queue<T, R, MichaelScott<T>> where R: Reclaim {
+    //- Accessors --------------------------------
+
+    /// Removes the nodes from this queue and returns the read and write nodes and the number of
+    /// items in the removed nodes.
+    ///
+    /// This queue is left empty with a new sentinel node. This method must not be called while
+    /// other threads are operating on this queue.
+    fn take(&self) -> (*mut Node<T>, *mut Node<T>, usize) {
+        let len = self.produced.swap(0, Relaxed).wrapping_sub(self.consumed.swap(0, Relaxed));
+        let (read, write) = self.backend.take();
+        (read, write, len)
+    }
+}
+
+unsafe impl<T, R, B> Sync for Queue<T, R, B> where T: Send, R: Reclaim, B: Backend<T> { }
+
+//================================================
+// Traits
+//================================================
+
+mod sealed {
+    pub trait Sealed { }
+}
+
+// Backend _______________________________________
+
+/// An unbounded MPMC lock-free queue algorithm.
+///
+/// This trait is sealed and cannot be implemented outside of this crate.
+pub trait Backend<T>: sealed::Sealed + Sized {
+    #[doc(hidden)]
+    fn domains() -> usize;
+
+    #[doc(hidden)]
+    fn new() -> Self;
+
+    #[doc(hidden)]
+    fn produce<R>(&self, reclaim: &R, thread: usize, item: T) -> Result<(), T> where R: Reclaim;
+
+    #[doc(hidden)]
+    fn produce_weak<R>(
+        &self, reclaim: &R, thread: usize, item: T
+    ) -> Result<(), ProduceError<T>> where R: Reclaim {
+        self.produce(reclaim, thread, item).map_err(ProduceError::AllocFailed)
+    }
+
+    #[doc(hidden)]
+    fn consume<R>(&self, reclaim: &R, thread: usize) -> Option<T> where R: Reclaim;
+
+    #[doc(hidden)]
+    fn consume_weak<R>(&self, reclaim: &R, thread: usize) -> Result<T, ConsumeError>
+        where R: Reclaim
+    {
+        self.consume(reclaim, thread).ok_or(ConsumeError::Empty)
+    }
+
+    #[doc(hidden)]
+    fn memory_usage(&self, len: usize, retired: usize) -> MemoryUsage;
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for an unbounded MPMC lock-free queue.
+///
+/// The value of `clones` indicates the number of clones of the initial producer and consumer the
+/// queue reserves space for. Both types of clones count towards this total, and more clones can be
+/// made at the cost of allocating space for them.
+pub fn channel<T>(clones: usize) -> (Producer<T>, Consumer<T>) {
+    Builder::new().clones(clones).build()
+}
+
+/// Returns a producer and consumer for an unbounded MPMC lock-free queue that uses the memory
+/// reclamation scheme and algorithm specified by the `R` and `B` type parameters.
+///
+/// The value of `clones` indicates the number of clones of the initial producer and consumer the
+/// queue reserves space for. Both types of clones count towards this total, and more clones can be
+/// made at the cost of allocating space for them.
+///
+/// # Example
+///
+/// ```
+/// extern crate npnc;
+///
+/// use npnc::reclaim::{Hazard};
+/// use npnc::unbounded::mpmc::{self, Consumer, FaaArray, Producer};
+///
+/// fn main() {
+///     let (producer, consumer): (Producer<i32, Hazard, FaaArray<i32>>, Consumer<_, _, _>) =
+///         mpmc::custom_channel(0);
+///     producer.produce(1).unwrap();
+///     assert_eq!(consumer.consume(), Ok(1));
+/// }
+/// ```
+pub fn custom_channel<T, R, B>(
+    clones: usize
+) -> (Producer<T, R, B>, Consumer<T, R, B>) where R: Reclaim, B: Backend<T> {
+    Builder::new().clones(clones).reclaim().backend().build()
+}
+
+/// Returns a producer and consumer for an unbounded MPMC lock-free queue containing the supplied
+/// nodes.
+pub(super) fn from_nodes<T>(
+    clones: usize, read: *mut Node<T>, write: *mut Node<T>, len: usize, closed: bool,
+    disconnect: Notifier
+) -> (Producer<T>, Consumer<T>) {
+    let backend = MichaelScott::from_nodes(read, write);
+    let domain = Domain::with_hazards(clones + 2, MichaelScott::<T>::domains());
+    let queue = Queue::new(domain, backend, Elimination::new(0), len, closed, false);
+    queue.disconnect.extend(disconnect);
+    let producer = Producer(queue.attach(), queue.clone(), PhantomData);
+    (producer, Consumer(queue.attach(), queue, PhantomData))
+}
+
+/// Returns a producer and consumer for an unbounded MPMC lock-free queue that uses
+/// quiescent-state-based reclamation instead of hazard pointers.
+///
+/// Removing items from this queue does not require any hazard pointer stores, but the nodes
+/// removed from the queue are not freed until every producer and consumer has called `quiescent`
+/// (twice). Producers and consumers should call `quiescent` periodically (e.g., once per iteration
+/// of a thread's event loop).
+///
+/// The value of `clones` indicates the number of clones of the initial producer and consumer the
+/// queue reserves space for. Both types of clones count towards this total, and more clones can be
+/// made at the cost of allocating space for them.
+pub fn qsbr_channel<T>(clones: usize) -> (Producer<T, Qsbr>, Consumer<T, Qsbr>) {
+    Builder::new().clones(clones).reclaim().build()
+}
+
+/// Attempts to convert the supplied producer and consumer for an unbounded MPMC queue into a
+/// producer and consumer for an unbounded SPSC queue.
+///
+/// The items in the MPMC queue are moved into the SPSC queue without being copied. This function
+/// returns `Err` containing the supplied producer and consumer if either has been cloned and the
+/// clones have not been dropped.
+///
+/// # Panics
+///
+/// * `producer` and `consumer` do not belong to the same queue
+pub fn downgrade<T, R>(
+    producer: Producer<T, R>, consumer: Consumer<T, R>
+) -> Result<(spsc::Producer<T>, spsc::Consumer<T>), Channel<T, R>>
+    where R: Reclaim
+{
+    assert!(Arc::ptr_eq(&producer.1, &consumer.1), "`producer` and `consumer` are unrelated");
+    let queue = &consumer.1;
+    if queue.producers.load(Acquire) != 1 || queue.consumers.load(Acquire) != 1 {
+        return Err((producer, consumer));
+    }
+
+    let (read, write, len) = queue.take();
+    let disconnect = queue.disconnect.take();
+    Ok(spsc::from_nodes(read, write, len, queue.closed.load(Acquire), disconnect))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_clones() {
+        let (producer, consumer) = Builder::<i32>::new().clones(1).limit_clones().build();
+        assert_eq!(producer.remaining_clones(), 1);
+        let clone = producer.try_clone().unwrap();
+        assert_eq!(consumer.try_clone().err(), Some(CloneError::LimitReached));
+        drop(clone);
+        let clone = consumer.try_clone().unwrap();
+        assert_eq!(producer.try_clone().err(), Some(CloneError::LimitReached));
+        drop((producer, clone));
+        assert_eq!(consumer.try_clone().err(), Some(CloneError::Disconnected));
+    }
+
+    #[test]
+    fn test_unlimited_clones() {
+        let (producer, _consumer) = Builder::<i32>::new().build();
+        assert_eq!(producer.remaining_clones(), 0);
+        let clones = (0..4).map(|_| producer.try_clone().unwrap()).collect::<Vec<_>>();
+        drop(clones);
+        assert_eq!(producer.remaining_clones(), 4);
+    }
+
+    #[test]
+    #[should_panic(expected="the queue has no space left for another clone")]
+    fn test_limit_clones_clone() {
+        let (producer, _consumer) = Builder::<i32>::new().limit_clones().build();
+        let _ = producer.clone();
+    }
+
+    #[test]
+    fn test_lscq() {
+        use std::thread;
+
+        let (producer, consumer): (Producer<usize, Hazard, Lscq<usize>>, Consumer<_, _, _>) =
+            custom_channel(4);
+        for item in 0..5000 {
+            producer.produce(item).unwrap();
+        }
+        assert!(producer.memory_usage().nodes > 1);
+        for item in 0..5000 {
+            assert_eq!(consumer.consume(), Ok(item));
+        }
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        let producers = (0..2).map(|i| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for item in 0..5000 {
+                    producer.produce((i * 5000) + item).unwrap();
+                }
+            })
+        }).collect::<Vec<_>>();
+        let consumers = (0..2).map(|_| {
+            let consumer = consumer.clone();
+            thread::spawn(move || {
+                let mut items = vec![];
+                let mut last = [None, None];
+                while items.len() < 5000 {
+                    if let Ok(item) = consumer.consume() {
+                        // Items from each producer are consumed in the order they were produced.
+                        assert!(last[item / 5000].is_none_or(|l| l < item));
+                        last[item / 5000] = Some(item);
+                        items.push(item);
+                    }
+                }
+                items
+            })
+        }).collect::<Vec<_>>();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut items = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, (0..10000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_lscq_drop() {
+        let item = Arc::new(());
+        let (producer, consumer): (Producer<_, Hazard, Lscq<_>>, Consumer<_, _, _>) =
+            custom_channel(1);
+        for _ in 0..3000 {
+            producer.produce(item.clone()).unwrap();
+        }
+        drop(consumer.consume());
+        drop((producer, consumer));
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    fn test_backend<B>(elimination: usize) where B: Backend<usize> + 'static {
+        use std::thread;
+
+        let builder = Builder::new().clones(4).elimination(elimination).backend::<B>();
+        let (producer, consumer) = builder.build();
+        let producers = (0..2).map(|i| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for item in 0..5000 {
+                    producer.produce((i * 5000) + item).unwrap();
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(producer);
+        let consumers = (0..2).map(|_| {
+            let consumer = consumer.clone();
+            thread::spawn(move || {
+                let mut items = vec![];
+                let mut last = [None, None];
+                loop {
+                    match consumer.consume() {
+                        // Items from each producer are consumed in the order they were produced.
+                        Ok(item) => {
+                            assert!(last[item / 5000].is_none_or(|l| l < item));
+                            last[item / 5000] = Some(item);
+                            items.push(item);
+                        },
+                        Err(ConsumeError::Disconnected) => return items,
+                        Err(_) => { },
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(consumer);
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut items = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, (0..10000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_michael_scott() {
+        test_backend::<MichaelScott<usize>>(0);
+    }
+
+    #[test]
+    fn test_faa_array() {
+        test_backend::<FaaArray<usize>>(0);
+    }
+
+    #[test]
+    fn test_elimination() {
+        test_backend::<MichaelScott<usize>>(4);
+        test_backend::<Lscq<usize>>(4);
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::cell::{UnsafeCell};
+use std::sync::{Mutex};
+use std::sync::atomic::{AtomicUsize};
+use std::sync::atomic::Ordering::*;
+
+use {Produce, ProduceError};
+use reclaim::{Hazard, Reclaim, Table};
+use super::{Backend, MichaelScott, Producer};
+
+/// The number of thread indices that have been handed out.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+/// The thread indices released by threads that have exited.
+static FREE: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static THREAD: ThreadIndex = ThreadIndex::new();
+}
+
+//================================================
+// Structs
+//================================================
+
+// Local _________________________________________
+
+/// The producer cloned for a thread, which is only accessed by the thread that owns its index.
+struct Local<P>(UnsafeCell<Option<P>>);
+
+impl<P> Local<P> {
+    //- Constructors -----------------------------
+
+    fn new() -> Self {
+        Local(UnsafeCell::new(None))
+    }
+}
+
+// SharedProducer ________________________________
+
+/// A producer for an unbounded MPMC lock-free queue that can be shared between threads.
+///
+/// A shared producer lazily clones the producer it was constructed with for each thread that adds
+/// items with it and keeps the clone for the thread, so each thread reserves its space in the
+/// queue (e.g., its hazard pointers) once. This lets a single handle be stored in a global and
+/// used from arbitrary threads without managing clones manually. The clone for a thread is reused
+/// by a later thread once the thread exits and the clones are dropped when this producer is
+/// dropped.
+///
+/// # Example
+///
+/// ```
+/// extern crate npnc;
+///
+/// use std::sync::{Arc};
+/// use std::thread;
+///
+/// use npnc::unbounded::mpmc::{self, SharedProducer};
+///
+/// fn main() {
+///     let (producer, consumer) = mpmc::channel(0);
+///     let producer = Arc::new(SharedProducer::new(producer));
+///
+///     let threads = (0..4).map(|i| {
+///         let producer = producer.clone();
+///         thread::spawn(move || producer.produce(i).unwrap())
+///     }).collect::<Vec<_>>();
+///
+///     for thread in threads {
+///         thread.join().unwrap();
+///     }
+///
+///     let mut items = (0..4).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+///     items.sort();
+///     assert_eq!(items, vec![0, 1, 2, 3]);
+/// }
+/// ```
+pub struct SharedProducer<T, R=Hazard, B=MichaelScott<T>> where R: Reclaim, B: Backend<T> {
+    producer: Mutex<Producer<T, R, B>>,
+    locals: Table<Local<Producer<T, R, B>>>,
+}
+
+impl<T, R, B> SharedProducer<T, R, B> where R: Reclaim, B: Backend<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `SharedProducer` that clones the supplied producer for each thread.
+    pub fn new(producer: Producer<T, R, B>) -> Self {
+        SharedProducer { producer: Mutex::new(producer), locals: Table::new(1, Local::new) }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of the queue with the producer for the
+    /// current thread.
+    ///
+    /// This method returns `Err` if the queue has no remaining consumers or if a node for the item
+    /// could not be allocated.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.with_local(|p| p.produce(item))
+    }
+
+    /// Calls the supplied function with the producer for the current thread.
+    ///
+    /// The producer for the current thread is cloned from the producer this shared producer was
+    /// constructed with the first time it is needed.
+    pub fn with_local<U, F>(&self, f: F) -> U where F: FnOnce(&Producer<T, R, B>) -> U {
+        // A thread that is exiting may no longer have an index, in which case a temporary clone is
+        // used instead.
+        let thread = match THREAD.try_with(|t| t.0) {
+            Ok(thread) => thread,
+            Err(_) => return f(&self.producer.lock().unwrap().clone()),
+        };
+
+        self.locals.reserve(thread, Local::new);
+        let local = self.locals.get(thread).0.get();
+        unsafe {
+            if (*local).is_none() {
+                *local = Some(self.producer.lock().unwrap().clone());
+            }
+            f((*local).as_ref().unwrap())
+        }
+    }
+}
+
+impl<T, R, B> fmt::Debug for SharedProducer<T, R, B> where R: Reclaim, B: Backend<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "SharedProducer {{ .. }}")
+    }
+}
+
+impl<T, R, B> From<Producer<T, R, B>> for SharedProducer<T, R, B>
+    where R: Reclaim, B: Backend<T>
+{
+    fn from(producer: Producer<T, R, B>) -> Self {
+        SharedProducer::new(producer)
+    }
+}
+
+impl<T, R, B> Produce<T> for SharedProducer<T, R, B> where R: Reclaim, B: Backend<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        SharedProducer::produce(self, item)
+    }
+}
+
+unsafe impl<T, R, B> Send for SharedProducer<T, R, B> where T: Send, R: Reclaim, B: Backend<T> { }
+unsafe impl<T, R, B> Sync for SharedProducer<T, R, B> where T: Send, R: Reclaim, B: Backend<T> { }
+
+// ThreadIndex ___________________________________
+
+/// A small index that is unique to a thread while the thread is running.
+struct ThreadIndex(usize);
+
+impl ThreadIndex {
+    //- Constructors -----------------------------
+
+    fn new() -> Self {
+        match FREE.lock().unwrap().pop() {
+            Some(index) => ThreadIndex(index),
+            None => ThreadIndex(THREADS.fetch_add(1, Relaxed)),
+        }
+    }
+}
+
+impl Drop for ThreadIndex {
+    fn drop(&mut self) {
+        FREE.lock().unwrap().push(self.0);
+    }
+}
@@ -0,0 +1,390 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unbounded multi-producer, single-consumer queue.
+//!
+//! The producers add nodes to the back of the queue by swapping the pointer to the last node and
+//! then linking the previous last node to the new node, so adding an item is wait-free. Since only
+//! the consumer removes and frees nodes, the consumer needs neither a CAS loop nor hazard
+//! pointers. Unlike the MPMC queue, the consumer can briefly see the queue as empty while a
+//! producer that has swapped the pointer to the last node has not yet linked the previous node.
+
+use std::mem::{self, MaybeUninit};
+use std::cell::{Cell};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration};
+
+use {Consume, ConsumeError, MemoryUsage, Produce, ProduceError, padding};
+use buffer;
+use notify::{Notifier};
+use super::{Node};
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for an unbounded MPSC queue.
+#[derive(Debug)]
+pub struct Consumer<T>(Arc<Queue<T>>);
+
+impl<T> Consumer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the front of the queue.
+    ///
+    /// This method returns `Err` if the queue is empty, including while the producer that is
+    /// adding the item at the front of the queue has not finished linking it.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        Consume::consume_uninit(self, items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        Consume::consume_latest(self)
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        Consume::discard(self, n)
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producers will receive `Disconnected` errors and the
+    /// consumer will receive a `Disconnected` error once the queue is empty, no matter how many
+    /// producers are still connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Disconnects the producers and returns the items remaining in the queue.
+    ///
+    /// Once this method has been called, the producers will receive `Disconnected` errors and this
+    /// consumer will receive a `Disconnected` error once the queue is empty. Items the producers
+    /// were in the middle of adding when this method was called may be left in the queue.
+    pub fn close_and_drain(&self) -> Vec<T> {
+        self.0.close();
+        let mut items = vec![];
+        while let Ok(item) = self.0.consume() {
+            items.push(item);
+        }
+        items
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.0.consumer.store(0, Release);
+        self.0.disconnect.notify();
+    }
+}
+
+unsafe impl<T> Send for Consumer<T> where T: Send { }
+
+// Producer ______________________________________
+
+/// A producer for an unbounded MPSC queue.
+#[derive(Debug)]
+pub struct Producer<T>(Arc<Queue<T>>);
+
+impl<T> Producer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of the queue.
+    ///
+    /// This method returns `Err` if the queue has no remaining consumers or if a node for the item
+    /// could not be allocated.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of producers currently connected to the queue.
+    pub fn producer_count(&self) -> usize {
+        self.0.producers.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producers will receive `Disconnected` errors and the
+    /// consumer will receive a `Disconnected` error once the queue is empty, no matter how many
+    /// producers are still connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers or has been
+    /// closed.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        self.0.producers.fetch_add(1, Release);
+        Producer(self.0.clone())
+    }
+}
+
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.producers.fetch_sub(1, Release);
+    }
+}
+
+unsafe impl<T> Send for Producer<T> where T: Send { }
+
+// Queue _________________________________________
+
+#[derive(Debug)]
+#[repr(C)]
+struct Queue<T> {
+    write: AtomicPtr<Node<T>>,
+    consumer: AtomicUsize,
+    closed: AtomicBool,
+    produced: AtomicUsize,
+    _wpadding: [usize; padding(4)],
+    read: Cell<*mut Node<T>>,
+    producers: AtomicUsize,
+    consumed: AtomicUsize,
+    _rpadding: [usize; padding(3)],
+    disconnect: Notifier,
+}
+
+impl<T> Queue<T> {
+    //- Constructors -----------------------------
+
+    fn new() -> Arc<Self> {
+        let sentinel = Node::sentinel();
+        Arc::new(Queue {
+            write: AtomicPtr::new(sentinel),
+            consumer: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
+            produced: AtomicUsize::new(0),
+            _wpadding: [0; padding(4)],
+            read: Cell::new(sentinel),
+            producers: AtomicUsize::new(1),
+            consumed: AtomicUsize::new(0),
+            _rpadding: [0; padding(3)],
+            disconnect: Notifier::new(),
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let consumed = self.consumed.load(Relaxed);
+        let nodes = self.produced.load(Relaxed).saturating_sub(consumed) + 1;
+        MemoryUsage { bytes: nodes * mem::size_of::<Node<T>>(), nodes, retired: 0 }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Release);
+        self.disconnect.notify();
+    }
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        // Return an error if the consumer has been disconnected or the queue has been closed.
+        if self.consumer.load(Acquire) == 0 || self.closed.load(Acquire) {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        // Add the item to the back of the queue.
+        let node = match buffer::try_box(Node::new(Some(item))) {
+            Ok(node) => node,
+            Err(node) => return Err(ProduceError::AllocFailed(node.item.unwrap())),
+        };
+        self.produced.fetch_add(1, Relaxed);
+        let previous = self.write.swap(node, AcqRel);
+        deref!(previous).next.store(node, Release);
+        Ok(())
+    }
+
+    fn consume(&self) -> Result<T, ConsumeError> {
+        // The producers are checked first so that an item added by a producer before it was
+        // disconnected is never missed.
+        let disconnected = self.producers.load(Acquire) == 0 || self.closed.load(Acquire);
+
+        // Return an error if the queue is empty.
+        let next = deref!(self.read.get()).next.load(Acquire);
+        if next.is_null() {
+            if disconnected {
+                return Err(ConsumeError::Disconnected);
+            } else {
+                return Err(ConsumeError::Empty);
+            }
+        }
+
+        // Remove and return the item at the front of the queue.
+        let item = deref_mut!(next).item.take().unwrap();
+        unsafe { drop(Box::from_raw(self.read.get())); }
+        self.read.set(next);
+        self.consumed.store(self.consumed.load(Relaxed).wrapping_add(1), Relaxed);
+        Ok(item)
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        while self.consume().is_ok() { }
+        unsafe { drop(Box::from_raw(self.read.get())); }
+    }
+}
+
+unsafe impl<T> Sync for Queue<T> where T: Send { }
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for an unbounded MPSC queue.
+pub fn channel<T>() -> (Producer<T>, Consumer<T>) {
+    let queue = Queue::new();
+    (Producer(queue.clone()), Consumer(queue))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_order() {
+        let (producer, consumer) = channel();
+        let producers = (0..4).map(|i| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for item in 0..5000 {
+                    producer.produce((i, item)).unwrap();
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(producer);
+
+        // Items from each producer are consumed in the order they were produced.
+        let mut next = [0; 4];
+        loop {
+            match consumer.consume() {
+                Ok((i, item)) => {
+                    assert_eq!(item, next[i]);
+                    next[i] += 1;
+                },
+                Err(ConsumeError::Disconnected) => break,
+                Err(_) => { },
+            }
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(next, [5000; 4]);
+        assert_eq!(consumer.memory_usage().nodes, 1);
+    }
+
+    #[test]
+    fn test_close() {
+        let (producer, consumer) = channel();
+        let (sender, receiver) = mpsc::channel();
+        producer.on_disconnect(move || sender.send(()).unwrap());
+        producer.produce(1).unwrap();
+        producer.produce(2).unwrap();
+        assert_eq!(producer.memory_usage().nodes, 3);
+
+        assert_eq!(consumer.close_and_drain(), &[1, 2]);
+        assert!(producer.is_closed());
+        assert_eq!(receiver.try_recv(), Ok(()));
+        assert_eq!(producer.produce(3), Err(ProduceError::Disconnected(3)));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+        assert_eq!(consumer.producer_count(), 1);
+    }
+
+    #[test]
+    fn test_drop() {
+        let item = Arc::new(());
+        let (producer, consumer) = channel();
+        for _ in 0..100 {
+            producer.produce(item.clone()).unwrap();
+        }
+        drop(consumer.consume());
+        drop(consumer);
+        assert_eq!(producer.produce(item.clone()).map_err(|_| ()), Err(()));
+        drop(producer);
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unbounded multi-producer, single-consumer queue that only preserves per-producer order.
+//!
+//! Each producer adds items to its own unbounded SPSC queue (a lane) and the consumer removes up
+//! to 64 items from a lane before moving on to the next lane. The items added by any one producer
+//! are removed in the order they were added, but the items added by different producers are not
+//! removed in any particular order. Because producers never contend with each other, this queue
+//! scales much better than the MPMC queue with large numbers of producers.
+
+use std::mem::{MaybeUninit};
+use std::cell::{Cell, UnsafeCell};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration};
+
+use {Consume, ConsumeError, Produce, ProduceError};
+use unbounded::spsc;
+
+/// The maximum number of items the consumer removes from a lane before moving to the next lane.
+const SEGMENT: usize = 64;
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for an unbounded relaxed-order MPSC queue.
+#[derive(Debug)]
+pub struct Consumer<T> {
+    queue: Arc<Queue<T>>,
+    lanes: UnsafeCell<Vec<spsc::Consumer<T>>>,
+    lane: Cell<usize>,
+    segment: Cell<usize>,
+}
+
+impl<T> Consumer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        // Determine whether the queue could have been disconnected before adding any new lanes.
+        let disconnected = self.queue.producers.load(Acquire) == 0;
+
+        let lanes = unsafe { &mut *self.lanes.get() };
+        if self.queue.pending.load(Acquire) {
+            let mut pending = self.queue.lanes.lock().unwrap();
+            lanes.extend(pending.drain(..));
+            self.queue.pending.store(false, Release);
+        }
+
+        let mut attempts = 0;
+        while attempts < lanes.len() {
+            let lane = self.lane.get() % lanes.len();
+            match lanes[lane].consume() {
+                Ok(item) => {
+                    // Move to the next lane if the current segment is complete.
+                    let segment = self.segment.get() + 1;
+                    if segment == SEGMENT {
+                        self.lane.set(lane + 1);
+                        self.segment.set(0);
+                    } else {
+                        self.lane.set(lane);
+                        self.segment.set(segment);
+                    }
+                    return Ok(item);
+                },
+                Err(ConsumeError::Disconnected) => {
+                    // Remove the lane since its producer has been disconnected.
+                    lanes.swap_remove(lane);
+                },
+                Err(_) => {
+                    self.lane.set(lane + 1);
+                    attempts += 1;
+                },
+            }
+            self.segment.set(0);
+        }
+
+        if disconnected {
+            Err(ConsumeError::Disconnected)
+        } else {
+            Err(ConsumeError::Empty)
+        }
+    }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        Consume::consume_uninit(self, items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        Consume::consume_latest(self)
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        Consume::discard(self, n)
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        let mut pending = self.queue.lanes.lock().unwrap();
+        self.queue.consumer.store(false, Release);
+        pending.clear();
+    }
+}
+
+// Producer __________________________________
+
+/// A producer for an unbounded relaxed-order MPSC queue.
+#[derive(Debug)]
+pub struct Producer<T> {
+    queue: Arc<Queue<T>>,
+    lane: spsc::Producer<T>,
+}
+
+impl<T> Producer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of this producer's lane.
+    ///
+    /// This method returns `Err` if the queue has no remaining consumers or if a node for the item
+    /// could not be allocated.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.lane.produce(item)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.lane.on_disconnect(callback);
+    }
+}
+
+impl<T> Clone for Producer<T> {
+    fn clone(&self) -> Self {
+        self.queue.producers.fetch_add(1, Release);
+        Producer { queue: self.queue.clone(), lane: self.queue.add() }
+    }
+}
+
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.lane.produce(item)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.queue.producers.fetch_sub(1, Release);
+    }
+}
+
+// Queue _________________________________________
+
+#[derive(Debug)]
+struct Queue<T> {
+    producers: AtomicUsize,
+    consumer: AtomicBool,
+    pending: AtomicBool,
+    lanes: Mutex<Vec<spsc::Consumer<T>>>,
+}
+
+impl<T> Queue<T> {
+    //- Constructors -----------------------------
+
+    fn new() -> Arc<Self> {
+        Arc::new(Queue {
+            producers: AtomicUsize::new(1),
+            consumer: AtomicBool::new(true),
+            pending: AtomicBool::new(false),
+            lanes: Mutex::new(vec![]),
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn add(&self) -> spsc::Producer<T> {
+        let (producer, consumer) = spsc::channel();
+        let mut pending = self.lanes.lock().unwrap();
+        if self.consumer.load(Acquire) {
+            pending.push(consumer);
+            self.pending.store(true, Release);
+        }
+        producer
+    }
+}
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for an unbounded relaxed-order MPSC queue.
+pub fn channel<T>() -> (Producer<T>, Consumer<T>) {
+    let queue = Queue::new();
+    let lane = queue.add();
+    let consumer = Consumer {
+        queue: queue.clone(),
+        lanes: UnsafeCell::new(vec![]),
+        lane: Cell::new(0),
+        segment: Cell::new(0),
+    };
+    (Producer { queue, lane }, consumer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_segments() {
+        let (a, consumer) = channel();
+        let b = a.clone();
+        for item in 0..100 {
+            a.produce(('a', item)).unwrap();
+            b.produce(('b', item)).unwrap();
+        }
+
+        // The consumer removes a segment of items from a lane before moving to the next lane.
+        let items = (0..200).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+        assert!(items[..SEGMENT].iter().all(|&(lane, _)| lane == 'a'));
+        assert!(items[SEGMENT..SEGMENT * 2].iter().all(|&(lane, _)| lane == 'b'));
+        for &lane in &['a', 'b'] {
+            let lane = items.iter().filter(|i| i.0 == lane).map(|i| i.1).collect::<Vec<_>>();
+            assert_eq!(lane, (0..100).collect::<Vec<_>>());
+        }
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (producer, consumer) = channel();
+        let clone = producer.clone();
+        clone.produce(1).unwrap();
+        drop(clone);
+        assert_eq!(consumer.consume(), Ok(1));
+        assert_eq!(consumer.consume(), Err(ConsumeError::Empty));
+
+        drop(producer);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+
+        let (producer, consumer) = channel();
+        drop(consumer);
+        assert_eq!(producer.produce(1), Err(ProduceError::Disconnected(1)));
+        assert_eq!(producer.clone().produce(2), Err(ProduceError::Disconnected(2)));
+    }
+
+    #[test]
+    fn test_threads() {
+        let (producer, consumer) = channel();
+        let producers = (0..4).map(|i| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for item in 0..5000 {
+                    producer.produce((i, item)).unwrap();
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(producer);
+
+        let mut next = [0; 4];
+        loop {
+            match consumer.consume() {
+                Ok((i, item)) => {
+                    assert_eq!(item, next[i]);
+                    next[i] += 1;
+                },
+                Err(ConsumeError::Disconnected) => break,
+                Err(_) => { },
+            }
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(next, [5000; 4]);
+    }
+}
@@ -0,0 +1,468 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unbounded single-producer, multi-consumer lock-free queue.
+//!
+//! The consumers remove nodes from the front of the queue with a compare-and-swap and use hazard
+//! pointers to free them like the Michael-Scott algorithm of the unbounded MPMC queue. Since there
+//! is only one producer and the consumers never remove the last node, the producer links new nodes
+//! to the back of the queue without a compare-and-swap or hazard pointers, so adding an item is
+//! wait-free (apart from allocating its node).
+
+use std::cell::{Cell};
+use std::marker::{PhantomData};
+use std::mem::{self, MaybeUninit};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration};
+
+use {Consume, ConsumeError, MemoryUsage, Produce, ProduceError, padding};
+use buffer;
+use notify::{Notifier};
+use reclaim::{Domain, Hazard, Reclaim};
+use super::{Node};
+
+const READ: usize = 0;
+const NEXT: usize = 1;
+
+//================================================
+// Structs
+//================================================
+
+// Consumer ______________________________________
+
+/// A consumer for an unbounded SPMC lock-free queue.
+///
+/// A consumer can be sent to another thread but can't be shared between threads, since it holds
+/// the thread index it reserved in the reclamation domain of the queue.
+///
+/// ```compile_fail,E0277
+/// extern crate npnc;
+///
+/// use std::sync::{Arc};
+/// use std::thread;
+///
+/// use npnc::unbounded::spmc;
+///
+/// fn main() {
+///     let (producer, consumer) = spmc::channel(0);
+///     producer.produce(1).unwrap();
+///     let consumer = Arc::new(consumer);
+///     thread::spawn(move || consumer.consume());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Consumer<T>(usize, Arc<Queue<T>>, PhantomData<Cell<()>>);
+
+impl<T> Consumer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return the item at the front of the queue.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume(&self) -> Result<T, ConsumeError> {
+        self.1.consume(self.0)
+    }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        Consume::consume_uninit(self, items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        Consume::consume_latest(self)
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        Consume::discard(self, n)
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.1.memory_usage()
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.1.consumers.load(Acquire)
+    }
+
+    /// Frees the nodes retired by this consumer that are no longer in use and returns the unused
+    /// capacity of its list of retired nodes to the allocator.
+    ///
+    /// Retired nodes are otherwise only freed once enough of them have accumulated, so this method
+    /// can be called during idle periods to release memory held after a burst of activity.
+    pub fn shrink_to_fit(&self) {
+        self.1.domain.reclaim().shrink(self.0);
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producer will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty, no matter how many
+    /// consumers are still connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.1.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.1.closed.load(Acquire)
+    }
+
+    /// Disconnects the producer and returns the items remaining in the queue.
+    ///
+    /// Once this method has been called, the producer will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty. An item the producer
+    /// was in the middle of adding when this method was called may be left in the queue.
+    pub fn close_and_drain(&self) -> Vec<T> {
+        self.1.close();
+        let mut items = vec![];
+        while let Ok(item) = self.1.consume(self.0) {
+            items.push(item);
+        }
+        items
+    }
+}
+
+impl<T> Clone for Consumer<T> {
+    fn clone(&self) -> Self {
+        let thread = self.1.domain.attach();
+        self.1.consumers.fetch_add(1, Release);
+        Consumer(thread, self.1.clone(), PhantomData)
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.1.consume(self.0)
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.1.domain.detach(self.0);
+        if self.1.consumers.fetch_sub(1, Release) == 1 {
+            self.1.disconnect.notify();
+        }
+    }
+}
+
+unsafe impl<T> Send for Consumer<T> where T: Send { }
+
+// Producer ______________________________________
+
+/// A producer for an unbounded SPMC lock-free queue.
+///
+/// A producer can be sent to another thread but can't be shared between threads, since it links
+/// new nodes to the back of the queue without synchronizing with other calls to `produce`.
+///
+/// ```compile_fail,E0277
+/// extern crate npnc;
+///
+/// use std::sync::{Arc};
+/// use std::thread;
+///
+/// use npnc::unbounded::spmc;
+///
+/// fn main() {
+///     let (producer, consumer) = spmc::channel(0);
+///     let producer = Arc::new(producer);
+///     thread::spawn(move || producer.produce(1));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Producer<T>(Arc<Queue<T>>, PhantomData<Cell<()>>);
+
+impl<T> Producer<T> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the back of the queue.
+    ///
+    /// This method returns `Err` if the queue has no remaining consumers or if a node for the item
+    /// could not be allocated.
+    pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Returns the number of consumers currently connected to the queue.
+    pub fn consumer_count(&self) -> usize {
+        self.0.consumers.load(Acquire)
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producer will receive `Disconnected` errors and the
+    /// consumers will receive `Disconnected` errors once the queue is empty, no matter how many
+    /// consumers are still connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Registers a callback that is called once the queue has no remaining consumers or has been
+    /// closed.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
+}
+
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.producer.store(0, Release);
+    }
+}
+
+unsafe impl<T> Send for Producer<T> where T: Send { }
+
+// Queue _________________________________________
+
+#[derive(Debug)]
+#[repr(C)]
+struct Queue<T> {
+    write: AtomicPtr<Node<T>>,
+    consumers: AtomicUsize,
+    closed: AtomicBool,
+    produced: AtomicUsize,
+    _wpadding: [usize; padding(4)],
+    read: AtomicPtr<Node<T>>,
+    producer: AtomicUsize,
+    consumed: AtomicUsize,
+    _rpadding: [usize; padding(3)],
+    domain: Domain<Hazard>,
+    disconnect: Notifier,
+}
+
+impl<T> Queue<T> {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Queue` that reserves space for the supplied number of consumers.
+    fn new(consumers: usize) -> Arc<Self> {
+        let sentinel = Node::sentinel();
+        Arc::new(Queue {
+            write: AtomicPtr::new(sentinel),
+            consumers: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
+            produced: AtomicUsize::new(0),
+            _wpadding: [0; padding(4)],
+            read: AtomicPtr::new(sentinel),
+            producer: AtomicUsize::new(1),
+            consumed: AtomicUsize::new(0),
+            _rpadding: [0; padding(3)],
+            domain: Domain::with_hazards(consumers, 2),
+            disconnect: Notifier::new(),
+        })
+    }
+
+    //- Accessors --------------------------------
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let consumed = self.consumed.load(Relaxed);
+        let nodes = self.produced.load(Relaxed).saturating_sub(consumed) + 1;
+        let retired = self.domain.reclaim().retired();
+        let bytes = (nodes + retired) * mem::size_of::<Node<T>>();
+        MemoryUsage { bytes, nodes, retired }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Release);
+        self.disconnect.notify();
+    }
+
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        // Return an error if all of the consumers have been disconnected or the queue has been
+        // closed.
+        if self.consumers.load(Acquire) == 0 || self.closed.load(Acquire) {
+            return Err(ProduceError::Disconnected(item));
+        }
+
+        // Add the item to the back of the queue. The consumers never remove the last node, so the
+        // last node can't be freed while it is being linked to the new node.
+        let node = match buffer::try_box(Node::new(Some(item))) {
+            Ok(node) => node,
+            Err(node) => return Err(ProduceError::AllocFailed(node.item.unwrap())),
+        };
+        self.produced.store(self.produced.load(Relaxed).wrapping_add(1), Relaxed);
+        let write = self.write.load(Relaxed);
+        deref!(write).next.store(node, Release);
+        self.write.store(node, Release);
+        Ok(())
+    }
+
+    fn consume(&self, thread: usize) -> Result<T, ConsumeError> {
+        // The producer is checked first so that an item added by the producer before it was
+        // disconnected is never missed.
+        let disconnected = self.producer.load(Acquire) == 0 || self.closed.load(Acquire);
+
+        let reclaim = self.domain.reclaim();
+        loop {
+            // Return an error if the queue is empty.
+            let read = reclaim.mark(thread, READ, &self.read);
+            if read == self.write.load(Acquire) {
+                reclaim.clear(thread, READ);
+                if disconnected {
+                    return Err(ConsumeError::Disconnected);
+                } else {
+                    return Err(ConsumeError::Empty);
+                }
+            }
+
+            // Remove and return the item at the front of the queue if this node is available.
+            let next = reclaim.mark(thread, NEXT, &deref!(read).next);
+            if self.read.compare_exchange(read, next, AcqRel, Acquire).is_ok() {
+                let item = deref_mut!(next).item.take().unwrap();
+                reclaim.clear(thread, READ);
+                reclaim.clear(thread, NEXT);
+                unsafe { reclaim.retire(thread, read); }
+                self.consumed.fetch_add(1, Relaxed);
+                return Ok(item);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        // No other threads can be operating on this queue so the nodes can be freed directly.
+        let mut node = self.read.load(Relaxed);
+        while !node.is_null() {
+            let next = deref!(node).next.load(Relaxed);
+            unsafe { drop(Box::from_raw(node)); }
+            node = next;
+        }
+    }
+}
+
+unsafe impl<T> Sync for Queue<T> where T: Send { }
+
+//================================================
+// Functions
+//================================================
+
+/// Returns a producer and consumer for an unbounded SPMC lock-free queue.
+///
+/// The value of `clones` indicates the number of clones of the initial consumer the queue reserves
+/// space for. More clones can be made at the cost of allocating space for them.
+pub fn channel<T>(clones: usize) -> (Producer<T>, Consumer<T>) {
+    let queue = Queue::new(clones + 1);
+    let thread = queue.domain.attach();
+    (Producer(queue.clone(), PhantomData), Consumer(thread, queue, PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_threads() {
+        let (producer, consumer) = channel(4);
+        let consumers = (0..4).map(|_| {
+            let consumer = consumer.clone();
+            thread::spawn(move || {
+                let mut items = vec![];
+                loop {
+                    match consumer.consume() {
+                        // Items are consumed in the order they were produced.
+                        Ok(item) => {
+                            assert!(items.last().is_none_or(|&l| l < item));
+                            items.push(item);
+                        },
+                        Err(ConsumeError::Disconnected) => return items,
+                        Err(_) => { },
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+        drop(consumer);
+
+        for item in 0..20000 {
+            producer.produce(item).unwrap();
+        }
+        drop(producer);
+        let mut items = consumers.into_iter().flat_map(|c| c.join().unwrap()).collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, (0..20000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_close() {
+        let (producer, consumer) = channel(1);
+        let clone = consumer.clone();
+        assert_eq!(producer.consumer_count(), 2);
+        for item in 0..3 {
+            producer.produce(item).unwrap();
+        }
+        assert_eq!(producer.memory_usage().nodes, 4);
+        assert_eq!(clone.consume(), Ok(0));
+
+        assert_eq!(consumer.close_and_drain(), &[1, 2]);
+        assert!(producer.is_closed());
+        assert_eq!(producer.produce(3), Err(ProduceError::Disconnected(3)));
+        assert_eq!(clone.consume(), Err(ConsumeError::Disconnected));
+
+        assert!(consumer.memory_usage().retired > 0);
+        drop(clone);
+        consumer.shrink_to_fit();
+    }
+
+    #[test]
+    fn test_drop() {
+        let item = Arc::new(());
+        let (producer, consumer) = channel(0);
+        for _ in 0..100 {
+            producer.produce(item.clone()).unwrap();
+        }
+        drop(consumer.consume());
+        drop((producer, consumer));
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+}
@@ -14,15 +14,17 @@
 
 //! Unbounded single-producer, single-consumer wait-free queue.
 
-use std::ptr;
+use std::mem::{self, MaybeUninit};
 use std::cell::{Cell};
 use std::sync::{Arc};
-use std::sync::atomic::{AtomicPtr, AtomicUsize};
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::atomic::Ordering::*;
+use std::time::{Duration};
 
-use hazard::{BoxMemory, Memory};
-
-use {ConsumeError, ProduceError, POINTERS};
+use {Consume, ConsumeError, MemoryUsage, Produce, ProduceError, padding};
+use buffer;
+use notify::{Notifier};
+use super::{mpmc, Node};
 
 //================================================
 // Structs
@@ -43,11 +45,81 @@ impl<T> Consumer<T> {
     pub fn consume(&self) -> Result<T, ConsumeError> {
         self.0.consume()
     }
+
+    /// Moves items from the front of the queue into the supplied slice until the slice is full or
+    /// the queue is empty and returns the number of items moved.
+    ///
+    /// The first `n` elements of the slice are initialized when this method returns `n`.
+    pub fn consume_uninit(&self, items: &mut [MaybeUninit<T>]) -> usize {
+        Consume::consume_uninit(self, items)
+    }
+
+    /// Removes and returns the item at the front of the queue, waiting up to the supplied duration
+    /// while the queue is empty.
+    ///
+    /// This method returns `Timeout` if the queue is still empty once the duration has elapsed.
+    pub fn consume_timeout(&self, timeout: Duration) -> Result<T, ConsumeError> {
+        Consume::consume_timeout(self, timeout)
+    }
+
+    /// Removes the items in the queue and returns the newest item and the number of older items
+    /// that were discarded.
+    ///
+    /// This method returns `Err` if the queue is empty.
+    pub fn consume_latest(&self) -> Result<(T, usize), ConsumeError> {
+        Consume::consume_latest(self)
+    }
+
+    /// Removes and drops up to the supplied number of items from the front of the queue and
+    /// returns the number of items dropped.
+    pub fn discard(&self, n: usize) -> usize {
+        Consume::discard(self, n)
+    }
+
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producer will receive `Disconnected` errors and the
+    /// consumer will receive a `Disconnected` error once the queue is empty, even if both are still
+    /// connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
+
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
+
+    /// Disconnects the producer and returns the items remaining in the queue.
+    ///
+    /// Once this method has been called, the producer will receive `Disconnected` errors and this
+    /// consumer will receive a `Disconnected` error once the queue is empty. An item the producer
+    /// was in the middle of adding when this method was called may be left in the queue.
+    pub fn close_and_drain(&self) -> Vec<T> {
+        self.0.close();
+        let mut items = vec![];
+        while let Ok(item) = self.0.consume() {
+            items.push(item);
+        }
+        items
+    }
+}
+
+impl<T> Consume<T> for Consumer<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        self.0.consume()
+    }
 }
 
 impl<T> Drop for Consumer<T> {
     fn drop(&mut self) {
         self.0.consumer.store(0, Release);
+        self.0.disconnect.notify();
     }
 }
 
@@ -64,36 +136,55 @@ impl<T> Producer<T> {
 
     /// Attempts to add the supplied item to the back of the queue.
     ///
-    /// This method returns `Err` if the queue is full or has no remaining consumers.
+    /// This method returns `Err` if the queue has no remaining consumers or if a node for the item
+    /// could not be allocated.
     pub fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
         self.0.produce(item)
     }
-}
 
-impl<T> Drop for Producer<T> {
-    fn drop(&mut self) {
-        self.0.producer.store(0, Release);
+    /// Returns the memory currently used by the queue.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
     }
-}
 
-unsafe impl<T> Send for Producer<T> where T: Send { }
+    /// Closes the queue.
+    ///
+    /// Once the queue has been closed, the producer will receive `Disconnected` errors and the
+    /// consumer will receive a `Disconnected` error once the queue is empty, even if both are still
+    /// connected. The items already in the queue can still be removed.
+    pub fn close(&self) {
+        self.0.close();
+    }
 
-// Node __________________________________________
+    /// Returns whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.0.closed.load(Acquire)
+    }
 
-#[derive(Debug)]
-struct Node<T> {
-    item: Option<T>,
-    next: AtomicPtr<Node<T>>,
+    /// Registers a callback that is called once the queue has no remaining consumers or has been
+    /// closed.
+    ///
+    /// The callback is called on the thread that disconnects the queue or immediately if the queue
+    /// has already been disconnected.
+    pub fn on_disconnect<F>(&self, callback: F) where F: FnOnce() + Send + 'static {
+        self.0.disconnect.register(Box::new(callback));
+    }
 }
 
-impl<T> Node<T> {
-    //- Constructors -----------------------------
+impl<T> Produce<T> for Producer<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        self.0.produce(item)
+    }
+}
 
-    fn new(item: Option<T>) -> Self {
-        Node { item: item, next: AtomicPtr::new(ptr::null_mut()) }
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.producer.store(0, Release);
     }
 }
 
+unsafe impl<T> Send for Producer<T> where T: Send { }
+
 // Queue _________________________________________
 
 #[derive(Debug)]
@@ -101,37 +192,69 @@ impl<T> Node<T> {
 struct Queue<T> {
     write: Cell<*mut Node<T>>,
     consumer: AtomicUsize,
-    _wpadding: [usize; POINTERS - 2],
+    closed: AtomicBool,
+    produced: AtomicUsize,
+    _wpadding: [usize; padding(4)],
     read: Cell<*mut Node<T>>,
     producer: AtomicUsize,
-    _rpadding: [usize; POINTERS - 2],
+    consumed: AtomicUsize,
+    _rpadding: [usize; padding(3)],
+    disconnect: Notifier,
 }
 
 impl<T> Queue<T> {
     //- Constructors -----------------------------
 
-    fn new() -> Arc<Self> {
-        let sentinel = BoxMemory.allocate(Node::new(None));
+    fn new(read: *mut Node<T>, write: *mut Node<T>, len: usize, closed: bool) -> Arc<Self> {
         Arc::new(Queue {
-            write: Cell::new(sentinel),
+            write: Cell::new(write),
             consumer: AtomicUsize::new(1),
-            _wpadding: [0; POINTERS - 2],
-            read: Cell::new(sentinel),
+            closed: AtomicBool::new(closed),
+            produced: AtomicUsize::new(len),
+            _wpadding: [0; padding(4)],
+            read: Cell::new(read),
             producer: AtomicUsize::new(1),
-            _rpadding: [0; POINTERS - 2],
+            consumed: AtomicUsize::new(0),
+            _rpadding: [0; padding(3)],
+            disconnect: Notifier::new(),
         })
     }
 
     //- Accessors --------------------------------
 
+    /// Removes the nodes from this queue and returns the read and write nodes and the number of
+    /// items in the removed nodes.
+    ///
+    /// This queue is left empty with a new sentinel node.
+    fn take(&self) -> (*mut Node<T>, *mut Node<T>, usize) {
+        let len = self.produced.swap(0, Relaxed).wrapping_sub(self.consumed.swap(0, Relaxed));
+        let sentinel = Node::sentinel();
+        (self.read.replace(sentinel), self.write.replace(sentinel), len)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let consumed = self.consumed.load(Relaxed);
+        let nodes = self.produced.load(Relaxed).saturating_sub(consumed) + 1;
+        MemoryUsage { bytes: nodes * mem::size_of::<Node<T>>(), nodes, retired: 0 }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Release);
+        self.disconnect.notify();
+    }
+
     fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
-        // Return an error if the consumer has been disconnected.
-        if self.consumer.load(Acquire) == 0 {
+        // Return an error if the consumer has been disconnected or the queue has been closed.
+        if self.consumer.load(Acquire) == 0 || self.closed.load(Acquire) {
             return Err(ProduceError::Disconnected(item));
         }
 
         // Add the item to the back of the queue.
-        let node = BoxMemory.allocate(Node::new(Some(item)));
+        let node = match buffer::try_box(Node::new(Some(item))) {
+            Ok(node) => node,
+            Err(node) => return Err(ProduceError::AllocFailed(node.item.unwrap())),
+        };
+        self.produced.store(self.produced.load(Relaxed).wrapping_add(1), Relaxed);
         deref!(self.write.get()).next.store(node, Release);
         self.write.set(node);
         Ok(())
@@ -139,19 +262,26 @@ impl<T> Queue<T> {
 
     fn consume(&self) -> Result<T, ConsumeError> {
         // Return an error if the queue is empty.
-        let next = deref!(self.read.get()).next.load(Acquire);
+        let mut next = deref!(self.read.get()).next.load(Acquire);
         if next.is_null() {
-            if self.producer.load(Acquire) == 0 {
-                return Err(ConsumeError::Disconnected);
-            } else {
-                return Err(ConsumeError::Empty);
+            // The producer is checked first so that an item added by the producer before it was
+            // disconnected is never missed.
+            let disconnected = self.producer.load(Acquire) == 0 || self.closed.load(Acquire);
+            next = deref!(self.read.get()).next.load(Acquire);
+            if next.is_null() {
+                if disconnected {
+                    return Err(ConsumeError::Disconnected);
+                } else {
+                    return Err(ConsumeError::Empty);
+                }
             }
         }
 
         // Remove and return the item at the front of the queue.
         let item = deref_mut!(next).item.take().unwrap();
-        unsafe { BoxMemory.deallocate(self.read.get()); }
+        unsafe { drop(Box::from_raw(self.read.get())); }
         self.read.set(next);
+        self.consumed.store(self.consumed.load(Relaxed).wrapping_add(1), Relaxed);
         Ok(item)
     }
 }
@@ -159,7 +289,7 @@ impl<T> Queue<T> {
 impl<T> Drop for Queue<T> {
     fn drop(&mut self) {
         while self.consume().is_ok() { }
-        unsafe { BoxMemory.deallocate(self.write.get()); }
+        unsafe { drop(Box::from_raw(self.write.get())); }
     }
 }
 
@@ -171,6 +301,62 @@ unsafe impl<T> Sync for Queue<T> where T: Send { }
 
 /// Returns a producer and consumer for an unbounded SPSC wait-free queue.
 pub fn channel<T>() -> (Producer<T>, Consumer<T>) {
-    let queue = Queue::new();
+    let sentinel = Node::sentinel();
+    from_nodes(sentinel, sentinel, 0, false, Notifier::new())
+}
+
+/// Returns a producer and consumer for an unbounded SPSC wait-free queue containing the supplied
+/// nodes.
+pub(super) fn from_nodes<T>(
+    read: *mut Node<T>, write: *mut Node<T>, len: usize, closed: bool, disconnect: Notifier
+) -> (Producer<T>, Consumer<T>) {
+    let queue = Queue::new(read, write, len, closed);
+    queue.disconnect.extend(disconnect);
     (Producer(queue.clone()), Consumer(queue))
 }
+
+/// Converts the supplied producer and consumer for an unbounded SPSC queue into a producer and
+/// consumer for an unbounded MPMC queue.
+///
+/// The items in the SPSC queue are moved into the MPMC queue without being copied. The value of
+/// `clones` indicates the number of clones of the returned producer and consumer the MPMC queue
+/// reserves space for.
+///
+/// # Panics
+///
+/// * `producer` and `consumer` do not belong to the same queue
+pub fn upgrade<T>(
+    producer: Producer<T>, consumer: Consumer<T>, clones: usize
+) -> (mpmc::Producer<T>, mpmc::Consumer<T>) {
+    assert!(Arc::ptr_eq(&producer.0, &consumer.0), "`producer` and `consumer` are unrelated");
+    let (read, write, len) = consumer.0.take();
+    let (closed, disconnect) = (consumer.0.closed.load(Acquire), consumer.0.disconnect.take());
+    mpmc::from_nodes(clones, read, write, len, closed, disconnect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade_downgrade() {
+        let (producer, consumer) = channel();
+        for index in 0..4 {
+            producer.produce(index).unwrap();
+        }
+        assert_eq!(consumer.consume(), Ok(0));
+
+        let (producer, consumer) = upgrade(producer, consumer, 1);
+        producer.produce(4).unwrap();
+
+        let clone = consumer.clone();
+        let (producer, consumer) = mpmc::downgrade(producer, consumer).unwrap_err();
+        drop(clone);
+        let (producer, consumer) = mpmc::downgrade(producer, consumer).unwrap();
+        producer.produce(5).unwrap();
+        drop(producer);
+        let items = (0..5).map(|_| consumer.consume().unwrap()).collect::<Vec<_>>();
+        assert_eq!(items, [1, 2, 3, 4, 5]);
+        assert_eq!(consumer.consume(), Err(ConsumeError::Disconnected));
+    }
+}
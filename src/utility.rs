@@ -12,6 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
+use std::hint;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The number of times a `Backoff` spins before it starts yielding.
+const SPINS: u32 = 6;
+/// The number of times a `Backoff` yields before it starts sleeping.
+const YIELDS: u32 = 10;
+/// The longest a `Backoff` sleeps for.
+const SLEEP: Duration = Duration::from_millis(1);
+
 //================================================
 // Macros
 //================================================
@@ -25,3 +37,49 @@ macro_rules! deref { ($pointer:expr) => (unsafe { &*$pointer }); }
 
 /// Dereferences the supplied pointer.
 macro_rules! deref_mut { ($pointer:expr) => (unsafe { &mut *$pointer }); }
+
+//================================================
+// Structs
+//================================================
+
+// Backoff _______________________________________
+
+/// Backs off between attempts to perform an operation that failed because of another thread.
+///
+/// A backoff starts by spinning for exponentially longer, then yields the thread, and finally
+/// sleeps for exponentially longer (up to `SLEEP`) so that a long wait doesn't waste a core.
+#[doc(hidden)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Backoff`.
+    pub fn new() -> Self {
+        Backoff { step: 0 }
+    }
+
+    //- Accessors --------------------------------
+
+    /// Backs off, sleeping no later than the supplied deadline, if any.
+    pub fn snooze(&mut self, deadline: Option<Instant>) {
+        if self.step < SPINS {
+            for _ in 0..(1 << self.step) {
+                hint::spin_loop();
+            }
+        } else if self.step < SPINS + YIELDS {
+            thread::yield_now();
+        } else {
+            let exponent = cmp::min(self.step - SPINS - YIELDS, 10);
+            let mut sleep = cmp::min(Duration::from_micros(1 << exponent), SLEEP);
+            if let Some(deadline) = deadline {
+                sleep = cmp::min(sleep, deadline.saturating_duration_since(Instant::now()));
+            }
+            thread::sleep(sleep);
+        }
+        self.step = cmp::min(self.step + 1, SPINS + YIELDS + 10);
+    }
+}
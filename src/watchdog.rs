@@ -0,0 +1,413 @@
+// Copyright 2017 Kyle Mayes
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detection of stalled producers and consumers.
+//!
+//! The producers and consumers returned by `Watchdog::wrap` keep track of the number of items in
+//! their queue and of how long their producers have been unable to add items. Calling
+//! `Watchdog::check` reports the queues whose depth has stayed above the threshold of the
+//! watchdog for longer than its deadline (i.e., the consumers have stalled) and the queues whose
+//! producers have failed to add items for longer than the deadline without succeeding (i.e., the
+//! producers have stalled). Stalls are returned by `check` and passed to the callback registered
+//! with `Watchdog::on_stall`, which can be used for alerting. Nothing is checked in the
+//! background, so `check` should be called periodically.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate npnc;
+//!
+//! use std::thread;
+//! use std::time::{Duration};
+//!
+//! use npnc::bounded::spsc;
+//! use npnc::watchdog::{Stall, Watchdog};
+//!
+//! fn main() {
+//!     let watchdog = Watchdog::new(1, Duration::from_millis(10));
+//!     let (producer, consumer) = spsc::channel(2);
+//!     let (producer, _consumer) = watchdog.wrap(producer, consumer);
+//!
+//!     producer.produce(1).unwrap();
+//!     producer.produce(2).unwrap();
+//!     assert!(producer.produce(3).is_err());
+//!     assert!(watchdog.check().is_empty());
+//!
+//!     thread::sleep(Duration::from_millis(20));
+//!     let stalls = watchdog.check();
+//!     assert_eq!(stalls.len(), 2);
+//!     assert!(stalls.iter().any(|s| s.kind == Stall::Consumer(2)));
+//!     assert!(stalls.iter().any(|s| s.kind == Stall::Producer));
+//! }
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::atomic::Ordering::*;
+use std::time::{Duration, Instant};
+
+use {Consume, ConsumeError, Produce, ProduceError};
+
+/// A function called with each stall detected by `check`.
+type Callback = Box<dyn Fn(&Report) + Send>;
+
+//================================================
+// Enums
+//================================================
+
+// Stall _________________________________________
+
+/// A way a queue can stall.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stall {
+    /// The queue contained more items than the threshold (the current number of items).
+    Consumer(usize),
+    /// The producers failed to add items to the queue.
+    Producer,
+}
+
+//================================================
+// Structs
+//================================================
+
+// Channel _______________________________________
+
+/// The state of a queue watched by a watchdog.
+///
+/// Times are stored as the number of nanoseconds since the watchdog was constructed plus one so
+/// that `0` can indicate that a condition does not hold.
+#[derive(Debug)]
+struct Channel {
+    index: usize,
+    depth: AtomicUsize,
+    above: AtomicU64,
+    blocked: AtomicU64,
+    shared: Arc<Shared>,
+}
+
+impl Channel {
+    //- Accessors --------------------------------
+
+    fn produced(&self) {
+        self.blocked.store(0, Relaxed);
+        if self.depth.fetch_add(1, Relaxed) == self.shared.threshold {
+            let _ = self.above.compare_exchange(0, self.shared.now(), Relaxed, Relaxed);
+        }
+    }
+
+    fn rejected(&self) {
+        let _ = self.blocked.compare_exchange(0, self.shared.now(), Relaxed, Relaxed);
+    }
+
+    fn consumed(&self) {
+        if self.depth.fetch_sub(1, Relaxed) == self.shared.threshold + 1 {
+            self.above.store(0, Relaxed);
+        }
+    }
+}
+
+// Consumer ______________________________________
+
+/// A consumer whose queue is watched by a watchdog.
+#[derive(Debug)]
+pub struct Consumer<C> {
+    consumer: C,
+    channel: Arc<Channel>,
+}
+
+impl<C> Consumer<C> {
+    //- Accessors --------------------------------
+
+    /// Attempts to remove and return an item from the queue.
+    pub fn consume<T>(&self) -> Result<T, ConsumeError> where C: Consume<T> {
+        let item = self.consumer.consume()?;
+        self.channel.consumed();
+        Ok(item)
+    }
+
+    /// Returns the index of the queue among the queues watched by the watchdog.
+    pub fn index(&self) -> usize {
+        self.channel.index
+    }
+
+    /// Returns a reference to the wrapped consumer.
+    pub fn get_ref(&self) -> &C {
+        &self.consumer
+    }
+}
+
+impl<C> Clone for Consumer<C> where C: Clone {
+    fn clone(&self) -> Self {
+        Consumer { consumer: self.consumer.clone(), channel: self.channel.clone() }
+    }
+}
+
+impl<T, C> Consume<T> for Consumer<C> where C: Consume<T> {
+    fn consume(&self) -> Result<T, ConsumeError> {
+        Consumer::consume(self)
+    }
+}
+
+// Producer ______________________________________
+
+/// A producer whose queue is watched by a watchdog.
+#[derive(Debug)]
+pub struct Producer<P> {
+    producer: P,
+    channel: Arc<Channel>,
+}
+
+impl<P> Producer<P> {
+    //- Accessors --------------------------------
+
+    /// Attempts to add the supplied item to the queue.
+    pub fn produce<T>(&self, item: T) -> Result<(), ProduceError<T>> where P: Produce<T> {
+        let result = self.producer.produce(item);
+        match result {
+            Ok(()) => self.channel.produced(),
+            Err(ProduceError::Disconnected(_)) => { },
+            Err(_) => self.channel.rejected(),
+        }
+        result
+    }
+
+    /// Returns the index of the queue among the queues watched by the watchdog.
+    pub fn index(&self) -> usize {
+        self.channel.index
+    }
+
+    /// Returns a reference to the wrapped producer.
+    pub fn get_ref(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<P> Clone for Producer<P> where P: Clone {
+    fn clone(&self) -> Self {
+        Producer { producer: self.producer.clone(), channel: self.channel.clone() }
+    }
+}
+
+impl<T, P> Produce<T> for Producer<P> where P: Produce<T> {
+    fn produce(&self, item: T) -> Result<(), ProduceError<T>> {
+        Producer::produce(self, item)
+    }
+}
+
+// Report ________________________________________
+
+/// A stall detected by a watchdog.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    /// The index of the queue among the queues watched by the watchdog.
+    pub index: usize,
+    /// The way the queue stalled.
+    pub kind: Stall,
+    /// How long the queue has been stalled.
+    pub duration: Duration,
+}
+
+// Shared ________________________________________
+
+struct Shared {
+    start: Instant,
+    threshold: usize,
+    deadline: Duration,
+    /// The watched queues and the number of queues that have been watched.
+    channels: Mutex<(Vec<Weak<Channel>>, usize)>,
+    callback: Mutex<Option<Callback>>,
+}
+
+impl Shared {
+    //- Accessors --------------------------------
+
+    /// Returns the current time.
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64 + 1
+    }
+
+    /// Returns how long ago the supplied time was.
+    fn since(&self, time: u64) -> Duration {
+        Duration::from_nanos(self.now().saturating_sub(time))
+    }
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "Shared {{ threshold: {:?}, deadline: {:?} }}",
+            self.threshold,
+            self.deadline,
+        )
+    }
+}
+
+// Watchdog ______________________________________
+
+/// Detects stalled producers and consumers in a group of queues.
+#[derive(Clone, Debug)]
+pub struct Watchdog(Arc<Shared>);
+
+impl Watchdog {
+    //- Constructors -----------------------------
+
+    /// Constructs a new `Watchdog` that reports queues that contain more than `threshold` items
+    /// or whose producers fail to add items for longer than `deadline`.
+    pub fn new(threshold: usize, deadline: Duration) -> Self {
+        Watchdog(Arc::new(Shared {
+            start: Instant::now(),
+            threshold,
+            deadline,
+            channels: Mutex::new((vec![], 0)),
+            callback: Mutex::new(None),
+        }))
+    }
+
+    //- Accessors --------------------------------
+
+    /// Wraps the supplied producer and consumer so their queue is watched by this watchdog.
+    ///
+    /// The supplied producer and consumer should belong to the same queue and should not be used
+    /// to add or remove items once wrapped. Queues are indexed in the order they are wrapped and
+    /// are no longer watched once their wrapped producers and consumers have all been dropped.
+    pub fn wrap<P, C>(&self, producer: P, consumer: C) -> (Producer<P>, Consumer<C>) {
+        let mut channels = self.0.channels.lock().unwrap();
+        channels.1 += 1;
+        let channel = Arc::new(Channel {
+            index: channels.1 - 1,
+            depth: AtomicUsize::new(0),
+            above: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            shared: self.0.clone(),
+        });
+        channels.0.push(Arc::downgrade(&channel));
+        let producer = Producer { producer, channel: channel.clone() };
+        (producer, Consumer { consumer, channel })
+    }
+
+    /// Sets the callback that is called with every stall detected by `check`.
+    pub fn on_stall<F>(&self, callback: F) where F: Fn(&Report) + Send + 'static {
+        *self.0.callback.lock().unwrap() = Some(Box::new(callback));
+    }
+
+    /// Returns the stalls in the watched queues that have lasted longer than the deadline.
+    ///
+    /// The callback set with `on_stall` is called with each of the returned stalls.
+    pub fn check(&self) -> Vec<Report> {
+        let mut channels = self.0.channels.lock().unwrap();
+        channels.0.retain(|c| c.upgrade().is_some());
+
+        let mut reports = vec![];
+        for channel in channels.0.iter().filter_map(|c| c.upgrade()) {
+            let index = channel.index;
+
+            let above = channel.above.load(Relaxed);
+            if above != 0 && self.0.since(above) >= self.0.deadline {
+                let kind = Stall::Consumer(channel.depth.load(Relaxed));
+                reports.push(Report { index, kind, duration: self.0.since(above) });
+            }
+
+            let blocked = channel.blocked.load(Relaxed);
+            if blocked != 0 && self.0.since(blocked) >= self.0.deadline {
+                let duration = self.0.since(blocked);
+                reports.push(Report { index, kind: Stall::Producer, duration });
+            }
+        }
+        drop(channels);
+
+        if let Some(ref callback) = *self.0.callback.lock().unwrap() {
+            for report in &reports {
+                callback(report);
+            }
+        }
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bounded::{spsc};
+
+    #[test]
+    fn test_consumer_stall() {
+        let watchdog = Watchdog::new(1, Duration::from_secs(0));
+        let (producer, consumer) = spsc::channel(4);
+        let (producer, consumer) = watchdog.wrap(producer, consumer);
+        producer.produce(1).unwrap();
+        assert!(watchdog.check().is_empty());
+
+        producer.produce(2).unwrap();
+        producer.produce(3).unwrap();
+        let stalls = watchdog.check();
+        assert_eq!(stalls.iter().map(|s| (s.index, s.kind)).collect::<Vec<_>>(), &[
+            (0, Stall::Consumer(3)),
+        ]);
+
+        // The stall ends once the depth is no longer above the threshold.
+        assert_eq!(consumer.consume(), Ok(1));
+        assert_eq!(watchdog.check().len(), 1);
+        assert_eq!(consumer.consume(), Ok(2));
+        assert!(watchdog.check().is_empty());
+    }
+
+    #[test]
+    fn test_producer_stall() {
+        let watchdog = Watchdog::new(8, Duration::from_secs(0));
+        let (producer, consumer) = spsc::channel(1);
+        let (producer, consumer) = watchdog.wrap(producer, consumer);
+        producer.produce(1).unwrap();
+        assert!(producer.produce(2).is_err());
+        assert_eq!(watchdog.check().iter().map(|s| s.kind).collect::<Vec<_>>(), &[Stall::Producer]);
+
+        // The stall ends once the producers add an item.
+        consumer.consume().unwrap();
+        producer.produce(2).unwrap();
+        assert!(watchdog.check().is_empty());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let watchdog = Watchdog::new(0, Duration::from_secs(3600));
+        let (producer, consumer) = spsc::channel(1);
+        let (producer, _consumer) = watchdog.wrap(producer, consumer);
+        producer.produce(1).unwrap();
+        assert!(producer.produce(2).is_err());
+        assert!(watchdog.check().is_empty());
+    }
+
+    #[test]
+    fn test_on_stall() {
+        let watchdog = Watchdog::new(0, Duration::from_secs(0));
+        let reports = Arc::new(Mutex::new(vec![]));
+        let sink = reports.clone();
+        watchdog.on_stall(move |r| sink.lock().unwrap().push(r.index));
+
+        let (ap, ac) = spsc::channel::<i32>(4);
+        let (_ap, _ac) = watchdog.wrap(ap, ac);
+        let (bp, bc) = spsc::channel(4);
+        let (bp, bc) = watchdog.wrap(bp, bc);
+        bp.produce(1).unwrap();
+        watchdog.check();
+        assert_eq!(*reports.lock().unwrap(), &[1]);
+
+        // Queues are no longer watched once their producers and consumers are dropped.
+        drop((bp, bc));
+        assert!(watchdog.check().is_empty());
+        assert_eq!(reports.lock().unwrap().len(), 1);
+    }
+}
@@ -22,6 +22,10 @@ extern crate queuecheck;
 extern crate npnc;
 
 use std::env;
+use std::thread;
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicIsize};
+use std::sync::atomic::Ordering::*;
 
 #[cfg(feature="valgrind")]
 const OPERATIONS: usize = 100_000;
@@ -47,6 +51,20 @@ macro_rules! test_mpmc {
     });
 }
 
+macro_rules! test_mpsc {
+    ([$($path:tt)*]$(, $size:expr)*) => ({
+        let (producer, consumer) = npnc::$($path)*::channel($($size)*);
+        test!([$($path)*], vec![producer.clone(), producer], vec![consumer]);
+    });
+}
+
+macro_rules! test_spmc {
+    ([$($path:tt)*]$(, $size:expr)*) => ({
+        let (producer, consumer) = npnc::$($path)*::channel($($size)*);
+        test!([$($path)*], vec![producer], vec![consumer.clone(), consumer]);
+    });
+}
+
 macro_rules! test_spsc {
     ([$($path:tt)*]$(, $size:expr)*) => ({
         let (producer, consumer) = npnc::$($path)*::channel($($size)*);
@@ -54,6 +72,273 @@ macro_rules! test_spsc {
     });
 }
 
+fn test_bounded_mpmc_wait_free() {
+    use npnc::{ConsumeError, ProduceError};
+    use npnc::bounded::mpmc::{self, Consumer, Producer};
+
+    let (producer, consumer) = mpmc::wait_free_channel(2 << 24, 16);
+    queuecheck_test!(
+        OPERATIONS,
+        vec![producer.clone(), producer],
+        vec![consumer.clone(), consumer],
+        |p: &Producer<String>, i: String| {
+            let mut item = i;
+            while let Err(ProduceError::Contended(i)) = p.produce(item) { item = i; }
+        },
+        |c: &Consumer<String>| {
+            loop {
+                match c.consume() {
+                    Ok(item) => return Some(item),
+                    Err(ConsumeError::Contended) => { },
+                    Err(_) => return None,
+                }
+            }
+        }
+    );
+}
+
+fn test_bounded_mpmc_scq() {
+    use npnc::bounded::mpmc::{self, Consumer, Producer, Scq};
+
+    let (producer, consumer) = mpmc::custom_channel(2 << 24);
+    queuecheck_test!(
+        OPERATIONS,
+        vec![producer.clone(), producer],
+        vec![consumer.clone(), consumer],
+        |p: &Producer<String, Scq<String>>, i: String| p.produce(i).unwrap(),
+        |c: &Consumer<String, Scq<String>>| c.consume().ok()
+    );
+}
+
+fn test_bounded_mpmc_exact() {
+    use npnc::{ProduceError};
+    use npnc::bounded::mpmc::{self, Consumer, Producer};
+
+    let (producer, consumer) = mpmc::channel_exact(1000);
+    queuecheck_test!(
+        OPERATIONS,
+        vec![producer.clone(), producer],
+        vec![consumer.clone(), consumer],
+        |p: &Producer<String>, i: String| {
+            let mut item = i;
+            while let Err(ProduceError::Full(i)) = p.produce(item) {
+                assert!(p.len() <= 1000);
+                item = i;
+            }
+        },
+        |c: &Consumer<String>| c.consume().ok()
+    );
+}
+
+/// An item that tracks the number of items that have been created but not dropped.
+struct Counted(Arc<AtomicIsize>);
+
+impl Counted {
+    fn new(live: &Arc<AtomicIsize>) -> Box<Self> {
+        live.fetch_add(1, SeqCst);
+        Box::new(Counted(live.clone()))
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, SeqCst);
+    }
+}
+
+fn test_bounded_mpmc_drop() {
+    use npnc::bounded::mpmc::{self, Backend, Consumer, Producer, Scq};
+
+    type Channel<B> = fn(usize) -> (Producer<Box<Counted>, B>, Consumer<Box<Counted>, B>);
+
+    fn test<B>(channel: Channel<B>) where B: Backend<Box<Counted>> + 'static {
+        let live = Arc::new(AtomicIsize::new(0));
+        for _ in 0..(OPERATIONS / 1_000) {
+            // The queue is dropped by whichever thread drops its producer or consumer last, which
+            // may be while the other threads are still adding or removing items.
+            let (producer, consumer) = channel(32);
+            let mut threads = (0..4).map(|index| {
+                let (producer, live) = (producer.clone(), live.clone());
+                thread::spawn(move || {
+                    for _ in 0..(index * 16) {
+                        let _ = producer.produce(Counted::new(&live));
+                    }
+                })
+            }).collect::<Vec<_>>();
+            threads.push(thread::spawn(move || {
+                for _ in 0..8 {
+                    let _ = consumer.consume();
+                }
+            }));
+            drop(producer);
+
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(live.load(SeqCst), 0);
+        }
+    }
+
+    test(mpmc::channel);
+    test(|size| mpmc::wait_free_channel(size, 4));
+    test(mpmc::custom_channel::<_, Scq<_>>);
+}
+
+fn test_bounded_mpmc_drain_snapshot() {
+    use std::sync::atomic::{AtomicUsize};
+
+    use npnc::bounded::mpmc::{self, Backend, Consumer, Producer, Scq};
+
+    fn test<B>(producer: Producer<(usize, usize), B>, consumer: Consumer<(usize, usize), B>)
+        where B: Backend<(usize, usize)> + 'static
+    {
+        // Every item is removed exactly once and each consumer sees the items added by each
+        // producer in the order they were added.
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let producers = (0..2).map(|id| {
+            let producer = producer.clone();
+            thread::spawn(move || {
+                for index in 0..OPERATIONS {
+                    while producer.produce((id, index)).is_err() { }
+                }
+            })
+        }).collect::<Vec<_>>();
+        let consumers = (0..2).map(|_| {
+            let (consumer, consumed) = (consumer.clone(), consumed.clone());
+            thread::spawn(move || {
+                let mut next = [0, 0];
+                let mut sums = [0, 0];
+                while consumed.load(SeqCst) < 2 * OPERATIONS {
+                    let items = consumer.drain_snapshot();
+                    consumed.fetch_add(items.len(), SeqCst);
+                    for (id, index) in items {
+                        assert!(index >= next[id]);
+                        next[id] = index + 1;
+                        sums[id] += index;
+                    }
+                }
+                sums
+            })
+        }).collect::<Vec<_>>();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let sum = (OPERATIONS * (OPERATIONS - 1)) / 2;
+        let sums = consumers.into_iter().map(|c| c.join().unwrap()).fold([0, 0], |a, s| {
+            [a[0] + s[0], a[1] + s[1]]
+        });
+        assert_eq!(sums, [sum, sum]);
+    }
+
+    let (producer, consumer) = mpmc::channel(1024);
+    test(producer, consumer);
+    let (producer, consumer) = mpmc::channel_exact(1000);
+    test(producer, consumer);
+    let (producer, consumer) = mpmc::custom_channel::<_, Scq<_>>(1024);
+    test(producer, consumer);
+}
+
+fn test_unbounded_mpmc_qsbr() {
+    use npnc::reclaim::{Qsbr};
+    use npnc::unbounded::mpmc::{self, Consumer, Producer};
+
+    let (producer, consumer) = mpmc::qsbr_channel(2);
+    queuecheck_test!(
+        OPERATIONS,
+        vec![producer.clone(), producer],
+        vec![consumer.clone(), consumer],
+        |p: &Producer<String, Qsbr>, i: String| { p.produce(i).unwrap(); p.quiescent(); },
+        |c: &Consumer<String, Qsbr>| { let item = c.consume().ok(); c.quiescent(); item }
+    );
+}
+
+fn test_unbounded_mpmc_faa_array() {
+    use npnc::reclaim::{Hazard};
+    use npnc::unbounded::mpmc::{self, Consumer, FaaArray, Producer};
+
+    let (producer, consumer) = mpmc::custom_channel(2);
+    queuecheck_test!(
+        OPERATIONS,
+        vec![producer.clone(), producer],
+        vec![consumer.clone(), consumer],
+        |p: &Producer<String, Hazard, FaaArray<String>>, i: String| p.produce(i).unwrap(),
+        |c: &Consumer<String, Hazard, FaaArray<String>>| c.consume().ok()
+    );
+}
+
+fn test_unbounded_mpmc_lscq() {
+    use npnc::reclaim::{Hazard};
+    use npnc::unbounded::mpmc::{self, Consumer, Lscq, Producer};
+
+    let (producer, consumer) = mpmc::custom_channel(2);
+    queuecheck_test!(
+        OPERATIONS,
+        vec![producer.clone(), producer],
+        vec![consumer.clone(), consumer],
+        |p: &Producer<String, Hazard, Lscq<String>>, i: String| p.produce(i).unwrap(),
+        |c: &Consumer<String, Hazard, Lscq<String>>| c.consume().ok()
+    );
+}
+
+fn test_unbounded_mpmc_elimination() {
+    use npnc::unbounded::mpmc::{Builder, Consumer, Producer};
+
+    let (producer, consumer) = Builder::new().clones(2).elimination(2).build();
+    queuecheck_test!(
+        OPERATIONS,
+        vec![producer.clone(), producer],
+        vec![consumer.clone(), consumer],
+        |p: &Producer<String>, i: String| p.produce(i).unwrap(),
+        |c: &Consumer<String>| c.consume().ok()
+    );
+}
+
+fn test_stack() {
+    use std::collections::{HashSet};
+
+    let (producer, consumer) = npnc::stack::channel(2);
+
+    let producers = (0..2).map(|p| {
+        let producer = producer.clone();
+        thread::spawn(move || {
+            for index in 0..OPERATIONS {
+                producer.produce(p * OPERATIONS + index).unwrap();
+            }
+        })
+    }).collect::<Vec<_>>();
+    drop(producer);
+
+    let consumers = (0..2).map(|_| {
+        let consumer = consumer.clone();
+        thread::spawn(move || {
+            let mut items = vec![];
+            loop {
+                match consumer.consume() {
+                    Ok(item) => items.push(item),
+                    Err(npnc::ConsumeError::Disconnected) => return items,
+                    Err(_) => thread::yield_now(),
+                }
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    for producer in producers {
+        producer.join().unwrap();
+    }
+
+    let mut seen = HashSet::new();
+    for consumer in consumers {
+        for item in consumer.join().unwrap() {
+            assert!(seen.insert(item), "duplicate");
+        }
+    }
+    while let Ok(item) = consumer.consume() {
+        assert!(seen.insert(item), "duplicate");
+    }
+    assert_eq!(seen.len(), 2 * OPERATIONS);
+}
+
 macro_rules! run {
     ($filter:expr, $name:expr, $test:expr) => ({
         if $filter.as_ref().map_or(true, |f| $name.contains(f)) {
@@ -68,5 +353,20 @@ fn main() {
     run!(filter, "bounded_spsc", test_spsc!([bounded::spsc], 2 << 24));
     run!(filter, "unbounded_spsc", test_spsc!([unbounded::spsc]));
     run!(filter, "bounded_mpmc", test_mpmc!([bounded::mpmc], 2 << 24));
+    run!(filter, "bounded_mpmc_wait_free", test_bounded_mpmc_wait_free());
+    run!(filter, "bounded_mpmc_scq", test_bounded_mpmc_scq());
+    run!(filter, "bounded_mpmc_exact", test_bounded_mpmc_exact());
+    run!(filter, "bounded_mpmc_drop", test_bounded_mpmc_drop());
+    run!(filter, "bounded_mpmc_drain_snapshot", test_bounded_mpmc_drain_snapshot());
+    run!(filter, "bounded_mpsc", test_mpsc!([bounded::mpsc], 2 << 24));
+    run!(filter, "bounded_spmc", test_spmc!([bounded::spmc], 2 << 24));
     run!(filter, "unbounded_mpmc", test_mpmc!([unbounded::mpmc], 2));
+    run!(filter, "unbounded_mpmc_qsbr", test_unbounded_mpmc_qsbr());
+    run!(filter, "unbounded_mpmc_faa_array", test_unbounded_mpmc_faa_array());
+    run!(filter, "unbounded_mpmc_lscq", test_unbounded_mpmc_lscq());
+    run!(filter, "unbounded_mpmc_elimination", test_unbounded_mpmc_elimination());
+    run!(filter, "unbounded_mpsc", test_mpsc!([unbounded::mpsc]));
+    run!(filter, "unbounded_spmc", test_spmc!([unbounded::spmc], 2));
+    run!(filter, "unbounded_relaxed", test_mpsc!([unbounded::relaxed]));
+    run!(filter, "stack", test_stack());
 }